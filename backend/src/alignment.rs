@@ -0,0 +1,392 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-15 14:40:12
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-15 14:40:12
+ * @FilePath: /udx710-backend/backend/src/alignment.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 天线对准模块
+//!
+//! 调整外置定向天线时以尽可能高的频率轮询服务小区的 RSRP/SINR，
+//! 通过 WebSocket 推送每个采样，并附带滑动平均、峰值保持和"目前最佳朝向"标记。
+//! 对准期间暂停数据连接看门狗、站点勘测、温度监控和小区优选等后台轮询（见 [`is_active`]），
+//! 把 AT 通道让给对准采样；
+//! 到达超时时间后自动结束。同一时间只允许一个对准会话。
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::band_lock::{fetch_primary_cell, parse_first};
+use crate::dbus::get_serving_cell_info;
+use crate::models::{AlignmentBest, AlignmentCommand, AlignmentRequest, AlignmentSample, AlignmentStatus, CellInfo};
+
+/// 最小采样间隔（毫秒）
+const MIN_INTERVAL_MS: u64 = 100;
+/// 超时时间范围（秒）
+const MIN_TIMEOUT_SECS: u64 = 10;
+const MAX_TIMEOUT_SECS: u64 = 1800;
+/// 滑动平均窗口上限
+const MAX_WINDOW: usize = 100;
+/// 服务小区制式刷新间隔（秒），采样失败时立即刷新
+const TECH_REFRESH_SECS: u64 = 5;
+
+/// 正在进行的对准会话
+static ACTIVE: Mutex<Option<AlignmentStatus>> = Mutex::new(None);
+
+/// 是否处于对准模式（后台轮询据此暂停）
+pub fn is_active() -> bool {
+    ACTIVE.lock().unwrap().is_some()
+}
+
+/// 获取对准状态
+pub fn status() -> AlignmentStatus {
+    ACTIVE.lock().unwrap().clone().unwrap_or_default()
+}
+
+/// 对准会话占用标记，释放时退出对准模式
+pub struct AlignmentGuard(());
+
+impl Drop for AlignmentGuard {
+    fn drop(&mut self) {
+        *ACTIVE.lock().unwrap() = None;
+    }
+}
+
+/// 进入对准模式（参数已规范化）
+pub fn begin(request: &AlignmentRequest) -> Result<(AlignmentRequest, AlignmentGuard), String> {
+    let mut active = ACTIVE.lock().unwrap();
+    if active.is_some() {
+        return Err("An alignment session is already running".to_string());
+    }
+
+    let request = AlignmentRequest {
+        interval_ms: request.interval_ms.max(MIN_INTERVAL_MS),
+        timeout_secs: request.timeout_secs.clamp(MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS),
+        window: request.window.clamp(1, MAX_WINDOW),
+    };
+    *active = Some(AlignmentStatus {
+        active: true,
+        started_at: Some(Utc::now().to_rfc3339()),
+        interval_ms: request.interval_ms,
+        timeout_secs: request.timeout_secs,
+        ..Default::default()
+    });
+    Ok((request, AlignmentGuard(())))
+}
+
+/// 滑动平均 / 峰值保持 / 最佳朝向计算
+pub struct AlignmentTracker {
+    window: usize,
+    rsrp: VecDeque<f64>,
+    sinr: VecDeque<f64>,
+    rsrp_peak: Option<f64>,
+    sinr_peak: Option<f64>,
+    best: Option<AlignmentBest>,
+    direction: String,
+    seq: u64,
+}
+
+impl AlignmentTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            rsrp: VecDeque::new(),
+            sinr: VecDeque::new(),
+            rsrp_peak: None,
+            sinr_peak: None,
+            best: None,
+            direction: String::new(),
+            seq: 0,
+        }
+    }
+
+    /// 切换朝向（清空滑动平均，避免不同朝向的采样混在一起）
+    pub fn set_direction(&mut self, direction: &str) {
+        self.direction = direction.trim().to_string();
+        self.rsrp.clear();
+        self.sinr.clear();
+    }
+
+    /// 清除峰值保持和最佳朝向
+    pub fn reset(&mut self) {
+        self.rsrp.clear();
+        self.sinr.clear();
+        self.rsrp_peak = None;
+        self.sinr_peak = None;
+        self.best = None;
+    }
+
+    pub fn direction(&self) -> &str {
+        &self.direction
+    }
+
+    pub fn best(&self) -> Option<&AlignmentBest> {
+        self.best.as_ref()
+    }
+
+    /// 加入一个采样
+    ///
+    /// 滑动平均窗口填满后才参与最佳朝向比较，RSRP 平均值更高者胜出，
+    /// 相同时比较 SINR 平均值
+    pub fn push(&mut self, cell: &CellInfo, timestamp: &str) -> AlignmentSample {
+        self.seq += 1;
+        let rsrp = signal_value(&cell.rsrp);
+        let rsrq = signal_value(&cell.rsrq);
+        let sinr = signal_value(&cell.sinr);
+
+        let push_window = |values: &mut VecDeque<f64>, value: Option<f64>, window: usize| {
+            if let Some(v) = value {
+                values.push_back(v);
+                if values.len() > window {
+                    values.pop_front();
+                }
+            }
+        };
+        push_window(&mut self.rsrp, rsrp, self.window);
+        push_window(&mut self.sinr, sinr, self.window);
+
+        let peak = |peak: Option<f64>, value: Option<f64>| match (peak, value) {
+            (Some(p), Some(v)) => Some(p.max(v)),
+            (p, v) => p.or(v),
+        };
+        self.rsrp_peak = peak(self.rsrp_peak, rsrp);
+        self.sinr_peak = peak(self.sinr_peak, sinr);
+
+        let rsrp_avg = average(&self.rsrp);
+        let sinr_avg = average(&self.sinr);
+
+        let mut new_best = false;
+        if let (Some(avg), true) = (rsrp_avg, self.rsrp.len() >= self.window) {
+            let better = match &self.best {
+                None => true,
+                Some(best) => {
+                    avg > best.rsrp_avg
+                        || (avg == best.rsrp_avg && sinr_avg.unwrap_or(f64::MIN) > best.sinr_avg.unwrap_or(f64::MIN))
+                }
+            };
+            if better {
+                self.best = Some(AlignmentBest {
+                    direction: self.direction.clone(),
+                    rsrp_avg: avg,
+                    sinr_avg,
+                    timestamp: timestamp.to_string(),
+                });
+                new_best = true;
+            }
+        }
+
+        AlignmentSample {
+            timestamp: timestamp.to_string(),
+            seq: self.seq,
+            direction: self.direction.clone(),
+            tech: cell.tech.clone(),
+            band: cell.band.split(',').next().unwrap_or_default().to_string(),
+            pci: cell.pci.split(',').next().unwrap_or_default().to_string(),
+            rsrp,
+            rsrq,
+            sinr,
+            rsrp_avg,
+            sinr_avg,
+            rsrp_peak: self.rsrp_peak,
+            sinr_peak: self.sinr_peak,
+            best: self.best.clone(),
+            new_best,
+        }
+    }
+}
+
+/// 信号原始值（×100）换算为 dBm/dB
+fn signal_value(raw: &str) -> Option<f64> {
+    parse_first::<i32>(raw).map(|v| v as f64 / 100.0)
+}
+
+/// 平均值（保留一位小数）
+fn average(values: &VecDeque<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    Some((avg * 10.0).round() / 10.0)
+}
+
+/// 采样服务小区（按需刷新驻留制式）
+async fn sample(conn: &Connection, tech: &mut Option<(String, Instant)>) -> Result<CellInfo, String> {
+    let stale = tech
+        .as_ref()
+        .is_none_or(|(_, at)| at.elapsed() >= Duration::from_secs(TECH_REFRESH_SECS));
+    if stale {
+        let serving = get_serving_cell_info(conn)
+            .await
+            .map_err(|e| format!("Failed to get serving cell info: {}", e))?;
+        *tech = Some((serving.tech, Instant::now()));
+    }
+
+    let current = tech.as_ref().map(|(t, _)| t.clone()).unwrap_or_default();
+    let result = fetch_primary_cell(conn, &current).await;
+    if result.is_err() {
+        *tech = None;
+    }
+    result
+}
+
+/// 对准会话主循环：定时采样并推送，处理客户端指令，超时或客户端关闭时结束
+pub async fn run(conn: Arc<Connection>, socket: WebSocket, request: AlignmentRequest, _guard: AlignmentGuard) {
+    let (mut writer, mut reader) = socket.split();
+
+    info!(
+        interval_ms = request.interval_ms,
+        timeout_secs = request.timeout_secs,
+        window = request.window,
+        "Antenna alignment started"
+    );
+
+    let start = serde_json::json!({
+        "type": "start",
+        "interval_ms": request.interval_ms,
+        "timeout_secs": request.timeout_secs,
+        "window": request.window,
+    });
+    let _ = writer.send(Message::Text(start.to_string().into())).await;
+
+    let mut tracker = AlignmentTracker::new(request.window);
+    let mut tech: Option<(String, Instant)> = None;
+    let mut ticker = tokio::time::interval(Duration::from_millis(request.interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = tokio::time::sleep(Duration::from_secs(request.timeout_secs));
+    tokio::pin!(deadline);
+
+    let reason = loop {
+        tokio::select! {
+            _ = &mut deadline => break "timeout",
+            message = reader.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let command: AlignmentCommand = match serde_json::from_str(&text) {
+                        Ok(command) => command,
+                        Err(e) => {
+                            let error = serde_json::json!({ "type": "error", "message": format!("Invalid command: {}", e) });
+                            let _ = writer.send(Message::Text(error.to_string().into())).await;
+                            continue;
+                        }
+                    };
+                    if command.reset {
+                        tracker.reset();
+                    }
+                    if let Some(direction) = command.direction {
+                        tracker.set_direction(&direction);
+                    }
+                    if let Some(status) = ACTIVE.lock().unwrap().as_mut() {
+                        status.direction = tracker.direction().to_string();
+                        status.best = tracker.best().cloned();
+                    }
+                }
+                // Ping 由 WebSocket 层自动回复 Pong
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break "client_closed",
+                Some(Ok(_)) => {}
+            },
+            _ = ticker.tick() => {
+                let message = match sample(&conn, &mut tech).await {
+                    Ok(cell) => {
+                        let sample = tracker.push(&cell, &Utc::now().to_rfc3339());
+                        if let Some(status) = ACTIVE.lock().unwrap().as_mut() {
+                            status.samples = sample.seq;
+                            status.direction = sample.direction.clone();
+                            status.best = sample.best.clone();
+                        }
+                        let mut value = serde_json::to_value(&sample).unwrap_or_default();
+                        value["type"] = "sample".into();
+                        value
+                    }
+                    Err(e) => serde_json::json!({ "type": "error", "message": e }),
+                };
+                if writer.send(Message::Text(message.to_string().into())).await.is_err() {
+                    break "client_closed";
+                }
+            }
+        }
+    };
+
+    let end = serde_json::json!({ "type": "end", "reason": reason, "best": tracker.best() });
+    if writer.send(Message::Text(end.to_string().into())).await.is_ok() {
+        let close = CloseFrame {
+            code: close_code::NORMAL,
+            reason: reason.into(),
+        };
+        if let Err(e) = writer.send(Message::Close(Some(close))).await {
+            warn!(error = %e, "Alignment: failed to send close frame");
+        }
+    }
+
+    info!(reason, best = ?tracker.best(), "Antenna alignment finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(rsrp: i32, sinr: i32) -> CellInfo {
+        CellInfo {
+            is_serving: true,
+            tech: "nr".to_string(),
+            band: "n78".to_string(),
+            pci: "597".to_string(),
+            rsrp: rsrp.to_string(),
+            rsrq: "-1100".to_string(),
+            sinr: sinr.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tracker_average_and_peak() {
+        let mut tracker = AlignmentTracker::new(3);
+        tracker.push(&cell(-9000, 1000), "t1");
+        let s = tracker.push(&cell(-9500, 500), "t2");
+        assert_eq!((s.rsrp, s.rsrp_avg, s.rsrp_peak), (Some(-95.0), Some(-92.5), Some(-90.0)));
+        // 窗口未满，不产生最佳朝向
+        assert!(s.best.is_none());
+
+        let s = tracker.push(&cell(-10000, 0), "t3");
+        assert_eq!(s.rsrp_avg, Some(-95.0));
+        assert_eq!(s.sinr_avg, Some(5.0));
+        assert!(s.new_best);
+
+        // 窗口滑动，最早的 -90 移出
+        let s = tracker.push(&cell(-10100, 200), "t4");
+        assert_eq!(s.rsrp_avg, Some(-98.7));
+        assert_eq!(s.rsrp_peak, Some(-90.0));
+        assert!(!s.new_best);
+    }
+
+    #[test]
+    fn test_tracker_best_direction() {
+        let mut tracker = AlignmentTracker::new(2);
+        tracker.set_direction("N");
+        tracker.push(&cell(-10500, 300), "t1");
+        tracker.push(&cell(-10300, 300), "t2");
+        assert_eq!(tracker.best().unwrap().direction, "N");
+
+        // 换向后窗口清空，需要重新填满
+        tracker.set_direction("NE 45°");
+        let s = tracker.push(&cell(-9000, 1200), "t3");
+        assert_eq!(s.rsrp_avg, Some(-90.0));
+        assert!(!s.new_best);
+        let s = tracker.push(&cell(-9200, 1100), "t4");
+        assert!(s.new_best);
+        let best = s.best.unwrap();
+        assert_eq!((best.direction.as_str(), best.rsrp_avg, best.timestamp.as_str()), ("NE 45°", -91.0, "t4"));
+
+        tracker.reset();
+        let s = tracker.push(&cell(-11000, 0), "t5");
+        assert_eq!((s.rsrp_peak, s.best), (Some(-110.0), None));
+    }
+}
@@ -0,0 +1,308 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-15 16:12:40
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-15 16:12:40
+ * @FilePath: /udx710-backend/backend/src/apn_db.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! APN 数据库模块
+//!
+//! 内置 Android apns-conf 格式的 APN 数据库（见 `apns-conf.xml`），按 SIM 卡归属的
+//! MCC/MNC 查找数据连接 APN，可选按 SPN / ICCID / IMSI 区分虚拟运营商。
+//! 配置文件中的自定义条目优先于内置条目，用于补充或覆盖内置数据。
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+use crate::config::ApnEntry;
+use crate::models::{ApnDatabaseEntry, ApnSimIdentity};
+
+/// 内置 APN 数据库
+const BUILTIN_APNS_CONF: &str = include_str!("apns-conf.xml");
+
+lazy_static! {
+    static ref BUILTIN: Vec<ApnEntry> = parse_apns_conf(BUILTIN_APNS_CONF);
+}
+
+/// 内置条目
+pub fn builtin() -> &'static [ApnEntry] {
+    &BUILTIN
+}
+
+/// 解析 apns-conf 格式的 XML（只处理 `<apn .../>` 元素的属性）
+pub fn parse_apns_conf(xml: &str) -> Vec<ApnEntry> {
+    let mut entries = Vec::new();
+    let mut rest = strip_comments(xml);
+
+    while let Some(start) = find_apn_tag(&rest) {
+        let tag = &rest[start + 4..];
+        let end = tag.find('>').unwrap_or(tag.len());
+        let attrs = parse_attributes(tag[..end].trim_end_matches('/'));
+
+        let get = |key: &str| attrs.get(key).cloned().unwrap_or_default();
+        let entry = ApnEntry {
+            carrier: get("carrier"),
+            mcc: get("mcc"),
+            mnc: get("mnc"),
+            apn: get("apn"),
+            apn_type: get("type"),
+            protocol: get("protocol"),
+            roaming_protocol: get("roaming_protocol"),
+            user: get("user"),
+            password: get("password"),
+            authtype: attrs.get("authtype").and_then(|v| v.parse().ok()),
+            mtu: attrs.get("mtu").and_then(|v| v.parse().ok()).filter(|m| *m > 0),
+            mvno_type: get("mvno_type"),
+            mvno_match_data: get("mvno_match_data"),
+        };
+        if !entry.mcc.is_empty() && !entry.mnc.is_empty() {
+            entries.push(entry);
+        }
+        rest = tag[end..].to_string();
+    }
+    entries
+}
+
+/// 去掉 XML 注释
+fn strip_comments(xml: &str) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find("<!--") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find("-->") {
+            Some(end) => &rest[start + end + 3..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 查找下一个 `<apn` 元素（排除 `<apns`）
+fn find_apn_tag(xml: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(pos) = xml[offset..].find("<apn") {
+        let start = offset + pos;
+        if xml[start + 4..].starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            return Some(start);
+        }
+        offset = start + 4;
+    }
+    None
+}
+
+/// 解析 `key="value"` 属性列表
+fn parse_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = tag.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let value_part = rest[eq + 1..].trim_start();
+        let Some(quote) = value_part.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(len) = value_part[1..].find(quote) else {
+            break;
+        };
+        attrs.insert(key, xml_unescape(&value_part[1..1 + len]));
+        rest = value_part[len + 2..].trim_start();
+    }
+    attrs
+}
+
+/// XML 实体反转义
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// 是否为数据连接条目（type 为空或包含 default/internet/*）
+fn is_data_apn(entry: &ApnEntry) -> bool {
+    entry.apn_type.trim().is_empty()
+        || entry
+            .apn_type
+            .split(',')
+            .any(|t| matches!(t.trim(), "default" | "internet" | "*"))
+}
+
+/// 前缀匹配，x/X 为单个数字通配
+fn prefix_matches(value: &str, pattern: &str) -> bool {
+    value.len() >= pattern.len()
+        && value
+            .chars()
+            .zip(pattern.chars())
+            .all(|(v, p)| p.eq_ignore_ascii_case(&'x') || v == p)
+}
+
+/// 虚拟运营商匹配
+///
+/// # Returns
+/// None 表示不匹配；Some(true) 表示按虚拟运营商精确匹配，Some(false) 表示普通条目
+fn mvno_matches(entry: &ApnEntry, sim: &ApnSimIdentity) -> Option<bool> {
+    let data = entry.mvno_match_data.trim();
+    let matched = match entry.mvno_type.trim().to_lowercase().as_str() {
+        "" => return Some(false),
+        "spn" => !data.is_empty() && sim.spn.trim().eq_ignore_ascii_case(data),
+        "iccid" => !data.is_empty() && prefix_matches(&sim.iccid, data),
+        "imsi" => !data.is_empty() && prefix_matches(&sim.imsi, data),
+        _ => false,
+    };
+    matched.then_some(true)
+}
+
+/// 列出指定 MCC/MNC 的全部条目（自定义在前）
+pub fn lookup(custom: &[ApnEntry], mcc: &str, mnc: &str) -> Vec<ApnDatabaseEntry> {
+    let tagged = custom
+        .iter()
+        .map(|e| ("custom", e))
+        .chain(builtin().iter().map(|e| ("builtin", e)));
+    tagged
+        .filter(|(_, e)| e.mcc == mcc && e.mnc == mnc)
+        .map(|(source, e)| ApnDatabaseEntry {
+            source: source.to_string(),
+            entry: e.clone(),
+        })
+        .collect()
+}
+
+/// 为 SIM 卡选择数据连接 APN
+///
+/// 优先级：自定义虚拟运营商条目 > 自定义普通条目 > 内置虚拟运营商条目 > 内置普通条目，
+/// 同级按出现顺序。虚拟运营商条目不匹配时跳过。
+pub fn recommend(custom: &[ApnEntry], sim: &ApnSimIdentity) -> Option<ApnDatabaseEntry> {
+    lookup(custom, &sim.mcc, &sim.mnc)
+        .into_iter()
+        .filter(|e| is_data_apn(&e.entry) && !e.entry.apn.is_empty())
+        .filter_map(|e| {
+            let specific = mvno_matches(&e.entry, sim)?;
+            let rank = (e.source != "custom") as u8 * 2 + (!specific) as u8;
+            Some((rank, e))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, e)| e)
+}
+
+/// apns-conf 协议转换为 ofono 协议（ip / ipv6 / dual）
+pub fn ofono_protocol(entry: &ApnEntry, roaming: bool) -> &'static str {
+    let protocol = if roaming && !entry.roaming_protocol.is_empty() {
+        &entry.roaming_protocol
+    } else {
+        &entry.protocol
+    };
+    match protocol.trim().to_uppercase().as_str() {
+        "IP" | "IPV4" => "ip",
+        "IPV6" => "ipv6",
+        _ => "dual",
+    }
+}
+
+/// apns-conf 认证类型转换为 ofono 认证方式（none / pap / chap）
+pub fn ofono_auth_method(entry: &ApnEntry) -> &'static str {
+    match entry.authtype {
+        Some(0) => "none",
+        Some(1) => "pap",
+        Some(_) => "chap",
+        None if entry.user.is_empty() => "none",
+        None => "chap",
+    }
+}
+
+/// 确保网络接口 MTU 为指定值（不一致时通过 ip link 设置）
+pub fn ensure_mtu(interface: &str, mtu: u32) -> Result<bool, String> {
+    if interface.is_empty() || interface.contains('/') {
+        return Err(format!("Invalid interface: {:?}", interface));
+    }
+    let current = std::fs::read_to_string(format!("/sys/class/net/{}/mtu", interface))
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok());
+    if current == Some(mtu) {
+        return Ok(false);
+    }
+
+    let output = std::process::Command::new("ip")
+        .args(["link", "set", "dev", interface, "mtu", &mtu.to_string()])
+        .output()
+        .map_err(|e| format!("Failed to run ip: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sim(mcc: &str, mnc: &str, spn: &str) -> ApnSimIdentity {
+        ApnSimIdentity {
+            mcc: mcc.to_string(),
+            mnc: mnc.to_string(),
+            spn: spn.to_string(),
+            iccid: "8944110068256270054".to_string(),
+            imsi: "234100123456789".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_apns_conf() {
+        let entries = parse_apns_conf(
+            r#"<apns version="8">
+              <!-- <apn carrier="commented" mcc="001" mnc="01" apn="x" /> -->
+              <apn carrier="A&amp;B" mcc="001" mnc='01' apn="test" type="default,supl"
+                   user="u" authtype="1" mtu="1400" protocol="IPV4V6" />
+              <apn carrier="no mcc" apn="x" />
+            </apns>"#,
+        );
+        assert_eq!(entries.len(), 1);
+        let e = &entries[0];
+        assert_eq!((e.carrier.as_str(), e.mnc.as_str(), e.apn.as_str()), ("A&B", "01", "test"));
+        assert_eq!((e.authtype, e.mtu), (Some(1), Some(1400)));
+        assert_eq!((ofono_protocol(e, false), ofono_auth_method(e)), ("dual", "pap"));
+
+        // 内置数据库可以正常解析
+        assert!(builtin().len() > 50);
+        assert!(builtin().iter().any(|e| e.carrier == "AT&T"));
+    }
+
+    #[test]
+    fn test_recommend() {
+        // 内置的中国移动条目，跳过 mms 条目
+        let cm = recommend(&[], &sim("460", "00", "")).unwrap();
+        assert_eq!((cm.source.as_str(), cm.entry.apn.as_str()), ("builtin", "cmnet"));
+
+        // 同一网络上的虚拟运营商按 SPN 区分
+        assert_eq!(recommend(&[], &sim("234", "10", "O2 - UK")).unwrap().entry.apn, "mobile.o2.co.uk");
+        assert_eq!(recommend(&[], &sim("234", "10", "giffgaff")).unwrap().entry.apn, "giffgaff.com");
+
+        // 自定义条目优先，ICCID 前缀匹配
+        let custom = vec![
+            ApnEntry {
+                mcc: "234".to_string(),
+                mnc: "10".to_string(),
+                apn: "iot.example".to_string(),
+                mvno_type: "iccid".to_string(),
+                mvno_match_data: "894411".to_string(),
+                ..Default::default()
+            },
+            ApnEntry {
+                mcc: "234".to_string(),
+                mnc: "10".to_string(),
+                apn: "generic.example".to_string(),
+                ..Default::default()
+            },
+        ];
+        let chosen = recommend(&custom, &sim("234", "10", "giffgaff")).unwrap();
+        assert_eq!((chosen.source.as_str(), chosen.entry.apn.as_str()), ("custom", "iot.example"));
+        assert_eq!(lookup(&custom, "234", "10").len(), 4);
+
+        assert!(recommend(&[], &sim("001", "01", "")).is_none());
+        assert!(prefix_matches("234100123", "23410x1"));
+    }
+}
@@ -0,0 +1,404 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 19:20:41
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 19:20:41
+ * @FilePath: /udx710-backend/backend/src/arfcn.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 频点换算模块
+//!
+//! 根据 3GPP TS 36.101（LTE EARFCN）和 TS 38.104（NR-ARFCN）的频段表，
+//! 将频点号换算为频段和上下行中心频率（MHz）。
+//!
+//! - LTE 各频段的 EARFCN 范围互不重叠，可以唯一确定频段
+//! - NR 使用全局频率栅格，多个频段可能覆盖同一频率（如 n77/n78、n38/n41），
+//!   优先使用模组上报的频段，否则按表中顺序（常用频段在前）选择
+
+use crate::models::ArfcnInfo;
+
+/// 双工方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Duplex {
+    Fdd,
+    Tdd,
+    /// 补充下行（Supplementary Downlink），无上行
+    Sdl,
+}
+
+impl Duplex {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Duplex::Fdd => "FDD",
+            Duplex::Tdd => "TDD",
+            Duplex::Sdl => "SDL",
+        }
+    }
+}
+
+/// LTE 频段定义（TS 36.101 Table 5.7.3-1）
+struct LteBand {
+    band: u16,
+    duplex: Duplex,
+    /// 下行起始频率 F_DL_low（MHz）
+    dl_low: f64,
+    /// 下行 EARFCN 偏移 N_Offs-DL
+    n_offs_dl: u32,
+    /// 下行 EARFCN 上限
+    n_dl_max: u32,
+    /// 上行频率范围（MHz），TDD/SDL 为 None
+    ul: Option<(f64, f64)>,
+}
+
+/// NR 频段定义（TS 38.104 Table 5.2-1，不含仅上行的 SUL 频段）
+struct NrBand {
+    band: u16,
+    duplex: Duplex,
+    /// 下行频率范围（MHz）
+    dl: (f64, f64),
+    /// 上行频率范围（MHz），TDD/SDL 为 None
+    ul: Option<(f64, f64)>,
+    /// 上下行是否按固定双工间隔配对（可由下行频率推算上行频率）
+    paired: bool,
+}
+
+const fn lte(band: u16, duplex: Duplex, dl_low: f64, n_offs_dl: u32, n_dl_max: u32, ul: Option<(f64, f64)>) -> LteBand {
+    LteBand { band, duplex, dl_low, n_offs_dl, n_dl_max, ul }
+}
+
+const fn nr(band: u16, duplex: Duplex, dl: (f64, f64), ul: Option<(f64, f64)>) -> NrBand {
+    NrBand { band, duplex, dl, ul, paired: true }
+}
+
+/// 上行为固定频段、与下行无固定间隔的 FDD 频段
+const fn nr_unpaired(band: u16, dl: (f64, f64), ul: (f64, f64)) -> NrBand {
+    NrBand { band, duplex: Fdd, dl, ul: Some(ul), paired: false }
+}
+
+use Duplex::{Fdd, Sdl, Tdd};
+
+/// LTE 频段表（按 EARFCN 升序）
+static LTE_BANDS: &[LteBand] = &[
+    lte(1, Fdd, 2110.0, 0, 599, Some((1920.0, 1980.0))),
+    lte(2, Fdd, 1930.0, 600, 1199, Some((1850.0, 1910.0))),
+    lte(3, Fdd, 1805.0, 1200, 1949, Some((1710.0, 1785.0))),
+    lte(4, Fdd, 2110.0, 1950, 2399, Some((1710.0, 1755.0))),
+    lte(5, Fdd, 869.0, 2400, 2649, Some((824.0, 849.0))),
+    lte(6, Fdd, 875.0, 2650, 2749, Some((830.0, 840.0))),
+    lte(7, Fdd, 2620.0, 2750, 3449, Some((2500.0, 2570.0))),
+    lte(8, Fdd, 925.0, 3450, 3799, Some((880.0, 915.0))),
+    lte(9, Fdd, 1844.9, 3800, 4149, Some((1749.9, 1784.9))),
+    lte(10, Fdd, 2110.0, 4150, 4749, Some((1710.0, 1770.0))),
+    lte(11, Fdd, 1475.9, 4750, 4949, Some((1427.9, 1447.9))),
+    lte(12, Fdd, 729.0, 5010, 5179, Some((699.0, 716.0))),
+    lte(13, Fdd, 746.0, 5180, 5279, Some((777.0, 787.0))),
+    lte(14, Fdd, 758.0, 5280, 5379, Some((788.0, 798.0))),
+    lte(17, Fdd, 734.0, 5730, 5849, Some((704.0, 716.0))),
+    lte(18, Fdd, 860.0, 5850, 5999, Some((815.0, 830.0))),
+    lte(19, Fdd, 875.0, 6000, 6149, Some((830.0, 845.0))),
+    lte(20, Fdd, 791.0, 6150, 6449, Some((832.0, 862.0))),
+    lte(21, Fdd, 1495.9, 6450, 6599, Some((1447.9, 1462.9))),
+    lte(22, Fdd, 3510.0, 6600, 7399, Some((3410.0, 3490.0))),
+    lte(23, Fdd, 2180.0, 7500, 7699, Some((2000.0, 2020.0))),
+    lte(24, Fdd, 1525.0, 7700, 8039, Some((1626.5, 1660.5))),
+    lte(25, Fdd, 1930.0, 8040, 8689, Some((1850.0, 1915.0))),
+    lte(26, Fdd, 859.0, 8690, 9039, Some((814.0, 849.0))),
+    lte(27, Fdd, 852.0, 9040, 9209, Some((807.0, 824.0))),
+    lte(28, Fdd, 758.0, 9210, 9659, Some((703.0, 748.0))),
+    lte(29, Sdl, 717.0, 9660, 9769, None),
+    lte(30, Fdd, 2350.0, 9770, 9869, Some((2305.0, 2315.0))),
+    lte(31, Fdd, 462.5, 9870, 9919, Some((452.5, 457.5))),
+    lte(32, Sdl, 1452.0, 9920, 10359, None),
+    lte(33, Tdd, 1900.0, 36000, 36199, None),
+    lte(34, Tdd, 2010.0, 36200, 36349, None),
+    lte(35, Tdd, 1850.0, 36350, 36949, None),
+    lte(36, Tdd, 1930.0, 36950, 37549, None),
+    lte(37, Tdd, 1910.0, 37550, 37749, None),
+    lte(38, Tdd, 2570.0, 37750, 38249, None),
+    lte(39, Tdd, 1880.0, 38250, 38649, None),
+    lte(40, Tdd, 2300.0, 38650, 39649, None),
+    lte(41, Tdd, 2496.0, 39650, 41589, None),
+    lte(42, Tdd, 3400.0, 41590, 43589, None),
+    lte(43, Tdd, 3600.0, 43590, 45589, None),
+    lte(44, Tdd, 703.0, 45590, 46589, None),
+    lte(45, Tdd, 1447.0, 46590, 46789, None),
+    lte(46, Tdd, 5150.0, 46790, 54539, None),
+    lte(47, Tdd, 5855.0, 54540, 55239, None),
+    lte(48, Tdd, 3550.0, 55240, 56739, None),
+    lte(49, Tdd, 3550.0, 56740, 58239, None),
+    lte(50, Tdd, 1432.0, 58240, 59089, None),
+    lte(51, Tdd, 1427.0, 59090, 59139, None),
+    lte(52, Tdd, 3300.0, 59140, 60139, None),
+    lte(53, Tdd, 2483.5, 60140, 60254, None),
+    lte(65, Fdd, 2110.0, 65536, 66435, Some((1920.0, 2010.0))),
+    lte(66, Fdd, 2110.0, 66436, 67335, Some((1710.0, 1780.0))),
+    lte(67, Sdl, 738.0, 67336, 67535, None),
+    lte(68, Fdd, 753.0, 67536, 67835, Some((698.0, 728.0))),
+    lte(69, Sdl, 2570.0, 67836, 68335, None),
+    lte(70, Fdd, 1995.0, 68336, 68585, Some((1695.0, 1710.0))),
+    lte(71, Fdd, 617.0, 68586, 68935, Some((663.0, 698.0))),
+    lte(72, Fdd, 461.0, 68936, 68985, Some((451.0, 456.0))),
+    lte(73, Fdd, 460.0, 68986, 69035, Some((450.0, 455.0))),
+    lte(74, Fdd, 1475.0, 69036, 69465, Some((1427.0, 1470.0))),
+    lte(75, Sdl, 1432.0, 69466, 70315, None),
+    lte(76, Sdl, 1427.0, 70316, 70365, None),
+    lte(85, Fdd, 728.0, 70366, 70545, Some((698.0, 716.0))),
+    lte(87, Fdd, 420.0, 70546, 70595, Some((410.0, 415.0))),
+    lte(88, Fdd, 422.0, 70596, 70645, Some((412.0, 417.0))),
+];
+
+/// NR 频段表
+///
+/// 顺序即重叠时的默认优先级：常用频段排在被其覆盖的频段之前
+/// （n41 优先于 n38/n90，n78 优先于 n77/n48，n1 优先于 n65/n66，n28 优先于 n14/n67）
+static NR_BANDS: &[NrBand] = &[
+    // FR1
+    nr(1, Fdd, (2110.0, 2170.0), Some((1920.0, 1980.0))),
+    nr(2, Fdd, (1930.0, 1990.0), Some((1850.0, 1910.0))),
+    nr(3, Fdd, (1805.0, 1880.0), Some((1710.0, 1785.0))),
+    nr(5, Fdd, (869.0, 894.0), Some((824.0, 849.0))),
+    nr(7, Fdd, (2620.0, 2690.0), Some((2500.0, 2570.0))),
+    nr(8, Fdd, (925.0, 960.0), Some((880.0, 915.0))),
+    nr(12, Fdd, (729.0, 746.0), Some((699.0, 716.0))),
+    nr(13, Fdd, (746.0, 756.0), Some((777.0, 787.0))),
+    nr(28, Fdd, (758.0, 803.0), Some((703.0, 748.0))),
+    nr(14, Fdd, (758.0, 768.0), Some((788.0, 798.0))),
+    nr(18, Fdd, (860.0, 875.0), Some((815.0, 830.0))),
+    nr(20, Fdd, (791.0, 821.0), Some((832.0, 862.0))),
+    nr(24, Fdd, (1525.0, 1559.0), Some((1626.5, 1660.5))),
+    nr(25, Fdd, (1930.0, 1995.0), Some((1850.0, 1915.0))),
+    nr(26, Fdd, (859.0, 894.0), Some((814.0, 849.0))),
+    nr(29, Sdl, (717.0, 728.0), None),
+    nr(30, Fdd, (2350.0, 2360.0), Some((2305.0, 2315.0))),
+    nr(34, Tdd, (2010.0, 2025.0), None),
+    nr(41, Tdd, (2496.0, 2690.0), None),
+    nr(38, Tdd, (2570.0, 2620.0), None),
+    nr(39, Tdd, (1880.0, 1920.0), None),
+    nr(40, Tdd, (2300.0, 2400.0), None),
+    nr(46, Tdd, (5150.0, 5925.0), None),
+    nr(78, Tdd, (3300.0, 3800.0), None),
+    nr(77, Tdd, (3300.0, 4200.0), None),
+    nr(48, Tdd, (3550.0, 3700.0), None),
+    nr(50, Tdd, (1432.0, 1517.0), None),
+    nr(51, Tdd, (1427.0, 1432.0), None),
+    nr(53, Tdd, (2483.5, 2495.0), None),
+    nr(65, Fdd, (2110.0, 2200.0), Some((1920.0, 2010.0))),
+    nr(66, Fdd, (2110.0, 2200.0), Some((1710.0, 1780.0))),
+    nr(67, Sdl, (738.0, 758.0), None),
+    nr(70, Fdd, (1995.0, 2020.0), Some((1695.0, 1710.0))),
+    nr(71, Fdd, (617.0, 652.0), Some((663.0, 698.0))),
+    nr(74, Fdd, (1475.0, 1518.0), Some((1427.0, 1470.0))),
+    nr(75, Sdl, (1432.0, 1517.0), None),
+    nr(76, Sdl, (1427.0, 1432.0), None),
+    nr(79, Tdd, (4400.0, 5000.0), None),
+    nr(85, Fdd, (728.0, 746.0), Some((698.0, 716.0))),
+    nr(90, Tdd, (2496.0, 2690.0), None),
+    // n91-n94 的 L 频段下行与 B20/B8 上行组合，上下行带宽不同、无固定间隔，
+    // 上行信道由网络单独配置，无法由下行频率推算
+    nr_unpaired(91, (1427.0, 1432.0), (832.0, 862.0)),
+    nr_unpaired(92, (1432.0, 1517.0), (832.0, 862.0)),
+    nr_unpaired(93, (1427.0, 1432.0), (880.0, 915.0)),
+    nr_unpaired(94, (1432.0, 1517.0), (880.0, 915.0)),
+    nr(96, Tdd, (5925.0, 7125.0), None),
+    nr(100, Fdd, (919.4, 925.0), Some((874.4, 880.0))),
+    nr(101, Tdd, (1900.0, 1910.0), None),
+    nr(102, Tdd, (5925.0, 6425.0), None),
+    nr(104, Tdd, (6425.0, 7125.0), None),
+    nr(105, Fdd, (612.0, 652.0), Some((663.0, 703.0))),
+    // FR2
+    nr(257, Tdd, (26500.0, 29500.0), None),
+    nr(258, Tdd, (24250.0, 27500.0), None),
+    nr(259, Tdd, (39500.0, 43500.0), None),
+    nr(260, Tdd, (37000.0, 40000.0), None),
+    nr(261, Tdd, (27500.0, 28350.0), None),
+    nr(262, Tdd, (47200.0, 48200.0), None),
+];
+
+/// 保留 3 位小数（kHz 精度），避免浮点误差
+fn round_mhz(freq: f64) -> f64 {
+    (freq * 1000.0).round() / 1000.0
+}
+
+/// 从频段字符串中解析频段号
+///
+/// 支持 "78"、"n78"、"B3"、"78,41"（载波聚合时取第一个）等格式
+pub fn parse_band_number(band: &str) -> Option<u16> {
+    band.split(',')
+        .next()?
+        .trim()
+        .trim_start_matches(['n', 'N', 'b', 'B'])
+        .parse::<u16>()
+        .ok()
+        .filter(|b| *b != 0)
+}
+
+/// 给模组上报的频段号加前缀（NR 为 n，LTE 为 B）
+///
+/// 空值和 "0" 保持原样，载波聚合时的列表（如 "3,1"）只加一次前缀，与主小区页显示一致
+pub fn format_band(tech: &str, raw: &str) -> String {
+    if raw.is_empty() || raw == "0" {
+        return raw.to_string();
+    }
+    match tech {
+        "nr" => format!("n{}", raw),
+        _ => format!("B{}", raw),
+    }
+}
+
+/// NR-ARFCN 转换为频率（TS 38.104 5.4.2.1 全局频率栅格）
+///
+/// F_REF = F_REF-Offs + ΔF_Global × (N_REF − N_REF-Offs)
+pub fn nr_arfcn_to_freq(arfcn: u32) -> Option<f64> {
+    let (offset_mhz, step_khz, n_offs) = match arfcn {
+        0..=599_999 => (0.0, 5.0, 0),
+        600_000..=2_016_666 => (3000.0, 15.0, 600_000),
+        2_016_667..=3_279_165 => (24250.08, 60.0, 2_016_667),
+        _ => return None,
+    };
+    Some(round_mhz(offset_mhz + step_khz / 1000.0 * (arfcn - n_offs) as f64))
+}
+
+/// 根据 LTE 下行 EARFCN 计算频段和频率
+pub fn lookup_lte(earfcn: u32) -> Option<ArfcnInfo> {
+    let band = LTE_BANDS
+        .iter()
+        .find(|b| (b.n_offs_dl..=b.n_dl_max).contains(&earfcn))?;
+
+    let dl = round_mhz(band.dl_low + 0.1 * (earfcn - band.n_offs_dl) as f64);
+    let ul = match band.duplex {
+        Duplex::Tdd => Some(dl),
+        Duplex::Sdl => None,
+        // 上行与下行使用相同的频段内偏移；超出上行范围时（如 B66 高端仅下行）为 None
+        Duplex::Fdd => band
+            .ul
+            .map(|(low, high)| (round_mhz(low + (dl - band.dl_low)), high))
+            .filter(|(ul, high)| ul <= high)
+            .map(|(ul, _)| ul),
+    };
+
+    Some(ArfcnInfo {
+        tech: "lte".to_string(),
+        arfcn: earfcn,
+        band: format!("B{}", band.band),
+        band_number: band.band,
+        duplex: band.duplex.as_str().to_string(),
+        dl_freq_mhz: dl,
+        ul_freq_mhz: ul,
+        candidates: vec![format!("B{}", band.band)],
+    })
+}
+
+/// 根据 NR 下行 ARFCN 计算频段和频率
+///
+/// # Arguments
+/// * `arfcn` - NR-ARFCN
+/// * `band_hint` - 模组上报的频段号，用于区分重叠频段
+pub fn lookup_nr(arfcn: u32, band_hint: Option<u16>) -> Option<ArfcnInfo> {
+    let dl = nr_arfcn_to_freq(arfcn)?;
+    let candidates: Vec<&NrBand> = NR_BANDS
+        .iter()
+        .filter(|b| dl >= b.dl.0 && dl <= b.dl.1)
+        .collect();
+
+    let band = band_hint
+        .and_then(|hint| candidates.iter().find(|b| b.band == hint))
+        .or_else(|| candidates.first())?;
+
+    let ul = match band.duplex {
+        Duplex::Tdd => Some(dl),
+        Duplex::Sdl => None,
+        Duplex::Fdd if !band.paired => None,
+        Duplex::Fdd => band
+            .ul
+            .map(|(low, high)| (round_mhz(low + (dl - band.dl.0)), high))
+            .filter(|(ul, high)| ul <= high)
+            .map(|(ul, _)| ul),
+    };
+
+    Some(ArfcnInfo {
+        tech: "nr".to_string(),
+        arfcn,
+        band: format!("n{}", band.band),
+        band_number: band.band,
+        duplex: band.duplex.as_str().to_string(),
+        dl_freq_mhz: dl,
+        ul_freq_mhz: ul,
+        candidates: candidates.iter().map(|b| format!("n{}", b.band)).collect(),
+    })
+}
+
+/// 按网络制式计算频段和频率
+///
+/// # Arguments
+/// * `tech` - 网络制式 ("nr" 或 "lte")
+/// * `arfcn` - 频点号
+/// * `band_hint` - 模组上报的频段号（可选）
+pub fn lookup(tech: &str, arfcn: u32, band_hint: Option<u16>) -> Option<ArfcnInfo> {
+    match tech {
+        "nr" => lookup_nr(arfcn, band_hint),
+        "lte" => lookup_lte(arfcn),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nr_lookup() {
+        // n78 与 n77 重叠，默认选 n78，模组上报 77 时使用 n77
+        let info = lookup_nr(633984, None).unwrap();
+        assert_eq!(info.band, "n78");
+        assert_eq!(info.dl_freq_mhz, 3509.76);
+        assert_eq!(info.ul_freq_mhz, Some(3509.76));
+        assert_eq!(info.candidates, vec!["n78", "n77"]);
+        assert_eq!(lookup_nr(633984, Some(77)).unwrap().band, "n77");
+
+        // n41 覆盖 n38
+        let info = lookup_nr(504990, Some(41)).unwrap();
+        assert_eq!(info.band, "n41");
+        assert_eq!(info.dl_freq_mhz, 2524.95);
+
+        // n28 FDD
+        let info = lookup_nr(154810, None).unwrap();
+        assert_eq!(info.band, "n28");
+        assert_eq!(info.dl_freq_mhz, 774.05);
+        assert_eq!(info.ul_freq_mhz, Some(719.05));
+
+        // n91 上行为固定的 832-862 MHz，不由下行推算
+        let info = lookup_nr(286000, Some(91)).unwrap();
+        assert_eq!(info.band, "n91");
+        assert_eq!(info.ul_freq_mhz, None);
+
+        assert!(lookup_nr(3_300_000, None).is_none());
+    }
+
+    #[test]
+    fn test_lte_lookup() {
+        let info = lookup_lte(1300).unwrap();
+        assert_eq!(info.band, "B3");
+        assert_eq!(info.dl_freq_mhz, 1815.0);
+        assert_eq!(info.ul_freq_mhz, Some(1720.0));
+
+        let info = lookup_lte(38950).unwrap();
+        assert_eq!(info.band, "B40");
+        assert_eq!(info.duplex, "TDD");
+        assert_eq!(info.dl_freq_mhz, 2330.0);
+
+        // B66 下行高端无对应上行
+        assert_eq!(lookup_lte(67200).unwrap().ul_freq_mhz, None);
+        assert!(lookup_lte(10500).is_none());
+    }
+
+    #[test]
+    fn test_parse_band_number() {
+        assert_eq!(parse_band_number("n78"), Some(78));
+        assert_eq!(parse_band_number("B3"), Some(3));
+        assert_eq!(parse_band_number("78,41"), Some(78));
+        assert_eq!(parse_band_number("0"), None);
+        assert_eq!(parse_band_number(""), None);
+    }
+}
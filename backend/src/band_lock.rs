@@ -0,0 +1,626 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 18:05:12
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 18:05:12
+ * @FilePath: /udx710-backend/backend/src/band_lock.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 频段/小区锁定模块
+//!
+//! 封装 AT+SPLBAND（频段锁定）和 AT+SPFORCEFRQ（小区锁定）的执行流程，
+//! 供 HTTP 接口和锁定配置方案（profile）复用。
+
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::config::LockProfile;
+use crate::dbus::{get_network_info_data, send_at_command, set_radio_mode};
+use crate::models::{BandCapabilities, BandLockRequest, CellInfo, CellLockRatStatus, CellLockStatusResponse};
+use crate::utils::{
+    bands_to_bitmask, bitmask_to_bands, build_splband_lte_command, build_splband_nr_command,
+    get_cell_command_config, parse_at_response_to_2d_vec, parse_primary_cell, parse_splband_lte_response,
+    parse_splband_nr_response,
+};
+
+// ==================== 频段能力 ====================
+
+/// UDX710 默认频段能力（来自 band.md 的实测结果），仅在模组查询失败时使用
+/// LTE: FDD=149 (B1+B3+B5+B8), TDD=320 (B39+B41)
+/// NR: FDD=517 (N1+N3+N28), TDD=912 (N41+N77+N78+N79)
+const DEFAULT_LTE_FDD_MASK: u16 = 149;
+const DEFAULT_LTE_TDD_MASK: u16 = 320;
+const DEFAULT_NR_FDD_MASK: u16 = 517;
+const DEFAULT_NR_TDD_MASK: u16 = 912;
+
+/// 频段能力缓存（只缓存模组查询成功的结果）
+static BAND_CAPABILITIES: RwLock<Option<BandCapabilities>> = RwLock::new(None);
+
+/// 根据位掩码构造频段能力
+fn capabilities_from_masks(
+    lte: (u16, u16),
+    nr: (u16, u16),
+    source: &str,
+    discovered_at: Option<String>,
+) -> BandCapabilities {
+    BandCapabilities {
+        lte_fdd_bands: bitmask_to_bands(lte.0, 1),
+        lte_tdd_bands: bitmask_to_bands(lte.1, 33),
+        nr_fdd_bands: bitmask_to_bands(nr.0, 100),
+        nr_tdd_bands: bitmask_to_bands(nr.1, 41),
+        lte_fdd_mask: lte.0,
+        lte_tdd_mask: lte.1,
+        nr_fdd_mask: nr.0,
+        nr_tdd_mask: nr.1,
+        source: source.to_string(),
+        discovered_at,
+    }
+}
+
+/// 查询模组的频段能力，成功时写入缓存
+///
+/// - AT+SPLBAND=5 返回 LTE 支持能力：`+SPLBAND: 0,<TDD>,0,<FDD>,0`
+/// - AT+SPLBAND=4 返回 NR 支持能力：`+SPLBAND: <FDD>,0,<TDD>,0`
+///
+/// 任一查询失败或返回空掩码时不写入缓存（下次调用重新查询）：已有缓存时返回缓存，
+/// 否则对应制式回退到 UDX710 默认能力
+pub async fn discover_band_capabilities(conn: &Connection) -> BandCapabilities {
+    let lte = match send_at_command(conn, "AT+SPLBAND=5").await {
+        Ok(response) => Some(parse_splband_lte_response(&response)).filter(|m| *m != (0, 0)),
+        Err(e) => {
+            warn!(error = %e, "Failed to query LTE band capabilities");
+            None
+        }
+    };
+    let nr = match send_at_command(conn, "AT+SPLBAND=4").await {
+        Ok(response) => Some(parse_splband_nr_response(&response)).filter(|m| *m != (0, 0)),
+        Err(e) => {
+            warn!(error = %e, "Failed to query NR band capabilities");
+            None
+        }
+    };
+
+    if lte.is_none() || nr.is_none() {
+        if let Some(cached) = BAND_CAPABILITIES.read().unwrap().clone() {
+            return cached;
+        }
+    }
+
+    let source = if lte.is_some() && nr.is_some() { "modem" } else { "default" };
+    let caps = capabilities_from_masks(
+        lte.unwrap_or((DEFAULT_LTE_FDD_MASK, DEFAULT_LTE_TDD_MASK)),
+        nr.unwrap_or((DEFAULT_NR_FDD_MASK, DEFAULT_NR_TDD_MASK)),
+        source,
+        Some(chrono::Utc::now().to_rfc3339()),
+    );
+
+    info!(
+        source = %caps.source,
+        lte_fdd = caps.lte_fdd_mask,
+        lte_tdd = caps.lte_tdd_mask,
+        nr_fdd = caps.nr_fdd_mask,
+        nr_tdd = caps.nr_tdd_mask,
+        "Band capabilities discovered"
+    );
+
+    if caps.source == "modem" {
+        *BAND_CAPABILITIES.write().unwrap() = Some(caps.clone());
+    }
+    caps
+}
+
+/// 获取频段能力（优先使用缓存，未缓存或上次查询失败时查询模组）
+pub async fn band_capabilities(conn: &Connection) -> BandCapabilities {
+    let cached = BAND_CAPABILITIES.read().unwrap().clone();
+    match cached {
+        Some(caps) => caps,
+        None => discover_band_capabilities(conn).await,
+    }
+}
+
+/// 检查请求的频段是否都在模组支持范围内
+///
+/// # Returns
+/// 存在不支持的频段时返回错误信息（列出所有不支持的频段）
+pub fn validate_bands(caps: &BandCapabilities, bands: &BandLockRequest) -> Result<(), String> {
+    let groups: [(&[u8], &[u8], &str); 4] = [
+        (&bands.lte_fdd_bands, &caps.lte_fdd_bands, "B"),
+        (&bands.lte_tdd_bands, &caps.lte_tdd_bands, "B"),
+        (&bands.nr_fdd_bands, &caps.nr_fdd_bands, "N"),
+        (&bands.nr_tdd_bands, &caps.nr_tdd_bands, "N"),
+    ];
+
+    let unsupported: Vec<String> = groups
+        .iter()
+        .flat_map(|(requested, supported, prefix)| {
+            requested
+                .iter()
+                .filter(|b| !supported.contains(b))
+                .map(move |b| format!("{}{}", prefix, b))
+        })
+        .collect();
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("模组不支持以下频段: {}", unsupported.join(", ")))
+    }
+}
+
+// ==================== 频段锁定 ====================
+
+/// 应用频段锁定
+///
+/// - 传入空数组表示不锁定对应类型的频段
+/// - 某一制式的数组全部为空时，解除该制式当前已生效的锁定（只锁 LTE 时 NR 恢复全频段，反之亦然）
+/// - 所有数组都为空时，解除当前已生效的 LTE/NR 频段锁定
+/// - 包含模组不支持的频段时直接拒绝
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `bands` - 要锁定的频段
+///
+/// # Returns
+/// 成功时返回提示信息
+pub async fn apply_band_lock(conn: &Connection, bands: &BandLockRequest) -> Result<String, String> {
+    // 如果所有频段都为空，则解除锁定
+    if bands.lte_fdd_bands.is_empty()
+        && bands.lte_tdd_bands.is_empty()
+        && bands.nr_fdd_bands.is_empty()
+        && bands.nr_tdd_bands.is_empty()
+    {
+        return unlock_all_bands(conn).await;
+    }
+
+    validate_bands(&band_capabilities(conn).await, bands)?;
+
+    // LTE 频段锁定
+    let lte_fdd_mask = bands_to_bitmask(&bands.lte_fdd_bands, 1);
+    let lte_tdd_mask = bands_to_bitmask(&bands.lte_tdd_bands, 33);
+
+    let has_lte = lte_fdd_mask != 0 || lte_tdd_mask != 0;
+    let lte_unlocked = if has_lte {
+        let lte_cmd = build_splband_lte_command(lte_fdd_mask, lte_tdd_mask);
+        send_at_command(conn, &lte_cmd)
+            .await
+            .map_err(|e| format!("Failed to set LTE band lock: {}", e))?;
+        false
+    } else {
+        unlock_lte_bands(conn).await?
+    };
+
+    // NR 频段锁定
+    let nr_fdd_mask = bands_to_bitmask(&bands.nr_fdd_bands, 100); // NR FDD: 展锐特殊映射
+    let nr_tdd_mask = bands_to_bitmask(&bands.nr_tdd_bands, 41);  // NR TDD: 展锐特殊映射
+
+    let has_nr = nr_fdd_mask != 0 || nr_tdd_mask != 0;
+    let nr_unlocked = if has_nr {
+        let nr_cmd = build_splband_nr_command(nr_fdd_mask, nr_tdd_mask);
+        send_at_command(conn, &nr_cmd)
+            .await
+            .map_err(|e| format!("Failed to set NR band lock: {}", e))?;
+        false
+    } else {
+        unlock_nr_bands(conn).await?
+    };
+
+    // 生成友好的提示信息
+    let message = match (has_lte, has_nr) {
+        (true, true) => "已同时锁定 LTE 和 NR 频段",
+        (true, false) if nr_unlocked => "LTE 频段锁定已应用，已解除 NR 频段锁定",
+        (true, false) => "LTE 频段锁定已应用",
+        (false, _) if lte_unlocked => "NR 频段锁定已应用，已解除 LTE 频段锁定",
+        (false, _) => "NR 频段锁定已应用",
+    };
+
+    Ok(message.to_string())
+}
+
+/// 解除 LTE 频段锁定（当前未锁定时不执行）
+///
+/// # Returns
+/// 是否执行了解锁
+async fn unlock_lte_bands(conn: &Connection) -> Result<bool, String> {
+    let lte_response = send_at_command(conn, "AT+SPLBAND=0")
+        .await
+        .map_err(|e| format!("Failed to read LTE band lock: {}", e))?;
+    let (lte_fdd_mask, lte_tdd_mask) = parse_splband_lte_response(&lte_response);
+    if lte_fdd_mask == 0 && lte_tdd_mask == 0 {
+        return Ok(false);
+    }
+    // 格式: AT+SPLBAND=1,0,<TDD>,0,<FDD>,0 (6 参数)
+    send_at_command(conn, "AT+SPLBAND=1,0,0,0,0,0")
+        .await
+        .map_err(|e| format!("Failed to unlock LTE bands: {}", e))?;
+    Ok(true)
+}
+
+/// 解除 NR 频段锁定（当前未锁定时不执行）
+///
+/// # Returns
+/// 是否执行了解锁
+async fn unlock_nr_bands(conn: &Connection) -> Result<bool, String> {
+    let nr_response = send_at_command(conn, "AT+SPLBAND=3")
+        .await
+        .map_err(|e| format!("Failed to read NR band lock: {}", e))?;
+    let (nr_fdd_mask, nr_tdd_mask) = parse_splband_nr_response(&nr_response);
+    if nr_fdd_mask == 0 && nr_tdd_mask == 0 {
+        return Ok(false);
+    }
+    send_at_command(conn, "AT+SPLBAND=2,0,0,0,0")
+        .await
+        .map_err(|e| format!("Failed to unlock NR bands: {}", e))?;
+    Ok(true)
+}
+
+/// 解除所有频段锁定（只对当前已锁定的制式执行解锁）
+async fn unlock_all_bands(conn: &Connection) -> Result<String, String> {
+    let lte_unlocked = unlock_lte_bands(conn).await?;
+    let nr_unlocked = unlock_nr_bands(conn).await?;
+
+    // 根据实际执行的解锁操作返回友好的提示信息
+    let message = match (lte_unlocked, nr_unlocked) {
+        (true, true) => "已解除所有频段锁定（LTE + NR）",
+        (true, false) => "已解除 LTE 频段锁定（NR 未锁定）",
+        (false, true) => "已解除 NR 频段锁定（LTE 未锁定）",
+        (false, false) => "当前没有锁定的频段，无需解锁",
+    };
+
+    Ok(message.to_string())
+}
+
+// ==================== 小区锁定 ====================
+// 使用 AT+SPFORCEFRQ 指令实现小区锁定
+// 发现来源：通过 dbus-monitor 监听实际锁频操作
+
+/// SPFORCEFRQ 网络类型常量
+pub const FORCEFRQ_TYPE_LTE: u8 = 12;
+pub const FORCEFRQ_TYPE_NR: u8 = 16;
+
+/// 获取 RAT 类型名称
+pub fn get_rat_name(rat: u8) -> String {
+    match rat {
+        12 => "LTE".to_string(),
+        16 => "NR".to_string(),
+        _ => format!("Unknown({})", rat),
+    }
+}
+
+/// 将请求中的 RAT 值转换为 SPFORCEFRQ 类型
+///
+/// 支持 12/16，以及旧值 1/2=LTE, 5/6/7=NR，其余默认 NR
+pub fn resolve_forcefrq_type(rat: u8) -> u8 {
+    match rat {
+        FORCEFRQ_TYPE_LTE | FORCEFRQ_TYPE_NR => rat,
+        1 | 2 => FORCEFRQ_TYPE_LTE,  // LTE FDD/TDD
+        5..=7 => FORCEFRQ_TYPE_NR,   // NR SA/NSA
+        _ => FORCEFRQ_TYPE_NR,
+    }
+}
+
+/// 解析 AT+SPFORCEFRQ 查询响应
+/// 
+/// 响应格式:
+/// - 未锁定: +SPFORCEFRQ: 16,3
+/// - 已锁定: +SPFORCEFRQ: 16,3,633984,597
+pub fn parse_spforcefrq_query_response(response: &str, rat: u8) -> CellLockRatStatus {
+    let prefix = format!("+SPFORCEFRQ: {},3", rat);
+    
+    if let Some(line) = response.lines().find(|l| l.starts_with(&prefix)) {
+        let data = line.strip_prefix(&format!("+SPFORCEFRQ: {},3", rat)).unwrap_or("");
+        let data = data.trim_start_matches(',');
+        
+        if data.is_empty() {
+            // 未锁定
+            CellLockRatStatus {
+                rat,
+                rat_name: get_rat_name(rat),
+                enabled: false,
+                lock_type: 0,
+                pci: None,
+                arfcn: None,
+            }
+        } else {
+            // 已锁定，解析 arfcn,pci
+            let parts: Vec<&str> = data.split(',').collect();
+            let arfcn = parts.first().and_then(|s| s.trim().parse::<u32>().ok());
+            let pci = parts.get(1).and_then(|s| s.trim().parse::<u16>().ok());
+            
+            CellLockRatStatus {
+                rat,
+                rat_name: get_rat_name(rat),
+                enabled: arfcn.is_some() && pci.is_some(),
+                lock_type: 3,
+                pci,
+                arfcn,
+            }
+        }
+    } else {
+        // 解析失败，返回未锁定状态
+        CellLockRatStatus {
+            rat,
+            rat_name: get_rat_name(rat),
+            enabled: false,
+            lock_type: 0,
+            pci: None,
+            arfcn: None,
+        }
+    }
+}
+
+/// 查询 NR 和 LTE 的小区锁定状态
+pub async fn query_cell_lock_status(conn: &Connection) -> CellLockStatusResponse {
+    let mut rat_status = Vec::new();
+
+    for rat in [FORCEFRQ_TYPE_NR, FORCEFRQ_TYPE_LTE] {
+        let cmd = format!("AT+SPFORCEFRQ={},3", rat);
+        let status = match send_at_command(conn, &cmd).await {
+            Ok(response) => parse_spforcefrq_query_response(&response, rat),
+            Err(_) => CellLockRatStatus {
+                rat,
+                rat_name: get_rat_name(rat),
+                enabled: false,
+                lock_type: 0,
+                pci: None,
+                arfcn: None,
+            },
+        };
+        rat_status.push(status);
+    }
+
+    let any_locked = rat_status.iter().any(|s| s.enabled);
+    CellLockStatusResponse {
+        rat_status,
+        any_locked,
+    }
+}
+
+/// 在工程模式下依次执行指令，失败时尝试恢复正常模式
+///
+/// 流程：AT+SFUN=5 → 指令列表 → AT+SFUN=4
+async fn run_in_engineering_mode(conn: &Connection, steps: &[(String, &str)]) -> Result<(), String> {
+    send_at_command(conn, "AT+SFUN=5")
+        .await
+        .map_err(|e| format!("进入工程模式失败: {}", e))?;
+
+    for (cmd, desc) in steps {
+        if let Err(e) = send_at_command(conn, cmd).await {
+            // 恢复正常模式
+            let _ = send_at_command(conn, "AT+SFUN=4").await;
+            return Err(format!("{}失败: {}", desc, e));
+        }
+    }
+
+    send_at_command(conn, "AT+SFUN=4")
+        .await
+        .map_err(|e| format!("恢复正常模式失败: {}", e))?;
+
+    Ok(())
+}
+
+/// 锁定到指定小区（会先清空 NR 和 LTE 的现有锁定）
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `forcefrq_type` - 12=LTE, 16=NR
+/// * `arfcn` - 频点号
+/// * `pci` - 物理小区标识
+pub async fn lock_cell(conn: &Connection, forcefrq_type: u8, arfcn: u32, pci: u16) -> Result<(), String> {
+    run_in_engineering_mode(
+        conn,
+        &[
+            (format!("AT+SPFORCEFRQ={},0", FORCEFRQ_TYPE_NR), "清空 NR 锁定"),
+            (format!("AT+SPFORCEFRQ={},0", FORCEFRQ_TYPE_LTE), "清空 LTE 锁定"),
+            (format!("AT+SPFORCEFRQ={},2,{},{}", forcefrq_type, arfcn, pci), "设置锁定"),
+        ],
+    )
+    .await
+}
+
+/// 解除指定制式的小区锁定
+pub async fn unlock_cell(conn: &Connection, forcefrq_type: u8) -> Result<(), String> {
+    run_in_engineering_mode(
+        conn,
+        &[(format!("AT+SPFORCEFRQ={},0", forcefrq_type), "清空锁定")],
+    )
+    .await
+}
+
+/// 解除 NR 和 LTE 的所有小区锁定
+pub async fn unlock_all_cells(conn: &Connection) -> Result<(), String> {
+    run_in_engineering_mode(
+        conn,
+        &[
+            (format!("AT+SPFORCEFRQ={},0", FORCEFRQ_TYPE_NR), "清空 NR 锁定"),
+            (format!("AT+SPFORCEFRQ={},0", FORCEFRQ_TYPE_LTE), "清空 LTE 锁定"),
+        ],
+    )
+    .await
+}
+
+// ==================== 锁定配置方案 ====================
+
+/// 应用锁定配置方案
+///
+/// 依次执行：射频模式（如果指定）→ 频段锁定 → 小区锁定（未指定则解除小区锁定）
+///
+/// 频段锁定按 [`apply_band_lock`] 处理：只指定一种制式时解除另一制式的锁定，
+/// 应用后模组的锁定状态与方案一致
+///
+/// # Returns
+/// 已完成的步骤说明；任一步骤失败时返回错误（包含已完成的步骤）
+pub async fn apply_lock_profile(conn: &Connection, profile: &LockProfile) -> Result<Vec<String>, String> {
+    let mut steps = Vec::new();
+
+    if let Some(mode) = &profile.radio_mode {
+        set_radio_mode(conn, mode.clone())
+            .await
+            .map_err(|e| format!("Failed to set radio mode: {}", e))?;
+        steps.push(format!("radio mode: {}", mode.to_ofono_value()));
+    }
+
+    let bands = BandLockRequest {
+        lte_fdd_bands: profile.lte_fdd_bands.clone(),
+        lte_tdd_bands: profile.lte_tdd_bands.clone(),
+        nr_fdd_bands: profile.nr_fdd_bands.clone(),
+        nr_tdd_bands: profile.nr_tdd_bands.clone(),
+        ..Default::default()
+    };
+    let band_message = apply_band_lock(conn, &bands)
+        .await
+        .map_err(|e| format!("{} (completed: [{}])", e, steps.join(", ")))?;
+    steps.push(band_message);
+
+    match &profile.cell_lock {
+        Some(cell) => {
+            let forcefrq_type = resolve_forcefrq_type(cell.rat);
+            lock_cell(conn, forcefrq_type, cell.arfcn, cell.pci)
+                .await
+                .map_err(|e| format!("{} (completed: [{}])", e, steps.join(", ")))?;
+            steps.push(format!(
+                "{} 小区锁定已设置 (ARFCN={}, PCI={})",
+                get_rat_name(forcefrq_type),
+                cell.arfcn,
+                cell.pci
+            ));
+        }
+        None => {
+            let status = query_cell_lock_status(conn).await;
+            if status.any_locked {
+                unlock_all_cells(conn)
+                    .await
+                    .map_err(|e| format!("{} (completed: [{}])", e, steps.join(", ")))?;
+                steps.push("已解除所有小区锁定".to_string());
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+// ==================== 锁定状态检查与恢复 ====================
+
+/// 锁定前的频段位掩码（用于恢复）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SavedBandMasks {
+    pub lte_fdd: u16,
+    pub lte_tdd: u16,
+    pub nr_fdd: u16,
+    pub nr_tdd: u16,
+}
+
+/// 读取当前 LTE/NR 频段锁定位掩码
+pub async fn read_band_masks(conn: &Connection) -> Result<SavedBandMasks, String> {
+    let lte = send_at_command(conn, "AT+SPLBAND=0")
+        .await
+        .map_err(|e| format!("Failed to read LTE band lock: {}", e))?;
+    let nr = send_at_command(conn, "AT+SPLBAND=3")
+        .await
+        .map_err(|e| format!("Failed to read NR band lock: {}", e))?;
+    let (lte_fdd, lte_tdd) = parse_splband_lte_response(&lte);
+    let (nr_fdd, nr_tdd) = parse_splband_nr_response(&nr);
+    Ok(SavedBandMasks { lte_fdd, lte_tdd, nr_fdd, nr_tdd })
+}
+
+/// 恢复 LTE/NR 频段锁定位掩码（全 0 表示解除锁定）
+pub async fn restore_band_masks(conn: &Connection, saved: &SavedBandMasks) -> Result<(), String> {
+    let lte_cmd = if saved.lte_fdd == 0 && saved.lte_tdd == 0 {
+        "AT+SPLBAND=1,0,0,0,0,0".to_string()
+    } else {
+        build_splband_lte_command(saved.lte_fdd, saved.lte_tdd)
+    };
+    let nr_cmd = if saved.nr_fdd == 0 && saved.nr_tdd == 0 {
+        "AT+SPLBAND=2,0,0,0,0".to_string()
+    } else {
+        build_splband_nr_command(saved.nr_fdd, saved.nr_tdd)
+    };
+
+    send_at_command(conn, &lte_cmd)
+        .await
+        .map_err(|e| format!("Failed to restore LTE band lock: {}", e))?;
+    send_at_command(conn, &nr_cmd)
+        .await
+        .map_err(|e| format!("Failed to restore NR band lock: {}", e))?;
+    Ok(())
+}
+
+/// 恢复之前查询到的小区锁定状态（之前未锁定则解除所有小区锁定）
+pub async fn restore_cell_lock(conn: &Connection, previous: &CellLockStatusResponse) -> Result<(), String> {
+    let locked = previous
+        .rat_status
+        .iter()
+        .find_map(|s| match (s.enabled, s.arfcn, s.pci) {
+            (true, Some(arfcn), Some(pci)) => Some((s.rat, arfcn, pci)),
+            _ => None,
+        });
+
+    match locked {
+        Some((rat, arfcn, pci)) => lock_cell(conn, rat, arfcn, pci).await,
+        None => unlock_all_cells(conn).await,
+    }
+}
+
+/// 读取主小区信息
+pub async fn fetch_primary_cell(conn: &Connection, tech: &str) -> Result<CellInfo, String> {
+    let cmd_config = get_cell_command_config(tech)
+        .ok_or_else(|| format!("Unsupported network type: {}", tech))?;
+    let response = send_at_command(conn, cmd_config.primary)
+        .await
+        .map_err(|e| format!("Primary cell AT command failed: {}", e))?;
+    Ok(parse_primary_cell(tech, &parse_at_response_to_2d_vec(&response)))
+}
+
+/// 网络是否已注册（含漫游）
+pub async fn is_registered(conn: &Connection) -> bool {
+    matches!(
+        get_network_info_data(conn).await.map(|info| info.registration_status),
+        Ok(status) if status == "registered" || status == "roaming"
+    )
+}
+
+/// 取逗号分隔值中的第一个（载波聚合时为主载波）并解析
+pub fn parse_first<T: std::str::FromStr>(value: &str) -> Option<T> {
+    value.split(',').next().and_then(|s| s.trim().parse::<T>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_bands_against_capabilities() {
+        let caps = capabilities_from_masks(
+            (DEFAULT_LTE_FDD_MASK, DEFAULT_LTE_TDD_MASK),
+            (DEFAULT_NR_FDD_MASK, DEFAULT_NR_TDD_MASK),
+            "default",
+            None,
+        );
+        assert_eq!(caps.lte_fdd_bands, vec![1, 3, 5, 8]);
+        assert_eq!(caps.lte_tdd_bands, vec![39, 41]);
+        assert_eq!(caps.nr_fdd_bands, vec![1, 3, 28]);
+        assert_eq!(caps.nr_tdd_bands, vec![41, 77, 78, 79]);
+
+        let ok = BandLockRequest {
+            lte_fdd_bands: vec![3, 8],
+            lte_tdd_bands: vec![],
+            nr_fdd_bands: vec![],
+            nr_tdd_bands: vec![78],
+            ..Default::default()
+        };
+        assert!(validate_bands(&caps, &ok).is_ok());
+
+        let bad = BandLockRequest {
+            lte_fdd_bands: vec![3, 7],
+            lte_tdd_bands: vec![38],
+            nr_fdd_bands: vec![],
+            nr_tdd_bands: vec![78],
+            ..Default::default()
+        };
+        assert_eq!(validate_bands(&caps, &bad).unwrap_err(), "模组不支持以下频段: B7, B38");
+    }
+}
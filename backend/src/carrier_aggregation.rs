@@ -0,0 +1,211 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 22:10:37
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 22:10:37
+ * @FilePath: /udx710-backend/backend/src/carrier_aggregation.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 载波聚合 / EN-DC 解析模块
+//!
+//! 从工程模式主小区页解析 PCell/SCell 的频段、频点、PCI 和信号，
+//! 以及 NSA（LTE 锚点 + NR 辅连接）时的锚点关系。
+//!
+//! 主小区页按列组织（见 [`crate::utils::parse_primary_cell`]），载波聚合时
+//! 频段/频点/PCI 等列为逗号分隔列表，第 i 个值对应第 i 个分量载波（0 为主载波）。
+//! 主小区页不含载波带宽，且带宽无法从频段推出（同一频段可配置多种信道带宽），
+//! 因此不提供单载波带宽和聚合带宽。
+
+use tracing::debug;
+use zbus::Connection;
+
+use crate::arfcn;
+use crate::dbus::send_at_command;
+use crate::models::{CarrierAggregation, ComponentCarrier, EnDcLink};
+use crate::utils::{get_cell_command_config, parse_at_response_to_2d_vec};
+
+/// 解析主小区页中的分量载波
+///
+/// # AT+SPENGMD 主小区页格式说明
+///
+/// **NR (AT+SPENGMD=0,14,1)** / **LTE (AT+SPENGMD=0,6,0)**，每列为一个字段，
+/// 载波聚合时列内按载波以逗号分隔：
+/// - `[0][i]`: Band (频段)
+/// - `[1][i]`: ARFCN (绝对频点号)
+/// - `[2][i]`: PCI (物理小区标识)
+/// - `[3][i]`: RSRP (原始值 ×100)
+/// - `[4][i]`: RSRQ (原始值 ×100)
+/// - `[15][i]`（NR）/ `[33][i]`（LTE）: SINR (原始值 ×100)
+///
+/// 信号列只有一个值时仅属于主载波，辅载波信号为空
+///
+/// # Returns
+/// 按列顺序排列的分量载波列表（角色由 build_carrier_aggregation 设置）
+pub fn parse_carriers(tech: &str, parsed_data: &[Vec<String>]) -> Vec<ComponentCarrier> {
+    let sinr_column = match tech {
+        "nr" => 15,
+        "lte" => 33,
+        _ => return Vec::new(),
+    };
+    if parsed_data.len() <= sinr_column {
+        return Vec::new();
+    }
+    let value = |column: usize, i: usize| {
+        parsed_data[column]
+            .get(i)
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    (0..parsed_data[1].len())
+        .filter_map(|i| {
+            let arfcn = value(1, i).parse::<u32>().ok().filter(|a| *a != 0)?;
+            let mut band = arfcn::format_band(tech, &value(0, i));
+            let info = arfcn::lookup(tech, arfcn, arfcn::parse_band_number(&band));
+            if let Some(info) = info.as_ref().filter(|_| band.is_empty() || band == "0") {
+                band = info.band.clone();
+            }
+            Some(ComponentCarrier {
+                tech: tech.to_string(),
+                band,
+                arfcn,
+                pci: value(2, i).parse().unwrap_or_default(),
+                dl_freq_mhz: info.map(|info| info.dl_freq_mhz),
+                rsrp: value(3, i),
+                rsrq: value(4, i),
+                sinr: value(sinr_column, i),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// 汇总载波聚合状态
+///
+/// # Arguments
+/// * `primary` - 主制式（驻留制式）的分量载波，第一个为 PCell
+/// * `nr_leg` - EN-DC 时 NR 辅连接的分量载波，第一个为 PSCell；非 EN-DC 为空
+pub fn build_carrier_aggregation(
+    mut primary: Vec<ComponentCarrier>,
+    mut nr_leg: Vec<ComponentCarrier>,
+) -> CarrierAggregation {
+    for (i, carrier) in primary.iter_mut().enumerate() {
+        carrier.role = if i == 0 { "pcell" } else { "scell" }.to_string();
+    }
+    for (i, carrier) in nr_leg.iter_mut().enumerate() {
+        carrier.role = if i == 0 { "pscell" } else { "scell" }.to_string();
+    }
+
+    let endc = match (primary.first(), nr_leg.first()) {
+        (Some(anchor), Some(pscell)) => Some(EnDcLink {
+            anchor_band: anchor.band.clone(),
+            anchor_arfcn: anchor.arfcn,
+            anchor_pci: anchor.pci,
+            nr_band: pscell.band.clone(),
+            nr_arfcn: pscell.arfcn,
+            nr_pci: pscell.pci,
+        }),
+        _ => None,
+    };
+
+    let mode = if endc.is_some() {
+        "endc".to_string()
+    } else if primary.len() > 1 {
+        format!("{}_ca", primary[0].tech)
+    } else {
+        "single".to_string()
+    };
+
+    CarrierAggregation {
+        mode,
+        carriers: primary.into_iter().chain(nr_leg).collect(),
+        endc,
+    }
+}
+
+/// 查询指定制式的主小区页并解析分量载波，失败时返回空列表
+async fn fetch_carriers(conn: &Connection, tech: &str) -> Vec<ComponentCarrier> {
+    let Some(cmd_config) = get_cell_command_config(tech) else {
+        return Vec::new();
+    };
+    match send_at_command(conn, cmd_config.primary).await {
+        Ok(response) => parse_carriers(tech, &parse_at_response_to_2d_vec(&response)),
+        Err(e) => {
+            debug!(tech, error = %e, "Primary cell page not available");
+            Vec::new()
+        }
+    }
+}
+
+/// 获取载波聚合 / EN-DC 状态
+///
+/// 驻留 LTE 时额外查询 NR 主小区页，NR 主载波有效即视为 EN-DC（NSA）
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `tech` - 驻留制式（来自服务小区信息）
+/// * `primary_page` - 已查询并解析的驻留制式主小区页
+pub async fn fetch_carrier_aggregation(
+    conn: &Connection,
+    tech: &str,
+    primary_page: &[Vec<String>],
+) -> CarrierAggregation {
+    let primary = parse_carriers(tech, primary_page);
+    // 注意：AT 指令必须串行执行
+    let nr_leg = if tech == "lte" {
+        fetch_carriers(conn, "nr").await
+    } else {
+        Vec::new()
+    };
+    build_carrier_aggregation(primary, nr_leg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按列构造主小区页（列之间以 "-" 分隔，与模组输出一致），其余列填 0
+    fn page(columns: &[(usize, &str)], len: usize) -> Vec<Vec<String>> {
+        let mut fields = vec!["0"; len];
+        for (i, value) in columns {
+            fields[*i] = value;
+        }
+        parse_at_response_to_2d_vec(&format!("{}\r\nOK", fields.join("-")))
+    }
+
+    #[test]
+    fn test_parse_lte_ca_with_endc_leg() {
+        // LTE B3 + B1 载波聚合，信号列只有主载波的值
+        let lte = page(&[(0, "3,1"), (1, "1650,100"), (2, "123,45"), (3, "-9500"), (4, "-1100"), (33, "1500")], 34);
+        let lte = parse_carriers("lte", &lte);
+        assert_eq!(lte.len(), 2);
+        assert_eq!((lte[0].band.as_str(), lte[0].arfcn, lte[0].pci), ("B3", 1650, 123));
+        assert_eq!((lte[0].rsrp.as_str(), lte[0].sinr.as_str()), ("-9500", "1500"));
+        assert_eq!((lte[1].band.as_str(), lte[1].arfcn, lte[1].pci), ("B1", 100, 45));
+        assert_eq!(lte[1].rsrp, "");
+        assert!(lte[0].dl_freq_mhz.is_some());
+
+        let nr = page(&[(0, "78"), (1, "633984"), (2, "597"), (3, "-8800"), (4, "-1050"), (15, "2100")], 16);
+        let ca = build_carrier_aggregation(lte, parse_carriers("nr", &nr));
+        assert_eq!(ca.mode, "endc");
+        assert_eq!(ca.carriers.iter().map(|c| c.role.as_str()).collect::<Vec<_>>(), ["pcell", "scell", "pscell"]);
+        let endc = ca.endc.unwrap();
+        assert_eq!((endc.anchor_pci, endc.nr_band.as_str(), endc.nr_pci), (123, "n78", 597));
+    }
+
+    #[test]
+    fn test_single_carrier_and_invalid_page() {
+        let nr = page(&[(0, "41"), (1, "504990"), (2, "12"), (3, "-9000"), (4, "-1000"), (15, "900")], 16);
+        let ca = build_carrier_aggregation(parse_carriers("nr", &nr), Vec::new());
+        assert_eq!(ca.mode, "single");
+        assert_eq!(ca.carriers[0].role, "pcell");
+        assert_eq!(ca.carriers[0].band, "n41");
+        assert!(ca.endc.is_none());
+
+        // 未驻留（频点为 0）或列数不足时没有分量载波
+        assert!(parse_carriers("nr", &page(&[], 16)).is_empty());
+        assert!(parse_carriers("lte", &page(&[(1, "1650")], 20)).is_empty());
+    }
+}
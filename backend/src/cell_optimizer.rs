@@ -0,0 +1,523 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 20:10:37
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 20:10:37
+ * @FilePath: /udx710-backend/backend/src/cell_optimizer.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 最佳小区优化模块
+//!
+//! 以主小区和邻区为候选，逐个通过 SPFORCEFRQ 试锁定，等待注册后采样 SINR/RSRP
+//! （可选测速），按结果排序后锁定最佳小区或仅报告结果。
+//! 任务失败、取消或只报告结果时，恢复任务开始前的小区锁定状态。
+//! 开始试锁定前先结束正在观察的安全锁定（见 [`SafeLockManager::supersede`]），
+//! 避免其到期恢复覆盖优化任务的锁定。
+
+use chrono::Utc;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::band_lock::{
+    fetch_primary_cell, is_registered, lock_cell, parse_first, query_cell_lock_status, restore_cell_lock,
+    FORCEFRQ_TYPE_LTE, FORCEFRQ_TYPE_NR,
+};
+use crate::config::ConfigManager;
+use crate::safe_lock::SafeLockManager;
+use crate::dbus::{get_serving_cell_info, send_at_command};
+use crate::models::{CellLockStatusResponse, CellOptimizeRequest};
+use crate::utils::{get_cell_command_config, parse_at_response_to_2d_vec, parse_neighbor_cells};
+
+/// 注册状态轮询间隔（秒）
+const REGISTRATION_POLL_SECS: u64 = 2;
+
+/// 优化任务状态
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizerState {
+    #[default]
+    Idle,
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// 单个候选小区的测试结果
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct CandidateResult {
+    pub tech: String,
+    pub band: String,
+    pub arfcn: u32,
+    pub pci: u16,
+    /// 测试前扫描到的 RSRP（dBm）
+    pub initial_rsrp: Option<f64>,
+    /// 是否成功注册到该小区
+    pub registered: bool,
+    /// 平均 RSRP（dBm）
+    pub avg_rsrp: Option<f64>,
+    /// 平均 SINR（dB）
+    pub avg_sinr: Option<f64>,
+    /// 下载速率（Mbps）
+    pub throughput_mbps: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// 优化任务状态
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct OptimizerStatus {
+    pub state: OptimizerState,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// 已测试 / 总候选数
+    pub tested: usize,
+    pub total: usize,
+    /// 正在测试的小区（如 "nr 633984/597"）
+    pub current: Option<String>,
+    /// 测试结果（完成后按优劣排序）
+    pub results: Vec<CandidateResult>,
+    pub best: Option<CandidateResult>,
+    /// 是否已锁定到最佳小区
+    pub applied: bool,
+    /// 是否已恢复任务前的锁定状态
+    pub restored: bool,
+    pub message: String,
+}
+
+/// 最佳小区优化器
+pub struct CellOptimizer {
+    config_manager: Arc<ConfigManager>,
+    safe_lock: Arc<SafeLockManager>,
+    status: RwLock<OptimizerStatus>,
+    /// 取消标记（true 表示已请求取消），等待中的操作通过订阅立即感知
+    cancel: watch::Sender<bool>,
+}
+
+impl CellOptimizer {
+    /// 创建优化器
+    pub fn new(config_manager: Arc<ConfigManager>, safe_lock: Arc<SafeLockManager>) -> Self {
+        Self {
+            config_manager,
+            safe_lock,
+            status: RwLock::new(OptimizerStatus::default()),
+            cancel: watch::Sender::new(false),
+        }
+    }
+
+    /// 获取当前状态
+    pub fn status(&self) -> OptimizerStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// 启动优化任务（同一时间只允许一个任务）
+    pub fn start(self: &Arc<Self>, conn: Arc<Connection>, request: CellOptimizeRequest) -> Result<(), String> {
+        {
+            let mut status = self.status.write().unwrap();
+            if status.state == OptimizerState::Running {
+                return Err("Optimizer is already running".to_string());
+            }
+            *status = OptimizerStatus {
+                state: OptimizerState::Running,
+                started_at: Some(Utc::now().to_rfc3339()),
+                message: "Scanning candidate cells".to_string(),
+                ..Default::default()
+            };
+        }
+        self.cancel.send_replace(false);
+
+        let optimizer = Arc::clone(self);
+        tokio::spawn(async move {
+            optimizer.run(&conn, request).await;
+        });
+        Ok(())
+    }
+
+    /// 是否有任务正在运行
+    pub fn is_running(&self) -> bool {
+        self.status.read().unwrap().state == OptimizerState::Running
+    }
+
+    /// 请求取消正在运行的任务
+    pub fn cancel(&self) -> bool {
+        if self.status.read().unwrap().state != OptimizerState::Running {
+            return false;
+        }
+        self.cancel.send_replace(true);
+        true
+    }
+
+    fn cancelled(&self) -> bool {
+        *self.cancel.borrow()
+    }
+
+    /// 等待取消请求
+    async fn wait_cancelled(&self) {
+        let mut rx = self.cancel.subscribe();
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// 可被取消的等待，返回 false 表示已取消
+    ///
+    /// 天线对准期间继续等待，直到对准结束
+    async fn sleep(&self, secs: u64) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(secs)) => {}
+            _ = self.wait_cancelled() => return false,
+        }
+        while crate::alignment::is_active() {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                _ = self.wait_cancelled() => return false,
+            }
+        }
+        !self.cancelled()
+    }
+
+    fn finish(&self, state: OptimizerState, message: String) {
+        info!(state = ?state, message = %message, "Cell optimizer finished");
+        let mut status = self.status.write().unwrap();
+        status.state = state;
+        status.current = None;
+        status.finished_at = Some(Utc::now().to_rfc3339());
+        status.message = message;
+    }
+
+    /// 执行优化任务
+    async fn run(&self, conn: &Connection, request: CellOptimizeRequest) {
+        let (tech, candidates) = match scan_candidates(conn, request.max_candidates).await {
+            Ok(v) => v,
+            Err(e) => {
+                self.finish(OptimizerState::Failed, e);
+                return;
+            }
+        };
+        if candidates.is_empty() {
+            self.finish(OptimizerState::Failed, "No candidate cells found".to_string());
+            return;
+        }
+
+        // 结束正在观察的安全锁定（正在恢复时等待恢复完成），再保存任务前的锁定状态
+        self.safe_lock.supersede().await;
+        if self.cancelled() {
+            self.finish(OptimizerState::Cancelled, "Cancelled".to_string());
+            return;
+        }
+
+        // 保存任务前的锁定状态，任何情况下都能恢复
+        let previous = query_cell_lock_status(conn).await;
+        let forcefrq_type = if tech == "nr" { FORCEFRQ_TYPE_NR } else { FORCEFRQ_TYPE_LTE };
+        self.status.write().unwrap().total = candidates.len();
+        info!(tech = %tech, candidates = candidates.len(), "Cell optimizer started");
+
+        let mut results = Vec::new();
+        for mut candidate in candidates {
+            if self.cancelled() {
+                break;
+            }
+            {
+                let mut status = self.status.write().unwrap();
+                status.current = Some(format!("{} {}/{}", tech, candidate.arfcn, candidate.pci));
+                status.message = format!("Testing {} {}/{}", tech, candidate.arfcn, candidate.pci);
+            }
+
+            if let Err(e) = self.test_candidate(conn, forcefrq_type, &request, &mut candidate).await {
+                candidate.error = Some(e);
+            }
+
+            results.push(candidate);
+            let mut status = self.status.write().unwrap();
+            status.tested = results.len();
+            status.results = results.clone();
+        }
+
+        rank_candidates(&mut results);
+        let best = results.iter().find(|c| c.registered).cloned();
+        {
+            let mut status = self.status.write().unwrap();
+            status.results = results;
+            status.best = best.clone();
+        }
+
+        if self.cancelled() {
+            let restored = self.restore(conn, &previous).await;
+            self.finish(OptimizerState::Cancelled, format!("Cancelled, {}", restored));
+            return;
+        }
+
+        let Some(best) = best else {
+            let restored = self.restore(conn, &previous).await;
+            self.finish(
+                OptimizerState::Failed,
+                format!("No candidate cell could be registered, {}", restored),
+            );
+            return;
+        };
+
+        if request.apply_best {
+            match lock_cell(conn, forcefrq_type, best.arfcn, best.pci).await {
+                Ok(()) => {
+                    self.config_manager.clear_active_lock_profile();
+                    self.status.write().unwrap().applied = true;
+                    self.finish(
+                        OptimizerState::Completed,
+                        format!("Locked to best cell {} {}/{}", tech, best.arfcn, best.pci),
+                    );
+                }
+                Err(e) => {
+                    let restored = self.restore(conn, &previous).await;
+                    self.finish(
+                        OptimizerState::Failed,
+                        format!("Failed to lock best cell: {}, {}", e, restored),
+                    );
+                }
+            }
+        } else {
+            let restored = self.restore(conn, &previous).await;
+            self.finish(
+                OptimizerState::Completed,
+                format!("Best cell {} {}/{}, {}", tech, best.arfcn, best.pci, restored),
+            );
+        }
+    }
+
+    /// 试锁定单个候选小区并采样
+    async fn test_candidate(
+        &self,
+        conn: &Connection,
+        forcefrq_type: u8,
+        request: &CellOptimizeRequest,
+        candidate: &mut CandidateResult,
+    ) -> Result<(), String> {
+        lock_cell(conn, forcefrq_type, candidate.arfcn, candidate.pci).await?;
+
+        // 等待注册到目标小区
+        let mut waited = 0;
+        loop {
+            if !self.sleep(REGISTRATION_POLL_SECS).await {
+                return Err("Cancelled".to_string());
+            }
+            waited += REGISTRATION_POLL_SECS;
+
+            if is_registered_on(conn, &candidate.tech, candidate.pci).await {
+                candidate.registered = true;
+                break;
+            }
+            if waited >= request.settle_secs {
+                return Err(format!("Not registered within {}s", request.settle_secs));
+            }
+        }
+
+        // 信号采样
+        let mut rsrp = Vec::new();
+        let mut sinr = Vec::new();
+        for i in 0..request.samples.max(1) {
+            if i > 0 && !self.sleep(request.sample_interval_secs).await {
+                return Err("Cancelled".to_string());
+            }
+            if let Ok(cell) = fetch_primary_cell(conn, &candidate.tech).await {
+                if parse_first::<u16>(&cell.pci) != Some(candidate.pci) {
+                    continue;
+                }
+                rsrp.extend(parse_raw_db(&cell.rsrp));
+                sinr.extend(parse_raw_db(&cell.sinr));
+            }
+        }
+        candidate.avg_rsrp = average(&rsrp);
+        candidate.avg_sinr = average(&sinr);
+
+        if let Some(url) = &request.throughput_url {
+            tokio::select! {
+                result = measure_throughput(url, request.throughput_secs) => match result {
+                    Ok(mbps) => candidate.throughput_mbps = Some(mbps),
+                    Err(e) => warn!(error = %e, "Cell optimizer: throughput test failed"),
+                },
+                _ = self.wait_cancelled() => return Err("Cancelled".to_string()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 恢复任务前的小区锁定状态，返回结果说明
+    async fn restore(&self, conn: &Connection, previous: &CellLockStatusResponse) -> String {
+        match restore_cell_lock(conn, previous).await {
+            Ok(()) => {
+                self.status.write().unwrap().restored = true;
+                "previous lock state restored".to_string()
+            }
+            Err(e) => {
+                warn!(error = %e, "Cell optimizer: failed to restore lock state");
+                format!("failed to restore previous lock state: {}", e)
+            }
+        }
+    }
+}
+
+/// 扫描候选小区（主小区 + 同制式邻区），按 RSRP 降序取前 N 个
+async fn scan_candidates(conn: &Connection, max_candidates: usize) -> Result<(String, Vec<CandidateResult>), String> {
+    let serving = get_serving_cell_info(conn)
+        .await
+        .map_err(|e| format!("Failed to get serving cell info: {}", e))?;
+    let tech = serving.tech;
+    let cmd_config = get_cell_command_config(&tech)
+        .ok_or_else(|| format!("Unsupported network type: {}", tech))?;
+
+    let primary = fetch_primary_cell(conn, &tech).await?;
+    let response = send_at_command(conn, cmd_config.neighbor)
+        .await
+        .map_err(|e| format!("Neighbor cell AT command failed: {}", e))?;
+    let neighbors = parse_neighbor_cells(&tech, &parse_at_response_to_2d_vec(&response));
+
+    let mut candidates: Vec<CandidateResult> = Vec::new();
+    for cell in std::iter::once(primary).chain(neighbors) {
+        // 主小区载波聚合时 arfcn 形如 "633984,504990"，取主载波
+        let arfcn = parse_first::<u32>(&cell.arfcn);
+        let pci = parse_first::<u16>(&cell.pci);
+        let (Some(arfcn), Some(pci)) = (arfcn, pci) else {
+            continue;
+        };
+        if arfcn == 0 || candidates.iter().any(|c| c.arfcn == arfcn && c.pci == pci) {
+            continue;
+        }
+        candidates.push(CandidateResult {
+            tech: tech.clone(),
+            band: cell.band.clone(),
+            arfcn,
+            pci,
+            initial_rsrp: parse_raw_db(&cell.rsrp),
+            ..Default::default()
+        });
+    }
+
+    candidates.sort_by(|a, b| cmp_option_desc(a.initial_rsrp, b.initial_rsrp));
+    candidates.truncate(max_candidates.max(1));
+    Ok((tech, candidates))
+}
+
+/// 判断是否已注册并驻留在指定 PCI 的小区
+async fn is_registered_on(conn: &Connection, tech: &str, pci: u16) -> bool {
+    if !is_registered(conn).await {
+        return false;
+    }
+    match fetch_primary_cell(conn, tech).await {
+        Ok(cell) => parse_first::<u16>(&cell.pci) == Some(pci),
+        Err(_) => false,
+    }
+}
+
+/// 下载测速，返回 Mbps
+async fn measure_throughput(url: &str, secs: u64) -> Result<f64, String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(secs + 10))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    let start = tokio::time::Instant::now();
+    let limit = Duration::from_secs(secs.max(1));
+    let mut bytes = 0u64;
+    while start.elapsed() < limit {
+        match response.chunk().await {
+            Ok(Some(chunk)) => bytes += chunk.len() as u64,
+            Ok(None) => break,
+            Err(e) => return Err(format!("Download failed: {}", e)),
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    if elapsed <= 0.0 || bytes == 0 {
+        return Err("No data received".to_string());
+    }
+    Ok((bytes as f64 * 8.0 / elapsed / 1_000_000.0 * 100.0).round() / 100.0)
+}
+
+/// 解析原始信号值（×100），无效值返回 None
+fn parse_raw_db(raw: &str) -> Option<f64> {
+    raw.split(',')
+        .next()
+        .and_then(|s| s.trim().parse::<f64>().ok())
+        .map(|v| v / 100.0)
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some((values.iter().sum::<f64>() / values.len() as f64 * 100.0).round() / 100.0)
+    }
+}
+
+/// 降序比较，None 排在最后
+fn cmp_option_desc(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// 候选小区排序：已注册优先，其次测速结果、SINR、RSRP 依次降序
+pub fn rank_candidates(results: &mut [CandidateResult]) {
+    results.sort_by(|a, b| {
+        b.registered
+            .cmp(&a.registered)
+            .then_with(|| cmp_option_desc(a.throughput_mbps, b.throughput_mbps))
+            .then_with(|| cmp_option_desc(a.avg_sinr, b.avg_sinr))
+            .then_with(|| cmp_option_desc(a.avg_rsrp, b.avg_rsrp))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(pci: u16, registered: bool, sinr: Option<f64>, rsrp: Option<f64>) -> CandidateResult {
+        CandidateResult {
+            pci,
+            registered,
+            avg_sinr: sinr,
+            avg_rsrp: rsrp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rank_candidates() {
+        let mut results = vec![
+            candidate(1, false, None, None),
+            candidate(2, true, Some(5.0), Some(-90.0)),
+            candidate(3, true, Some(12.0), Some(-100.0)),
+            candidate(4, true, Some(12.0), Some(-85.0)),
+            candidate(5, true, None, Some(-80.0)),
+        ];
+        rank_candidates(&mut results);
+        let order: Vec<u16> = results.iter().map(|c| c.pci).collect();
+        assert_eq!(order, vec![4, 3, 2, 5, 1]);
+
+        // 有测速结果时优先按速率排序
+        results[2].throughput_mbps = Some(200.0);
+        results[0].throughput_mbps = Some(150.0);
+        rank_candidates(&mut results);
+        assert_eq!(results[0].pci, 2);
+        assert_eq!(results[1].pci, 4);
+    }
+
+    #[test]
+    fn test_parse_raw_db() {
+        assert_eq!(parse_raw_db("-9850"), Some(-98.5));
+        assert_eq!(parse_raw_db("1200,800"), Some(12.0));
+        assert_eq!(parse_raw_db("-"), None);
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 23:05:12
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 23:05:12
+ * @FilePath: /udx710-backend/backend/src/cell_tower.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 离线基站库模块
+//!
+//! 导入 OpenCellID / Mozilla Location Service 的 CSV 数据（可按 MCC 过滤），
+//! 根据主服务小区和邻区在本地库中的位置，按信号强度加权估算设备位置，无需联网。
+//!
+//! CSV 列：radio,mcc,net,area,cell,unit,lon,lat,range,samples,...
+//! - 主服务小区按 Cell ID 匹配
+//! - 邻区只有 PCI，按同 TAC 下的 unit（LTE/NR 为 PCI）匹配，多个结果时取离主小区最近的
+
+use chrono::Utc;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::db::{CellTower, Database};
+use crate::models::{CellLocationInfo, CellLocationResponse, LocationEstimate, TowerImportRequest, TowerImportStatus, TowerMatch};
+
+/// 每批写入数据库的条数
+const IMPORT_BATCH_SIZE: usize = 5000;
+/// 基站未提供覆盖半径时使用的默认值（米）
+const DEFAULT_RANGE_M: u32 = 1000;
+/// 精度半径下限（米）
+const MIN_ACCURACY_M: f64 = 100.0;
+/// 地球平均半径（米）
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// 最近一次导入状态
+static IMPORT_STATUS: Mutex<Option<TowerImportStatus>> = Mutex::new(None);
+
+/// 获取最近一次导入状态
+pub fn import_status() -> Option<TowerImportStatus> {
+    IMPORT_STATUS.lock().unwrap().clone()
+}
+
+/// 在后台启动导入任务
+///
+/// # Returns
+/// 初始导入状态；已有导入任务在运行时返回错误
+pub fn start_import(db: Arc<Database>, request: TowerImportRequest) -> Result<TowerImportStatus, String> {
+    let status = {
+        let mut current = IMPORT_STATUS.lock().unwrap();
+        if current.as_ref().is_some_and(|s| s.running) {
+            return Err("A cell tower import is already running".to_string());
+        }
+        if !std::path::Path::new(&request.path).is_file() {
+            return Err(format!("File not found: {}", request.path));
+        }
+        let status = TowerImportStatus {
+            running: true,
+            path: request.path.clone(),
+            started_at: Utc::now().to_rfc3339(),
+            ..Default::default()
+        };
+        *current = Some(status.clone());
+        status
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let result = run_import(&db, &request);
+        let mut current = IMPORT_STATUS.lock().unwrap();
+        if let Some(status) = current.as_mut() {
+            status.running = false;
+            status.finished_at = Some(Utc::now().to_rfc3339());
+            match result {
+                Ok(()) => info!(imported = status.imported, skipped = status.skipped, "Cell tower import finished"),
+                Err(e) => {
+                    warn!(error = %e, "Cell tower import failed");
+                    status.error = Some(e);
+                }
+            }
+        }
+    });
+
+    Ok(status)
+}
+
+/// 执行导入（阻塞）
+fn run_import(db: &Database, request: &TowerImportRequest) -> Result<(), String> {
+    if request.replace {
+        db.clear_cell_towers(&request.mcc)
+            .map_err(|e| format!("Failed to clear cell towers: {}", e))?;
+    }
+
+    // .gz 文件通过 gzip -dc 解压读取
+    if !request.path.ends_with(".gz") {
+        let file = File::open(&request.path).map_err(|e| format!("Failed to open {}: {}", request.path, e))?;
+        return import_lines(db, request, file);
+    }
+
+    let mut child = Command::new("gzip")
+        .args(["-dc", &request.path])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run gzip: {}", e))?;
+    let stdout = child.stdout.take().ok_or("Failed to read gzip output")?;
+    let result = import_lines(db, request, stdout);
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    // 压缩包损坏或被截断时 gzip 以非零状态退出，此时已读到的数据不完整
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait for gzip: {}", e))?;
+    result?;
+    if !output.status.success() {
+        return Err(format!(
+            "gzip failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// 逐行解析 CSV 并分批写入数据库（阻塞）
+fn import_lines(db: &Database, request: &TowerImportRequest, reader: impl Read) -> Result<(), String> {
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let (mut lines_read, mut imported, mut skipped) = (0u64, 0u64, 0u64);
+
+    let flush = |batch: &mut Vec<CellTower>, lines_read: u64, imported: &mut u64, skipped: u64| -> Result<(), String> {
+        if !batch.is_empty() {
+            *imported += db
+                .insert_cell_towers(batch)
+                .map_err(|e| format!("Failed to insert cell towers: {}", e))? as u64;
+            batch.clear();
+        }
+        if let Some(status) = IMPORT_STATUS.lock().unwrap().as_mut() {
+            status.lines_read = lines_read;
+            status.imported = *imported;
+            status.skipped = skipped;
+        }
+        Ok(())
+    };
+
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", request.path, e))?;
+        lines_read += 1;
+
+        match parse_tower_line(&line) {
+            Some(tower) if request.mcc.is_empty() || request.mcc.contains(&tower.mcc) => batch.push(tower),
+            _ => skipped += 1,
+        }
+
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            flush(&mut batch, lines_read, &mut imported, skipped)?;
+        }
+    }
+
+    flush(&mut batch, lines_read, &mut imported, skipped)
+}
+
+/// 解析一行 CSV（表头和格式错误的行返回 None）
+pub fn parse_tower_line(line: &str) -> Option<CellTower> {
+    let fields: Vec<&str> = line.trim().split(',').map(str::trim).collect();
+    if fields.len() < 10 {
+        return None;
+    }
+
+    let lon: f64 = fields[6].parse().ok()?;
+    let lat: f64 = fields[7].parse().ok()?;
+    if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+        return None;
+    }
+
+    Some(CellTower {
+        radio: fields[0].to_uppercase(),
+        mcc: fields[1].parse().ok()?,
+        mnc: fields[2].parse().ok()?,
+        area: fields[3].parse().ok()?,
+        cell: fields[4].parse().ok()?,
+        unit: fields[5].parse().ok(),
+        lon,
+        lat,
+        range: fields[8].parse::<f64>().map(|r| r.max(0.0) as u32).unwrap_or(0),
+        samples: fields[9].parse().unwrap_or(0),
+    })
+}
+
+/// 两点间球面距离（米）
+pub fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// 构造参与定位的基站（权重稍后由 weighted_estimate 归一化）
+fn tower_match(role: &str, tower: &CellTower, cell: &CellLocationInfo) -> TowerMatch {
+    TowerMatch {
+        role: role.to_string(),
+        radio: tower.radio.clone(),
+        area: tower.area,
+        cell: tower.cell,
+        pci: tower.unit,
+        latitude: tower.lat,
+        longitude: tower.lon,
+        range_m: if tower.range > 0 { tower.range } else { DEFAULT_RANGE_M },
+        rsrp_dbm: cell.signal_strength,
+        weight: 0.0,
+    }
+}
+
+/// 在离线基站库中查找主服务小区和邻区
+pub fn match_towers(db: &Database, location: &CellLocationResponse) -> Result<Vec<TowerMatch>, String> {
+    let mut matches = Vec::new();
+    let Some(serving) = location.cell_info.as_ref() else {
+        return Ok(matches);
+    };
+    let (Ok(mcc), Ok(mnc)) = (serving.mcc.parse::<u16>(), serving.mnc.parse::<u16>()) else {
+        return Err(format!("Invalid MCC/MNC: {}/{}", serving.mcc, serving.mnc));
+    };
+
+    let serving_tower = db
+        .find_cell_tower(&serving.radio_type.to_uppercase(), mcc, mnc, serving.cid as i64)
+        .map_err(|e| format!("Failed to query cell towers: {}", e))?;
+    if let Some(tower) = &serving_tower {
+        matches.push(tower_match("serving", tower, serving));
+    }
+
+    for neighbor in &location.neighbor_cells {
+        let Some(pci) = neighbor.pci else { continue };
+        let candidates = db
+            .find_cell_towers_by_pci(&neighbor.radio_type.to_uppercase(), mcc, mnc, neighbor.lac, pci)
+            .map_err(|e| format!("Failed to query cell towers: {}", e))?;
+
+        // PCI 会复用：有主小区位置时取最近的，否则只接受唯一匹配
+        let chosen = match &serving_tower {
+            Some(anchor) => candidates.iter().min_by(|a, b| {
+                haversine_m(anchor.lat, anchor.lon, a.lat, a.lon)
+                    .total_cmp(&haversine_m(anchor.lat, anchor.lon, b.lat, b.lon))
+            }),
+            None if candidates.len() == 1 => candidates.first(),
+            None => None,
+        };
+        if let Some(tower) = chosen {
+            let duplicate = matches.iter().any(|m| m.radio == tower.radio && m.cell == tower.cell && m.area == tower.area);
+            if !duplicate {
+                matches.push(tower_match("neighbor", tower, neighbor));
+            }
+        }
+    }
+
+    Ok(matches)
+}
+
+/// 按信号强度加权估算位置
+///
+/// 权重为 RSRP 换算的线性幅度 10^((RSRP+140)/20)，信号强的基站离设备更近。
+/// 精度半径取各基站到估算点的加权平均距离，且不小于最近基站的覆盖半径
+/// （只有一个基站时即为其覆盖半径）。
+pub fn weighted_estimate(mut towers: Vec<TowerMatch>) -> Option<LocationEstimate> {
+    if towers.is_empty() {
+        return None;
+    }
+
+    let raw_weights: Vec<f64> = towers
+        .iter()
+        .map(|t| 10f64.powf((t.rsrp_dbm.clamp(-140, -40) + 140) as f64 / 20.0))
+        .collect();
+    let total: f64 = raw_weights.iter().sum();
+    for (tower, w) in towers.iter_mut().zip(&raw_weights) {
+        tower.weight = w / total;
+    }
+
+    let latitude: f64 = towers.iter().map(|t| t.latitude * t.weight).sum();
+    let longitude: f64 = towers.iter().map(|t| t.longitude * t.weight).sum();
+
+    let spread: f64 = towers
+        .iter()
+        .map(|t| haversine_m(latitude, longitude, t.latitude, t.longitude) * t.weight)
+        .sum();
+    let nearest_range = towers.iter().map(|t| t.range_m).min().unwrap_or(DEFAULT_RANGE_M) as f64;
+    let accuracy_m = spread.max(nearest_range).max(MIN_ACCURACY_M).round();
+
+    Some(LocationEstimate {
+        latitude,
+        longitude,
+        accuracy_m,
+        source: "offline".to_string(),
+        towers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tower_line() {
+        assert!(parse_tower_line("radio,mcc,net,area,cell,unit,lon,lat,range,samples,changeable,created,updated,averageSignal").is_none());
+
+        let tower = parse_tower_line("LTE,460,0,6178,123456789,301,116.397128,39.916527,850,12,1,1459813819,1608000000,0").unwrap();
+        assert_eq!((tower.radio.as_str(), tower.mcc, tower.mnc, tower.area), ("LTE", 460, 0, 6178));
+        assert_eq!((tower.cell, tower.unit, tower.range), (123456789, Some(301), 850));
+
+        let no_pci = parse_tower_line("UMTS,460,1,100,42,,116.0,39.0,0,1,1,0,0,").unwrap();
+        assert_eq!(no_pci.unit, None);
+    }
+
+    #[test]
+    fn test_weighted_estimate() {
+        let tower = |lat: f64, lon: f64, rsrp: i32, range: u32| TowerMatch {
+            role: "neighbor".to_string(),
+            radio: "LTE".to_string(),
+            area: 1,
+            cell: 1,
+            pci: None,
+            latitude: lat,
+            longitude: lon,
+            range_m: range,
+            rsrp_dbm: rsrp,
+            weight: 0.0,
+        };
+
+        let single = weighted_estimate(vec![tower(39.9, 116.4, -90, 800)]).unwrap();
+        assert_eq!((single.latitude, single.longitude, single.accuracy_m), (39.9, 116.4, 800.0));
+
+        // 两个基站相距约 1.1km，信号强 20dB 的一侧权重为 10 倍
+        let estimate = weighted_estimate(vec![tower(39.90, 116.40, -80, 300), tower(39.91, 116.40, -100, 300)]).unwrap();
+        assert!((estimate.towers[0].weight - 10.0 / 11.0).abs() < 1e-9);
+        assert!(estimate.latitude > 39.900 && estimate.latitude < 39.901);
+        assert!(estimate.accuracy_m >= 300.0);
+
+        assert!(weighted_estimate(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,477 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-18 10:20:41
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-18 10:20:41
+ * @FilePath: /udx710-backend/backend/src/clients.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 已连接客户端与按客户端流量统计
+//!
+//! 从 ARP 表、IPv6 邻居表和 DHCP 租约发现 LAN 侧主机，在 mangle 表 FORWARD
+//! 链挂载 UDX_ACCOUNT 计数链：每个客户端地址一条上行（`-i lan -s`）和一条
+//! 下行（`-o lan -d`）规则。周期性读取并清零计数器，按 MAC 汇总速率，
+//! 每日总量写入数据库。mangle 表先于 filter 表执行，统计不受防火墙放行
+//! 顺序影响；只统计经设备转发的流量，访问设备本身的流量不计入。
+//!
+//! SFP 硬件加速的流量不经过 netfilter，统计开启期间关闭 SFP，因此默认关闭；
+//! LAN 配置 client_accounting 开启后才挂载计数链，关闭时卸载计数链，恢复 SFP。
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv6Addr;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::config::ConfigManager;
+use crate::db::Database;
+use crate::iptables::run_iptables;
+
+/// 托管的计数链（mangle 表）
+pub const ACCOUNT_CHAIN: &str = "UDX_ACCOUNT";
+
+/// 采样间隔（秒）
+pub const SAMPLE_INTERVAL_SECS: u64 = 10;
+
+/// 每日流量保留天数
+const HISTORY_RETENTION_DAYS: i64 = 90;
+
+/// 客户端状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ClientInfo {
+    pub mac: String,
+    /// DHCP 租约中的主机名
+    pub hostname: String,
+    pub ipv4: Option<String>,
+    /// 全局 IPv6 地址（不含链路本地地址）
+    pub ipv6: Vec<String>,
+    /// ARP / 邻居表中存在有效条目
+    pub connected: bool,
+    /// 最近一次出现在 ARP / 邻居表或产生流量的时间 ISO 8601
+    pub last_seen: Option<String>,
+    /// 当前下行速率（字节/秒）
+    pub download_rate: u64,
+    /// 当前上行速率（字节/秒）
+    pub upload_rate: u64,
+    /// 今日下行字节数
+    pub today_download_bytes: i64,
+    /// 今日上行字节数
+    pub today_upload_bytes: i64,
+}
+
+/// 当前客户端列表（由 [`run`] 周期性刷新）
+static CLIENTS: Mutex<BTreeMap<String, ClientInfo>> = Mutex::new(BTreeMap::new());
+
+/// 获取客户端列表（在线优先，再按今日流量降序）
+pub fn snapshot() -> Vec<ClientInfo> {
+    let mut clients: Vec<ClientInfo> = CLIENTS
+        .lock()
+        .map(|clients| clients.values().cloned().collect())
+        .unwrap_or_default();
+    clients.sort_by_key(|c| {
+        (
+            !c.connected,
+            std::cmp::Reverse(c.today_download_bytes + c.today_upload_bytes),
+        )
+    });
+    clients
+}
+
+/// 获取单个客户端
+pub fn get(mac: &str) -> Option<ClientInfo> {
+    CLIENTS.lock().ok()?.get(mac).cloned()
+}
+
+/// 一个计数周期内客户端的流量增量
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Delta {
+    download: u64,
+    upload: u64,
+}
+
+/// 计数规则的方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Download,
+    Upload,
+}
+
+/// 读取 IPv6 邻居表中指定接口的全局地址
+///
+/// # Returns
+/// (IPv6, MAC) 列表，MAC 为小写
+fn read_neighbors_v6(interface: &str) -> Vec<(String, String)> {
+    Command::new("ip")
+        .args(["-6", "neigh", "show", "dev", interface])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| parse_neighbors_v6(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+/// 解析 `ip -6 neigh show dev <if>` 输出，跳过链路本地、组播和未解析条目
+fn parse_neighbors_v6(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let addr: Ipv6Addr = fields.first()?.parse().ok()?;
+            if addr.is_multicast() || addr.segments()[0] & 0xffc0 == 0xfe80 {
+                return None;
+            }
+            if matches!(fields.last(), Some(&"FAILED") | Some(&"INCOMPLETE")) {
+                return None;
+            }
+            let pos = fields.iter().position(|f| *f == "lladdr")?;
+            let mac = fields.get(pos + 1)?.to_lowercase();
+            Some((addr.to_string(), mac))
+        })
+        .collect()
+}
+
+/// 生成计数链规则（每个地址先上行后下行）
+fn build_account_rules(interface: &str, hosts: &[(String, String)]) -> Vec<Vec<String>> {
+    let mut rules = Vec::new();
+    for (addr, _) in hosts {
+        for (dir, flag) in [("-i", "-s"), ("-o", "-d")] {
+            rules.push(
+                [dir, interface, flag, addr.as_str(), "-j", "RETURN"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            );
+        }
+    }
+    rules
+}
+
+/// 解析 `iptables -nvxL` 计数输出
+///
+/// 源地址不为任意地址的是上行规则，否则按目的地址计为下行。ip6tables 的 opt
+/// 列为空，因此只按首尾字段取计数和地址。
+fn parse_counters(content: &str) -> Vec<(Direction, String, u64)> {
+    content
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            let bytes: u64 = fields[1].parse().ok()?;
+            let source = strip_host_mask(fields[fields.len() - 2]);
+            let destination = strip_host_mask(fields[fields.len() - 1]);
+            if !source.ends_with("/0") {
+                Some((Direction::Upload, source.to_string(), bytes))
+            } else if !destination.ends_with("/0") {
+                Some((Direction::Download, destination.to_string(), bytes))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn strip_host_mask(addr: &str) -> &str {
+    addr.strip_suffix("/32")
+        .or_else(|| addr.strip_suffix("/128"))
+        .unwrap_or(addr)
+}
+
+/// 计数链状态（按协议族记录已下发的地址 → MAC）
+#[derive(Default)]
+struct Accounting {
+    /// 上一周期是否开启统计（None 表示尚未采样）
+    enabled: Option<bool>,
+    interface: String,
+    installed: [Vec<(String, String)>; 2],
+    error: [Option<String>; 2],
+}
+
+impl Accounting {
+    /// 开启或关闭统计，状态变化时切换 SFP 并卸载计数链
+    ///
+    /// # Returns
+    /// 是否开启统计
+    fn set_enabled(&mut self, enabled: bool) -> bool {
+        if self.enabled == Some(enabled) {
+            return enabled;
+        }
+        crate::usb_switch::inhibit_sfp(crate::usb_switch::SfpInhibitor::Accounting, enabled);
+        if !enabled {
+            for ipv6 in [false, true] {
+                remove_account_chain(ipv6);
+            }
+            info!("Client accounting disabled");
+        }
+        *self = Accounting { enabled: Some(enabled), ..Default::default() };
+        enabled
+    }
+
+    /// 读取并清零计数器，按需重建计数链
+    ///
+    /// # Returns
+    /// MAC → 本周期流量增量
+    fn collect(&mut self, interface: &str, wanted: [Vec<(String, String)>; 2]) -> HashMap<String, Delta> {
+        let mut deltas: HashMap<String, Delta> = HashMap::new();
+        let interface_changed = self.interface != interface;
+        self.interface = interface.to_string();
+
+        for (family, wanted) in wanted.into_iter().enumerate() {
+            let ipv6 = family == 1;
+            if !self.installed[family].is_empty() {
+                if let Ok(output) = run_mangle(ipv6, &["-nvxL", ACCOUNT_CHAIN, "-Z"]) {
+                    for (direction, addr, bytes) in parse_counters(&output) {
+                        let Some((_, mac)) = self.installed[family].iter().find(|(a, _)| *a == addr) else {
+                            continue;
+                        };
+                        let delta = deltas.entry(mac.clone()).or_default();
+                        match direction {
+                            Direction::Download => delta.download += bytes,
+                            Direction::Upload => delta.upload += bytes,
+                        }
+                    }
+                }
+            }
+
+            let intact = run_mangle(ipv6, &["-C", "FORWARD", "-j", ACCOUNT_CHAIN]).is_ok();
+            if interface_changed || !intact || wanted != self.installed[family] {
+                let result = apply_account_chain(ipv6, interface, &wanted);
+                if let Err(e) = &result {
+                    if self.error[family].as_ref() != Some(e) {
+                        warn!(ipv6, error = %e, "Failed to apply client accounting chain");
+                    }
+                }
+                self.error[family] = result.err();
+                self.installed[family] = wanted;
+            }
+        }
+        deltas
+    }
+}
+
+/// mangle 表中执行 iptables 命令
+fn run_mangle<S: AsRef<str>>(ipv6: bool, args: &[S]) -> Result<String, String> {
+    let mut full = vec!["-t".to_string(), "mangle".to_string()];
+    full.extend(args.iter().map(|a| a.as_ref().to_string()));
+    run_iptables(ipv6, &full)
+}
+
+/// 下发一个协议族的计数链
+fn apply_account_chain(ipv6: bool, interface: &str, hosts: &[(String, String)]) -> Result<(), String> {
+    let _ = run_mangle(ipv6, &["-N", ACCOUNT_CHAIN]);
+    run_mangle(ipv6, &["-F", ACCOUNT_CHAIN])?;
+    for args in build_account_rules(interface, hosts) {
+        let mut full = vec!["-A".to_string(), ACCOUNT_CHAIN.to_string()];
+        full.extend(args);
+        run_mangle(ipv6, &full)?;
+    }
+    if run_mangle(ipv6, &["-C", "FORWARD", "-j", ACCOUNT_CHAIN]).is_err() {
+        run_mangle(ipv6, &["-I", "FORWARD", "1", "-j", ACCOUNT_CHAIN])?;
+    }
+    Ok(())
+}
+
+/// 卸载并删除一个协议族的计数链
+fn remove_account_chain(ipv6: bool) {
+    while run_mangle(ipv6, &["-D", "FORWARD", "-j", ACCOUNT_CHAIN]).is_ok() {}
+    let _ = run_mangle(ipv6, &["-F", ACCOUNT_CHAIN]);
+    let _ = run_mangle(ipv6, &["-X", ACCOUNT_CHAIN]);
+}
+
+/// 本地时区日期 YYYY-MM-DD
+fn local_date(offset_days: i64) -> String {
+    (chrono::Local::now() - chrono::Duration::days(offset_days))
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// 客户端发现与流量统计主循环
+pub async fn run(config_manager: Arc<ConfigManager>, db: Arc<Database>) {
+    info!(interval = SAMPLE_INTERVAL_SECS, "Client accounting started");
+
+    // 恢复今日已统计的流量
+    let mut today = local_date(0);
+    if let Ok(mut clients) = CLIENTS.lock() {
+        for record in db.get_client_traffic_by_date(&today).unwrap_or_default() {
+            let client = clients.entry(record.mac.clone()).or_default();
+            client.mac = record.mac;
+            client.today_download_bytes = record.download_bytes;
+            client.today_upload_bytes = record.upload_bytes;
+        }
+    }
+
+    let accounting = Arc::new(Mutex::new(Accounting::default()));
+    let mut last_sample = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_secs(SAMPLE_INTERVAL_SECS));
+
+    loop {
+        ticker.tick().await;
+
+        let lan = config_manager.get_lan();
+        let interface = lan.interface;
+        let accounting_enabled = lan.client_accounting;
+        let now = chrono::Utc::now();
+        let leases = db.get_dhcp_leases().unwrap_or_default();
+
+        let accounting_clone = Arc::clone(&accounting);
+        let leases_clone = leases.clone();
+        let sampled = task::spawn_blocking(move || {
+            let arp = crate::lan::read_arp_table(&interface);
+            let neighbors = read_neighbors_v6(&interface);
+
+            // 在线的 ARP 条目优先，其次是未过期的租约
+            let mut ipv4 = arp.clone();
+            for lease in &leases_clone {
+                if lease.expires_at > now.timestamp() && !ipv4.iter().any(|(ip, _)| *ip == lease.ip) {
+                    ipv4.push((lease.ip.clone(), lease.mac.clone()));
+                }
+            }
+            ipv4.sort();
+            let mut ipv6 = neighbors.clone();
+            ipv6.sort();
+
+            let deltas = match accounting_clone.lock() {
+                Ok(mut accounting) => {
+                    if accounting.set_enabled(accounting_enabled) {
+                        accounting.collect(&interface, [ipv4.clone(), ipv6])
+                    } else {
+                        HashMap::new()
+                    }
+                }
+                Err(_) => HashMap::new(),
+            };
+            (arp, neighbors, ipv4, deltas)
+        })
+        .await;
+        let Ok((arp, neighbors, ipv4, deltas)) = sampled else {
+            continue;
+        };
+
+        let elapsed = last_sample.elapsed().as_secs_f64().max(1.0);
+        last_sample = Instant::now();
+
+        // 跨日：清零今日统计并清理过期记录
+        let date = local_date(0);
+        let new_day = date != today;
+        if new_day {
+            today = date;
+            if let Err(e) = db.cleanup_client_traffic(&local_date(HISTORY_RETENTION_DAYS)) {
+                warn!(error = %e, "Failed to cleanup client traffic history");
+            }
+        }
+
+        for (mac, delta) in &deltas {
+            if *delta == Delta::default() {
+                continue;
+            }
+            if let Err(e) = db.add_client_traffic(mac, &today, delta.download as i64, delta.upload as i64) {
+                warn!(mac = %mac, error = %e, "Failed to save client traffic");
+            }
+        }
+
+        let Ok(mut clients) = CLIENTS.lock() else {
+            continue;
+        };
+        let seen = now.to_rfc3339();
+        let macs: Vec<String> = ipv4
+            .iter()
+            .chain(neighbors.iter())
+            .map(|(_, mac)| mac.clone())
+            .chain(deltas.keys().cloned())
+            .collect();
+        for mac in macs {
+            clients.entry(mac.clone()).or_insert_with(|| ClientInfo { mac, ..Default::default() });
+        }
+        for (mac, client) in clients.iter_mut() {
+            if new_day {
+                client.today_download_bytes = 0;
+                client.today_upload_bytes = 0;
+            }
+            let delta = deltas.get(mac).copied().unwrap_or_default();
+            client.download_rate = (delta.download as f64 / elapsed) as u64;
+            client.upload_rate = (delta.upload as f64 / elapsed) as u64;
+            client.today_download_bytes += delta.download as i64;
+            client.today_upload_bytes += delta.upload as i64;
+
+            client.ipv4 = ipv4.iter().find(|(_, m)| m == mac).map(|(ip, _)| ip.clone());
+            client.ipv6 = neighbors.iter().filter(|(_, m)| m == mac).map(|(ip, _)| ip.clone()).collect();
+            client.connected = arp.iter().chain(neighbors.iter()).any(|(_, m)| m == mac);
+            if let Some(lease) = leases.iter().find(|l| l.mac == *mac) {
+                client.hostname = lease.hostname.clone();
+            }
+            if client.connected || delta != Delta::default() {
+                client.last_seen = Some(seen.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_neighbors_v6() {
+        let output = "\
+2409:8a00:1:2::10 lladdr AA:BB:CC:DD:EE:01 REACHABLE
+fe80::a8bb:ccff:fedd:ee01 lladdr aa:bb:cc:dd:ee:01 STALE
+2409:8a00:1:2::11 lladdr aa:bb:cc:dd:ee:02 router STALE
+2409:8a00:1:2::12 FAILED
+ff02::1 lladdr 33:33:00:00:00:01 NOARP
+";
+        assert_eq!(
+            parse_neighbors_v6(output),
+            vec![
+                ("2409:8a00:1:2::10".to_string(), "aa:bb:cc:dd:ee:01".to_string()),
+                ("2409:8a00:1:2::11".to_string(), "aa:bb:cc:dd:ee:02".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_account_rules() {
+        let hosts = vec![("192.168.66.100".to_string(), "aa:bb:cc:dd:ee:01".to_string())];
+        let rules = build_account_rules("usb0", &hosts);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].join(" "), "-i usb0 -s 192.168.66.100 -j RETURN");
+        assert_eq!(rules[1].join(" "), "-o usb0 -d 192.168.66.100 -j RETURN");
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        let v4 = "\
+Chain UDX_ACCOUNT (1 references)
+    pkts      bytes target     prot opt in     out     source               destination
+      12     3456 RETURN     all  --  usb0   *       192.168.66.100       0.0.0.0/0
+      20    78901 RETURN     all  --  *      usb0    0.0.0.0/0            192.168.66.100
+";
+        assert_eq!(
+            parse_counters(v4),
+            vec![
+                (Direction::Upload, "192.168.66.100".to_string(), 3456),
+                (Direction::Download, "192.168.66.100".to_string(), 78901),
+            ]
+        );
+
+        let v6 = "\
+Chain UDX_ACCOUNT (1 references)
+    pkts      bytes target     prot opt in     out     source               destination
+       3      240 RETURN     all      usb0   *       2409:8a00:1:2::10/128  ::/0
+       4      512 RETURN     all      *      usb0    ::/0                 2409:8a00:1:2::10/128
+";
+        assert_eq!(
+            parse_counters(v6),
+            vec![
+                (Direction::Upload, "2409:8a00:1:2::10".to_string(), 240),
+                (Direction::Download, "2409:8a00:1:2::10".to_string(), 512),
+            ]
+        );
+    }
+}
@@ -19,6 +19,8 @@ use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use tracing::{info, warn};
 
+use crate::models::RadioMode;
+
 /// Webhook 配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebhookConfig {
@@ -34,6 +36,14 @@ pub struct WebhookConfig {
     pub sms_template: String,  // 短信 payload 模板
     #[serde(default = "default_call_template")]
     pub call_template: String,  // 通话 payload 模板
+    #[serde(default = "default_true")]
+    pub forward_events: bool,  // 是否转发设备事件（过温告警等）
+    #[serde(default = "default_event_template")]
+    pub event_template: String,  // 设备事件 payload 模板
+}
+
+fn default_true() -> bool {
+    true
 }
 
 /// 默认短信模板 (飞书机器人格式)
@@ -56,6 +66,16 @@ fn default_call_template() -> String {
 }"#.to_string()
 }
 
+/// 默认设备事件模板 (飞书机器人格式)
+fn default_event_template() -> String {
+    r#"{
+  "msg_type": "text",
+  "content": {
+    "text": "⚠️ 设备事件\n事件: {{event}}\n详情: {{message}}\n时间: {{timestamp}}"
+  }
+}"#.to_string()
+}
+
 impl Default for WebhookConfig {
     fn default() -> Self {
         Self {
@@ -67,6 +87,726 @@ impl Default for WebhookConfig {
             secret: String::new(),
             sms_template: default_sms_template(),
             call_template: default_call_template(),
+            forward_events: true,
+            event_template: default_event_template(),
+        }
+    }
+}
+
+/// 温度监控配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,         // 采样间隔（秒）
+    pub warning_temp: f64,          // 告警阈值（℃）
+    pub critical_temp: f64,         // 过温保护阈值（℃）
+    pub recovery_temp: f64,         // 恢复阈值（℃），降到此温度以下才解除告警
+    pub zones: Vec<String>,         // 参与判断的传感器（zone 或 type），为空表示全部
+    pub action_force_lte: bool,     // 过温时强制 LTE only
+    pub action_single_carrier: bool, // 过温时锁定主小区频段，减少载波聚合
+    pub action_webhook: bool,       // 告警/恢复时发送 Webhook 事件
+    pub history_retention_hours: u32, // 温度历史保留时长（小时）
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 60,
+            warning_temp: 75.0,
+            critical_temp: 85.0,
+            recovery_temp: 70.0,
+            zones: Vec::new(),
+            action_force_lte: false,
+            action_single_carrier: false,
+            action_webhook: false,
+            history_retention_hours: 72,
+        }
+    }
+}
+
+/// 锁定方案中的小区锁定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileCellLock {
+    pub rat: u8,    // 12=LTE, 16=NR
+    pub arfcn: u32,
+    pub pci: u16,
+}
+
+/// 锁定配置方案（频段锁定 + 射频模式 + 可选小区锁定）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockProfile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub lte_fdd_bands: Vec<u8>,
+    #[serde(default)]
+    pub lte_tdd_bands: Vec<u8>,
+    #[serde(default)]
+    pub nr_fdd_bands: Vec<u8>,
+    #[serde(default)]
+    pub nr_tdd_bands: Vec<u8>,
+    #[serde(default)]
+    pub radio_mode: Option<RadioMode>,      // 为空表示不修改射频模式
+    #[serde(default)]
+    pub cell_lock: Option<ProfileCellLock>, // 为空表示解除小区锁定
+}
+
+/// 锁定配置方案列表
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct LockProfilesConfig {
+    pub profiles: Vec<LockProfile>,
+    pub active: Option<String>,     // 当前生效的方案名称，手动修改锁定后清空
+    pub applied_at: Option<String>, // 方案应用时间（RFC3339）
+}
+
+/// 在线定位服务类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeolocationProviderKind {
+    Google,
+    UnwiredLabs,
+    OpenCellId,
+}
+
+/// 在线定位服务配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeolocationProvider {
+    pub kind: GeolocationProviderKind,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub api_key: String,
+    #[serde(default)]
+    pub url: String,                // 自定义接口地址，为空使用官方地址
+    #[serde(default = "default_geolocation_interval")]
+    pub min_interval_secs: u64,     // 两次请求的最小间隔（秒），未到间隔时跳过该服务
+}
+
+fn default_geolocation_interval() -> u64 {
+    30
+}
+
+/// 在线定位配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeolocationConfig {
+    pub providers: Vec<GeolocationProvider>, // 按顺序尝试，失败或限速时使用下一个
+    pub cache_ttl_secs: u64,        // 同一组小区的定位结果缓存时长（秒）
+    pub timeout_secs: u64,          // 单次请求超时（秒）
+}
+
+impl Default for GeolocationConfig {
+    fn default() -> Self {
+        Self {
+            providers: Vec::new(),
+            cache_ttl_secs: 86400,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// APN 条目（字段与 Android apns-conf 的 <apn> 属性一致）
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct ApnEntry {
+    pub carrier: String,
+    pub mcc: String,
+    pub mnc: String,
+    pub apn: String,
+    #[serde(rename = "type")]
+    pub apn_type: String,           // 逗号分隔，如 "default,supl"；为空视为数据连接
+    pub protocol: String,           // IP / IPV6 / IPV4V6
+    pub roaming_protocol: String,   // 漫游时使用的协议，为空同 protocol
+    pub user: String,
+    pub password: String,
+    pub authtype: Option<u8>,       // 0=none 1=PAP 2=CHAP 3=PAP/CHAP
+    pub mtu: Option<u32>,
+    pub mvno_type: String,          // spn / iccid / imsi，为空表示不区分虚拟运营商
+    pub mvno_match_data: String,    // spn 全匹配；iccid/imsi 前缀匹配（imsi 支持 x 通配）
+}
+
+/// 最近一次自动配置的 APN
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProvisionedApn {
+    pub iccid: String,
+    pub carrier: String,
+    pub apn: String,
+    pub source: String,             // custom / builtin
+    pub mtu: Option<u32>,
+    pub provisioned_at: String,
+}
+
+/// APN 自动配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApnConfig {
+    pub auto_provision: bool,       // APN 为空或更换 SIM 卡时按数据库自动配置
+    pub entries: Vec<ApnEntry>,     // 用户自定义条目，优先于内置数据库
+    pub provisioned: Option<ProvisionedApn>,
+    pub watchdog_context: Option<String>, // watchdog 保持激活的 context 路径，为空时自动选择
+}
+
+impl Default for ApnConfig {
+    fn default() -> Self {
+        Self {
+            auto_provision: true,
+            entries: Vec::new(),
+            provisioned: None,
+            watchdog_context: None,
+        }
+    }
+}
+
+/// 数据连接恢复动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryAction {
+    #[default]
+    Reactivate,     // 重新激活 context
+    AirplaneToggle, // 通过飞行模式切换 Modem Online
+    Reregister,     // 重新自动注册运营商
+    RestartOfono,   // 重启 ofono 服务
+    Reboot,         // 重启系统
+}
+
+impl RecoveryAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryAction::Reactivate => "reactivate",
+            RecoveryAction::AirplaneToggle => "airplane_toggle",
+            RecoveryAction::Reregister => "reregister",
+            RecoveryAction::RestartOfono => "restart_ofono",
+            RecoveryAction::Reboot => "reboot",
+        }
+    }
+}
+
+/// 重启 ofono 的方式（固定命令，不接受任意 shell）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OfonoRestartMethod {
+    #[default]
+    Systemctl,  // systemctl restart ofono
+    InitScript, // /etc/init.d/ofono restart
+}
+
+impl OfonoRestartMethod {
+    /// 命令及参数
+    pub fn argv(&self) -> &'static [&'static str] {
+        match self {
+            OfonoRestartMethod::Systemctl => &["systemctl", "restart", "ofono"],
+            OfonoRestartMethod::InitScript => &["/etc/init.d/ofono", "restart"],
+        }
+    }
+}
+
+/// 恢复阶梯中的一步
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecoveryStep {
+    pub action: RecoveryAction,
+    pub enabled: bool,
+    pub failure_threshold: u32, // 连续失败检查次数达到该值后才执行
+    pub max_attempts: u32,      // 本步最多尝试次数，用完后升级到下一步
+    pub backoff_secs: u64,      // 每次尝试后的等待时间，同一步重复尝试时翻倍
+}
+
+impl Default for RecoveryStep {
+    fn default() -> Self {
+        Self::new(RecoveryAction::Reactivate, 1, 3, 10)
+    }
+}
+
+impl RecoveryStep {
+    fn new(action: RecoveryAction, failure_threshold: u32, max_attempts: u32, backoff_secs: u64) -> Self {
+        Self {
+            action,
+            enabled: true,
+            failure_threshold,
+            max_attempts,
+            backoff_secs,
+        }
+    }
+}
+
+/// 连通性探测类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    #[default]
+    Icmp, // ping 目标地址
+    Dns,  // 通过数据网络接口向运营商 DNS 查询域名
+    Http, // 请求 URL，期望返回 204
+}
+
+/// 连通性探测目标
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProbeTarget {
+    pub kind: ProbeKind,
+    pub target: String, // IP 地址 / 域名 / URL
+}
+
+/// 连通性探测配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,      // 探测间隔（秒）
+    pub timeout_secs: u64,       // 单个目标超时（秒）
+    pub failure_threshold: u32,  // 连续几轮全部目标失败后视为断开
+    pub targets: Vec<ProbeTarget>, // 任一目标成功即视为连通
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 30,
+            timeout_secs: 5,
+            failure_threshold: 3,
+            targets: vec![
+                ProbeTarget {
+                    kind: ProbeKind::Icmp,
+                    target: "223.5.5.5".to_string(),
+                },
+                ProbeTarget {
+                    kind: ProbeKind::Dns,
+                    target: "www.baidu.com".to_string(),
+                },
+                ProbeTarget {
+                    kind: ProbeKind::Http,
+                    target: "http://connect.rom.miui.com/generate_204".to_string(),
+                },
+            ],
+        }
+    }
+}
+
+/// 数据连接 watchdog 配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,               // 关闭后只记录数据会话，不做恢复
+    pub interval_secs: u64,          // 检查间隔（秒）
+    pub steps: Vec<RecoveryStep>,    // 恢复阶梯，按顺序升级
+    pub max_backoff_secs: u64,       // 退避时间上限（秒）
+    pub max_reboots_per_day: u32,    // 24 小时内最多重启系统次数
+    pub airplane_toggle_secs: u64,   // 飞行模式切换时保持离线的时间（秒）
+    pub ofono_restart_method: OfonoRestartMethod, // 重启 ofono 的方式
+    pub probe: ProbeConfig,          // context 激活时的端到端连通性探测
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 5,
+            steps: vec![
+                RecoveryStep::new(RecoveryAction::Reactivate, 1, 3, 10),
+                RecoveryStep::new(RecoveryAction::AirplaneToggle, 6, 2, 30),
+                RecoveryStep::new(RecoveryAction::Reregister, 12, 2, 60),
+                RecoveryStep::new(RecoveryAction::RestartOfono, 24, 2, 120),
+                RecoveryStep::new(RecoveryAction::Reboot, 120, 1, 600),
+            ],
+            max_backoff_secs: 1800,
+            max_reboots_per_day: 1,
+            airplane_toggle_secs: 5,
+            ofono_restart_method: OfonoRestartMethod::Systemctl,
+            probe: ProbeConfig::default(),
+        }
+    }
+}
+
+/// 防火墙动作（规则动作和默认策略）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallAction {
+    #[default]
+    Accept,
+    Drop,
+    Reject,
+}
+
+impl FirewallAction {
+    /// iptables 目标
+    pub fn target(&self) -> &'static str {
+        match self {
+            FirewallAction::Accept => "ACCEPT",
+            FirewallAction::Drop => "DROP",
+            FirewallAction::Reject => "REJECT",
+        }
+    }
+}
+
+/// 防火墙区域
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallZone {
+    #[default]
+    Wan, // 蜂窝数据接口
+    Lan, // USB 网卡
+}
+
+/// 防火墙规则所在链
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallChain {
+    #[default]
+    Input,   // 访问设备本身
+    Forward, // 经设备转发
+}
+
+/// 规则适用的 IP 协议族
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    #[default]
+    Both,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    pub fn includes(&self, ipv6: bool) -> bool {
+        match self {
+            IpFamily::Both => true,
+            IpFamily::Ipv4 => !ipv6,
+            IpFamily::Ipv6 => ipv6,
+        }
+    }
+}
+
+/// 防火墙规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirewallRule {
+    pub name: String,           // 规则名称（唯一）
+    pub enabled: bool,
+    pub zone: FirewallZone,     // 入站区域（按入接口匹配）
+    pub chain: FirewallChain,
+    pub family: IpFamily,
+    pub protocol: String,       // all / tcp / udp / icmp
+    pub source: String,         // 源地址或网段，为空表示任意
+    pub destination: String,    // 目的地址或网段，为空表示任意
+    pub port: String,           // 目的端口或范围（如 22、8000:8100），仅 tcp/udp
+    pub action: FirewallAction,
+}
+
+impl Default for FirewallRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            zone: FirewallZone::Wan,
+            chain: FirewallChain::Input,
+            family: IpFamily::Both,
+            protocol: "all".to_string(),
+            source: String::new(),
+            destination: String::new(),
+            port: String::new(),
+            action: FirewallAction::Accept,
+        }
+    }
+}
+
+/// 端口转发协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardProtocol {
+    #[default]
+    Tcp,
+    Udp,
+    Both,
+}
+
+impl ForwardProtocol {
+    /// 对应的 iptables 协议列表
+    pub fn protocols(&self) -> &'static [&'static str] {
+        match self {
+            ForwardProtocol::Tcp => &["tcp"],
+            ForwardProtocol::Udp => &["udp"],
+            ForwardProtocol::Both => &["tcp", "udp"],
+        }
+    }
+}
+
+/// 端口转发规则（WAN → LAN 主机，仅 IPv4）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortForward {
+    pub name: String,            // 规则名称（唯一）
+    pub enabled: bool,
+    pub protocol: ForwardProtocol,
+    pub external_port: String,   // 外部端口或范围（如 8080、8000:8100）
+    pub internal_host: String,   // LAN 主机 IPv4 地址
+    pub internal_port: String,   // 内部端口或范围，为空表示与外部端口相同
+    pub source: String,          // 允许的来源地址或网段，为空表示任意
+}
+
+impl Default for PortForward {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            protocol: ForwardProtocol::Tcp,
+            external_port: String::new(),
+            internal_host: String::new(),
+            internal_port: String::new(),
+            source: String::new(),
+        }
+    }
+}
+
+/// TTL / Hop Limit 修改方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TtlMode {
+    #[default]
+    Set,       // 设为固定值
+    Increment, // 在转发递减后的基础上增加
+}
+
+/// 转发流量 TTL / Hop Limit 规范化配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TtlConfig {
+    pub enabled: bool,
+    pub mode: TtlMode,
+    pub value: u8,          // set 时为目标值，increment 时为增加量
+    pub ipv6: bool,         // 同时修改 IPv6 Hop Limit
+}
+
+impl Default for TtlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: TtlMode::Set,
+            value: 64,
+            ipv6: true,
+        }
+    }
+}
+
+/// 防火墙配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirewallConfig {
+    pub enabled: bool,                // 是否启用托管规则链
+    pub lan_interface: String,        // LAN 接口（USB 网卡）
+    pub wan_interface: String,        // WAN 接口，支持 iptables 通配（如 sipa_eth+）
+    pub lan_input: FirewallAction,    // LAN 访问设备的默认策略
+    pub wan_input: FirewallAction,    // WAN 访问设备的默认策略
+    pub lan_forward: FirewallAction,  // LAN → WAN 转发的默认策略
+    pub wan_forward: FirewallAction,  // WAN → LAN 转发的默认策略（已建立的连接始终放行），默认放行以保持升级前的转发行为
+    pub rules: Vec<FirewallRule>,     // 按顺序匹配，先于默认策略
+    pub remove_harmful_rules: bool,   // 删除系统注入的已知有害规则（整体丢弃 LAN / WAN 接口流量的 DROP/REJECT）
+    pub port_forwards: Vec<PortForward>, // 端口转发规则
+    pub dmz_host: String,             // DMZ 主机（未匹配端口转发的入站连接全部转发到该主机），为空表示关闭
+    pub ttl: TtlConfig,               // 经 WAN 接口转发出去的报文 TTL / Hop Limit
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lan_interface: "usb0".to_string(),
+            wan_interface: "sipa_eth+".to_string(),
+            lan_input: FirewallAction::Accept,
+            wan_input: FirewallAction::Accept,
+            lan_forward: FirewallAction::Accept,
+            wan_forward: FirewallAction::Accept,
+            rules: Vec::new(),
+            remove_harmful_rules: true,
+            port_forwards: Vec::new(),
+            dmz_host: String::new(),
+            ttl: TtlConfig::default(),
+        }
+    }
+}
+
+/// LAN 侧 IPv6 模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6LanMode {
+    #[default]
+    Disabled, // LAN 侧只有 IPv4
+    Relay,    // 在 LAN 上通告运营商分配的 /64 前缀（SLAAC），并将该前缀路由到 LAN
+}
+
+/// LAN 侧 IPv6 配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ipv6LanConfig {
+    pub mode: Ipv6LanMode,
+    pub ra_interval_secs: u32,      // RA 周期通告间隔
+    pub dhcpv6: bool,               // 无状态 DHCPv6（RA 置 O 标志，下发 DNS）
+    pub ndp_proxy: bool,            // 在 WAN 接口为 LAN 主机代理邻居发现（运营商要求地址在链路上可达时启用）
+    pub dns_servers: Vec<String>,   // 下发的 IPv6 DNS，为空时使用运营商下发的 DNS
+}
+
+impl Default for Ipv6LanConfig {
+    fn default() -> Self {
+        Self {
+            mode: Ipv6LanMode::Disabled,
+            ra_interval_secs: 60,
+            dhcpv6: true,
+            ndp_proxy: false,
+            dns_servers: Vec::new(),
+        }
+    }
+}
+
+/// USB 网卡（LAN）配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanConfig {
+    pub interface: String,          // LAN 接口
+    pub ip_address: String,         // 设备在 LAN 侧的 IPv4 地址（网关）
+    pub prefix_len: u8,             // 子网前缀长度
+    pub mac_address: String,        // USB 网卡 MAC 地址
+    pub dhcp_enabled: bool,         // 启用内置 DHCP 服务（关闭时沿用 connman gadget tethering）
+    pub dhcp_start: String,         // 地址池起始
+    pub dhcp_end: String,           // 地址池结束
+    pub lease_time_secs: u32,       // 租期
+    pub dns_servers: Vec<String>,   // 下发的 DNS 服务器，为空时下发运营商 DNS
+    pub ipv6: Ipv6LanConfig,        // LAN 侧 IPv6 直通
+    pub client_accounting: bool,    // 按客户端流量统计（开启时关闭 SFP 硬件加速），默认关闭
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            interface: "usb0".to_string(),
+            ip_address: "192.168.66.1".to_string(),
+            prefix_len: 24,
+            mac_address: "CC:E8:AC:C0:00:00".to_string(),
+            dhcp_enabled: false,
+            dhcp_start: "192.168.66.100".to_string(),
+            dhcp_end: "192.168.66.200".to_string(),
+            lease_time_secs: 43200,
+            dns_servers: Vec::new(),
+            ipv6: Ipv6LanConfig::default(),
+            client_accounting: false,
+        }
+    }
+}
+
+/// 单个客户端的限速（按 MAC 匹配，IPv4 / IPv6 均生效）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientLimit {
+    pub mac: String,                // 客户端 MAC（唯一）
+    pub name: String,               // 备注名称
+    pub enabled: bool,
+    pub download_kbps: u32,         // 下行限速（kbit/s），0 表示不限
+    pub upload_kbps: u32,           // 上行限速（kbit/s），0 表示不限
+}
+
+impl Default for ClientLimit {
+    fn default() -> Self {
+        Self {
+            mac: String::new(),
+            name: String::new(),
+            enabled: true,
+            download_kbps: 0,
+            upload_kbps: 0,
+        }
+    }
+}
+
+/// LAN 接口限速配置（tc HTB）
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ShapingConfig {
+    pub enabled: bool,
+    pub download_kbps: u32,         // LAN 接口总下行限速（kbit/s），0 表示不限
+    pub upload_kbps: u32,           // LAN 接口总上行限速（kbit/s），0 表示不限
+    pub clients: Vec<ClientLimit>,  // 按客户端限速，受总限速约束
+}
+
+/// DDNS 服务商
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DdnsProvider {
+    #[default]
+    Custom,     // 自定义 HTTP GET / POST 模板
+    Cloudflare,
+    Duckdns,
+    Dnspod,
+    Aliyun,
+}
+
+impl DdnsProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DdnsProvider::Custom => "custom",
+            DdnsProvider::Cloudflare => "cloudflare",
+            DdnsProvider::Duckdns => "duckdns",
+            DdnsProvider::Dnspod => "dnspod",
+            DdnsProvider::Aliyun => "aliyun",
+        }
+    }
+}
+
+/// 自定义 DDNS 请求方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DdnsMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+/// 自定义 DDNS 请求模板
+///
+/// url / body / headers 支持 {{ip}}、{{type}}（A / AAAA）、{{domain}}、{{ipv4}}、{{ipv6}}、
+/// {{key_id}}、{{secret}} 变量
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct DdnsCustomRequest {
+    pub method: DdnsMethod,
+    pub url: String,
+    pub body: String,                       // POST 请求体
+    pub headers: HashMap<String, String>,
+    pub success_keyword: String,            // 响应需包含的内容，为空时只检查 HTTP 状态码
+}
+
+/// 动态域名配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DdnsConfig {
+    pub enabled: bool,
+    pub provider: DdnsProvider,
+    pub domain: String,             // 完整域名（如 home.example.com，DuckDNS 可只填子域名）
+    pub zone: String,               // Cloudflare 为 Zone ID；DNSPod / 阿里云为主域名，为空时取域名后两段
+    pub key_id: String,             // DNSPod ID / 阿里云 AccessKey ID
+    pub secret: String,             // Cloudflare API Token / DuckDNS Token / DNSPod Token / 阿里云 AccessKey Secret
+    pub ipv4: bool,                 // 更新 A 记录（数据连接 IPv4 位于运营商 NAT 后时跳过）
+    pub ipv6: bool,                 // 更新 AAAA 记录
+    pub ttl: u32,
+    pub check_interval_secs: u64,   // 地址检查间隔
+    pub force_update_hours: u64,    // 地址未变化时强制更新的间隔，0 表示不强制
+    pub custom: DdnsCustomRequest,
+}
+
+impl Default for DdnsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: DdnsProvider::Custom,
+            domain: String::new(),
+            zone: String::new(),
+            key_id: String::new(),
+            secret: String::new(),
+            ipv4: true,
+            ipv6: true,
+            ttl: 600,
+            check_interval_secs: 60,
+            force_update_hours: 24,
+            custom: DdnsCustomRequest::default(),
         }
     }
 }
@@ -76,6 +816,24 @@ impl Default for WebhookConfig {
 pub struct AppConfig {
     #[serde(default)]
     pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub thermal: ThermalConfig,
+    #[serde(default)]
+    pub lock_profiles: LockProfilesConfig,
+    #[serde(default)]
+    pub geolocation: GeolocationConfig,
+    #[serde(default)]
+    pub apn: ApnConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub firewall: FirewallConfig,
+    #[serde(default)]
+    pub lan: LanConfig,
+    #[serde(default)]
+    pub shaping: ShapingConfig,
+    #[serde(default)]
+    pub ddns: DdnsConfig,
     // 未来可以添加更多配置项
 }
 
@@ -142,6 +900,175 @@ impl ConfigManager {
         self.save()
     }
     
+    /// 获取温度监控配置
+    pub fn get_thermal(&self) -> ThermalConfig {
+        self.config.read().unwrap().thermal.clone()
+    }
+    
+    /// 更新温度监控配置
+    pub fn set_thermal(&self, thermal: ThermalConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.thermal = thermal;
+        }
+        self.save()
+    }
+    
+    /// 获取锁定配置方案
+    pub fn get_lock_profiles(&self) -> LockProfilesConfig {
+        self.config.read().unwrap().lock_profiles.clone()
+    }
+    
+    /// 更新锁定配置方案
+    pub fn set_lock_profiles(&self, lock_profiles: LockProfilesConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.lock_profiles = lock_profiles;
+        }
+        self.save()
+    }
+    
+    /// 清除当前生效的方案标记（手动修改锁定时调用）
+    pub fn clear_active_lock_profile(&self) {
+        let changed = {
+            let mut config = self.config.write().unwrap();
+            let changed = config.lock_profiles.active.is_some();
+            config.lock_profiles.active = None;
+            config.lock_profiles.applied_at = None;
+            changed
+        };
+        if changed {
+            let _ = self.save();
+        }
+    }
+    
+    /// 获取在线定位配置
+    pub fn get_geolocation(&self) -> GeolocationConfig {
+        self.config.read().unwrap().geolocation.clone()
+    }
+    
+    /// 更新在线定位配置
+    pub fn set_geolocation(&self, geolocation: GeolocationConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.geolocation = geolocation;
+        }
+        self.save()
+    }
+    
+    /// 获取 APN 自动配置
+    pub fn get_apn(&self) -> ApnConfig {
+        self.config.read().unwrap().apn.clone()
+    }
+    
+    /// 更新 APN 自动配置（保留最近一次自动配置记录和 watchdog context）
+    pub fn set_apn(&self, apn: ApnConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            let provisioned = config.apn.provisioned.take();
+            let watchdog_context = config.apn.watchdog_context.take();
+            config.apn = ApnConfig {
+                provisioned,
+                watchdog_context,
+                ..apn
+            };
+        }
+        self.save()
+    }
+    
+    /// 获取手动指定的 watchdog context，未指定时返回 None（自动选择）
+    pub fn get_watchdog_context(&self) -> Option<String> {
+        self.config.read().unwrap().apn.watchdog_context.clone().filter(|p| !p.is_empty())
+    }
+    
+    /// 更新 watchdog 保持激活的 context
+    pub fn set_watchdog_context(&self, context_path: Option<String>) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.apn.watchdog_context = context_path.filter(|p| !p.is_empty());
+        }
+        self.save()
+    }
+    
+    /// 记录自动配置结果（watchdog 据此判断 SIM 卡是否更换）
+    pub fn set_provisioned_apn(&self, provisioned: ProvisionedApn) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.apn.provisioned = Some(provisioned);
+        }
+        self.save()
+    }
+    
+    /// 获取数据连接 watchdog 配置
+    pub fn get_watchdog(&self) -> WatchdogConfig {
+        self.config.read().unwrap().watchdog.clone()
+    }
+    
+    /// 更新数据连接 watchdog 配置
+    pub fn set_watchdog(&self, watchdog: WatchdogConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.watchdog = watchdog;
+        }
+        self.save()
+    }
+    
+    /// 获取防火墙配置
+    pub fn get_firewall(&self) -> FirewallConfig {
+        self.config.read().unwrap().firewall.clone()
+    }
+    
+    /// 更新防火墙配置
+    pub fn set_firewall(&self, firewall: FirewallConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.firewall = firewall;
+        }
+        self.save()
+    }
+    
+    /// 获取 LAN 配置
+    pub fn get_lan(&self) -> LanConfig {
+        self.config.read().unwrap().lan.clone()
+    }
+    
+    /// 更新 LAN 配置
+    pub fn set_lan(&self, lan: LanConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.lan = lan;
+        }
+        self.save()
+    }
+    
+    /// 获取限速配置
+    pub fn get_shaping(&self) -> ShapingConfig {
+        self.config.read().unwrap().shaping.clone()
+    }
+    
+    /// 更新限速配置
+    pub fn set_shaping(&self, shaping: ShapingConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.shaping = shaping;
+        }
+        self.save()
+    }
+    
+    /// 获取 DDNS 配置
+    pub fn get_ddns(&self) -> DdnsConfig {
+        self.config.read().unwrap().ddns.clone()
+    }
+    
+    /// 更新 DDNS 配置
+    pub fn set_ddns(&self, ddns: DdnsConfig) -> Result<(), String> {
+        {
+            let mut config = self.config.write().unwrap();
+            config.ddns = ddns;
+        }
+        self.save()
+    }
+    
     /// 更新整个配置
     #[allow(dead_code)]
     pub fn set(&self, config: AppConfig) -> Result<(), String> {
@@ -0,0 +1,498 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 10:12:36
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 10:12:36
+ * @FilePath: /udx710-backend/backend/src/data_session.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 数据会话记录模块
+//!
+//! 由 watchdog 驱动，记录每一次数据连接会话（起止时间、APN、IP、流量、结束原因），
+//! 并据此计算中断区间和每日可用率。
+//!
+//! 追踪器由 main.rs 创建并共享给 watchdog 和 API：主动断开数据连接之前
+//! （用户操作、watchdog 恢复动作）通过 [`DataSessionTracker::mark_end_reason`] 标记结束原因，
+//! 避免被计为网络侧中断。
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::db::{DataSessionRecord, Database};
+use crate::models::{DailyAvailability, DataContextSnapshot, DataOutage};
+use crate::utils::read_interface_stats;
+
+/// 进行中会话的流量写库间隔（秒），避免频繁写 Flash
+const PROGRESS_PERSIST_INTERVAL_SECS: u64 = 60;
+
+/// 预先标记的结束原因有效期（秒），超时未消费则丢弃
+const PENDING_REASON_TTL_SECS: u64 = 60;
+
+/// 会话记录保留天数
+const SESSION_RETENTION_DAYS: i64 = 90;
+
+/// 数据会话结束原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEndReason {
+    /// 网络注册丢失
+    NetworkDeregistered,
+    /// context 被去激活（网络侧或 modem 侧）
+    ContextDeactivated,
+    /// watchdog 恢复流程主动断开
+    WatchdogRestore,
+    /// 用户操作（关闭数据、修改 APN、飞行模式等）
+    UserAction,
+    /// 服务重启时遗留的未结束会话
+    ServiceRestart,
+}
+
+impl SessionEndReason {
+    /// 写入数据库的字符串表示
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionEndReason::NetworkDeregistered => "network_deregistered",
+            SessionEndReason::ContextDeactivated => "context_deactivated",
+            SessionEndReason::WatchdogRestore => "watchdog_restore",
+            SessionEndReason::UserAction => "user_action",
+            SessionEndReason::ServiceRestart => "service_restart",
+        }
+    }
+}
+
+/// 进行中的会话
+struct ActiveSession {
+    id: i64,
+    interface: String,
+    /// 上一次读取的接口计数器
+    last_counters: Option<(u64, u64)>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    last_persist: Instant,
+}
+
+impl ActiveSession {
+    /// 累加接口流量（兼容接口重建导致的计数器归零）
+    fn accumulate(&mut self) {
+        if self.interface.is_empty() {
+            return;
+        }
+        let Ok((rx, tx)) = read_interface_stats(&self.interface) else {
+            return;
+        };
+        if let Some((last_rx, last_tx)) = self.last_counters {
+            self.rx_bytes += if rx >= last_rx { rx - last_rx } else { rx };
+            self.tx_bytes += if tx >= last_tx { tx - last_tx } else { tx };
+        }
+        self.last_counters = Some((rx, tx));
+    }
+}
+
+/// 数据会话追踪器
+///
+/// 由 watchdog 在每次轮询时调用 [`DataSessionTracker::update`]。
+pub struct DataSessionTracker {
+    db: Arc<Database>,
+    current: Mutex<Option<ActiveSession>>,
+    /// 预先标记的结束原因及标记时间
+    pending_end_reason: Mutex<Option<(SessionEndReason, Instant)>>,
+}
+
+impl DataSessionTracker {
+    /// 创建追踪器，并关闭上次运行遗留的未结束会话
+    pub fn new(db: Arc<Database>) -> Self {
+        match db.close_stale_data_sessions(SessionEndReason::ServiceRestart.as_str()) {
+            Ok(count) if count > 0 => info!(count, "Data session: closed stale sessions"),
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Data session: failed to close stale sessions"),
+        }
+
+        let cutoff = (Utc::now() - Duration::days(SESSION_RETENTION_DAYS)).to_rfc3339();
+        if let Err(e) = db.cleanup_old_data_sessions(&cutoff) {
+            warn!(error = %e, "Data session: cleanup failed");
+        }
+
+        Self {
+            db,
+            current: Mutex::new(None),
+            pending_end_reason: Mutex::new(None),
+        }
+    }
+
+    /// 标记下一次会话结束的原因
+    ///
+    /// 在主动断开数据连接之前调用（如用户关闭数据、watchdog 恢复动作），
+    /// 检测到会话结束时优先使用该原因。
+    pub fn mark_end_reason(&self, reason: SessionEndReason) {
+        *self.pending_end_reason.lock().unwrap() = Some((reason, Instant::now()));
+    }
+
+    /// 取出并清除预先标记的结束原因（过期则忽略）
+    fn take_pending_end_reason(&self) -> Option<SessionEndReason> {
+        self.pending_end_reason
+            .lock()
+            .unwrap()
+            .take()
+            .filter(|(_, marked_at)| marked_at.elapsed().as_secs() <= PENDING_REASON_TTL_SECS)
+            .map(|(reason, _)| reason)
+    }
+
+    /// 根据最新快照更新会话状态
+    pub fn update(&self, snapshot: &DataContextSnapshot) {
+        let mut current = self.current.lock().unwrap();
+        let registered = snapshot.network_status == "registered" || snapshot.network_status == "roaming";
+        let connected = registered && snapshot.active;
+
+        // 接口变化视为新会话
+        let interface_changed = matches!(
+            &*current,
+            Some(session) if !snapshot.interface.is_empty()
+                && !session.interface.is_empty()
+                && session.interface != snapshot.interface
+        );
+
+        if current.is_some() && (!connected || interface_changed) {
+            let reason = self.take_pending_end_reason().unwrap_or(if registered {
+                SessionEndReason::ContextDeactivated
+            } else {
+                SessionEndReason::NetworkDeregistered
+            });
+            self.end_session(&mut current, reason);
+        }
+
+        if !connected {
+            return;
+        }
+
+        match current.as_mut() {
+            Some(session) => {
+                // 会话开始时接口可能尚未就绪，补充记录
+                if session.interface.is_empty() && !snapshot.interface.is_empty() {
+                    session.interface = snapshot.interface.clone();
+                }
+                session.accumulate();
+                if session.last_persist.elapsed().as_secs() >= PROGRESS_PERSIST_INTERVAL_SECS {
+                    let _ = self.db.update_data_session_progress(
+                        session.id,
+                        session.rx_bytes as i64,
+                        session.tx_bytes as i64,
+                    );
+                    session.last_persist = Instant::now();
+                }
+            }
+            None => *current = self.start_session(snapshot),
+        }
+    }
+
+    fn start_session(&self, snapshot: &DataContextSnapshot) -> Option<ActiveSession> {
+        // 新会话开始时丢弃未消费的结束原因
+        let _ = self.take_pending_end_reason();
+
+        match self.db.insert_data_session(
+            &snapshot.apn,
+            &snapshot.interface,
+            &snapshot.ipv4_address,
+            &snapshot.ipv6_address,
+        ) {
+            Ok(id) => {
+                info!(
+                    id,
+                    apn = %snapshot.apn,
+                    interface = %snapshot.interface,
+                    ipv4 = %snapshot.ipv4_address,
+                    "Data session started"
+                );
+                let mut session = ActiveSession {
+                    id,
+                    interface: snapshot.interface.clone(),
+                    last_counters: None,
+                    rx_bytes: 0,
+                    tx_bytes: 0,
+                    last_persist: Instant::now(),
+                };
+                session.accumulate();
+                Some(session)
+            }
+            Err(e) => {
+                warn!(error = %e, "Data session: failed to record start");
+                None
+            }
+        }
+    }
+
+    fn end_session(&self, current: &mut Option<ActiveSession>, reason: SessionEndReason) {
+        let Some(mut session) = current.take() else {
+            return;
+        };
+        session.accumulate();
+
+        if let Err(e) = self.db.end_data_session(
+            session.id,
+            reason.as_str(),
+            session.rx_bytes as i64,
+            session.tx_bytes as i64,
+        ) {
+            warn!(error = %e, "Data session: failed to record end");
+        }
+        info!(
+            id = session.id,
+            reason = reason.as_str(),
+            rx_bytes = session.rx_bytes,
+            tx_bytes = session.tx_bytes,
+            "Data session ended"
+        );
+    }
+}
+
+/// 解析 RFC3339 时间字符串
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// 根据会话记录计算中断区间
+///
+/// 中断定义为相邻两次会话之间的空档；最后一次会话已结束则视为仍在中断中。
+/// 结果裁剪到 `[window_start, now]`，按开始时间升序返回。
+///
+/// # Arguments
+/// * `sessions` - 按开始时间升序排列的会话记录
+/// * `window_start` - 统计窗口起点
+/// * `now` - 当前时间
+pub fn compute_outages(
+    sessions: &[DataSessionRecord],
+    window_start: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<DataOutage> {
+    let mut outages = Vec::new();
+    // 已覆盖到的最晚时间及对应的结束原因
+    let mut covered_until: Option<(DateTime<Utc>, Option<String>)> = None;
+
+    for session in sessions {
+        let Some(start) = parse_time(&session.start_time) else {
+            continue;
+        };
+
+        if let Some((gap_start, reason)) = &covered_until {
+            if start > *gap_start {
+                push_outage(&mut outages, *gap_start, Some(start), reason.clone(), window_start, now);
+            }
+        }
+
+        let end = match &session.end_time {
+            Some(end) => match parse_time(end) {
+                Some(t) => t,
+                None => continue,
+            },
+            // 进行中的会话覆盖到当前时间
+            None => now,
+        };
+
+        let extends = covered_until.as_ref().map(|(t, _)| end >= *t).unwrap_or(true);
+        if extends {
+            covered_until = Some((end, session.end_reason.clone()));
+        }
+    }
+
+    // 最后一次会话结束后尚未恢复
+    if let Some((gap_start, reason)) = covered_until {
+        if gap_start < now {
+            push_outage(&mut outages, gap_start, None, reason, window_start, now);
+        }
+    }
+
+    outages
+}
+
+fn push_outage(
+    outages: &mut Vec<DataOutage>,
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    reason: Option<String>,
+    window_start: DateTime<Utc>,
+    now: DateTime<Utc>,
+) {
+    let effective_end = end.unwrap_or(now);
+    if effective_end <= window_start {
+        return;
+    }
+    let start = start.max(window_start);
+    outages.push(DataOutage {
+        start_time: start.to_rfc3339(),
+        end_time: end.map(|t| t.to_rfc3339()),
+        duration_secs: (effective_end - start).num_seconds(),
+        reason,
+        ongoing: end.is_none(),
+    });
+}
+
+/// 计算两个区间的重叠秒数
+fn overlap_secs(
+    a_start: DateTime<Utc>,
+    a_end: DateTime<Utc>,
+    b_start: DateTime<Utc>,
+    b_end: DateTime<Utc>,
+) -> i64 {
+    let start = a_start.max(b_start);
+    let end = a_end.min(b_end);
+    if end > start {
+        (end - start).num_seconds()
+    } else {
+        0
+    }
+}
+
+/// 按天计算数据连接可用率
+///
+/// 可用率 = 在线时长 / (在线时长 + 中断时长)，未被任何会话或中断覆盖的时间
+/// （如服务未运行、尚无记录）不计入分母。
+///
+/// # Arguments
+/// * `sessions` - 会话记录
+/// * `outages` - 由 [`compute_outages`] 计算出的中断区间
+/// * `window_start` - 统计窗口起点
+/// * `now` - 当前时间
+/// * `tz` - 划分自然日使用的时区
+pub fn compute_daily_availability<Tz: TimeZone>(
+    sessions: &[DataSessionRecord],
+    outages: &[DataOutage],
+    window_start: DateTime<Utc>,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<DailyAvailability> {
+    let session_ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = sessions
+        .iter()
+        .filter_map(|s| {
+            let start = parse_time(&s.start_time)?;
+            let end = match &s.end_time {
+                Some(end) => parse_time(end)?,
+                None => now,
+            };
+            Some((start, end))
+        })
+        .collect();
+
+    let outage_ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = outages
+        .iter()
+        .filter_map(|o| {
+            let start = parse_time(&o.start_time)?;
+            let end = match &o.end_time {
+                Some(end) => parse_time(end)?,
+                None => now,
+            };
+            Some((start, end))
+        })
+        .collect();
+
+    let mut result = Vec::new();
+    let mut day = window_start.with_timezone(tz).date_naive();
+    let last_day = now.with_timezone(tz).date_naive();
+
+    while day <= last_day {
+        let Some(next_day) = day.succ_opt() else {
+            break;
+        };
+        let day_start = tz
+            .from_local_datetime(&day.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|t| t.with_timezone(&Utc));
+        let day_end = tz
+            .from_local_datetime(&next_day.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|t| t.with_timezone(&Utc));
+
+        if let (Some(day_start), Some(day_end)) = (day_start, day_end) {
+            let range_start = day_start.max(window_start);
+            let range_end = day_end.min(now);
+
+            let connected_secs: i64 = session_ranges
+                .iter()
+                .map(|(s, e)| overlap_secs(*s, *e, range_start, range_end))
+                .sum();
+            let outage_secs: i64 = outage_ranges
+                .iter()
+                .map(|(s, e)| overlap_secs(*s, *e, range_start, range_end))
+                .sum();
+            let outage_count = outage_ranges
+                .iter()
+                .filter(|(s, _)| *s >= day_start && *s < day_end)
+                .count() as u32;
+
+            let observed = connected_secs + outage_secs;
+            result.push(DailyAvailability {
+                date: day.format("%Y-%m-%d").to_string(),
+                connected_secs,
+                outage_secs,
+                outage_count,
+                availability_percent: availability_percent(connected_secs, observed),
+            });
+        }
+
+        day = next_day;
+    }
+
+    result
+}
+
+/// 计算可用率百分比（保留两位小数）
+pub fn availability_percent(connected_secs: i64, observed_secs: i64) -> Option<f64> {
+    if observed_secs <= 0 {
+        return None;
+    }
+    let percent = connected_secs as f64 / observed_secs as f64 * 100.0;
+    Some((percent * 100.0).round() / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(start: &str, end: Option<&str>, reason: Option<&str>) -> DataSessionRecord {
+        DataSessionRecord {
+            id: 0,
+            start_time: start.to_string(),
+            end_time: end.map(|s| s.to_string()),
+            apn: "cmnet".to_string(),
+            interface: "sipa_eth0".to_string(),
+            ipv4_address: String::new(),
+            ipv6_address: String::new(),
+            rx_bytes: 0,
+            tx_bytes: 0,
+            end_reason: reason.map(|s| s.to_string()),
+        }
+    }
+
+    fn t(value: &str) -> DateTime<Utc> {
+        parse_time(value).unwrap()
+    }
+
+    #[test]
+    fn test_outages_and_daily_availability() {
+        let sessions = vec![
+            session("2025-12-01T00:00:00+00:00", Some("2025-12-01T12:00:00+00:00"), Some("network_deregistered")),
+            session("2025-12-01T13:00:00+00:00", Some("2025-12-02T06:00:00+00:00"), Some("user_action")),
+        ];
+        let window_start = t("2025-12-01T00:00:00+00:00");
+        let now = t("2025-12-02T12:00:00+00:00");
+
+        let outages = compute_outages(&sessions, window_start, now);
+        assert_eq!(outages.len(), 2);
+        assert_eq!(outages[0].duration_secs, 3600);
+        assert_eq!(outages[0].reason.as_deref(), Some("network_deregistered"));
+        assert!(!outages[0].ongoing);
+        assert!(outages[1].ongoing);
+        assert_eq!(outages[1].duration_secs, 6 * 3600);
+
+        let daily = compute_daily_availability(&sessions, &outages, window_start, now, &Utc);
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[0].date, "2025-12-01");
+        assert_eq!(daily[0].connected_secs, 23 * 3600);
+        assert_eq!(daily[0].outage_secs, 3600);
+        assert_eq!(daily[0].availability_percent, Some(95.83));
+        assert_eq!(daily[1].availability_percent, Some(50.0));
+    }
+}
@@ -10,7 +10,7 @@
  */
 //! 数据库模块
 //!
-//! 使用 SQLite 存储短信历史记录和通话记录
+//! 使用 SQLite 存储短信历史记录、通话记录、数据会话记录、温度历史、离线基站库和站点勘测数据
 
 use chrono::Utc;
 use rusqlite::{params, Connection, Result};
@@ -42,6 +42,137 @@ pub struct CallRecord {
     pub answered: bool,         // 是否接通
 }
 
+/// 数据会话记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataSessionRecord {
+    pub id: i64,
+    pub start_time: String,       // 开始时间 ISO 8601
+    pub end_time: Option<String>, // 结束时间 ISO 8601（进行中为 None）
+    pub apn: String,              // 使用的 APN
+    pub interface: String,        // 网络接口名（如 sipa_eth0）
+    pub ipv4_address: String,     // IPv4 地址
+    pub ipv6_address: String,     // IPv6 地址
+    pub rx_bytes: i64,            // 会话期间接收字节数
+    pub tx_bytes: i64,            // 会话期间发送字节数
+    pub end_reason: Option<String>, // 结束原因
+}
+
+/// 温度历史记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermalRecord {
+    pub timestamp: String,      // 采样时间 ISO 8601
+    pub zone: String,           // 传感器名称（thermal_zone0）
+    pub sensor_type: String,    // 传感器类型
+    pub temperature: f64,       // 温度（℃）
+}
+
+/// 安全锁定记录（锁定后确认或自动恢复）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockRecord {
+    pub id: i64,
+    pub kind: String,             // 锁定类型：cell / band
+    pub target: String,           // 请求的锁定参数（JSON）
+    pub previous: String,         // 锁定前的状态（JSON，用于恢复）
+    pub started_at: String,       // 应用时间 ISO 8601
+    pub finished_at: Option<String>, // 结束时间 ISO 8601（观察中为 None）
+    pub outcome: String,          // pending / confirmed / reverted / revert_failed / superseded
+    pub detail: String,           // 结果说明
+}
+
+/// 数据连接 watchdog 恢复动作记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogActionRecord {
+    pub id: i64,
+    pub timestamp: String,      // 执行时间 ISO 8601
+    pub action: String,         // reactivate / airplane_toggle / reregister / restart_ofono / reboot
+    pub failures: i64,          // 执行时的连续失败检查次数
+    pub success: bool,          // 动作本身是否执行成功（不代表连接已恢复）
+    pub detail: String,         // 结果说明
+}
+
+/// DHCP 租约记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DhcpLeaseRecord {
+    pub mac: String,            // 客户端 MAC（小写，冒号分隔）
+    pub ip: String,             // 分配的 IPv4 地址
+    pub hostname: String,       // 客户端上报的主机名（option 12）
+    pub expires_at: i64,        // 到期时间（Unix 秒）
+    pub updated_at: String,     // 最近一次续租时间 ISO 8601
+}
+
+/// DDNS 更新记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DdnsUpdateRecord {
+    pub id: i64,
+    pub timestamp: String,      // 更新时间 ISO 8601
+    pub provider: String,       // custom / cloudflare / duckdns / dnspod / aliyun
+    pub domain: String,
+    pub record_type: String,    // A / AAAA
+    pub ip: String,
+    pub success: bool,
+    pub detail: String,         // 结果说明或错误信息
+}
+
+/// 客户端每日流量记录（按设备本地时区划分自然日）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientTrafficRecord {
+    pub mac: String,            // 客户端 MAC（小写，冒号分隔）
+    pub date: String,           // 日期 YYYY-MM-DD
+    pub download_bytes: i64,    // 下行字节数（WAN → 客户端）
+    pub upload_bytes: i64,      // 上行字节数（客户端 → WAN）
+}
+
+/// 离线基站记录（OpenCellID / Mozilla Location Service CSV 格式）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CellTower {
+    pub radio: String,          // 网络制式：GSM / UMTS / LTE / NR
+    pub mcc: u16,
+    pub mnc: u16,
+    pub area: u32,              // LAC / TAC
+    pub cell: i64,              // Cell ID（LTE 为 ECI，NR 为 NCI）
+    pub unit: Option<u32>,      // LTE/NR 为 PCI，UMTS 为 PSC
+    pub lon: f64,
+    pub lat: f64,
+    pub range: u32,             // 覆盖半径估计（米）
+    pub samples: u32,           // 测量样本数
+}
+
+/// 离线基站库统计（按制式和 MCC 分组）
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CellTowerCount {
+    pub radio: String,
+    pub mcc: u16,
+    pub count: i64,
+}
+
+/// 站点勘测会话
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SurveySession {
+    pub id: i64,
+    pub name: String,
+    pub started_at: String,         // 开始时间 ISO 8601
+    pub finished_at: Option<String>, // 结束时间 ISO 8601（进行中为 None）
+    pub interval_ms: i64,           // 采样间隔（毫秒）
+    pub sample_count: i64,          // 小区采样条数
+}
+
+/// 站点勘测采样（每次采样中观测到的每个小区一条）
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SurveySample {
+    pub timestamp: String,          // 采样时间 ISO 8601
+    pub label: String,              // 位置标签（用户输入）
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub tech: String,               // nr / lte
+    pub band: String,
+    pub arfcn: u32,
+    pub pci: u16,
+    pub is_serving: bool,
+    pub rsrp: Option<f64>,          // dBm
+    pub rsrq: Option<f64>,          // dB
+    pub sinr: Option<f64>,          // dB
+}
+
 /// 短信统计
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SmsStats {
@@ -122,6 +253,193 @@ impl Database {
             [],
         )?;
         
+        // 创建数据会话表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS data_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                start_time TEXT NOT NULL,
+                end_time TEXT,
+                last_seen TEXT NOT NULL,
+                apn TEXT NOT NULL DEFAULT '',
+                interface TEXT NOT NULL DEFAULT '',
+                ipv4_address TEXT NOT NULL DEFAULT '',
+                ipv6_address TEXT NOT NULL DEFAULT '',
+                rx_bytes INTEGER DEFAULT 0,
+                tx_bytes INTEGER DEFAULT 0,
+                end_reason TEXT
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_data_session_start ON data_sessions(start_time DESC)",
+            [],
+        )?;
+        
+        // 创建温度历史表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS thermal_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                zone TEXT NOT NULL,
+                sensor_type TEXT NOT NULL DEFAULT '',
+                temperature REAL NOT NULL
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_thermal_zone_time ON thermal_history(zone, timestamp)",
+            [],
+        )?;
+        
+        // 创建安全锁定记录表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS lock_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                target TEXT NOT NULL,
+                previous TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                finished_at TEXT,
+                outcome TEXT NOT NULL DEFAULT 'pending',
+                detail TEXT NOT NULL DEFAULT ''
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_lock_history_start ON lock_history(started_at DESC)",
+            [],
+        )?;
+        
+        // 创建 watchdog 恢复动作表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS watchdog_actions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                action TEXT NOT NULL,
+                failures INTEGER NOT NULL DEFAULT 0,
+                success INTEGER NOT NULL DEFAULT 0,
+                detail TEXT NOT NULL DEFAULT ''
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_watchdog_actions_time ON watchdog_actions(timestamp DESC)",
+            [],
+        )?;
+        
+        // 创建 DHCP 租约表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dhcp_leases (
+                mac TEXT PRIMARY KEY,
+                ip TEXT NOT NULL,
+                hostname TEXT NOT NULL DEFAULT '',
+                expires_at INTEGER NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        
+        // 创建 DDNS 更新记录表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ddns_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                domain TEXT NOT NULL,
+                record_type TEXT NOT NULL,
+                ip TEXT NOT NULL,
+                success INTEGER NOT NULL DEFAULT 0,
+                detail TEXT NOT NULL DEFAULT ''
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_ddns_history_time ON ddns_history(timestamp DESC)",
+            [],
+        )?;
+        
+        // 创建客户端每日流量表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS client_traffic_daily (
+                mac TEXT NOT NULL,
+                date TEXT NOT NULL,
+                download_bytes INTEGER NOT NULL DEFAULT 0,
+                upload_bytes INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (mac, date)
+            )",
+            [],
+        )?;
+        
+        // 创建离线基站表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cell_towers (
+                radio TEXT NOT NULL,
+                mcc INTEGER NOT NULL,
+                mnc INTEGER NOT NULL,
+                area INTEGER NOT NULL,
+                cell INTEGER NOT NULL,
+                unit INTEGER,
+                lon REAL NOT NULL,
+                lat REAL NOT NULL,
+                range INTEGER NOT NULL DEFAULT 0,
+                samples INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (radio, mcc, mnc, area, cell)
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_cell_towers_cell ON cell_towers(mcc, mnc, cell)",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_cell_towers_unit ON cell_towers(mcc, mnc, area, unit)",
+            [],
+        )?;
+        
+        // 创建站点勘测表（如果不存在）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS survey_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                finished_at TEXT,
+                interval_ms INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS survey_samples (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                timestamp TEXT NOT NULL,
+                label TEXT NOT NULL DEFAULT '',
+                latitude REAL,
+                longitude REAL,
+                tech TEXT NOT NULL,
+                band TEXT NOT NULL DEFAULT '',
+                arfcn INTEGER NOT NULL,
+                pci INTEGER NOT NULL,
+                is_serving INTEGER NOT NULL DEFAULT 0,
+                rsrp REAL,
+                rsrq REAL,
+                sinr REAL
+            )",
+            [],
+        )?;
+        
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_survey_samples_session ON survey_samples(session_id, timestamp)",
+            [],
+        )?;
+        
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
         })
@@ -434,6 +752,753 @@ impl Database {
         conn.execute("DELETE FROM call_history", [])?;
         Ok(())
     }
+    
+    // ==================== 数据会话相关方法 ====================
+    
+    /// 插入新数据会话（会话开始时调用）
+    pub fn insert_data_session(
+        &self,
+        apn: &str,
+        interface: &str,
+        ipv4_address: &str,
+        ipv6_address: &str,
+    ) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let now = Utc::now().to_rfc3339();
+        
+        conn.execute(
+            "INSERT INTO data_sessions (start_time, last_seen, apn, interface, ipv4_address, ipv6_address)
+             VALUES (?1, ?1, ?2, ?3, ?4, ?5)",
+            params![now, apn, interface, ipv4_address, ipv6_address],
+        )?;
+        
+        Ok(conn.last_insert_rowid())
+    }
+    
+    /// 更新进行中会话的流量和最后存活时间
+    pub fn update_data_session_progress(&self, id: i64, rx_bytes: i64, tx_bytes: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = Utc::now().to_rfc3339();
+        
+        conn.execute(
+            "UPDATE data_sessions SET rx_bytes = ?1, tx_bytes = ?2, last_seen = ?3 WHERE id = ?4",
+            params![rx_bytes, tx_bytes, now, id],
+        )?;
+        Ok(())
+    }
+    
+    /// 结束数据会话
+    pub fn end_data_session(&self, id: i64, reason: &str, rx_bytes: i64, tx_bytes: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = Utc::now().to_rfc3339();
+        
+        conn.execute(
+            "UPDATE data_sessions SET end_time = ?1, last_seen = ?1, end_reason = ?2, rx_bytes = ?3, tx_bytes = ?4
+             WHERE id = ?5",
+            params![now, reason, rx_bytes, tx_bytes, id],
+        )?;
+        Ok(())
+    }
+    
+    /// 关闭上次运行遗留的未结束会话（以最后存活时间作为结束时间）
+    pub fn close_stale_data_sessions(&self, reason: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute(
+            "UPDATE data_sessions SET end_time = last_seen, end_reason = ?1 WHERE end_time IS NULL",
+            params![reason],
+        )?;
+        Ok(count)
+    }
+    
+    /// 获取数据会话记录（分页，最新在前）
+    pub fn get_data_sessions(&self, limit: i64, offset: i64) -> Result<Vec<DataSessionRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, start_time, end_time, apn, interface, ipv4_address, ipv6_address,
+                    rx_bytes, tx_bytes, end_reason
+             FROM data_sessions
+             ORDER BY start_time DESC
+             LIMIT ?1 OFFSET ?2"
+        )?;
+        
+        let records = stmt.query_map(params![limit, offset], Self::map_data_session)?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 获取与指定时间之后有交集的数据会话（按开始时间升序）
+    ///
+    /// 额外包含 `since` 之前最后结束的一条会话，用于计算跨越窗口起点的中断。
+    pub fn get_data_sessions_since(&self, since: &str) -> Result<Vec<DataSessionRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, start_time, end_time, apn, interface, ipv4_address, ipv6_address,
+                    rx_bytes, tx_bytes, end_reason
+             FROM data_sessions
+             WHERE end_time IS NULL
+                OR end_time >= ?1
+                OR id = (SELECT id FROM data_sessions WHERE end_time < ?1 ORDER BY end_time DESC LIMIT 1)
+             ORDER BY start_time ASC"
+        )?;
+        
+        let records = stmt.query_map(params![since], Self::map_data_session)?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 删除指定时间之前结束的数据会话
+    pub fn cleanup_old_data_sessions(&self, before: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute(
+            "DELETE FROM data_sessions WHERE end_time IS NOT NULL AND end_time < ?1",
+            params![before],
+        )?;
+        Ok(count)
+    }
+    
+    fn map_data_session(row: &rusqlite::Row<'_>) -> Result<DataSessionRecord> {
+        Ok(DataSessionRecord {
+            id: row.get(0)?,
+            start_time: row.get(1)?,
+            end_time: row.get(2)?,
+            apn: row.get(3)?,
+            interface: row.get(4)?,
+            ipv4_address: row.get(5)?,
+            ipv6_address: row.get(6)?,
+            rx_bytes: row.get(7)?,
+            tx_bytes: row.get(8)?,
+            end_reason: row.get(9)?,
+        })
+    }
+    
+    // ==================== 温度历史相关方法 ====================
+    
+    /// 批量插入一次采样的所有传感器温度
+    pub fn insert_thermal_samples(&self, samples: &[(String, String, f64)]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let now = Utc::now().to_rfc3339();
+        
+        let tx = conn.transaction()?;
+        for (zone, sensor_type, temperature) in samples {
+            tx.execute(
+                "INSERT INTO thermal_history (timestamp, zone, sensor_type, temperature)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![now, zone, sensor_type, temperature],
+            )?;
+        }
+        tx.commit()
+    }
+    
+    /// 获取温度历史（可按传感器过滤，按时间升序）
+    pub fn get_thermal_history(&self, zone: Option<&str>, since: &str) -> Result<Vec<ThermalRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, zone, sensor_type, temperature
+             FROM thermal_history
+             WHERE timestamp >= ?1 AND (?2 IS NULL OR zone = ?2)
+             ORDER BY timestamp ASC"
+        )?;
+        
+        let records = stmt.query_map(params![since, zone], |row| {
+            Ok(ThermalRecord {
+                timestamp: row.get(0)?,
+                zone: row.get(1)?,
+                sensor_type: row.get(2)?,
+                temperature: row.get(3)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 删除指定时间之前的温度历史
+    pub fn cleanup_thermal_history(&self, before: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute(
+            "DELETE FROM thermal_history WHERE timestamp < ?1",
+            params![before],
+        )?;
+        Ok(count)
+    }
+    
+    // ==================== 安全锁定记录相关方法 ====================
+    
+    /// 新增安全锁定记录（状态为 pending），返回记录 ID
+    pub fn insert_lock_record(&self, kind: &str, target: &str, previous: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO lock_history (kind, target, previous, started_at, outcome)
+             VALUES (?1, ?2, ?3, ?4, 'pending')",
+            params![kind, target, previous, Utc::now().to_rfc3339()],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+    
+    /// 记录安全锁定结果
+    pub fn finish_lock_record(&self, id: i64, outcome: &str, detail: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE lock_history SET finished_at = ?1, outcome = ?2, detail = ?3 WHERE id = ?4",
+            params![Utc::now().to_rfc3339(), outcome, detail, id],
+        )?;
+        Ok(())
+    }
+    
+    /// 获取未结束的记录（上次服务退出时仍在观察期内，按时间倒序）
+    pub fn get_pending_lock_records(&self) -> Result<Vec<LockRecord>> {
+        self.query_lock_records(
+            "SELECT id, kind, target, previous, started_at, finished_at, outcome, detail
+             FROM lock_history
+             WHERE outcome = 'pending'
+             ORDER BY started_at DESC",
+            params![],
+        )
+    }
+    
+    /// 获取安全锁定记录（按时间倒序）
+    pub fn get_lock_history(&self, limit: i64, offset: i64) -> Result<Vec<LockRecord>> {
+        self.query_lock_records(
+            "SELECT id, kind, target, previous, started_at, finished_at, outcome, detail
+             FROM lock_history
+             ORDER BY started_at DESC
+             LIMIT ?1 OFFSET ?2",
+            params![limit, offset],
+        )
+    }
+    
+    fn query_lock_records(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<LockRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(sql)?;
+        
+        let records = stmt.query_map(params, |row| {
+            Ok(LockRecord {
+                id: row.get(0)?,
+                kind: row.get(1)?,
+                target: row.get(2)?,
+                previous: row.get(3)?,
+                started_at: row.get(4)?,
+                finished_at: row.get(5)?,
+                outcome: row.get(6)?,
+                detail: row.get(7)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        
+        Ok(result)
+    }
+    
+    // ==================== watchdog 恢复动作相关方法 ====================
+    
+    /// 记录 watchdog 恢复动作
+    pub fn insert_watchdog_action(&self, action: &str, failures: u32, success: bool, detail: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO watchdog_actions (timestamp, action, failures, success, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![Utc::now().to_rfc3339(), action, failures, success, detail],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+    
+    /// 获取最近的 watchdog 恢复动作（按时间倒序）
+    pub fn get_watchdog_actions(&self, limit: i64) -> Result<Vec<WatchdogActionRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, timestamp, action, failures, success, detail
+             FROM watchdog_actions
+             ORDER BY timestamp DESC
+             LIMIT ?1"
+        )?;
+        
+        let records = stmt.query_map(params![limit], |row| {
+            Ok(WatchdogActionRecord {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                action: row.get(2)?,
+                failures: row.get(3)?,
+                success: row.get(4)?,
+                detail: row.get(5)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 统计指定时间之后某种动作的执行次数
+    pub fn count_watchdog_actions_since(&self, action: &str, since: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*) FROM watchdog_actions WHERE action = ?1 AND timestamp >= ?2",
+            params![action, since],
+            |row| row.get(0),
+        )
+    }
+    
+    /// 删除指定时间之前的 watchdog 恢复动作
+    pub fn cleanup_watchdog_actions(&self, before: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute(
+            "DELETE FROM watchdog_actions WHERE timestamp < ?1",
+            params![before],
+        )?;
+        Ok(count)
+    }
+    
+    // ==================== DHCP 租约相关方法 ====================
+    
+    /// 写入或更新 DHCP 租约（同一 MAC 只保留一条）
+    pub fn upsert_dhcp_lease(&self, mac: &str, ip: &str, hostname: &str, expires_at: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        // 同一地址分配给新客户端时删除旧记录
+        conn.execute("DELETE FROM dhcp_leases WHERE ip = ?1 AND mac != ?2", params![ip, mac])?;
+        conn.execute(
+            "INSERT INTO dhcp_leases (mac, ip, hostname, expires_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(mac) DO UPDATE SET
+                ip = excluded.ip, hostname = excluded.hostname,
+                expires_at = excluded.expires_at, updated_at = excluded.updated_at",
+            params![mac, ip, hostname, expires_at, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+    
+    /// 删除 DHCP 租约
+    pub fn delete_dhcp_lease(&self, mac: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute("DELETE FROM dhcp_leases WHERE mac = ?1", params![mac])?;
+        Ok(count)
+    }
+    
+    /// 获取全部 DHCP 租约（按地址排序）
+    pub fn get_dhcp_leases(&self) -> Result<Vec<DhcpLeaseRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT mac, ip, hostname, expires_at, updated_at
+             FROM dhcp_leases
+             ORDER BY ip"
+        )?;
+        
+        let records = stmt.query_map([], |row| {
+            Ok(DhcpLeaseRecord {
+                mac: row.get(0)?,
+                ip: row.get(1)?,
+                hostname: row.get(2)?,
+                expires_at: row.get(3)?,
+                updated_at: row.get(4)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        Ok(result)
+    }
+    
+    /// 删除指定时间之前过期的 DHCP 租约
+    pub fn cleanup_dhcp_leases(&self, before: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute("DELETE FROM dhcp_leases WHERE expires_at < ?1", params![before])?;
+        Ok(count)
+    }
+    
+    // ==================== DDNS 更新记录相关方法 ====================
+    
+    /// 记录 DDNS 更新
+    pub fn insert_ddns_update(
+        &self,
+        provider: &str,
+        domain: &str,
+        record_type: &str,
+        ip: &str,
+        success: bool,
+        detail: &str,
+    ) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO ddns_history (timestamp, provider, domain, record_type, ip, success, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![Utc::now().to_rfc3339(), provider, domain, record_type, ip, success, detail],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+    
+    /// 获取 DDNS 更新记录（按时间倒序，分页）
+    pub fn get_ddns_history(&self, limit: i64, offset: i64) -> Result<Vec<DdnsUpdateRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, timestamp, provider, domain, record_type, ip, success, detail
+             FROM ddns_history
+             ORDER BY timestamp DESC
+             LIMIT ?1 OFFSET ?2"
+        )?;
+        
+        let records = stmt.query_map(params![limit, offset], |row| {
+            Ok(DdnsUpdateRecord {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                provider: row.get(2)?,
+                domain: row.get(3)?,
+                record_type: row.get(4)?,
+                ip: row.get(5)?,
+                success: row.get(6)?,
+                detail: row.get(7)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        Ok(result)
+    }
+    
+    /// 删除指定时间之前的 DDNS 更新记录
+    pub fn cleanup_ddns_history(&self, before: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute("DELETE FROM ddns_history WHERE timestamp < ?1", params![before])?;
+        Ok(count)
+    }
+    
+    // ==================== 客户端流量相关方法 ====================
+    
+    /// 累加客户端当日流量
+    pub fn add_client_traffic(&self, mac: &str, date: &str, download_bytes: i64, upload_bytes: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO client_traffic_daily (mac, date, download_bytes, upload_bytes)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(mac, date) DO UPDATE SET
+                download_bytes = download_bytes + excluded.download_bytes,
+                upload_bytes = upload_bytes + excluded.upload_bytes",
+            params![mac, date, download_bytes, upload_bytes],
+        )?;
+        Ok(())
+    }
+    
+    /// 获取指定日期所有客户端的流量
+    pub fn get_client_traffic_by_date(&self, date: &str) -> Result<Vec<ClientTrafficRecord>> {
+        self.query_client_traffic(
+            "SELECT mac, date, download_bytes, upload_bytes
+             FROM client_traffic_daily
+             WHERE date = ?1
+             ORDER BY mac",
+            params![date],
+        )
+    }
+    
+    /// 获取客户端指定日期（含）之后的每日流量（按日期升序）
+    pub fn get_client_traffic_history(&self, mac: &str, since_date: &str) -> Result<Vec<ClientTrafficRecord>> {
+        self.query_client_traffic(
+            "SELECT mac, date, download_bytes, upload_bytes
+             FROM client_traffic_daily
+             WHERE mac = ?1 AND date >= ?2
+             ORDER BY date",
+            params![mac, since_date],
+        )
+    }
+    
+    fn query_client_traffic(&self, sql: &str, args: &[&dyn rusqlite::ToSql]) -> Result<Vec<ClientTrafficRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(sql)?;
+        
+        let records = stmt.query_map(args, |row| {
+            Ok(ClientTrafficRecord {
+                mac: row.get(0)?,
+                date: row.get(1)?,
+                download_bytes: row.get(2)?,
+                upload_bytes: row.get(3)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for record in records {
+            result.push(record?);
+        }
+        Ok(result)
+    }
+    
+    /// 删除指定日期之前的客户端流量记录
+    pub fn cleanup_client_traffic(&self, before_date: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute("DELETE FROM client_traffic_daily WHERE date < ?1", params![before_date])?;
+        Ok(count)
+    }
+    
+    // ==================== 离线基站库相关方法 ====================
+    
+    /// 批量导入基站（已存在则覆盖），返回写入条数
+    pub fn insert_cell_towers(&self, towers: &[CellTower]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO cell_towers
+                 (radio, mcc, mnc, area, cell, unit, lon, lat, range, samples)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for t in towers {
+                stmt.execute(params![
+                    t.radio, t.mcc, t.mnc, t.area, t.cell, t.unit, t.lon, t.lat, t.range, t.samples
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(towers.len())
+    }
+    
+    /// 删除指定 MCC 的基站（mcc 为空时清空全部）
+    pub fn clear_cell_towers(&self, mcc: &[u16]) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        if mcc.is_empty() {
+            return conn.execute("DELETE FROM cell_towers", []);
+        }
+        let mut count = 0;
+        for m in mcc {
+            count += conn.execute("DELETE FROM cell_towers WHERE mcc = ?1", params![m])?;
+        }
+        Ok(count)
+    }
+    
+    /// 按 Cell ID 查找基站
+    pub fn find_cell_tower(&self, radio: &str, mcc: u16, mnc: u16, cell: i64) -> Result<Option<CellTower>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT radio, mcc, mnc, area, cell, unit, lon, lat, range, samples
+             FROM cell_towers
+             WHERE radio = ?1 AND mcc = ?2 AND mnc = ?3 AND cell = ?4
+             ORDER BY samples DESC
+             LIMIT 1"
+        )?;
+        
+        let mut rows = stmt.query_map(params![radio, mcc, mnc, cell], Self::row_to_cell_tower)?;
+        rows.next().transpose()
+    }
+    
+    /// 按 TAC + PCI 查找基站（邻区没有 Cell ID，可能匹配多个）
+    pub fn find_cell_towers_by_pci(
+        &self,
+        radio: &str,
+        mcc: u16,
+        mnc: u16,
+        area: u32,
+        pci: u32,
+    ) -> Result<Vec<CellTower>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT radio, mcc, mnc, area, cell, unit, lon, lat, range, samples
+             FROM cell_towers
+             WHERE radio = ?1 AND mcc = ?2 AND mnc = ?3 AND area = ?4 AND unit = ?5"
+        )?;
+        
+        let towers = stmt.query_map(params![radio, mcc, mnc, area, pci], Self::row_to_cell_tower)?;
+        
+        let mut result = Vec::new();
+        for tower in towers {
+            result.push(tower?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 获取离线基站库统计
+    pub fn get_cell_tower_counts(&self) -> Result<Vec<CellTowerCount>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT radio, mcc, COUNT(*) FROM cell_towers GROUP BY radio, mcc ORDER BY mcc, radio"
+        )?;
+        
+        let counts = stmt.query_map([], |row| {
+            Ok(CellTowerCount {
+                radio: row.get(0)?,
+                mcc: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for count in counts {
+            result.push(count?);
+        }
+        
+        Ok(result)
+    }
+    
+    fn row_to_cell_tower(row: &rusqlite::Row) -> Result<CellTower> {
+        Ok(CellTower {
+            radio: row.get(0)?,
+            mcc: row.get(1)?,
+            mnc: row.get(2)?,
+            area: row.get(3)?,
+            cell: row.get(4)?,
+            unit: row.get(5)?,
+            lon: row.get(6)?,
+            lat: row.get(7)?,
+            range: row.get(8)?,
+            samples: row.get(9)?,
+        })
+    }
+    
+    // ==================== 站点勘测相关方法 ====================
+    
+    /// 新建勘测会话，返回会话 ID
+    pub fn insert_survey_session(&self, name: &str, interval_ms: i64) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO survey_sessions (name, started_at, interval_ms) VALUES (?1, ?2, ?3)",
+            params![name, Utc::now().to_rfc3339(), interval_ms],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+    
+    /// 结束勘测会话
+    pub fn finish_survey_session(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE survey_sessions SET finished_at = ?1 WHERE id = ?2 AND finished_at IS NULL",
+            params![Utc::now().to_rfc3339(), id],
+        )?;
+        Ok(())
+    }
+    
+    /// 结束所有未结束的勘测会话（服务重启时调用）
+    pub fn close_open_survey_sessions(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count = conn.execute(
+            "UPDATE survey_sessions SET finished_at = ?1 WHERE finished_at IS NULL",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        Ok(count)
+    }
+    
+    /// 批量写入一次采样的所有小区
+    pub fn insert_survey_samples(&self, session_id: i64, samples: &[SurveySample]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        
+        let tx = conn.transaction()?;
+        for s in samples {
+            tx.execute(
+                "INSERT INTO survey_samples
+                 (session_id, timestamp, label, latitude, longitude, tech, band, arfcn, pci, is_serving, rsrp, rsrq, sinr)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    session_id, s.timestamp, s.label, s.latitude, s.longitude, s.tech, s.band,
+                    s.arfcn, s.pci, s.is_serving, s.rsrp, s.rsrq, s.sinr
+                ],
+            )?;
+        }
+        tx.commit()
+    }
+    
+    /// 获取勘测会话列表（按时间倒序）
+    pub fn get_survey_sessions(&self) -> Result<Vec<SurveySession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.name, s.started_at, s.finished_at, s.interval_ms,
+                    (SELECT COUNT(*) FROM survey_samples WHERE session_id = s.id)
+             FROM survey_sessions s
+             ORDER BY s.started_at DESC"
+        )?;
+        
+        let sessions = stmt.query_map([], Self::row_to_survey_session)?;
+        
+        let mut result = Vec::new();
+        for session in sessions {
+            result.push(session?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 获取单个勘测会话
+    pub fn get_survey_session(&self, id: i64) -> Result<Option<SurveySession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.name, s.started_at, s.finished_at, s.interval_ms,
+                    (SELECT COUNT(*) FROM survey_samples WHERE session_id = s.id)
+             FROM survey_sessions s
+             WHERE s.id = ?1"
+        )?;
+        
+        let mut rows = stmt.query_map(params![id], Self::row_to_survey_session)?;
+        rows.next().transpose()
+    }
+    
+    /// 获取勘测会话的所有采样（按时间升序）
+    pub fn get_survey_samples(&self, session_id: i64) -> Result<Vec<SurveySample>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, label, latitude, longitude, tech, band, arfcn, pci, is_serving, rsrp, rsrq, sinr
+             FROM survey_samples
+             WHERE session_id = ?1
+             ORDER BY id ASC"
+        )?;
+        
+        let samples = stmt.query_map(params![session_id], |row| {
+            Ok(SurveySample {
+                timestamp: row.get(0)?,
+                label: row.get(1)?,
+                latitude: row.get(2)?,
+                longitude: row.get(3)?,
+                tech: row.get(4)?,
+                band: row.get(5)?,
+                arfcn: row.get(6)?,
+                pci: row.get(7)?,
+                is_serving: row.get(8)?,
+                rsrp: row.get(9)?,
+                rsrq: row.get(10)?,
+                sinr: row.get(11)?,
+            })
+        })?;
+        
+        let mut result = Vec::new();
+        for sample in samples {
+            result.push(sample?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// 删除勘测会话及其采样
+    pub fn delete_survey_session(&self, id: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM survey_samples WHERE session_id = ?1", params![id])?;
+        conn.execute("DELETE FROM survey_sessions WHERE id = ?1", params![id])
+    }
+    
+    fn row_to_survey_session(row: &rusqlite::Row) -> Result<SurveySession> {
+        Ok(SurveySession {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            started_at: row.get(2)?,
+            finished_at: row.get(3)?,
+            interval_ms: row.get(4)?,
+            sample_count: row.get(5)?,
+        })
+    }
 }
-
-
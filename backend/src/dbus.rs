@@ -17,10 +17,12 @@ use tracing::{info, warn};
 use zbus::{proxy, zvariant::OwnedValue, Connection, Proxy};
 
 use crate::models::{
-    AirplaneModeResponse, ApnContext, DeviceInfoResponse, NetworkInfoResponse, QosInfoResponse, RadioMode,
+    AirplaneModeResponse, ApnContext, ApnSimIdentity, DataContextSnapshot, DataIpConfigResponse, DataIpv4Settings,
+    DataIpv6Settings, DeviceInfoResponse, NetworkInfoResponse, QosInfoResponse, RadioMode,
     RadioModeResponse, ServingCell, SimInfoResponse,
 };
 use crate::serial::with_serial;
+use crate::watchdog::DataHealth;
 
 /// ofono NetworkMonitor 代理接口
 #[proxy(
@@ -157,21 +159,29 @@ pub async fn get_serving_cell_info(conn: &Connection) -> zbus::Result<ServingCel
     }).await
 }
 
-/// 查找第一个有效的 internet 类型 context 路径
+/// 查找数据连接使用的 context 路径
 ///
-/// 遍历所有 context，返回第一个类型为 internet 且配置了 APN 的 context 路径。
+/// 手动指定了 watchdog context 且该 context 仍然存在时直接返回它；
+/// 否则遍历所有 context，返回第一个类型为 internet 且配置了 APN 的 context 路径。
 /// 如果没有配置 APN 的 context，则返回第一个 internet 类型的 context。
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（配置 apn.watchdog_context，见 /api/apn/watchdog）
 ///
 /// # Returns
 /// context 路径字符串
-pub async fn find_internet_context(conn: &Connection) -> zbus::Result<String> {
+pub async fn find_internet_context(conn: &Connection, selected: Option<&str>) -> zbus::Result<String> {
     let proxy = Proxy::new(conn, "org.ofono", "/ril_0", "org.ofono.ConnectionManager").await?;
     let contexts: Vec<(zbus::zvariant::OwnedObjectPath, HashMap<String, OwnedValue>)> = 
         proxy.call("GetContexts", &()).await?;
     
+    if let Some(selected) = selected {
+        if contexts.iter().any(|(path, _)| path.as_str() == selected) {
+            return Ok(selected.to_string());
+        }
+    }
+    
     let mut first_internet_context: Option<String> = None;
     
     for (path, props) in contexts {
@@ -202,14 +212,20 @@ pub async fn find_internet_context(conn: &Connection) -> zbus::Result<String> {
     Ok(first_internet_context.unwrap_or_else(|| "/ril_0/context2".to_string()))
 }
 
-/// 获取所有 APN Context 列表
+/// 获取 APN Context 列表
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `context_type` - 类型过滤（如 internet），None 返回全部
+/// * `selected` - 手动指定的 watchdog context
 ///
 /// # Returns
 /// APN Context 列表
-pub async fn get_all_apn_contexts(conn: &Connection) -> zbus::Result<Vec<ApnContext>> {
+pub async fn get_all_apn_contexts(
+    conn: &Connection,
+    context_type: Option<&str>,
+    selected: Option<&str>,
+) -> zbus::Result<Vec<ApnContext>> {
     let proxy = Proxy::new(conn, "org.ofono", "/ril_0", "org.ofono.ConnectionManager").await?;
     let contexts: Vec<(zbus::zvariant::OwnedObjectPath, HashMap<String, OwnedValue>)> = 
         proxy.call("GetContexts", &()).await?;
@@ -217,14 +233,14 @@ pub async fn get_all_apn_contexts(conn: &Connection) -> zbus::Result<Vec<ApnCont
     let mut result = Vec::new();
     
     for (path, props) in contexts {
-        let context_type = props
+        let ctx_type = props
             .get("Type")
             .and_then(|v| String::try_from(v.clone()).ok())
             .unwrap_or_default();
         
-        // 只返回 internet 类型的 context
-        if context_type == "internet" {
+        if context_type.is_none_or(|t| t == ctx_type) {
             let apn_context = ApnContext {
+                watchdog: selected == Some(path.as_str()),
                 path: path.to_string(),
                 name: props
                     .get("Name")
@@ -254,7 +270,7 @@ pub async fn get_all_apn_contexts(conn: &Connection) -> zbus::Result<Vec<ApnCont
                     .get("AuthenticationMethod")
                     .and_then(|v| String::try_from(v.clone()).ok())
                     .unwrap_or_else(|| "chap".to_string()),
-                context_type,
+                context_type: ctx_type,
             };
             result.push(apn_context);
         }
@@ -376,18 +392,49 @@ pub async fn set_apn_properties(
     Ok(())
 }
 
+/// 创建 APN context
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `context_type` - 类型: internet/mms/ims/wap
+///
+/// # Returns
+/// 新 context 的 D-Bus 路径
+pub async fn add_apn_context(conn: &Connection, context_type: &str) -> zbus::Result<String> {
+    with_serial(async {
+        let proxy = Proxy::new(conn, "org.ofono", "/ril_0", "org.ofono.ConnectionManager").await?;
+        let path: zbus::zvariant::OwnedObjectPath = proxy.call("AddContext", &(context_type,)).await?;
+        Ok(path.to_string())
+    }).await
+}
+
+/// 删除 APN context（已激活的 context 由 ofono 先断开）
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `context_path` - context 的 D-Bus 路径
+pub async fn remove_apn_context(conn: &Connection, context_path: &str) -> zbus::Result<()> {
+    with_serial(async {
+        let proxy = Proxy::new(conn, "org.ofono", "/ril_0", "org.ofono.ConnectionManager").await?;
+        let path = zbus::zvariant::ObjectPath::try_from(context_path)?;
+        proxy.call::<_, _, ()>("RemoveContext", &(path,)).await?;
+        Ok(())
+    }).await
+}
+
 /// 设置数据连接状态
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（为空时自动选择）
 /// * `active` - true 开启数据流量，false 关闭数据流量
 ///
 /// # Returns
 /// 操作结果
-pub async fn set_data_connection(conn: &Connection, active: bool) -> zbus::Result<()> {
+pub async fn set_data_connection(conn: &Connection, selected: Option<&str>, active: bool) -> zbus::Result<()> {
     with_serial(async {
         // 自动查找有效的 internet context
-        let context_path = find_internet_context(conn).await?;
+        let context_path = find_internet_context(conn, selected).await?;
         
         let proxy = ConnectionContextProxy::builder(conn)
             .path(context_path)?
@@ -402,12 +449,13 @@ pub async fn set_data_connection(conn: &Connection, active: bool) -> zbus::Resul
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（为空时自动选择）
 ///
 /// # Returns
 /// 数据连接是否激活
-pub async fn get_data_connection_status(conn: &Connection) -> zbus::Result<bool> {
+pub async fn get_data_connection_status(conn: &Connection, selected: Option<&str>) -> zbus::Result<bool> {
     // 自动查找有效的 internet context
-    let context_path = find_internet_context(conn).await?;
+    let context_path = find_internet_context(conn, selected).await?;
     
     let proxy = ConnectionContextProxy::builder(conn)
         .path(context_path)?
@@ -423,6 +471,168 @@ pub async fn get_data_connection_status(conn: &Connection) -> zbus::Result<bool>
     Ok(active)
 }
 
+/// 读取 context 的 Settings / IPv6.Settings 字典
+fn get_settings_dict(
+    props: &HashMap<String, OwnedValue>,
+    settings_key: &str,
+) -> HashMap<String, OwnedValue> {
+    props
+        .get(settings_key)
+        .and_then(|v| HashMap::<String, OwnedValue>::try_from(v.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// 从 context 的 Settings / IPv6.Settings 字典中提取字符串属性
+fn get_settings_string(
+    props: &HashMap<String, OwnedValue>,
+    settings_key: &str,
+    field: &str,
+) -> String {
+    get_settings_dict(props, settings_key)
+        .get(field)
+        .and_then(|v| String::try_from(v.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// 获取数据连接的完整 IP 配置
+///
+/// 读取 internet context 的 Settings（IPv4）和 IPv6.Settings 字典。
+/// 公网 IP 和 NAT 类型不在此处填充（见 [`crate::ip_info`]）。
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（为空时自动选择）
+pub async fn get_data_ip_config(conn: &Connection, selected: Option<&str>) -> zbus::Result<DataIpConfigResponse> {
+    let context_path = find_internet_context(conn, selected).await?;
+    let proxy = ConnectionContextProxy::builder(conn)
+        .path(context_path.clone())?
+        .build()
+        .await?;
+    let props = proxy.get_properties().await?;
+
+    let get_string = |settings: &HashMap<String, OwnedValue>, field: &str| {
+        settings
+            .get(field)
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .unwrap_or_default()
+    };
+    let get_dns = |settings: &HashMap<String, OwnedValue>| {
+        settings
+            .get("DomainNameServers")
+            .and_then(|v| <Vec<String>>::try_from(v.clone()).ok())
+            .unwrap_or_default()
+    };
+
+    let v4 = get_settings_dict(&props, "Settings");
+    let v6 = get_settings_dict(&props, "IPv6.Settings");
+
+    let ipv4 = DataIpv4Settings {
+        method: get_string(&v4, "Method"),
+        address: get_string(&v4, "Address"),
+        netmask: get_string(&v4, "Netmask"),
+        gateway: get_string(&v4, "Gateway"),
+        dns: get_dns(&v4),
+    };
+    let ipv6_address = get_string(&v6, "Address");
+    let prefix_length = v6
+        .get("PrefixLength")
+        .and_then(|v| u8::try_from(v.clone()).ok());
+    let ipv6 = DataIpv6Settings {
+        prefix: prefix_length
+            .and_then(|len| crate::ip_info::ipv6_prefix(&ipv6_address, len))
+            .unwrap_or_default(),
+        address: ipv6_address,
+        prefix_length,
+        gateway: get_string(&v6, "Gateway"),
+        dns: get_dns(&v6),
+    };
+
+    let mut interface = get_string(&v4, "Interface");
+    if interface.is_empty() {
+        interface = get_string(&v6, "Interface");
+    }
+
+    Ok(DataIpConfigResponse {
+        context_path,
+        active: props
+            .get("Active")
+            .and_then(|v| bool::try_from(v.clone()).ok())
+            .unwrap_or(false),
+        apn: props
+            .get("AccessPointName")
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .unwrap_or_default(),
+        interface,
+        has_ipv6: !ipv6.address.is_empty(),
+        ipv4,
+        ipv6,
+        nat_type: "unknown".to_string(),
+        ..Default::default()
+    })
+}
+
+/// 获取数据连接上下文快照
+///
+/// 一次性读取网络注册状态和 internet context 的激活状态、APN、接口、IP 地址及 DNS，
+/// 供 watchdog 记录数据会话和连通性探测使用。
+///
+/// # Arguments
+/// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（为空时自动选择）
+///
+/// # Returns
+/// 数据连接上下文快照
+pub async fn get_data_context_snapshot(conn: &Connection, selected: Option<&str>) -> zbus::Result<DataContextSnapshot> {
+    let net_proxy = NetworkRegistrationProxy::new(conn).await?;
+    let net_props = net_proxy.get_properties().await?;
+    let network_status = net_props
+        .get("Status")
+        .and_then(|v| String::try_from(v.clone()).ok())
+        .unwrap_or_else(|| "unknown".to_string());
+    
+    let context_path = find_internet_context(conn, selected).await?;
+    let proxy = ConnectionContextProxy::builder(conn)
+        .path(context_path)?
+        .build()
+        .await?;
+    let props = proxy.get_properties().await?;
+    
+    let active = props
+        .get("Active")
+        .and_then(|v| bool::try_from(v.clone()).ok())
+        .unwrap_or(false);
+    
+    let apn = props
+        .get("AccessPointName")
+        .and_then(|v| String::try_from(v.clone()).ok())
+        .unwrap_or_default();
+    
+    let mut interface = get_settings_string(&props, "Settings", "Interface");
+    if interface.is_empty() {
+        interface = get_settings_string(&props, "IPv6.Settings", "Interface");
+    }
+    
+    let dns_servers = ["Settings", "IPv6.Settings"]
+        .iter()
+        .flat_map(|key| {
+            get_settings_dict(&props, key)
+                .get("DomainNameServers")
+                .and_then(|v| <Vec<String>>::try_from(v.clone()).ok())
+                .unwrap_or_default()
+        })
+        .collect();
+    
+    Ok(DataContextSnapshot {
+        network_status,
+        active,
+        apn,
+        interface,
+        ipv4_address: get_settings_string(&props, "Settings", "Address"),
+        ipv6_address: get_settings_string(&props, "IPv6.Settings", "Address"),
+        dns_servers,
+    })
+}
+
 /// 获取漫游状态
 ///
 /// # Arguments
@@ -478,10 +688,11 @@ pub async fn set_roaming_allowed(conn: &Connection, allowed: bool) -> zbus::Resu
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `selected` - 手动指定的 watchdog context（为空时自动选择）
 ///
 /// # Returns
 /// 初始化结果消息
-pub async fn init_data_connection(conn: &Connection) -> String {
+pub async fn init_data_connection(conn: &Connection, selected: Option<&str>) -> String {
     // 1. 先检查网络注册状态
     match NetworkRegistrationProxy::new(conn).await {
         Ok(net_proxy) => {
@@ -502,7 +713,7 @@ pub async fn init_data_connection(conn: &Connection) -> String {
     }
     
     // 2. 自动查找有效的 internet context
-    let context_path = match find_internet_context(conn).await {
+    let context_path = match find_internet_context(conn, selected).await {
         Ok(path) => path,
         Err(e) => {
             return format!("Failed to find internet context: {}", e);
@@ -547,96 +758,173 @@ pub async fn init_data_connection(conn: &Connection) -> String {
     }
     
     // 6. 尝试激活数据连接
-    match set_data_connection(conn, true).await {
+    match set_data_connection(conn, selected, true).await {
         Ok(_) => format!("Data connection activated on {} (APN: {})", context_path, apn),
         Err(e) => format!("Failed to activate data connection: {}", e),
     }
 }
 
-/// 根据 MCC/MNC 获取推荐的 APN 配置
+/// 获取 SIM 卡标识（归属运营商 MCC/MNC、SPN、ICCID、IMSI）
 ///
 /// # Arguments
-/// * `mcc` - 移动国家代码
-/// * `mnc` - 移动网络代码
-///
-/// # Returns
-/// (apn, protocol) 元组，如果未找到则返回 None
-fn get_recommended_apn(mcc: &str, mnc: &str) -> Option<(&'static str, &'static str)> {
-    match (mcc, mnc) {
-        // 中国移动 (46000, 46002, 46007, 46008)
-        ("460", "00") | ("460", "02") | ("460", "07") | ("460", "08") => Some(("cmnet", "dual")),
-        // 中国联通 (46001, 46006, 46009)
-        ("460", "01") | ("460", "06") | ("460", "09") => Some(("3gnet", "dual")),
-        // 中国电信 (46003, 46005, 46011)
-        ("460", "03") | ("460", "05") | ("460", "11") => Some(("ctnet", "dual")),
-        // 中国广电 (46015)
-        ("460", "15") => Some(("cbnet", "dual")),
-        _ => None,
-    }
+/// * `conn` - D-Bus 连接
+pub async fn get_sim_identity(conn: &Connection) -> zbus::Result<ApnSimIdentity> {
+    let sim_proxy = SimManagerProxy::new(conn).await?;
+    let props = sim_proxy.get_properties().await?;
+    let get = |key: &str| {
+        props
+            .get(key)
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .unwrap_or_default()
+    };
+    Ok(ApnSimIdentity {
+        mcc: get("MobileCountryCode"),
+        mnc: get("MobileNetworkCode"),
+        spn: get("ServiceProviderName"),
+        iccid: get("CardIdentifier"),
+        imsi: get("SubscriberIdentity"),
+    })
 }
 
-/// 自动配置 APN（根据 SIM 卡运营商）
+/// 自动配置 APN（根据 SIM 卡归属运营商查询 APN 数据库）
 ///
-/// 根据 SIM 卡的 MCC/MNC 自动查找并设置推荐的 APN 配置
+/// 使用 SIM 卡的 MCC/MNC（而非当前注册网络）匹配，漫游时使用条目的漫游协议。
+/// 配置结果记录到配置文件，watchdog 据此判断 SIM 卡是否更换、是否需要设置 MTU。
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
 /// * `context_path` - 要配置的 context 路径
+/// * `config` - 配置管理器（自定义 APN 条目）
 ///
 /// # Returns
 /// 配置结果消息
-async fn auto_configure_apn(conn: &Connection, context_path: &str) -> Result<String, String> {
-    // 1. 获取网络注册信息中的 MCC/MNC
-    let net_proxy = NetworkRegistrationProxy::new(conn)
+pub async fn auto_configure_apn(
+    conn: &Connection,
+    context_path: &str,
+    config: &crate::config::ConfigManager,
+) -> Result<String, String> {
+    // 1. 获取 SIM 卡标识，读不到时退回注册网络的 MCC/MNC
+    let mut sim = get_sim_identity(conn)
         .await
-        .map_err(|e| format!("Failed to create network proxy: {}", e))?;
+        .map_err(|e| format!("Failed to get SIM info: {}", e))?;
     
-    let props = net_proxy
+    let net_props = NetworkRegistrationProxy::new(conn)
+        .await
+        .map_err(|e| format!("Failed to create network proxy: {}", e))?
         .get_properties()
         .await
         .map_err(|e| format!("Failed to get network properties: {}", e))?;
+    let net_string = |key: &str| {
+        net_props
+            .get(key)
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .unwrap_or_default()
+    };
     
-    let mcc = props
-        .get("MobileCountryCode")
-        .and_then(|v| String::try_from(v.clone()).ok())
-        .unwrap_or_default();
-    
-    let mnc = props
-        .get("MobileNetworkCode")
-        .and_then(|v| String::try_from(v.clone()).ok())
-        .unwrap_or_default();
-    
-    if mcc.is_empty() || mnc.is_empty() {
+    if sim.mcc.is_empty() || sim.mnc.is_empty() {
+        sim.mcc = net_string("MobileCountryCode");
+        sim.mnc = net_string("MobileNetworkCode");
+    }
+    if sim.mcc.is_empty() || sim.mnc.is_empty() {
         return Err("MCC/MNC not available".to_string());
     }
+    let roaming = net_string("Status") == "roaming";
     
     // 2. 查找推荐 APN
-    let (apn, protocol) = get_recommended_apn(&mcc, &mnc)
-        .ok_or_else(|| format!("No recommended APN for MCC={} MNC={}", mcc, mnc))?;
-    
-    // 3. 设置 APN 和协议
-    set_apn_property(conn, context_path, "AccessPointName", apn)
-        .await
-        .map_err(|e| format!("Failed to set APN: {}", e))?;
-    
-    set_apn_property(conn, context_path, "Protocol", protocol)
-        .await
-        .map_err(|e| format!("Failed to set protocol: {}", e))?;
+    let apn_config = config.get_apn();
+    let recommended = crate::apn_db::recommend(&apn_config.entries, &sim)
+        .ok_or_else(|| format!("No recommended APN for MCC={} MNC={}", sim.mcc, sim.mnc))?;
+    let entry = &recommended.entry;
+    let protocol = crate::apn_db::ofono_protocol(entry, roaming);
+    let auth_method = crate::apn_db::ofono_auth_method(entry);
+    
+    // 3. 设置 APN、协议和认证信息
+    set_apn_properties(
+        conn,
+        context_path,
+        Some(&entry.apn),
+        Some(protocol),
+        Some(&entry.user),
+        Some(&entry.password),
+        Some(auth_method),
+    )
+    .await
+    .map_err(|e| format!("Failed to set APN: {}", e))?;
+    
+    let provisioned = crate::config::ProvisionedApn {
+        iccid: sim.iccid.clone(),
+        carrier: entry.carrier.clone(),
+        apn: entry.apn.clone(),
+        source: recommended.source.clone(),
+        mtu: entry.mtu,
+        provisioned_at: chrono::Utc::now().to_rfc3339(),
+    };
+    if let Err(e) = config.set_provisioned_apn(provisioned) {
+        warn!(error = %e, "Failed to record provisioned APN");
+    }
     
-    Ok(format!("Auto-configured APN: {} ({})", apn, protocol))
+    info!(
+        mcc = %sim.mcc,
+        mnc = %sim.mnc,
+        carrier = %entry.carrier,
+        apn = %entry.apn,
+        source = %recommended.source,
+        "APN auto-provisioned"
+    );
+    Ok(format!("Auto-configured APN: {} ({}, {})", entry.apn, protocol, entry.carrier))
 }
 
-/// 检查并恢复数据连接
+/// SIM 卡是否已更换（与上次自动配置时的 ICCID 不同）
 ///
-/// 这个函数被 watchdog 调用，检查数据连接状态并在需要时恢复
+/// 从未自动配置过时记录当前 ICCID 而不视为更换，避免覆盖用户手动设置的 APN
+///
+/// # Returns
+/// 已更换时返回新 SIM 卡的 ICCID
+async fn sim_changed_since_provisioning(conn: &Connection, config: &crate::config::ConfigManager) -> Option<String> {
+    let sim = get_sim_identity(conn).await.ok()?;
+    if sim.iccid.is_empty() {
+        return None;
+    }
+    match config.get_apn().provisioned {
+        Some(provisioned) => (provisioned.iccid != sim.iccid).then_some(sim.iccid),
+        None => {
+            let _ = config.set_provisioned_apn(crate::config::ProvisionedApn {
+                iccid: sim.iccid,
+                provisioned_at: chrono::Utc::now().to_rfc3339(),
+                ..Default::default()
+            });
+            None
+        }
+    }
+}
+
+/// 检查数据连接
+///
+/// 这个函数被 watchdog 调用，检查数据连接状态，在 APN 为空或更换 SIM 卡时自动配置 APN。
+/// 断开后的恢复动作由恢复阶梯执行（见 [`crate::watchdog`]）。
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
+/// * `config` - 配置管理器（APN 自动配置）
+/// * `sessions` - 数据会话追踪器（自动配置 APN 前标记会话结束原因）
 ///
 /// # Returns
-/// 当前状态描述字符串
-async fn check_and_restore_data_connection(conn: &Connection) -> String {
-    // 1. 检查网络注册状态
+/// (状态描述, 检查结果)
+async fn check_data_connection(
+    conn: &Connection,
+    config: &crate::config::ConfigManager,
+    sessions: &crate::data_session::DataSessionTracker,
+) -> (String, DataHealth) {
+    // 1. 用户开启飞行模式时不恢复
+    match get_airplane_mode(conn).await {
+        Ok(airplane) if airplane.enabled => {
+            return ("Airplane mode enabled".to_string(), DataHealth::Idle)
+        }
+        Ok(_) => {}
+        Err(e) => return (format!("Modem unavailable: {}", e), DataHealth::Down),
+    }
+    
+    // 2. 检查网络注册状态
     let net_status = match NetworkRegistrationProxy::new(conn).await {
         Ok(net_proxy) => {
             match net_proxy.get_properties().await {
@@ -647,35 +935,49 @@ async fn check_and_restore_data_connection(conn: &Connection) -> String {
                 Err(_) => "unknown".to_string(),
             }
         }
-        Err(_) => return "Network proxy unavailable".to_string(),
+        Err(_) => return ("Network proxy unavailable".to_string(), DataHealth::Down),
     };
     
-    // 网络未注册时不尝试恢复
+    // 网络未注册：无 SIM 卡时无需恢复，否则计为未注册（只执行到重新注册、重启 ofono）
     if net_status != "registered" && net_status != "roaming" {
-        return format!("Waiting for network (status: {})", net_status);
+        let sim_present = match SimManagerProxy::new(conn).await {
+            Ok(proxy) => proxy
+                .get_properties()
+                .await
+                .ok()
+                .and_then(|props| props.get("Present").and_then(|v| bool::try_from(v.clone()).ok()))
+                .unwrap_or(true),
+            Err(_) => true,
+        };
+        if !sim_present {
+            return ("SIM card not present".to_string(), DataHealth::Idle);
+        }
+        return (format!("Waiting for network (status: {})", net_status), DataHealth::Unregistered);
     }
     
-    // 2. 查找 internet context
-    let context_path = match find_internet_context(conn).await {
+    // 3. 查找 internet context
+    let selected = config.get_watchdog_context();
+    let selected = selected.as_deref();
+    let context_path = match find_internet_context(conn, selected).await {
         Ok(path) => path,
-        Err(e) => return format!("No internet context: {}", e),
+        Err(e) => return (format!("No internet context: {}", e), DataHealth::Idle),
     };
     
-    // 3. 获取 context 属性
+    // 4. 获取 context 属性
     let proxy = match ConnectionContextProxy::builder(conn)
         .path(context_path.as_str())
         .and_then(|b| Ok(b))
     {
         Ok(builder) => match builder.build().await {
             Ok(p) => p,
-            Err(e) => return format!("Context proxy error: {}", e),
+            Err(e) => return (format!("Context proxy error: {}", e), DataHealth::Down),
         },
-        Err(e) => return format!("Context path error: {}", e),
+        Err(e) => return (format!("Context path error: {}", e), DataHealth::Idle),
     };
     
     let props = match proxy.get_properties().await {
         Ok(p) => p,
-        Err(e) => return format!("Get properties error: {}", e),
+        Err(e) => return (format!("Get properties error: {}", e), DataHealth::Down),
     };
     
     let apn = props
@@ -688,80 +990,146 @@ async fn check_and_restore_data_connection(conn: &Connection) -> String {
         .and_then(|v| bool::try_from(v.clone()).ok())
         .unwrap_or(false);
     
-    // 4. 如果 APN 为空，尝试自动配置
-    if apn.is_empty() {
-        match auto_configure_apn(conn, &context_path).await {
-            Ok(msg) => {
-                // APN 配置成功后，继续尝试激活
-                match set_data_connection(conn, true).await {
-                    Ok(_) => return format!("{}, connection activated", msg),
-                    Err(e) => return format!("{}, but activation failed: {}", msg, e),
+    // 5. 如果 APN 为空或更换了 SIM 卡，按 APN 数据库自动配置
+    if config.get_apn().auto_provision {
+        let new_sim = if apn.is_empty() {
+            None
+        } else {
+            sim_changed_since_provisioning(conn, config).await
+        };
+        if apn.is_empty() || new_sim.is_some() {
+            sessions.mark_end_reason(crate::data_session::SessionEndReason::WatchdogRestore);
+            match auto_configure_apn(conn, &context_path, config).await {
+                Ok(msg) => {
+                    // APN 配置成功后，继续尝试激活
+                    match set_data_connection(conn, selected, true).await {
+                        Ok(_) => return (format!("{}, connection activated", msg), DataHealth::Healthy),
+                        Err(e) => return (format!("{}, but activation failed: {}", msg, e), DataHealth::Down),
+                    }
+                }
+                Err(e) if apn.is_empty() => return (format!("APN not configured: {}", e), DataHealth::Idle),
+                Err(e) => {
+                    // 数据库中没有新 SIM 卡的条目，保留当前 APN，不再重复尝试
+                    warn!(error = %e, apn = %apn, "Watchdog: SIM changed, keeping current APN");
+                    let _ = config.set_provisioned_apn(crate::config::ProvisionedApn {
+                        iccid: new_sim.unwrap_or_default(),
+                        apn: apn.clone(),
+                        provisioned_at: chrono::Utc::now().to_rfc3339(),
+                        ..Default::default()
+                    });
                 }
             }
-            Err(e) => return format!("APN not configured: {}", e),
         }
     }
     
-    // 5. 如果连接未激活，尝试激活
+    if apn.is_empty() {
+        return ("APN not configured (auto provisioning disabled)".to_string(), DataHealth::Idle);
+    }
+    
+    // 6. 连接未激活，交给恢复阶梯处理
     if !active {
-        match set_data_connection(conn, true).await {
-            Ok(_) => return format!("Connection restored (APN: {})", apn),
-            Err(e) => return format!("Activation failed: {}", e),
+        return (format!("Context inactive (APN: {})", apn), DataHealth::Down);
+    }
+    
+    // 7. 连接正常，按自动配置的条目设置 MTU
+    let interface = get_settings_string(&props, "Settings", "Interface");
+    if let (Some(mtu), false) = (config.get_apn().provisioned.and_then(|p| p.mtu), interface.is_empty()) {
+        match crate::apn_db::ensure_mtu(&interface, mtu) {
+            Ok(true) => info!(interface = %interface, mtu, "Watchdog: MTU applied"),
+            Ok(false) => {}
+            Err(e) => warn!(interface = %interface, error = %e, "Watchdog: failed to set MTU"),
         }
     }
     
-    // 6. 连接正常
-    format!("Connected (APN: {})", apn)
+    (format!("Connected (APN: {})", apn), DataHealth::Healthy)
 }
 
 /// 数据连接 Watchdog - 后台轮询监控并自动恢复
 ///
-/// 持续监控数据连接状态，在断开时自动尝试恢复。
-/// 支持自动识别运营商并配置 APN。
+/// 持续监控数据连接状态，在断开时按配置的恢复阶梯逐级恢复（见 [`crate::watchdog`]）。
+/// 支持自动识别运营商并配置 APN。检查间隔和恢复阶梯每次轮询时从配置读取。
+///
+/// 每次轮询同时采集数据会话快照，写入会话记录（见 [`crate::data_session`]）。
 ///
 /// # Arguments
 /// * `conn` - D-Bus 连接
-/// * `interval_secs` - 检查间隔（秒）
-pub async fn data_connection_watchdog(conn: std::sync::Arc<Connection>, interval_secs: u64) {
-    use crate::iptables::{flush_iptables, get_iptables_rule_count};
-    
+/// * `db` - 数据库（用于记录数据会话和恢复动作）
+/// * `config` - 配置管理器（APN 自动配置、watchdog 配置）
+/// * `firewall` - 防火墙管理器（每次轮询检查托管链）
+/// * `cell_optimizer` / `safe_lock` - 有任务进行时暂停恢复阶梯
+/// * `sessions` - 数据会话追踪器
+pub async fn data_connection_watchdog(
+    conn: std::sync::Arc<Connection>,
+    db: std::sync::Arc<crate::db::Database>,
+    config: std::sync::Arc<crate::config::ConfigManager>,
+    firewall: std::sync::Arc<crate::firewall::FirewallManager>,
+    cell_optimizer: std::sync::Arc<crate::cell_optimizer::CellOptimizer>,
+    safe_lock: std::sync::Arc<crate::safe_lock::SafeLockManager>,
+    sessions: std::sync::Arc<crate::data_session::DataSessionTracker>,
+) {
+    use crate::watchdog::RecoveryController;
+    
+    let mut recovery = RecoveryController::new(db, std::sync::Arc::clone(&sessions));
     let mut last_data_log = String::new();
-    let mut last_iptables_action = false; // 上次是否清空了 iptables
+    let mut last_firewall_error = false; // 只在首次失败时打印日志
     
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(interval_secs)).await;
-        
-        // 1. 检查并清空 iptables 规则
-        match get_iptables_rule_count().await {
-            Ok(count) => {
-                if count.has_rules() {
-                    // 有规则，执行清空
-                    if let Err(e) = flush_iptables().await {
-                        warn!(error = %e, "Watchdog: iptables flush failed");
-                    } else {
-                        if !last_iptables_action {
-                            // 只在首次清空时打印日志
-                            info!(
-                                total = count.total(),
-                                ipv4 = count.ipv4_rules,
-                                ipv6 = count.ipv6_rules,
-                                "Watchdog: iptables flushed"
-                            );
-                        }
-                        last_iptables_action = true;
-                    }
-                } else {
-                    // 无规则，重置标志
-                    last_iptables_action = false;
-                }
+        let watchdog_config = config.get_watchdog();
+        tokio::time::sleep(tokio::time::Duration::from_secs(watchdog_config.interval_secs.max(1))).await;
+        
+        // 天线对准期间暂停，把 AT 通道让给对准采样
+        if crate::alignment::is_active() {
+            continue;
+        }
+        
+        // 1. 检查托管防火墙链，删除系统注入的有害规则
+        if let Err(e) = firewall.enforce(config.get_firewall()).await {
+            if !last_firewall_error {
+                warn!(error = %e, "Watchdog: firewall enforcement failed");
+            }
+            last_firewall_error = true;
+        } else {
+            last_firewall_error = false;
+        }
+        
+        // 2. 记录数据会话（需在恢复之前采样，否则断开后立即恢复会被视为同一会话）
+        let watchdog_context = config.get_watchdog_context();
+        let snapshot = match get_data_context_snapshot(&conn, watchdog_context.as_deref()).await {
+            Ok(snapshot) => {
+                sessions.update(&snapshot);
+                Some(snapshot)
             }
             Err(e) => {
-                warn!(error = %e, "Watchdog: iptables check failed");
+                warn!(error = %e, "Watchdog: data context snapshot failed");
+                None
             }
+        };
+        
+        // 小区优化、安全锁定观察期间锁定会导致掉网，暂停恢复阶梯
+        let paused = if cell_optimizer.is_running() {
+            Some("Paused: cell optimizer running")
+        } else if safe_lock.pending().is_some() {
+            Some("Paused: safe lock in grace period")
+        } else {
+            None
+        };
+        if let Some(reason) = paused {
+            recovery.suspend(reason, &watchdog_config);
+            if reason != last_data_log {
+                info!(status = %reason, "Watchdog: data connection");
+                last_data_log = reason.to_string();
+            }
+            continue;
         }
         
-        // 2. 检查并恢复数据连接
-        let result = check_and_restore_data_connection(&conn).await;
+        // 3. 检查数据连接（可选连通性探测），断开时按恢复阶梯处理
+        let (mut result, health) = check_data_connection(&conn, &config, &sessions).await;
+        if let Some(probe_failure) = recovery
+            .on_check(&conn, health, &result, snapshot.as_ref(), watchdog_context.as_deref(), &watchdog_config)
+            .await
+        {
+            result = probe_failure;
+        }
         
         // 只在状态变化时打印日志，避免刷屏
         if result != last_data_log {
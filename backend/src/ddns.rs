@@ -0,0 +1,784 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-19 09:42:30
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-19 09:42:30
+ * @FilePath: /udx710-backend/backend/src/ddns.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 动态域名（DDNS）模块
+//!
+//! 按 [`DdnsConfig`] 周期性读取 internet context 的 IPv4 地址和数据接口的全局
+//! IPv6 地址，地址变化或超过强制更新间隔时更新服务商的 A / AAAA 记录：
+//! - 自定义 HTTP GET / POST 模板
+//! - Cloudflare（API Token + Zone ID）、DuckDNS、DNSPod（ID + Token）、阿里云（AccessKey）
+//!
+//! IPv4 位于运营商 NAT 后（私网 / 100.64.0.0/10）时不更新 A 记录。更新失败后按
+//! MIN_RETRY_SECS 起翻倍退避，不超过 MAX_RETRY_SECS；每次更新写入 ddns_history。
+
+use base64::Engine;
+use reqwest::{Client, RequestBuilder};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::config::{ConfigManager, DdnsConfig, DdnsMethod, DdnsProvider};
+use crate::db::{Database, DdnsUpdateRecord};
+
+/// 失败重试的初始间隔（秒）
+const MIN_RETRY_SECS: u64 = 60;
+
+/// 失败重试的最长间隔（秒）
+const MAX_RETRY_SECS: u64 = 3600;
+
+/// 服务商请求超时
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 更新记录保留天数
+const HISTORY_RETENTION_DAYS: i64 = 30;
+
+/// 状态中附带的最近更新记录数
+const RECENT_UPDATES: i64 = 10;
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const DUCKDNS_API: &str = "https://www.duckdns.org/update";
+const DNSPOD_API: &str = "https://dnsapi.cn";
+const ALIYUN_API: &str = "https://alidns.aliyuncs.com/";
+
+/// 配置变更 / 立即更新通知
+static RELOAD: Notify = Notify::const_new();
+
+/// 下一轮忽略退避并强制更新
+static FORCE: AtomicBool = AtomicBool::new(false);
+
+/// 运行状态
+static STATUS: Mutex<Option<DdnsStatus>> = Mutex::new(None);
+
+/// 单条记录（A / AAAA）的状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DdnsRecordStatus {
+    pub record_type: String,
+    pub enabled: bool,
+    /// 当前数据连接地址
+    pub current_ip: Option<String>,
+    /// 最近一次成功更新的地址
+    pub published_ip: Option<String>,
+    pub last_update_at: Option<String>,
+    pub last_error: Option<String>,
+    /// 未更新的原因（如位于运营商 NAT 后）
+    pub skipped: Option<String>,
+}
+
+/// DDNS 状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DdnsStatus {
+    pub enabled: bool,
+    pub provider: String,
+    pub domain: String,
+    pub last_check_at: Option<String>,
+    /// 读取数据连接地址失败的原因
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub next_retry_at: Option<String>,
+    pub ipv4: DdnsRecordStatus,
+    pub ipv6: DdnsRecordStatus,
+    /// 最近的更新记录，按时间倒序
+    pub recent_updates: Vec<DdnsUpdateRecord>,
+}
+
+/// 通知 DDNS 按最新配置重新检查
+pub fn reload() {
+    RELOAD.notify_one();
+}
+
+/// 忽略退避和地址缓存，立即更新
+pub fn update_now() {
+    FORCE.store(true, Ordering::SeqCst);
+    RELOAD.notify_one();
+}
+
+/// 获取 DDNS 状态
+pub fn status(db: &Database, config: &DdnsConfig) -> DdnsStatus {
+    let mut status = STATUS.lock().ok().and_then(|s| s.clone()).unwrap_or_default();
+    status.enabled = config.enabled;
+    status.provider = config.provider.as_str().to_string();
+    status.domain = config.domain.clone();
+    status.ipv4.record_type = "A".to_string();
+    status.ipv4.enabled = config.ipv4;
+    status.ipv6.record_type = "AAAA".to_string();
+    status.ipv6.enabled = config.ipv6;
+    status.recent_updates = db.get_ddns_history(RECENT_UPDATES, 0).unwrap_or_default();
+    status
+}
+
+fn update_status(f: impl FnOnce(&mut DdnsStatus)) {
+    if let Ok(mut guard) = STATUS.lock() {
+        f(guard.get_or_insert_with(Default::default));
+    }
+}
+
+fn record_status(status: &mut DdnsStatus, ipv6: bool) -> &mut DdnsRecordStatus {
+    if ipv6 {
+        &mut status.ipv6
+    } else {
+        &mut status.ipv4
+    }
+}
+
+/// 返回 secret 已掩码的配置（用于 GET 接口）
+pub fn mask_secret(config: &DdnsConfig) -> DdnsConfig {
+    DdnsConfig {
+        secret: crate::utils::mask_secret(&config.secret),
+        ..config.clone()
+    }
+}
+
+/// 保存配置前还原未修改的 secret
+///
+/// 客户端回传掩码值或空值时，沿用当前配置中的 secret
+pub fn restore_secret(config: &mut DdnsConfig, current: &DdnsConfig) {
+    if config.secret.is_empty() || config.secret == crate::utils::mask_secret(&current.secret) {
+        config.secret = current.secret.clone();
+    }
+}
+
+/// 校验 DDNS 配置
+pub fn validate(config: &DdnsConfig) -> Result<(), String> {
+    if !(10..=86400).contains(&config.check_interval_secs) {
+        return Err("check_interval_secs must be between 10 and 86400".to_string());
+    }
+    if !config.enabled {
+        return Ok(());
+    }
+    if config.domain.trim().is_empty() {
+        return Err("domain is required".to_string());
+    }
+    if !config.ipv4 && !config.ipv6 {
+        return Err("At least one of ipv4 / ipv6 must be enabled".to_string());
+    }
+    if config.ttl == 0 {
+        return Err("ttl must be greater than 0".to_string());
+    }
+    match config.provider {
+        DdnsProvider::Custom => {
+            let url = &config.custom.url;
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err("custom.url must be an HTTP(S) URL".to_string());
+            }
+        }
+        DdnsProvider::Cloudflare => {
+            if config.zone.is_empty() || config.secret.is_empty() {
+                return Err("zone (Zone ID) and secret (API token) are required for Cloudflare".to_string());
+            }
+        }
+        DdnsProvider::Duckdns => {
+            if config.secret.is_empty() {
+                return Err("secret (token) is required for DuckDNS".to_string());
+            }
+        }
+        DdnsProvider::Dnspod | DdnsProvider::Aliyun => {
+            if config.key_id.is_empty() || config.secret.is_empty() {
+                return Err(format!("key_id and secret are required for {}", config.provider.as_str()));
+            }
+            if config.zone.is_empty() && config.domain.trim_end_matches('.').split('.').count() < 2 {
+                return Err("zone is required when domain has no parent domain".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 连续失败 n 次后的重试间隔（秒）
+fn backoff_secs(failures: u32) -> u64 {
+    if failures == 0 {
+        return 0;
+    }
+    (MIN_RETRY_SECS << (failures - 1).min(10)).min(MAX_RETRY_SECS)
+}
+
+/// 拆分主机记录和主域名，zone 为空时取域名后两段
+///
+/// # Returns
+/// (主机记录, 主域名)，域名与主域名相同时主机记录为 `@`
+fn split_domain(domain: &str, zone: &str) -> (String, String) {
+    let domain = domain.trim_end_matches('.');
+    let zone = if zone.is_empty() {
+        let labels: Vec<&str> = domain.split('.').collect();
+        labels[labels.len().saturating_sub(2)..].join(".")
+    } else {
+        zone.trim_end_matches('.').to_string()
+    };
+    let sub = if domain == zone {
+        "@"
+    } else {
+        domain.strip_suffix(&format!(".{}", zone)).unwrap_or(domain)
+    };
+    (sub.to_string(), zone)
+}
+
+/// 替换模板中的 {{变量}}
+fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_string(), |acc, (name, value)| {
+        acc.replace(&format!("{{{{{}}}}}", name), value)
+    })
+}
+
+/// RFC 3986 编码（阿里云签名要求空格为 %20、保留 ~）
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn canonical_query(params: &BTreeMap<String, String>) -> String {
+    params
+        .iter()
+        .map(|(k, v)| format!("{}={}", percent_encode(k), percent_encode(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// 阿里云 RPC 签名（HMAC-SHA1，密钥为 AccessKeySecret + "&"）
+fn aliyun_signature(secret: &str, method: &str, params: &BTreeMap<String, String>) -> String {
+    let string_to_sign = format!(
+        "{}&{}&{}",
+        method,
+        percent_encode("/"),
+        percent_encode(&canonical_query(params))
+    );
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, format!("{}&", secret).as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(ring::hmac::sign(&key, string_to_sign.as_bytes()).as_ref())
+}
+
+/// 截断响应内容用于记录
+fn summarize(body: &str) -> String {
+    body.trim().chars().take(200).collect()
+}
+
+/// 读取响应 JSON
+async fn send_json(request: RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    serde_json::from_str(&body).map_err(|_| format!("HTTP {}: {}", status.as_u16(), summarize(&body)))
+}
+
+/// 自定义模板
+async fn update_custom(client: &Client, config: &DdnsConfig, vars: &[(&str, &str)]) -> Result<String, String> {
+    let custom = &config.custom;
+    let url = render_template(&custom.url, vars);
+    let mut request = match custom.method {
+        DdnsMethod::Get => client.get(&url),
+        DdnsMethod::Post => client.post(&url).body(render_template(&custom.body, vars)),
+    };
+    for (key, value) in &custom.headers {
+        request = request.header(key, render_template(value, vars));
+    }
+    let response = request.send().await.map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!("HTTP {}: {}", status.as_u16(), summarize(&body)));
+    }
+    if !custom.success_keyword.is_empty() && !body.contains(&custom.success_keyword) {
+        return Err(format!("Response does not contain \"{}\": {}", custom.success_keyword, summarize(&body)));
+    }
+    Ok(format!("HTTP {}: {}", status.as_u16(), summarize(&body)))
+}
+
+/// DuckDNS（域名可只填子域名）
+async fn update_duckdns(client: &Client, config: &DdnsConfig, record_type: &str, ip: &str) -> Result<String, String> {
+    let sub = config.domain.trim_end_matches('.').trim_end_matches(".duckdns.org");
+    let ip_param = if record_type == "A" { "ip" } else { "ipv6" };
+    let response = client
+        .get(DUCKDNS_API)
+        .query(&[("domains", sub), ("token", config.secret.as_str()), (ip_param, ip)])
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    let body = response.text().await.unwrap_or_default();
+    if body.trim() == "OK" {
+        Ok("Record updated".to_string())
+    } else {
+        Err(format!("DuckDNS returned: {}", summarize(&body)))
+    }
+}
+
+async fn cloudflare_call(request: RequestBuilder, token: &str) -> Result<Value, String> {
+    let body = send_json(request.bearer_auth(token)).await?;
+    if body["success"].as_bool() == Some(true) {
+        Ok(body)
+    } else {
+        Err(format!("Cloudflare API error: {}", body["errors"]))
+    }
+}
+
+/// Cloudflare（zone 为 Zone ID）
+async fn update_cloudflare(client: &Client, config: &DdnsConfig, record_type: &str, ip: &str) -> Result<String, String> {
+    let base = format!("{}/zones/{}/dns_records", CLOUDFLARE_API, config.zone);
+    let list = cloudflare_call(
+        client.get(&base).query(&[("type", record_type), ("name", config.domain.as_str())]),
+        &config.secret,
+    )
+    .await?;
+    match list["result"].as_array().and_then(|r| r.first()) {
+        Some(record) if record["content"].as_str() == Some(ip) => Ok("Record already up to date".to_string()),
+        Some(record) => {
+            let id = record["id"].as_str().ok_or("Missing record id")?;
+            cloudflare_call(
+                client
+                    .patch(format!("{}/{}", base, id))
+                    .json(&json!({"content": ip, "ttl": config.ttl})),
+                &config.secret,
+            )
+            .await?;
+            Ok("Record updated".to_string())
+        }
+        None => {
+            cloudflare_call(
+                client.post(&base).json(&json!({
+                    "type": record_type,
+                    "name": config.domain,
+                    "content": ip,
+                    "ttl": config.ttl,
+                    "proxied": false,
+                })),
+                &config.secret,
+            )
+            .await?;
+            Ok("Record created".to_string())
+        }
+    }
+}
+
+async fn dnspod_call(client: &Client, action: &str, params: &[(&str, String)]) -> Result<Value, String> {
+    let body = send_json(client.post(format!("{}/{}", DNSPOD_API, action)).form(params)).await?;
+    if body["status"]["code"].as_str() == Some("1") {
+        Ok(body)
+    } else {
+        Err(format!(
+            "DNSPod API error: {}",
+            body["status"]["message"].as_str().unwrap_or("unknown error")
+        ))
+    }
+}
+
+/// DNSPod（login_token = ID,Token）
+async fn update_dnspod(client: &Client, config: &DdnsConfig, record_type: &str, ip: &str) -> Result<String, String> {
+    let (sub, zone) = split_domain(&config.domain, &config.zone);
+    let mut params = vec![
+        ("login_token", format!("{},{}", config.key_id, config.secret)),
+        ("format", "json".to_string()),
+        ("lang", "en".to_string()),
+        ("error_on_empty", "no".to_string()),
+        ("domain", zone),
+        ("sub_domain", sub),
+        ("record_type", record_type.to_string()),
+    ];
+    let list = dnspod_call(client, "Record.List", &params).await?;
+    params.push(("record_line_id", "0".to_string()));
+    params.push(("value", ip.to_string()));
+    params.push(("ttl", config.ttl.to_string()));
+    match list["records"].as_array().and_then(|r| r.first()) {
+        Some(record) if record["value"].as_str() == Some(ip) => Ok("Record already up to date".to_string()),
+        Some(record) => {
+            let id = record["id"].as_str().ok_or("Missing record id")?;
+            params.push(("record_id", id.to_string()));
+            dnspod_call(client, "Record.Modify", &params).await?;
+            Ok("Record updated".to_string())
+        }
+        None => {
+            dnspod_call(client, "Record.Create", &params).await?;
+            Ok("Record created".to_string())
+        }
+    }
+}
+
+async fn aliyun_call(client: &Client, config: &DdnsConfig, params: &[(&str, String)]) -> Result<Value, String> {
+    let nonce = format!(
+        "{}-{}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        std::process::id()
+    );
+    let mut query: BTreeMap<String, String> = [
+        ("Format", "JSON".to_string()),
+        ("Version", "2015-01-09".to_string()),
+        ("AccessKeyId", config.key_id.clone()),
+        ("SignatureMethod", "HMAC-SHA1".to_string()),
+        ("SignatureVersion", "1.0".to_string()),
+        ("SignatureNonce", nonce),
+        ("Timestamp", chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+    ]
+    .into_iter()
+    .chain(params.iter().cloned())
+    .map(|(k, v)| (k.to_string(), v))
+    .collect();
+    let signature = aliyun_signature(&config.secret, "GET", &query);
+    query.insert("Signature".to_string(), signature);
+
+    let body = send_json(client.get(format!("{}?{}", ALIYUN_API, canonical_query(&query)))).await?;
+    match body["Code"].as_str() {
+        Some(code) => Err(format!(
+            "Aliyun API error: {} {}",
+            code,
+            body["Message"].as_str().unwrap_or_default()
+        )),
+        None => Ok(body),
+    }
+}
+
+/// 阿里云 DNS（zone 为主域名）
+async fn update_aliyun(client: &Client, config: &DdnsConfig, record_type: &str, ip: &str) -> Result<String, String> {
+    let (rr, zone) = split_domain(&config.domain, &config.zone);
+    let full_name = if rr == "@" { zone.clone() } else { format!("{}.{}", rr, zone) };
+    let list = aliyun_call(
+        client,
+        config,
+        &[
+            ("Action", "DescribeSubDomainRecords".to_string()),
+            ("SubDomain", full_name),
+            ("Type", record_type.to_string()),
+        ],
+    )
+    .await?;
+    let mut params = vec![
+        ("RR", rr),
+        ("Type", record_type.to_string()),
+        ("Value", ip.to_string()),
+        ("TTL", config.ttl.to_string()),
+    ];
+    match list["DomainRecords"]["Record"].as_array().and_then(|r| r.first()) {
+        Some(record) if record["Value"].as_str() == Some(ip) => Ok("Record already up to date".to_string()),
+        Some(record) => {
+            let id = record["RecordId"].as_str().ok_or("Missing record id")?;
+            params.push(("Action", "UpdateDomainRecord".to_string()));
+            params.push(("RecordId", id.to_string()));
+            aliyun_call(client, config, &params).await?;
+            Ok("Record updated".to_string())
+        }
+        None => {
+            params.push(("Action", "AddDomainRecord".to_string()));
+            params.push(("DomainName", zone));
+            aliyun_call(client, config, &params).await?;
+            Ok("Record created".to_string())
+        }
+    }
+}
+
+/// 更新一条记录
+///
+/// # Returns
+/// * `Ok(String)` - 结果说明
+/// * `Err(String)` - 失败原因
+async fn update_record(
+    client: &Client,
+    config: &DdnsConfig,
+    record_type: &str,
+    ip: &str,
+    vars: &[(&str, &str)],
+) -> Result<String, String> {
+    match config.provider {
+        DdnsProvider::Custom => update_custom(client, config, vars).await,
+        DdnsProvider::Cloudflare => update_cloudflare(client, config, record_type, ip).await,
+        DdnsProvider::Duckdns => update_duckdns(client, config, record_type, ip).await,
+        DdnsProvider::Dnspod => update_dnspod(client, config, record_type, ip).await,
+        DdnsProvider::Aliyun => update_aliyun(client, config, record_type, ip).await,
+    }
+}
+
+/// 读取数据连接的 IPv4 地址和全局 IPv6 地址
+async fn current_addresses(
+    conn: &Connection,
+    watchdog_context: Option<&str>,
+) -> Result<(Option<Ipv4Addr>, Option<Ipv6Addr>), String> {
+    let ip = crate::dbus::get_data_ip_config(conn, watchdog_context)
+        .await
+        .map_err(|e| format!("Failed to get data context: {}", e))?;
+    if !ip.active {
+        return Err("Data connection is not active".to_string());
+    }
+    let ipv4 = ip.ipv4.address.parse::<Ipv4Addr>().ok().filter(|a| !a.is_unspecified());
+    let if_inet6 = std::fs::read_to_string("/proc/net/if_inet6").unwrap_or_default();
+    let ipv6 = crate::ipv6_lan::parse_global_ipv6(&if_inet6, &ip.interface)
+        .or_else(|| ip.ipv6.address.split('/').next()?.parse().ok())
+        // 2000::/3
+        .filter(|a: &Ipv6Addr| a.segments()[0] & 0xe000 == 0x2000);
+    Ok((ipv4, ipv6))
+}
+
+/// DDNS 主循环
+pub async fn run(conn: Arc<Connection>, config_manager: Arc<ConfigManager>, db: Arc<Database>) {
+    let client = Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .user_agent(concat!("udx710/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Failed to create HTTP client");
+
+    let mut last_config: Option<DdnsConfig> = None;
+    // 已发布的地址和发布时间（A, AAAA）
+    let mut published: [Option<(String, Instant)>; 2] = [None, None];
+    let mut failures: u32 = 0;
+    let mut retry_at: Option<Instant> = None;
+
+    loop {
+        let config = config_manager.get_ddns();
+        if last_config.as_ref() != Some(&config) {
+            // 配置变化后重新发布并清除退避
+            published = [None, None];
+            failures = 0;
+            retry_at = None;
+            if let Ok(mut status) = STATUS.lock() {
+                *status = None;
+            }
+            if config.enabled {
+                info!(provider = config.provider.as_str(), domain = %config.domain, "DDNS enabled");
+            }
+            last_config = Some(config.clone());
+        }
+        let force = FORCE.swap(false, Ordering::SeqCst);
+
+        if config.enabled {
+            update_status(|s| s.last_check_at = Some(chrono::Utc::now().to_rfc3339()));
+            match current_addresses(&conn, config_manager.get_watchdog_context().as_deref()).await {
+                Err(e) => update_status(|s| s.last_error = Some(e)),
+                Ok((ipv4, ipv6)) => {
+                    update_status(|s| s.last_error = None);
+                    let ipv4_text = ipv4.map(|a| a.to_string()).unwrap_or_default();
+                    let ipv6_text = ipv6.map(|a| a.to_string()).unwrap_or_default();
+
+                    // (是否启用, 待发布地址, 跳过原因)
+                    let ipv4_target = match ipv4 {
+                        Some(addr) if crate::ip_info::classify_nat(&ipv4_text, None).1 == Some(true) => {
+                            (None, Some(format!("IPv4 address {} is behind carrier NAT", addr)))
+                        }
+                        Some(_) => (Some(ipv4_text.clone()), None),
+                        None => (None, Some("No IPv4 address on the data connection".to_string())),
+                    };
+                    let ipv6_target = match ipv6 {
+                        Some(_) => (Some(ipv6_text.clone()), None),
+                        None => (None, Some("No global IPv6 address on the data connection".to_string())),
+                    };
+
+                    let retry_due = force || retry_at.is_none_or(|at| Instant::now() >= at);
+                    let mut attempted = false;
+                    let mut all_ok = true;
+
+                    for (family, (enabled, (target, skipped))) in
+                        [(config.ipv4, ipv4_target), (config.ipv6, ipv6_target)].into_iter().enumerate()
+                    {
+                        let ipv6_family = family == 1;
+                        let record_type = if ipv6_family { "AAAA" } else { "A" };
+                        update_status(|s| {
+                            let record = record_status(s, ipv6_family);
+                            record.current_ip = target.clone();
+                            record.skipped = if enabled { skipped } else { None };
+                        });
+                        let Some(ip) = target.filter(|_| enabled) else {
+                            continue;
+                        };
+                        let stale = match &published[family] {
+                            Some((addr, at)) => {
+                                *addr != ip
+                                    || (config.force_update_hours > 0
+                                        && at.elapsed() >= Duration::from_secs(config.force_update_hours * 3600))
+                            }
+                            None => true,
+                        };
+                        if !(stale || force) || !retry_due {
+                            continue;
+                        }
+
+                        attempted = true;
+                        let vars = [
+                            ("ip", ip.as_str()),
+                            ("type", record_type),
+                            ("domain", config.domain.as_str()),
+                            ("ipv4", ipv4_text.as_str()),
+                            ("ipv6", ipv6_text.as_str()),
+                            ("key_id", config.key_id.as_str()),
+                            ("secret", config.secret.as_str()),
+                        ];
+                        let result = update_record(&client, &config, record_type, &ip, &vars).await;
+                        let (success, detail) = match &result {
+                            Ok(detail) => (true, detail.clone()),
+                            Err(e) => (false, e.clone()),
+                        };
+                        if let Err(e) = db.insert_ddns_update(
+                            config.provider.as_str(),
+                            &config.domain,
+                            record_type,
+                            &ip,
+                            success,
+                            &detail,
+                        ) {
+                            warn!(error = %e, "Failed to save DDNS update");
+                        }
+
+                        let now = chrono::Utc::now().to_rfc3339();
+                        if success {
+                            info!(record_type, ip = %ip, detail = %detail, "DDNS record updated");
+                            update_status(|s| {
+                                let record = record_status(s, ipv6_family);
+                                record.published_ip = Some(ip.clone());
+                                record.last_update_at = Some(now);
+                                record.last_error = None;
+                            });
+                            published[family] = Some((ip, Instant::now()));
+                        } else {
+                            warn!(record_type, ip = %ip, error = %detail, "DDNS update failed");
+                            all_ok = false;
+                            update_status(|s| record_status(s, ipv6_family).last_error = Some(detail));
+                        }
+                    }
+
+                    if attempted {
+                        if all_ok {
+                            failures = 0;
+                            retry_at = None;
+                        } else {
+                            failures += 1;
+                            retry_at = Some(Instant::now() + Duration::from_secs(backoff_secs(failures)));
+                        }
+                        let cutoff = (chrono::Utc::now() - chrono::Duration::days(HISTORY_RETENTION_DAYS)).to_rfc3339();
+                        let _ = db.cleanup_ddns_history(&cutoff);
+                    }
+                }
+            }
+        }
+
+        update_status(|s| {
+            s.consecutive_failures = failures;
+            s.next_retry_at = retry_at.map(|at| {
+                let remaining = at.saturating_duration_since(Instant::now());
+                (chrono::Utc::now() + chrono::Duration::seconds(remaining.as_secs() as i64)).to_rfc3339()
+            });
+        });
+
+        let mut wait = Duration::from_secs(config.check_interval_secs.max(10));
+        if let Some(at) = retry_at {
+            wait = wait.min(at.saturating_duration_since(Instant::now()).max(Duration::from_secs(1)));
+        }
+        tokio::select! {
+            _ = RELOAD.notified() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_secs() {
+        assert_eq!(backoff_secs(0), 0);
+        assert_eq!(backoff_secs(1), 60);
+        assert_eq!(backoff_secs(2), 120);
+        assert_eq!(backoff_secs(4), 480);
+        assert_eq!(backoff_secs(7), MAX_RETRY_SECS);
+        assert_eq!(backoff_secs(100), MAX_RETRY_SECS);
+    }
+
+    #[test]
+    fn test_mask_and_restore_secret() {
+        let current = DdnsConfig {
+            secret: "cf-token-abcd1234".to_string(),
+            ..Default::default()
+        };
+        let masked = mask_secret(&current);
+        assert_eq!(masked.secret, "****1234");
+
+        // 回传掩码值或空值时保留原 secret，填写新值时替换
+        let mut update = masked.clone();
+        restore_secret(&mut update, &current);
+        assert_eq!(update.secret, "cf-token-abcd1234");
+        let mut update = DdnsConfig { secret: String::new(), ..masked.clone() };
+        restore_secret(&mut update, &current);
+        assert_eq!(update.secret, "cf-token-abcd1234");
+        let mut update = DdnsConfig { secret: "new-token".to_string(), ..masked };
+        restore_secret(&mut update, &current);
+        assert_eq!(update.secret, "new-token");
+    }
+
+    #[test]
+    fn test_split_domain() {
+        assert_eq!(split_domain("home.example.com", ""), ("home".to_string(), "example.com".to_string()));
+        assert_eq!(split_domain("example.com", ""), ("@".to_string(), "example.com".to_string()));
+        assert_eq!(
+            split_domain("a.b.example.com.cn", "example.com.cn"),
+            ("a.b".to_string(), "example.com.cn".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars = [("ip", "1.2.3.4"), ("domain", "home.example.com")];
+        assert_eq!(
+            render_template("https://api.example.com/update?host={{domain}}&ip={{ip}}&v6={{ipv6}}", &vars),
+            "https://api.example.com/update?host=home.example.com&ip=1.2.3.4&v6={{ipv6}}"
+        );
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("a b*~/:"), "a%20b%2A~%2F%3A");
+        assert_eq!(percent_encode("2025-12-19T01:02:03Z"), "2025-12-19T01%3A02%3A03Z");
+    }
+
+    #[test]
+    fn test_aliyun_signature() {
+        // 阿里云 RPC 签名文档示例
+        let params: BTreeMap<String, String> = [
+            ("AccessKeyId", "testid"),
+            ("Action", "DescribeDomainRecords"),
+            ("DomainName", "example.com"),
+            ("Format", "XML"),
+            ("SignatureMethod", "HMAC-SHA1"),
+            ("SignatureNonce", "f59ed6a9-83fc-473b-9cc6-99c95df3856e"),
+            ("SignatureVersion", "1.0"),
+            ("Timestamp", "2016-03-24T16:41:54Z"),
+            ("Version", "2015-01-09"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(aliyun_signature("testsecret", "GET", &params), "uRpHwaSEt3J+6KQD//svCh/x+pI=");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = DdnsConfig {
+            enabled: true,
+            provider: DdnsProvider::Cloudflare,
+            domain: "home.example.com".to_string(),
+            ..Default::default()
+        };
+        assert!(validate(&config).is_err());
+        config.zone = "023e105f4ecef8ad9ca31a8372d0c353".to_string();
+        config.secret = "token".to_string();
+        assert!(validate(&config).is_ok());
+
+        config.ipv4 = false;
+        config.ipv6 = false;
+        assert!(validate(&config).is_err());
+
+        let custom = DdnsConfig {
+            enabled: true,
+            domain: "home.example.com".to_string(),
+            ..Default::default()
+        };
+        assert!(validate(&custom).is_err());
+    }
+}
@@ -0,0 +1,675 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-17 15:40:26
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-17 15:40:26
+ * @FilePath: /udx710-backend/backend/src/dhcp.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 内置 DHCPv4 服务
+//!
+//! 在 LAN 接口上监听 UDP 67 端口（SO_BINDTODEVICE），按 [`LanConfig`] 的地址池分配地址：
+//! - DISCOVER：优先沿用该 MAC 的租约，其次客户端请求的地址，最后地址池中第一个空闲地址
+//! - REQUEST：地址可用时 ACK 并写入数据库，否则 NAK
+//! - RELEASE / DECLINE / INFORM
+//!
+//! 未配置 DNS 服务器时下发运营商 DNS（数据连接断开时沿用上次获取的地址）。
+//! 租约保存在数据库中，重启后继续有效。配置变更时调用 [`reload`] 重新绑定。
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+use zbus::Connection;
+
+use crate::config::{ConfigManager, LanConfig};
+use crate::db::Database;
+use crate::lan::{format_mac, netmask, subnet};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// BOOTP 固定头部长度（不含 magic cookie）
+const BOOTP_HEADER_LEN: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// OFFER 后为客户端保留地址的时间（秒）
+const OFFER_HOLD_SECS: i64 = 60;
+
+/// DECLINE 的地址暂停分配的时间（秒）
+const DECLINE_HOLD_SECS: i64 = 600;
+
+// 消息类型（option 53）
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_DECLINE: u8 = 4;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+const DHCP_RELEASE: u8 = 7;
+const DHCP_INFORM: u8 = 8;
+
+// 选项
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_HOSTNAME: u8 = 12;
+const OPT_BROADCAST: u8 = 28;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+const OPT_PAD: u8 = 0;
+
+/// 配置变更通知
+static RELOAD: Notify = Notify::const_new();
+
+/// 通知 DHCP 服务按最新配置重新启动
+pub fn reload() {
+    RELOAD.notify_one();
+}
+
+/// 解析后的 DHCP 报文
+#[derive(Debug, Clone)]
+pub struct DhcpPacket {
+    pub xid: u32,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub options: HashMap<u8, Vec<u8>>,
+}
+
+impl DhcpPacket {
+    /// 解析客户端报文（仅接受以太网 BOOTREQUEST）
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < BOOTP_HEADER_LEN + 4 || buf[0] != 1 || buf[1] != 1 || buf[2] != 6 {
+            return None;
+        }
+        if buf[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4] != MAGIC_COOKIE {
+            return None;
+        }
+        let ip = |offset: usize| Ipv4Addr::new(buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]);
+        let mut chaddr = [0u8; 16];
+        chaddr.copy_from_slice(&buf[28..44]);
+
+        let mut options = HashMap::new();
+        let mut i = BOOTP_HEADER_LEN + 4;
+        while i < buf.len() {
+            match buf[i] {
+                OPT_PAD => i += 1,
+                OPT_END => break,
+                code => {
+                    let len = *buf.get(i + 1)? as usize;
+                    let value = buf.get(i + 2..i + 2 + len)?;
+                    options.insert(code, value.to_vec());
+                    i += 2 + len;
+                }
+            }
+        }
+
+        Some(Self {
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            flags: u16::from_be_bytes([buf[10], buf[11]]),
+            ciaddr: ip(12),
+            giaddr: ip(24),
+            chaddr,
+            options,
+        })
+    }
+
+    pub fn message_type(&self) -> Option<u8> {
+        self.options.get(&OPT_MESSAGE_TYPE).and_then(|v| v.first().copied())
+    }
+
+    /// 客户端 MAC（小写，冒号分隔）
+    pub fn mac(&self) -> String {
+        format_mac(&self.chaddr[..6])
+    }
+
+    fn option_ip(&self, code: u8) -> Option<Ipv4Addr> {
+        self.options
+            .get(&code)
+            .filter(|v| v.len() == 4)
+            .map(|v| Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+    }
+
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.option_ip(OPT_REQUESTED_IP)
+    }
+
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.option_ip(OPT_SERVER_ID)
+    }
+
+    /// 客户端主机名（过滤不可打印字符）
+    pub fn hostname(&self) -> String {
+        self.options
+            .get(&OPT_HOSTNAME)
+            .map(|v| {
+                String::from_utf8_lossy(v)
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .take(63)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// 应答报文中下发的网络参数
+#[derive(Debug, Clone)]
+pub struct ServerParams {
+    pub server_ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub broadcast: Ipv4Addr,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time_secs: u32,
+}
+
+impl ServerParams {
+    /// # Arguments
+    /// * `config` - LAN 配置
+    /// * `upstream_dns` - 运营商 DNS，配置中未指定 DNS 时下发
+    pub fn from_config(config: &LanConfig, upstream_dns: &[Ipv4Addr]) -> Option<Self> {
+        let server_ip: Ipv4Addr = config.ip_address.parse().ok()?;
+        let mut dns_servers: Vec<Ipv4Addr> = config.dns_servers.iter().filter_map(|d| d.parse().ok()).collect();
+        if dns_servers.is_empty() {
+            dns_servers = upstream_dns.to_vec();
+        }
+        Some(Self {
+            server_ip,
+            netmask: netmask(config.prefix_len),
+            broadcast: subnet(server_ip, config.prefix_len).1,
+            dns_servers,
+            lease_time_secs: config.lease_time_secs,
+        })
+    }
+}
+
+/// 构造应答报文
+///
+/// # Arguments
+/// * `request` - 客户端报文
+/// * `message_type` - OFFER / ACK / NAK
+/// * `yiaddr` - 分配的地址（NAK 和 INFORM 的 ACK 为 0.0.0.0）
+/// * `params` - 网络参数
+/// * `with_lease` - 是否携带租期（INFORM 的 ACK 不携带）
+pub fn build_reply(
+    request: &DhcpPacket,
+    message_type: u8,
+    yiaddr: Ipv4Addr,
+    params: &ServerParams,
+    with_lease: bool,
+) -> Vec<u8> {
+    let mut buf = vec![0u8; BOOTP_HEADER_LEN];
+    buf[0] = 2; // BOOTREPLY
+    buf[1] = 1;
+    buf[2] = 6;
+    buf[4..8].copy_from_slice(&request.xid.to_be_bytes());
+    buf[10..12].copy_from_slice(&request.flags.to_be_bytes());
+    if message_type != DHCP_NAK {
+        buf[12..16].copy_from_slice(&request.ciaddr.octets());
+    }
+    buf[16..20].copy_from_slice(&yiaddr.octets());
+    buf[24..28].copy_from_slice(&request.giaddr.octets());
+    buf[28..44].copy_from_slice(&request.chaddr);
+    buf.extend_from_slice(&MAGIC_COOKIE);
+
+    let mut option = |code: u8, value: &[u8]| {
+        buf.push(code);
+        buf.push(value.len() as u8);
+        buf.extend_from_slice(value);
+    };
+    option(OPT_MESSAGE_TYPE, &[message_type]);
+    option(OPT_SERVER_ID, &params.server_ip.octets());
+    if message_type != DHCP_NAK {
+        if with_lease {
+            let lease = params.lease_time_secs;
+            option(OPT_LEASE_TIME, &lease.to_be_bytes());
+            option(OPT_RENEWAL_TIME, &(lease / 2).to_be_bytes());
+            option(OPT_REBINDING_TIME, &(lease / 8 * 7).to_be_bytes());
+        }
+        option(OPT_SUBNET_MASK, &params.netmask.octets());
+        option(OPT_BROADCAST, &params.broadcast.octets());
+        option(OPT_ROUTER, &params.server_ip.octets());
+        if !params.dns_servers.is_empty() {
+            let dns: Vec<u8> = params.dns_servers.iter().flat_map(|d| d.octets()).collect();
+            option(OPT_DNS, &dns);
+        }
+    }
+    buf.push(OPT_END);
+    // BOOTP 最小报文长度 300 字节
+    if buf.len() < 300 {
+        buf.resize(300, 0);
+    }
+    buf
+}
+
+/// 内存中的租约
+#[derive(Debug, Clone)]
+struct Lease {
+    ip: Ipv4Addr,
+    hostname: String,
+    expires_at: i64,
+}
+
+/// 地址池
+#[derive(Debug)]
+pub struct LeasePool {
+    start: u32,
+    end: u32,
+    server_ip: Ipv4Addr,
+    /// MAC → 租约（DECLINE 的地址以 "declined:IP" 为键）
+    leases: HashMap<String, Lease>,
+}
+
+impl LeasePool {
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr, server_ip: Ipv4Addr) -> Self {
+        Self {
+            start: u32::from(start),
+            end: u32::from(end),
+            server_ip,
+            leases: HashMap::new(),
+        }
+    }
+
+    fn in_range(&self, ip: Ipv4Addr) -> bool {
+        (self.start..=self.end).contains(&u32::from(ip)) && ip != self.server_ip
+    }
+
+    /// 地址是否可分配给该 MAC（空闲、已过期或本就属于该 MAC）
+    fn available(&self, mac: &str, ip: Ipv4Addr, now: i64) -> bool {
+        self.in_range(ip)
+            && !self
+                .leases
+                .iter()
+                .any(|(owner, lease)| owner != mac && lease.ip == ip && lease.expires_at > now)
+    }
+
+    /// 为客户端选择地址
+    pub fn select(&self, mac: &str, requested: Option<Ipv4Addr>, now: i64) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.leases.get(mac) {
+            if self.available(mac, lease.ip, now) {
+                return Some(lease.ip);
+            }
+        }
+        if let Some(ip) = requested.filter(|ip| self.available(mac, *ip, now)) {
+            return Some(ip);
+        }
+        (self.start..=self.end)
+            .map(Ipv4Addr::from)
+            .find(|ip| self.available(mac, *ip, now) && !self.leases.values().any(|l| l.ip == *ip))
+            // 没有从未分配过的地址时复用已过期的
+            .or_else(|| {
+                (self.start..=self.end)
+                    .map(Ipv4Addr::from)
+                    .find(|ip| self.available(mac, *ip, now))
+            })
+    }
+
+    /// 记录租约，地址不可用时返回 false
+    pub fn commit(&mut self, mac: &str, ip: Ipv4Addr, hostname: &str, expires_at: i64, now: i64) -> bool {
+        if !self.available(mac, ip, now) {
+            return false;
+        }
+        // 同一地址的过期租约让位给新客户端
+        self.leases.retain(|owner, lease| owner == mac || lease.ip != ip);
+        self.leases.insert(
+            mac.to_string(),
+            Lease {
+                ip,
+                hostname: hostname.to_string(),
+                expires_at,
+            },
+        );
+        true
+    }
+
+    /// 释放租约，返回是否存在
+    pub fn release(&mut self, mac: &str, ip: Ipv4Addr) -> bool {
+        if self.leases.get(mac).is_some_and(|l| l.ip == ip) {
+            self.leases.remove(mac);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 客户端检测到地址冲突，暂停分配该地址
+    pub fn decline(&mut self, mac: &str, ip: Ipv4Addr, now: i64) {
+        if self.release(mac, ip) {
+            self.leases.insert(
+                format!("declined:{}", ip),
+                Lease {
+                    ip,
+                    hostname: String::new(),
+                    expires_at: now + DECLINE_HOLD_SECS,
+                },
+            );
+        }
+    }
+
+    fn hostname(&self, mac: &str) -> Option<&str> {
+        self.leases.get(mac).map(|l| l.hostname.as_str())
+    }
+}
+
+/// 服务端状态
+struct DhcpServer {
+    params: ServerParams,
+    pool: LeasePool,
+    db: Arc<Database>,
+    /// 是否下发运营商 DNS（配置中未指定 DNS）
+    upstream_dns: bool,
+}
+
+impl DhcpServer {
+    fn new(config: &LanConfig, db: Arc<Database>, upstream_dns: &[Ipv4Addr]) -> Option<Self> {
+        let params = ServerParams::from_config(config, upstream_dns)?;
+        let mut pool = LeasePool::new(config.dhcp_start.parse().ok()?, config.dhcp_end.parse().ok()?, params.server_ip);
+        let now = chrono::Utc::now().timestamp();
+        for record in db.get_dhcp_leases().unwrap_or_default() {
+            if let Ok(ip) = record.ip.parse() {
+                pool.commit(&record.mac, ip, &record.hostname, record.expires_at, now);
+            }
+        }
+        Some(Self {
+            params,
+            pool,
+            db,
+            upstream_dns: config.dns_servers.is_empty(),
+        })
+    }
+
+    /// 更新运营商 DNS（数据连接断开时保留上次的地址）
+    async fn refresh_upstream_dns(&mut self, conn: &Connection, watchdog_context: Option<&str>) {
+        if !self.upstream_dns {
+            return;
+        }
+        let dns = query_upstream_dns(conn, watchdog_context).await;
+        if !dns.is_empty() && dns != self.params.dns_servers {
+            info!(dns = ?dns, "DHCP: carrier DNS servers updated");
+            self.params.dns_servers = dns;
+        }
+    }
+
+    /// 处理一个客户端报文
+    ///
+    /// # Returns
+    /// (应答报文, 目的地址)
+    fn handle(&mut self, packet: &DhcpPacket) -> Option<(Vec<u8>, SocketAddrV4)> {
+        let now = chrono::Utc::now().timestamp();
+        let mac = packet.mac();
+        let lease_time = self.params.lease_time_secs as i64;
+        // 续租时客户端已有地址，直接单播；否则广播
+        let destination = if packet.ciaddr.is_unspecified() {
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT)
+        } else {
+            SocketAddrV4::new(packet.ciaddr, CLIENT_PORT)
+        };
+
+        match packet.message_type()? {
+            DHCP_DISCOVER => {
+                let ip = self.pool.select(&mac, packet.requested_ip(), now)?;
+                let hostname = packet.hostname();
+                self.pool.commit(&mac, ip, &hostname, now + OFFER_HOLD_SECS, now);
+                debug!(mac = %mac, ip = %ip, "DHCP: offer");
+                Some((build_reply(packet, DHCP_OFFER, ip, &self.params, true), destination))
+            }
+            DHCP_REQUEST => {
+                // 客户端选择了其他服务器
+                if packet.server_id().is_some_and(|id| id != self.params.server_ip) {
+                    return None;
+                }
+                let requested = packet
+                    .requested_ip()
+                    .or_else(|| (!packet.ciaddr.is_unspecified()).then_some(packet.ciaddr))?;
+                let mut hostname = packet.hostname();
+                if hostname.is_empty() {
+                    hostname = self.pool.hostname(&mac).unwrap_or_default().to_string();
+                }
+                if self.pool.commit(&mac, requested, &hostname, now + lease_time, now) {
+                    if let Err(e) = self.db.upsert_dhcp_lease(&mac, &requested.to_string(), &hostname, now + lease_time) {
+                        warn!(error = %e, "DHCP: failed to persist lease");
+                    }
+                    info!(mac = %mac, ip = %requested, hostname = %hostname, "DHCP: lease");
+                    Some((build_reply(packet, DHCP_ACK, requested, &self.params, true), destination))
+                } else {
+                    info!(mac = %mac, ip = %requested, "DHCP: nak");
+                    let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+                    Some((build_reply(packet, DHCP_NAK, Ipv4Addr::UNSPECIFIED, &self.params, false), broadcast))
+                }
+            }
+            DHCP_RELEASE => {
+                if self.pool.release(&mac, packet.ciaddr) {
+                    let _ = self.db.delete_dhcp_lease(&mac);
+                    info!(mac = %mac, ip = %packet.ciaddr, "DHCP: release");
+                }
+                None
+            }
+            DHCP_DECLINE => {
+                if let Some(ip) = packet.requested_ip() {
+                    warn!(mac = %mac, ip = %ip, "DHCP: address declined (conflict)");
+                    self.pool.decline(&mac, ip, now);
+                    let _ = self.db.delete_dhcp_lease(&mac);
+                }
+                None
+            }
+            DHCP_INFORM => Some((
+                build_reply(packet, DHCP_ACK, Ipv4Addr::UNSPECIFIED, &self.params, false),
+                destination,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// 创建绑定到接口的 UDP 67 套接字
+fn open_socket(interface: &str) -> std::io::Result<UdpSocket> {
+    let set_option = |fd: i32, name: libc::c_int, value: &[u8]| -> std::io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                name,
+                value.as_ptr() as *const libc::c_void,
+                value.len() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // 之后出错时由 socket 析构关闭 fd
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    let one = 1i32.to_ne_bytes();
+    set_option(fd, libc::SO_REUSEADDR, &one)?;
+    set_option(fd, libc::SO_BROADCAST, &one)?;
+    set_option(fd, libc::SO_BINDTODEVICE, interface.as_bytes())?;
+
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: SERVER_PORT.to_be(),
+        sin_addr: libc::in_addr { s_addr: 0 },
+        sin_zero: [0; 8],
+    };
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    UdpSocket::from_std(socket)
+}
+
+/// 查询数据连接的运营商 IPv4 DNS
+async fn query_upstream_dns(conn: &Connection, watchdog_context: Option<&str>) -> Vec<Ipv4Addr> {
+    match crate::dbus::get_data_ip_config(conn, watchdog_context).await {
+        Ok(ip) if ip.active => ip.ipv4.dns.iter().filter_map(|d| d.parse().ok()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// DHCP 服务主循环（配置关闭时等待 [`reload`]）
+pub async fn run_dhcp_server(conn: Arc<Connection>, config_manager: Arc<ConfigManager>, db: Arc<Database>) {
+    let mut upstream_dns = Vec::new();
+    loop {
+        let config = config_manager.get_lan();
+        if !config.dhcp_enabled || crate::lan::validate(&config).is_err() {
+            RELOAD.notified().await;
+            continue;
+        }
+
+        let socket = match open_socket(&config.interface) {
+            Ok(socket) => socket,
+            Err(e) => {
+                // USB 未枚举时接口可能不存在，稍后重试
+                warn!(interface = %config.interface, error = %e, "DHCP: failed to bind, retrying");
+                tokio::select! {
+                    _ = RELOAD.notified() => {}
+                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(10)) => {}
+                }
+                continue;
+            }
+        };
+        let Some(mut server) = DhcpServer::new(&config, Arc::clone(&db), &upstream_dns) else {
+            RELOAD.notified().await;
+            continue;
+        };
+        let _ = db.cleanup_dhcp_leases(chrono::Utc::now().timestamp() - 86400 * 7);
+        info!(
+            interface = %config.interface,
+            range = %format!("{}-{}", config.dhcp_start, config.dhcp_end),
+            "DHCP server started"
+        );
+
+        let mut buf = [0u8; 1500];
+        loop {
+            tokio::select! {
+                _ = RELOAD.notified() => {
+                    info!("DHCP server reloading");
+                    break;
+                }
+                received = socket.recv_from(&mut buf) => {
+                    let len = match received {
+                        Ok((len, _)) => len,
+                        Err(e) => {
+                            warn!(error = %e, "DHCP: receive failed");
+                            break;
+                        }
+                    };
+                    let Some(packet) = DhcpPacket::parse(&buf[..len]) else {
+                        continue;
+                    };
+                    server
+                        .refresh_upstream_dns(&conn, config_manager.get_watchdog_context().as_deref())
+                        .await;
+                    upstream_dns.clone_from(&server.params.dns_servers);
+                    if let Some((reply, destination)) = server.handle(&packet) {
+                        if let Err(e) = socket.send_to(&reply, destination).await {
+                            warn!(error = %e, "DHCP: send failed");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(message_type: u8, mac: [u8; 6], requested: Option<Ipv4Addr>) -> Vec<u8> {
+        let mut buf = vec![0u8; BOOTP_HEADER_LEN];
+        buf[0] = 1;
+        buf[1] = 1;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        buf[10] = 0x80;
+        buf[28..34].copy_from_slice(&mac);
+        buf.extend_from_slice(&MAGIC_COOKIE);
+        buf.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, message_type]);
+        buf.extend_from_slice(&[OPT_HOSTNAME, 6, b'l', b'a', b'p', b't', b'o', b'p']);
+        if let Some(ip) = requested {
+            buf.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+            buf.extend_from_slice(&ip.octets());
+        }
+        buf.push(OPT_END);
+        buf
+    }
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let mac = [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01];
+        let packet = DhcpPacket::parse(&request(DHCP_DISCOVER, mac, None)).unwrap();
+        assert_eq!(packet.message_type(), Some(DHCP_DISCOVER));
+        assert_eq!(packet.mac(), "aa:bb:cc:dd:ee:01");
+        assert_eq!(packet.hostname(), "laptop");
+
+        let upstream = [Ipv4Addr::new(211, 136, 17, 107), Ipv4Addr::new(211, 136, 20, 203)];
+        let params = ServerParams::from_config(&LanConfig::default(), &upstream).unwrap();
+        let reply = build_reply(&packet, DHCP_OFFER, Ipv4Addr::new(192, 168, 66, 100), &params, true);
+        assert_eq!(reply.len(), 300);
+        assert_eq!(reply[0], 2);
+        assert_eq!(&reply[4..8], &0x1234_5678u32.to_be_bytes());
+        assert_eq!(&reply[16..20], &[192, 168, 66, 100]);
+        // 复用解析器检查选项（改为 BOOTREQUEST）
+        let mut parsed = reply.clone();
+        parsed[0] = 1;
+        let options = DhcpPacket::parse(&parsed).unwrap().options;
+        assert_eq!(options[&OPT_MESSAGE_TYPE], vec![DHCP_OFFER]);
+        assert_eq!(options[&OPT_ROUTER], vec![192, 168, 66, 1]);
+        assert_eq!(options[&OPT_DNS], vec![211, 136, 17, 107, 211, 136, 20, 203]);
+        assert_eq!(options[&OPT_LEASE_TIME], 43200u32.to_be_bytes().to_vec());
+
+        assert!(DhcpPacket::parse(&reply).is_none());
+    }
+
+    #[test]
+    fn test_lease_pool() {
+        let server = Ipv4Addr::new(192, 168, 66, 1);
+        let mut pool = LeasePool::new(Ipv4Addr::new(192, 168, 66, 1), Ipv4Addr::new(192, 168, 66, 3), server);
+        let now = 1_000;
+
+        // 网关地址不分配，优先客户端请求的地址
+        assert_eq!(pool.select("a", None, now), Some(Ipv4Addr::new(192, 168, 66, 2)));
+        assert_eq!(pool.select("a", Some(Ipv4Addr::new(192, 168, 66, 3)), now), Some(Ipv4Addr::new(192, 168, 66, 3)));
+        assert!(pool.commit("a", Ipv4Addr::new(192, 168, 66, 3), "", now + 100, now));
+        assert_eq!(pool.select("a", None, now), Some(Ipv4Addr::new(192, 168, 66, 3)));
+
+        // 已被占用的地址不能分配给其他客户端
+        assert!(!pool.commit("b", Ipv4Addr::new(192, 168, 66, 3), "", now + 100, now));
+        assert!(pool.commit("b", Ipv4Addr::new(192, 168, 66, 2), "", now + 100, now));
+        assert_eq!(pool.select("c", None, now), None);
+
+        // 过期后可被复用
+        assert_eq!(pool.select("c", None, now + 200), Some(Ipv4Addr::new(192, 168, 66, 2)));
+
+        // DECLINE 后暂停分配
+        pool.decline("b", Ipv4Addr::new(192, 168, 66, 2), now);
+        assert_eq!(pool.select("c", None, now), None);
+        assert!(pool.release("a", Ipv4Addr::new(192, 168, 66, 3)));
+        assert_eq!(pool.select("c", None, now), Some(Ipv4Addr::new(192, 168, 66, 3)));
+    }
+}
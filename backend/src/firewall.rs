@@ -0,0 +1,964 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-17 09:26:51
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-17 09:26:51
+ * @FilePath: /udx710-backend/backend/src/firewall.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 防火墙管理模块
+//!
+//! 在 filter 表中维护自有规则链 UDX_INPUT / UDX_FORWARD（IPv4 和 IPv6 各一套），
+//! 由内置 INPUT / FORWARD 链首条规则跳转进入：
+//! 1. 放行回环接口和已建立的连接（IPv6 额外放行 ICMPv6，邻居发现依赖它）
+//! 2. 按顺序匹配用户规则
+//! 3. 放行 LAN 侧的 DHCP / DHCPv6 请求
+//! 4. 按 LAN / WAN 区域执行默认策略
+//!
+//! 端口转发和 DMZ 在 nat 表中维护 UDX_PREROUTING 链（仅 IPv4），由 PREROUTING 跳转进入，
+//! 经 DNAT 的连接在 UDX_FORWARD 中先于 WAN → LAN 默认策略放行。
+//!
+//! TTL 规范化在 mangle 表中维护 UDX_TTL 链（IPv4 / IPv6），由 FORWARD 跳转进入，
+//! 修改经 WAN 接口转发出去的报文 TTL / Hop Limit。目标模块不可用时只记录错误，不影响其他规则，
+//! watchdog 每次轮询重试。SFP 硬件加速的流量不经过 netfilter，TTL 规范化开启期间关闭 SFP。
+//!
+//! 托管链通过 iptables-restore --noflush 原子替换，下发过程中不会出现规则为空的窗口。
+//!
+//! watchdog 每次轮询调用 [`FirewallManager::enforce`]：规则链被清空或跳转丢失时重新下发；
+//! 开启 remove_harmful_rules（默认开启）时还会删除系统注入的已知有害规则（见 [`harmful_rules`]）。
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::config::{FirewallChain, FirewallConfig, FirewallRule, FirewallZone, PortForward, TtlMode};
+use crate::iptables::{restore_chains, run_iptables};
+
+/// 托管的输入链
+pub const INPUT_CHAIN: &str = "UDX_INPUT";
+
+/// 托管的转发链
+pub const FORWARD_CHAIN: &str = "UDX_FORWARD";
+
+/// 托管的 DNAT 链（nat 表）
+pub const PREROUTING_CHAIN: &str = "UDX_PREROUTING";
+
+/// 托管的 TTL 链（mangle 表）
+pub const TTL_CHAIN: &str = "UDX_TTL";
+
+/// 内置链与托管链的对应关系
+const MANAGED_CHAINS: [(&str, &str); 2] = [("INPUT", INPUT_CHAIN), ("FORWARD", FORWARD_CHAIN)];
+
+/// 检查有害规则的内置链
+const BUILTIN_CHAINS: [&str; 2] = ["INPUT", "FORWARD"];
+
+/// 内存中保留的最近修正记录数
+const MAX_RECENT_FIXES: usize = 50;
+
+/// 运行状态
+#[derive(Default)]
+struct FirewallRuntime {
+    last_applied_at: Option<String>,
+    last_error: Option<String>,
+    ttl_error: Option<String>,
+    recent_fixes: VecDeque<FirewallFix>,
+}
+
+/// 一次自动修正记录
+#[derive(Debug, Clone, Serialize)]
+pub struct FirewallFix {
+    pub timestamp: String,
+    pub detail: String,
+}
+
+/// 防火墙状态
+#[derive(Debug, Clone, Serialize)]
+pub struct FirewallStatus {
+    pub enabled: bool,
+    /// 托管链是否已挂载到内置链（IPv4）
+    pub active_ipv4: bool,
+    /// 托管链是否已挂载到内置链（IPv6）
+    pub active_ipv6: bool,
+    /// filter 表规则总数
+    pub ipv4_rules: usize,
+    pub ipv6_rules: usize,
+    /// 配置中的用户规则数
+    pub managed_rules: usize,
+    /// 已启用的端口转发数
+    pub port_forwards: usize,
+    pub dmz_host: Option<String>,
+    /// DNAT 链是否已挂载到 PREROUTING
+    pub nat_active: bool,
+    pub last_applied_at: Option<String>,
+    pub last_error: Option<String>,
+    pub ttl: TtlStatus,
+    /// 最近的自动修正（重新下发、删除有害规则等），按时间倒序
+    pub recent_fixes: Vec<FirewallFix>,
+}
+
+/// TTL 规范化状态
+#[derive(Debug, Clone, Serialize)]
+pub struct TtlStatus {
+    pub enabled: bool,
+    pub mode: TtlMode,
+    pub value: u8,
+    /// UDX_TTL 链是否已挂载到 FORWARD（IPv4）
+    pub active_ipv4: bool,
+    pub active_ipv6: bool,
+    /// 最近一次下发失败原因（如内核缺少 TTL / HL 模块）
+    pub error: Option<String>,
+}
+
+/// 校验地址或网段，返回是否为 IPv6
+fn address_family(addr: &str) -> Result<bool, String> {
+    let (ip, prefix) = match addr.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (addr, None),
+    };
+    let ip: IpAddr = ip.parse().map_err(|_| format!("Invalid address: {}", addr))?;
+    let max_prefix = if ip.is_ipv6() { 128 } else { 32 };
+    if let Some(prefix) = prefix {
+        match prefix.parse::<u8>() {
+            Ok(p) if p <= max_prefix => {}
+            _ => return Err(format!("Invalid prefix length: {}", addr)),
+        }
+    }
+    Ok(ip.is_ipv6())
+}
+
+/// 校验端口或端口范围（22 / 8000:8100）
+pub fn valid_port_spec(port: &str) -> bool {
+    let parse = |p: &str| p.parse::<u16>().ok().filter(|p| *p > 0);
+    match port.split_once(':') {
+        Some((start, end)) => matches!((parse(start), parse(end)), (Some(s), Some(e)) if s <= e),
+        None => parse(port).is_some(),
+    }
+}
+
+/// 校验接口名（允许 iptables 的 + 通配）
+pub fn valid_interface(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 15
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+'))
+}
+
+/// 校验防火墙配置
+pub fn validate(config: &FirewallConfig) -> Result<(), String> {
+    if !valid_interface(&config.lan_interface) {
+        return Err(format!("Invalid LAN interface: {:?}", config.lan_interface));
+    }
+    if !valid_interface(&config.wan_interface) {
+        return Err(format!("Invalid WAN interface: {:?}", config.wan_interface));
+    }
+
+    let mut names = HashSet::new();
+    for rule in &config.rules {
+        if rule.name.trim().is_empty() {
+            return Err("Rule name is required".to_string());
+        }
+        if !names.insert(rule.name.as_str()) {
+            return Err(format!("Duplicate rule name: {}", rule.name));
+        }
+        if !matches!(rule.protocol.as_str(), "all" | "tcp" | "udp" | "icmp") {
+            return Err(format!("Rule {}: invalid protocol {}", rule.name, rule.protocol));
+        }
+        if !rule.port.is_empty() {
+            if !matches!(rule.protocol.as_str(), "tcp" | "udp") {
+                return Err(format!("Rule {}: port requires tcp or udp", rule.name));
+            }
+            if !valid_port_spec(&rule.port) {
+                return Err(format!("Rule {}: invalid port {}", rule.name, rule.port));
+            }
+        }
+        for addr in [&rule.source, &rule.destination] {
+            if addr.is_empty() {
+                continue;
+            }
+            let ipv6 = address_family(addr).map_err(|e| format!("Rule {}: {}", rule.name, e))?;
+            if !rule.family.includes(ipv6) {
+                return Err(format!("Rule {}: address {} does not match family", rule.name, addr));
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    for forward in &config.port_forwards {
+        validate_port_forward(forward)?;
+        if !names.insert(forward.name.as_str()) {
+            return Err(format!("Duplicate port forward name: {}", forward.name));
+        }
+    }
+    if !config.dmz_host.is_empty() && !valid_lan_host(&config.dmz_host) {
+        return Err(format!("Invalid DMZ host: {}", config.dmz_host));
+    }
+    if config.ttl.value == 0 {
+        return Err("TTL value must be between 1 and 255".to_string());
+    }
+    Ok(())
+}
+
+/// 转发目标必须是单播 IPv4 地址
+fn valid_lan_host(host: &str) -> bool {
+    host.parse::<Ipv4Addr>()
+        .is_ok_and(|ip| !ip.is_unspecified() && !ip.is_broadcast() && !ip.is_multicast() && !ip.is_loopback())
+}
+
+/// 校验单条端口转发规则
+pub fn validate_port_forward(forward: &PortForward) -> Result<(), String> {
+    if forward.name.trim().is_empty() {
+        return Err("Port forward name is required".to_string());
+    }
+    if !valid_port_spec(&forward.external_port) {
+        return Err(format!("Port forward {}: invalid external port {:?}", forward.name, forward.external_port));
+    }
+    if !forward.internal_port.is_empty() && !valid_port_spec(&forward.internal_port) {
+        return Err(format!("Port forward {}: invalid internal port {:?}", forward.name, forward.internal_port));
+    }
+    if !valid_lan_host(&forward.internal_host) {
+        return Err(format!("Port forward {}: invalid internal host {:?}", forward.name, forward.internal_host));
+    }
+    if !forward.source.is_empty() && address_family(&forward.source).map_err(|e| format!("Port forward {}: {}", forward.name, e))? {
+        return Err(format!("Port forward {}: source must be IPv4", forward.name));
+    }
+    Ok(())
+}
+
+/// 是否有需要下发的 DNAT 规则
+fn nat_configured(config: &FirewallConfig) -> bool {
+    !config.dmz_host.is_empty() || config.port_forwards.iter().any(|f| f.enabled)
+}
+
+/// 生成 DNAT 链的全部规则（端口转发在前，DMZ 兜底）
+pub fn build_nat_rules(config: &FirewallConfig) -> Vec<Vec<String>> {
+    let wan = &config.wan_interface;
+    let mut rules = Vec::new();
+    for forward in config.port_forwards.iter().filter(|f| f.enabled) {
+        // DNAT 的端口范围写作 a-b
+        let destination = if forward.internal_port.is_empty() {
+            forward.internal_host.clone()
+        } else {
+            format!("{}:{}", forward.internal_host, forward.internal_port.replace(':', "-"))
+        };
+        for protocol in forward.protocol.protocols() {
+            let mut args = vec!["-i".to_string(), wan.clone(), "-p".to_string(), protocol.to_string()];
+            if !forward.source.is_empty() {
+                args.extend(["-s".to_string(), forward.source.clone()]);
+            }
+            args.extend([
+                "--dport".to_string(),
+                forward.external_port.clone(),
+                "-j".to_string(),
+                "DNAT".to_string(),
+                "--to-destination".to_string(),
+                destination.clone(),
+            ]);
+            rules.push(args);
+        }
+    }
+    if !config.dmz_host.is_empty() {
+        // 保留 ICMP，设备本身仍可被 ping
+        rules.push(
+            ["-i", wan, "!", "-p", "icmp", "-j", "DNAT", "--to-destination", &config.dmz_host]
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+        );
+    }
+    rules
+}
+
+/// 用户规则对应的 iptables 参数（不适用于该协议族时返回 None）
+fn rule_args(rule: &FirewallRule, config: &FirewallConfig, ipv6: bool) -> Option<Vec<String>> {
+    if !rule.enabled || !rule.family.includes(ipv6) {
+        return None;
+    }
+    // 指定了地址时只下发到对应协议族
+    for addr in [&rule.source, &rule.destination] {
+        if !addr.is_empty() && address_family(addr).ok()? != ipv6 {
+            return None;
+        }
+    }
+
+    let interface = match rule.zone {
+        FirewallZone::Lan => &config.lan_interface,
+        FirewallZone::Wan => &config.wan_interface,
+    };
+    let mut args = vec!["-i".to_string(), interface.clone()];
+    match rule.protocol.as_str() {
+        "all" => {}
+        "icmp" => args.extend(["-p".to_string(), if ipv6 { "ipv6-icmp" } else { "icmp" }.to_string()]),
+        protocol => args.extend(["-p".to_string(), protocol.to_string()]),
+    }
+    if !rule.source.is_empty() {
+        args.extend(["-s".to_string(), rule.source.clone()]);
+    }
+    if !rule.destination.is_empty() {
+        args.extend(["-d".to_string(), rule.destination.clone()]);
+    }
+    if !rule.port.is_empty() {
+        args.extend(["--dport".to_string(), rule.port.clone()]);
+    }
+    args.extend(["-j".to_string(), rule.action.target().to_string()]);
+    Some(args)
+}
+
+/// 生成托管链的全部规则
+///
+/// # Returns
+/// (链名, iptables 参数) 列表，按下发顺序排列
+pub fn build_rules(config: &FirewallConfig, ipv6: bool) -> Vec<(&'static str, Vec<String>)> {
+    let to_args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    let lan = config.lan_interface.as_str();
+    let wan = config.wan_interface.as_str();
+    let established = ["-m", "conntrack", "--ctstate", "RELATED,ESTABLISHED", "-j", "ACCEPT"];
+    let mut rules = Vec::new();
+
+    // 输入链
+    rules.push((INPUT_CHAIN, to_args(&["-i", "lo", "-j", "ACCEPT"])));
+    rules.push((INPUT_CHAIN, to_args(&established)));
+    if ipv6 {
+        rules.push((INPUT_CHAIN, to_args(&["-p", "ipv6-icmp", "-j", "ACCEPT"])));
+    }
+    for rule in config.rules.iter().filter(|r| r.chain == FirewallChain::Input) {
+        if let Some(args) = rule_args(rule, config, ipv6) {
+            rules.push((INPUT_CHAIN, args));
+        }
+    }
+    // 内置 DHCP / DHCPv6 服务，LAN 默认策略为 DROP 时仍可获取地址
+    let dhcp_port = if ipv6 { "547" } else { "67" };
+    rules.push((INPUT_CHAIN, to_args(&["-i", lan, "-p", "udp", "--dport", dhcp_port, "-j", "ACCEPT"])));
+    rules.push((INPUT_CHAIN, to_args(&["-i", lan, "-j", config.lan_input.target()])));
+    rules.push((INPUT_CHAIN, to_args(&["-i", wan, "-j", config.wan_input.target()])));
+
+    // 转发链
+    rules.push((FORWARD_CHAIN, to_args(&established)));
+    if ipv6 {
+        rules.push((FORWARD_CHAIN, to_args(&["-p", "ipv6-icmp", "-j", "ACCEPT"])));
+    }
+    for rule in config.rules.iter().filter(|r| r.chain == FirewallChain::Forward) {
+        if let Some(args) = rule_args(rule, config, ipv6) {
+            rules.push((FORWARD_CHAIN, args));
+        }
+    }
+    if !ipv6 && nat_configured(config) {
+        rules.push((
+            FORWARD_CHAIN,
+            to_args(&["-i", wan, "-o", lan, "-m", "conntrack", "--ctstate", "DNAT", "-j", "ACCEPT"]),
+        ));
+    }
+    rules.push((FORWARD_CHAIN, to_args(&["-i", lan, "-o", wan, "-j", config.lan_forward.target()])));
+    rules.push((FORWARD_CHAIN, to_args(&["-i", wan, "-o", lan, "-j", config.wan_forward.target()])));
+
+    rules
+}
+
+/// 生成 TTL 链的规则（未启用时为空）
+pub fn build_ttl_rules(config: &FirewallConfig, ipv6: bool) -> Vec<Vec<String>> {
+    let ttl = &config.ttl;
+    if !ttl.enabled || (ipv6 && !ttl.ipv6) {
+        return Vec::new();
+    }
+    let (target, option) = match (ipv6, ttl.mode) {
+        (false, TtlMode::Set) => ("TTL", "--ttl-set"),
+        (false, TtlMode::Increment) => ("TTL", "--ttl-inc"),
+        (true, TtlMode::Set) => ("HL", "--hl-set"),
+        (true, TtlMode::Increment) => ("HL", "--hl-inc"),
+    };
+    vec![["-o", &config.wan_interface, "-j", target, option, &ttl.value.to_string()]
+        .iter()
+        .map(|a| a.to_string())
+        .collect()]
+}
+
+/// 按 shell 规则拆分 `iptables -S` 输出的一行（处理双引号）
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_token = true;
+            }
+            '\\' if in_quotes => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    args.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        args.push(current);
+    }
+    args
+}
+
+/// 接口是否为托管的 LAN / WAN 接口（支持 iptables 通配 +）
+fn managed_interface(name: &str, config: &FirewallConfig) -> bool {
+    [&config.lan_interface, &config.wan_interface]
+        .iter()
+        .any(|iface| match iface.strip_suffix('+') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == iface.as_str(),
+        })
+}
+
+/// 是否为已知有害的规则
+///
+/// 特征：内置 INPUT / FORWARD 链中只按 LAN / WAN 接口匹配、没有其他条件的
+/// DROP / REJECT，即整体丢弃 USB 网卡或数据连接的流量。插在托管链跳转之前时
+/// 托管链的放行规则全部失效。带协议、地址、端口、注释等条件的规则视为有意配置，保留。
+fn is_harmful(args: &[String], config: &FirewallConfig) -> bool {
+    if args.len() < 4 || args[0] != "-A" || !BUILTIN_CHAINS.contains(&args[1].as_str()) {
+        return false;
+    }
+    let mut matches_interface = false;
+    let mut target = None;
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).map(|s| s.as_str());
+        match (args[i].as_str(), value) {
+            ("-i" | "-o", Some(name)) if managed_interface(name, config) => matches_interface = true,
+            ("-j", Some(t)) => target = Some(t),
+            ("--reject-with", Some(_)) => {}
+            _ => return false,
+        }
+        i += 2;
+    }
+    matches_interface && matches!(target, Some("DROP") | Some("REJECT"))
+}
+
+/// 从 `iptables -S` 输出中找出已知有害的规则（见 [`is_harmful`]）
+///
+/// 内置链的默认策略和其他规则不做修改。
+///
+/// # Returns
+/// 删除命令参数
+pub fn harmful_rules(spec: &str, config: &FirewallConfig) -> Vec<Vec<String>> {
+    spec.lines()
+        .map(split_args)
+        .filter(|args| is_harmful(args, config))
+        .map(|mut args| {
+            args[0] = "-D".to_string();
+            args
+        })
+        .collect()
+}
+
+/// 托管链是否已挂载
+fn jumps_present(ipv6: bool) -> bool {
+    MANAGED_CHAINS
+        .iter()
+        .all(|(builtin, chain)| run_iptables(ipv6, &["-C", builtin, "-j", chain]).is_ok())
+}
+
+/// 托管链中的规则数（链不存在时为 None）
+fn chain_rule_count(ipv6: bool, chain: &str) -> Option<usize> {
+    run_iptables(ipv6, &["-S", chain])
+        .ok()
+        .map(|out| out.lines().filter(|l| l.starts_with("-A ")).count())
+}
+
+/// 在指定表中执行 iptables 命令
+fn run_table<S: AsRef<str>>(ipv6: bool, table: &str, args: &[S]) -> Result<String, String> {
+    let mut full = vec!["-t".to_string(), table.to_string()];
+    full.extend(args.iter().map(|a| a.as_ref().to_string()));
+    run_iptables(ipv6, &full)
+}
+
+/// nat 表中执行 iptables 命令（仅 IPv4）
+fn run_nat<S: AsRef<str>>(args: &[S]) -> Result<String, String> {
+    run_table(false, "nat", args)
+}
+
+/// TTL 链是否已挂载
+fn ttl_jump_present(ipv6: bool) -> bool {
+    run_table(ipv6, "mangle", &["-C", "FORWARD", "-j", TTL_CHAIN]).is_ok()
+}
+
+/// TTL 链是否与配置一致
+fn ttl_intact(config: &FirewallConfig, ipv6: bool) -> bool {
+    let expected = build_ttl_rules(config, ipv6);
+    if expected.is_empty() {
+        return !ttl_jump_present(ipv6);
+    }
+    let count = run_table(ipv6, "mangle", &["-S", TTL_CHAIN])
+        .ok()
+        .map(|out| out.lines().filter(|l| l.starts_with("-A ")).count());
+    ttl_jump_present(ipv6) && count == Some(expected.len())
+}
+
+/// 下发一个协议族的 TTL 链，未启用时卸载
+fn apply_ttl(config: &FirewallConfig, ipv6: bool) -> Result<(), String> {
+    let rules = build_ttl_rules(config, ipv6);
+    if rules.is_empty() {
+        while run_table(ipv6, "mangle", &["-D", "FORWARD", "-j", TTL_CHAIN]).is_ok() {}
+        let _ = run_table(ipv6, "mangle", &["-F", TTL_CHAIN]);
+        let _ = run_table(ipv6, "mangle", &["-X", TTL_CHAIN]);
+        return Ok(());
+    }
+
+    let _ = run_table(ipv6, "mangle", &["-N", TTL_CHAIN]);
+    run_table(ipv6, "mangle", &["-F", TTL_CHAIN])?;
+    for args in rules {
+        let mut full = vec!["-A".to_string(), TTL_CHAIN.to_string()];
+        full.extend(args);
+        run_table(ipv6, "mangle", &full)?;
+    }
+    if !ttl_jump_present(ipv6) {
+        run_table(ipv6, "mangle", &["-I", "FORWARD", "1", "-j", TTL_CHAIN])?;
+    }
+    Ok(())
+}
+
+/// DNAT 链是否已挂载
+fn nat_jump_present() -> bool {
+    run_nat(&["-C", "PREROUTING", "-j", PREROUTING_CHAIN]).is_ok()
+}
+
+/// DNAT 链是否与配置一致
+fn nat_intact(config: &FirewallConfig) -> bool {
+    if !nat_configured(config) {
+        return true;
+    }
+    let count = run_nat(&["-S", PREROUTING_CHAIN])
+        .ok()
+        .map(|out| out.lines().filter(|l| l.starts_with("-A ")).count());
+    nat_jump_present() && count == Some(build_nat_rules(config).len())
+}
+
+/// 下发 DNAT 链，没有转发规则时卸载
+fn apply_nat(config: &FirewallConfig) -> Result<(), String> {
+    if !nat_configured(config) {
+        while run_nat(&["-D", "PREROUTING", "-j", PREROUTING_CHAIN]).is_ok() {}
+        let _ = run_nat(&["-F", PREROUTING_CHAIN]);
+        let _ = run_nat(&["-X", PREROUTING_CHAIN]);
+        return Ok(());
+    }
+
+    let mut lines: Vec<Vec<String>> = build_nat_rules(config)
+        .into_iter()
+        .map(|args| prepend(&["-A", PREROUTING_CHAIN], args))
+        .collect();
+    if !nat_jump_present() {
+        lines.push(prepend(&["-I", "PREROUTING", "1", "-j", PREROUTING_CHAIN], Vec::new()));
+    }
+    restore_chains(false, "nat", &[PREROUTING_CHAIN], &lines)
+}
+
+/// 在参数前加上命令和链名
+fn prepend(head: &[&str], args: Vec<String>) -> Vec<String> {
+    head.iter().map(|a| a.to_string()).chain(args).collect()
+}
+
+/// 卸载并删除托管链
+fn teardown_family(ipv6: bool) {
+    for (builtin, chain) in MANAGED_CHAINS {
+        while run_iptables(ipv6, &["-D", builtin, "-j", chain]).is_ok() {}
+        let _ = run_iptables(ipv6, &["-F", chain]);
+        let _ = run_iptables(ipv6, &["-X", chain]);
+    }
+}
+
+/// 下发一个协议族的托管链
+fn apply_family(config: &FirewallConfig, ipv6: bool) -> Result<(), String> {
+    if !config.enabled {
+        teardown_family(ipv6);
+        return Ok(());
+    }
+
+    let mut lines: Vec<Vec<String>> = build_rules(config, ipv6)
+        .into_iter()
+        .map(|(chain, args)| prepend(&["-A", chain], args))
+        .collect();
+    for (builtin, chain) in MANAGED_CHAINS {
+        if run_iptables(ipv6, &["-C", builtin, "-j", chain]).is_err() {
+            lines.push(prepend(&["-I", builtin, "1", "-j", chain], Vec::new()));
+        }
+    }
+    let chains: Vec<&str> = MANAGED_CHAINS.iter().map(|(_, chain)| *chain).collect();
+    restore_chains(ipv6, "filter", &chains, &lines)
+}
+
+/// 防火墙管理器
+///
+/// 串行化 API 和 watchdog 的规则下发，并保存最近一次下发结果和自动修正记录
+pub struct FirewallManager {
+    /// 防止 API 和 watchdog 同时下发规则
+    apply_lock: Mutex<()>,
+    runtime: Mutex<FirewallRuntime>,
+}
+
+impl FirewallManager {
+    /// 创建防火墙管理器
+    pub fn new() -> Self {
+        Self {
+            apply_lock: Mutex::new(()),
+            runtime: Mutex::new(FirewallRuntime::default()),
+        }
+    }
+
+    /// 获取下发锁
+    fn lock_apply(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.apply_lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 记录自动修正
+    fn record_fix(&self, detail: String) {
+        info!(detail = %detail, "Firewall: fixed");
+        if let Ok(mut runtime) = self.runtime.lock() {
+            runtime.recent_fixes.push_front(FirewallFix {
+                timestamp: Utc::now().to_rfc3339(),
+                detail,
+            });
+            runtime.recent_fixes.truncate(MAX_RECENT_FIXES);
+        }
+    }
+
+    /// 下发两个协议族的 TTL 链并记录错误
+    fn apply_ttl_families(&self, config: &FirewallConfig) -> Result<(), String> {
+        let active = [false, true].iter().any(|&ipv6| !build_ttl_rules(config, ipv6).is_empty());
+        crate::usb_switch::inhibit_sfp(crate::usb_switch::SfpInhibitor::Ttl, active);
+        let result = apply_ttl(config, false).and_then(|_| apply_ttl(config, true));
+        if let Ok(mut runtime) = self.runtime.lock() {
+            runtime.ttl_error = result.as_ref().err().cloned();
+        }
+        result
+    }
+
+    /// 下发两个协议族并记录结果
+    fn apply_blocking(&self, config: &FirewallConfig) -> Result<(), String> {
+        let _guard = self.lock_apply();
+        let result = apply_family(config, false)
+            .and_then(|_| apply_family(config, true))
+            .and_then(|_| apply_nat(config));
+        if let Ok(mut runtime) = self.runtime.lock() {
+            runtime.last_applied_at = Some(Utc::now().to_rfc3339());
+            runtime.last_error = result.as_ref().err().cloned();
+        }
+        // TTL 目标模块可能不存在，失败不影响其他规则
+        if let Err(e) = self.apply_ttl_families(config) {
+            warn!(error = %e, "Firewall: failed to apply TTL rules");
+        }
+        result
+    }
+
+    /// 下发防火墙配置（启动时和配置变更后调用）
+    pub async fn apply(self: &Arc<Self>, config: FirewallConfig) -> Result<(), String> {
+        let manager = Arc::clone(self);
+        task::spawn_blocking(move || manager.apply_blocking(&config))
+            .await
+            .map_err(|e| format!("Task execution failed: {}", e))?
+    }
+
+    /// 检查并修正一次防火墙状态
+    fn enforce_blocking(&self, config: &FirewallConfig) -> Result<usize, String> {
+        let mut fixes = 0;
+        let mut ttl_results = Vec::new();
+        for ipv6 in [false, true] {
+            let family = if ipv6 { "IPv6" } else { "IPv4" };
+
+            if config.enabled {
+                let expected = build_rules(config, ipv6);
+                let intact = jumps_present(ipv6)
+                    && MANAGED_CHAINS.iter().all(|(_, chain)| {
+                        chain_rule_count(ipv6, chain)
+                            == Some(expected.iter().filter(|(c, _)| c == chain).count())
+                    });
+                if !intact {
+                    let _guard = self.lock_apply();
+                    apply_family(config, ipv6)?;
+                    self.record_fix(format!("{}: managed chains re-applied", family));
+                    fixes += 1;
+                }
+            }
+
+            // 下发失败（如缺少内核模块）时下次轮询继续重试
+            if !ttl_intact(config, ipv6) {
+                let _guard = self.lock_apply();
+                let result = apply_ttl(config, ipv6);
+                if result.is_ok() {
+                    self.record_fix(format!("{}: TTL chain re-applied", family));
+                    fixes += 1;
+                }
+                ttl_results.push(result);
+            }
+
+            if !ipv6 && !nat_intact(config) {
+                let _guard = self.lock_apply();
+                apply_nat(config)?;
+                self.record_fix("IPv4: port forwarding chain re-applied".to_string());
+                fixes += 1;
+            }
+
+            if config.remove_harmful_rules {
+                let spec = run_iptables(ipv6, &["-S"])?;
+                for fix in harmful_rules(&spec, config) {
+                    match run_iptables(ipv6, &fix) {
+                        Ok(_) => {
+                            self.record_fix(format!("{}: {}", family, fix.join(" ")));
+                            fixes += 1;
+                        }
+                        Err(e) => warn!(error = %e, "Firewall: failed to remove harmful rule"),
+                    }
+                }
+            }
+        }
+
+        // 只有重试过的协议族会更新 TTL 错误，错误变化时才记录日志
+        if !ttl_results.is_empty() {
+            let error = ttl_results.into_iter().find_map(|r| r.err());
+            if let Ok(mut runtime) = self.runtime.lock() {
+                if let Some(e) = error.as_ref().filter(|e| runtime.ttl_error.as_ref() != Some(e)) {
+                    warn!(error = %e, "Firewall: failed to apply TTL rules");
+                }
+                runtime.ttl_error = error;
+            }
+        }
+        Ok(fixes)
+    }
+
+    /// 检查并修正防火墙状态（watchdog 周期调用）
+    ///
+    /// # Returns
+    /// 本次执行的修正数量
+    pub async fn enforce(self: &Arc<Self>, config: FirewallConfig) -> Result<usize, String> {
+        let manager = Arc::clone(self);
+        task::spawn_blocking(move || manager.enforce_blocking(&config))
+            .await
+            .map_err(|e| format!("Task execution failed: {}", e))?
+    }
+
+    /// 获取防火墙状态
+    pub async fn status(&self, config: FirewallConfig) -> FirewallStatus {
+        let counts = crate::iptables::get_iptables_rule_count().await.unwrap_or_default();
+        let (active_ipv4, active_ipv6, nat_active, ttl_ipv4, ttl_ipv6) = task::spawn_blocking(|| {
+            (
+                jumps_present(false),
+                jumps_present(true),
+                nat_jump_present(),
+                ttl_jump_present(false),
+                ttl_jump_present(true),
+            )
+        })
+        .await
+        .unwrap_or_default();
+        let runtime = self.runtime.lock().ok();
+        FirewallStatus {
+            enabled: config.enabled,
+            active_ipv4,
+            active_ipv6,
+            ipv4_rules: counts.ipv4_rules,
+            ipv6_rules: counts.ipv6_rules,
+            managed_rules: config.rules.len(),
+            port_forwards: config.port_forwards.iter().filter(|f| f.enabled).count(),
+            dmz_host: (!config.dmz_host.is_empty()).then(|| config.dmz_host.clone()),
+            nat_active,
+            last_applied_at: runtime.as_ref().and_then(|r| r.last_applied_at.clone()),
+            last_error: runtime.as_ref().and_then(|r| r.last_error.clone()),
+            ttl: TtlStatus {
+                enabled: config.ttl.enabled,
+                mode: config.ttl.mode,
+                value: config.ttl.value,
+                active_ipv4: ttl_ipv4,
+                active_ipv6: ttl_ipv6,
+                error: runtime.as_ref().and_then(|r| r.ttl_error.clone()),
+            },
+            recent_fixes: runtime
+                .as_ref()
+                .map(|r| r.recent_fixes.iter().cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for FirewallManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FirewallAction, ForwardProtocol, IpFamily};
+
+    #[test]
+    fn test_build_rules() {
+        let mut config = FirewallConfig {
+            wan_input: FirewallAction::Drop,
+            wan_forward: FirewallAction::Drop,
+            rules: vec![
+                FirewallRule {
+                    name: "ssh".to_string(),
+                    protocol: "tcp".to_string(),
+                    port: "22".to_string(),
+                    source: "203.0.113.0/24".to_string(),
+                    family: IpFamily::Ipv4,
+                    ..Default::default()
+                },
+                FirewallRule {
+                    name: "ping".to_string(),
+                    protocol: "icmp".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(validate(&config).is_ok());
+
+        let v4 = build_rules(&config, false);
+        let input: Vec<String> = v4
+            .iter()
+            .filter(|(c, _)| *c == INPUT_CHAIN)
+            .map(|(_, a)| a.join(" "))
+            .collect();
+        assert_eq!(input[2], "-i sipa_eth+ -p tcp -s 203.0.113.0/24 --dport 22 -j ACCEPT");
+        assert_eq!(input[3], "-i sipa_eth+ -p icmp -j ACCEPT");
+        assert_eq!(input[4], "-i usb0 -p udp --dport 67 -j ACCEPT");
+        assert_eq!(input.last().unwrap(), "-i sipa_eth+ -j DROP");
+        assert_eq!(
+            v4.last().unwrap().1.join(" "),
+            "-i sipa_eth+ -o usb0 -j DROP"
+        );
+
+        // IPv4 地址规则不下发到 IPv6，ICMP 转换为 ipv6-icmp
+        let v6: Vec<String> = build_rules(&config, true).iter().map(|(_, a)| a.join(" ")).collect();
+        assert!(!v6.iter().any(|r| r.contains("--dport 22")));
+        assert!(v6.contains(&"-i sipa_eth+ -p ipv6-icmp -j ACCEPT".to_string()));
+
+        config.rules[1].port = "80".to_string();
+        assert!(validate(&config).is_err());
+        config.rules[1].port.clear();
+        config.rules[1].name = "ssh".to_string();
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_build_nat_rules() {
+        let mut config = FirewallConfig {
+            port_forwards: vec![
+                PortForward {
+                    name: "web".to_string(),
+                    protocol: ForwardProtocol::Both,
+                    external_port: "8080".to_string(),
+                    internal_host: "192.168.66.10".to_string(),
+                    internal_port: "80".to_string(),
+                    ..Default::default()
+                },
+                PortForward {
+                    name: "games".to_string(),
+                    protocol: ForwardProtocol::Udp,
+                    external_port: "27000:27015".to_string(),
+                    internal_host: "192.168.66.20".to_string(),
+                    internal_port: "27000:27015".to_string(),
+                    source: "198.51.100.0/24".to_string(),
+                    ..Default::default()
+                },
+            ],
+            dmz_host: "192.168.66.30".to_string(),
+            ..Default::default()
+        };
+        assert!(validate(&config).is_ok());
+
+        let nat: Vec<String> = build_nat_rules(&config).iter().map(|a| a.join(" ")).collect();
+        assert_eq!(
+            nat,
+            vec![
+                "-i sipa_eth+ -p tcp --dport 8080 -j DNAT --to-destination 192.168.66.10:80",
+                "-i sipa_eth+ -p udp --dport 8080 -j DNAT --to-destination 192.168.66.10:80",
+                "-i sipa_eth+ -p udp -s 198.51.100.0/24 --dport 27000:27015 -j DNAT --to-destination 192.168.66.20:27000-27015",
+                "-i sipa_eth+ ! -p icmp -j DNAT --to-destination 192.168.66.30",
+            ]
+        );
+
+        // DNAT 连接先于 WAN → LAN 默认策略放行，仅 IPv4
+        let forward: Vec<String> = build_rules(&config, false)
+            .iter()
+            .filter(|(c, _)| *c == FORWARD_CHAIN)
+            .map(|(_, a)| a.join(" "))
+            .collect();
+        assert_eq!(forward[forward.len() - 3], "-i sipa_eth+ -o usb0 -m conntrack --ctstate DNAT -j ACCEPT");
+        assert!(!build_rules(&config, true).iter().any(|(_, a)| a.contains(&"DNAT".to_string())));
+
+        config.dmz_host = "2001:db8::1".to_string();
+        assert!(validate(&config).is_err());
+        config.dmz_host.clear();
+        config.port_forwards[1].name = "web".to_string();
+        assert!(validate(&config).is_err());
+        config.port_forwards[1].name = "games".to_string();
+        config.port_forwards[1].internal_host = "255.255.255.255".to_string();
+        assert!(validate(&config).is_err());
+        config.port_forwards.iter_mut().for_each(|f| f.enabled = false);
+        assert!(build_nat_rules(&config).is_empty() && !nat_configured(&config));
+    }
+
+    #[test]
+    fn test_build_ttl_rules() {
+        let mut config = FirewallConfig::default();
+        assert!(build_ttl_rules(&config, false).is_empty());
+
+        config.ttl.enabled = true;
+        config.ttl.value = 65;
+        assert_eq!(build_ttl_rules(&config, false)[0].join(" "), "-o sipa_eth+ -j TTL --ttl-set 65");
+        config.ttl.mode = TtlMode::Increment;
+        config.ttl.value = 1;
+        assert_eq!(build_ttl_rules(&config, true)[0].join(" "), "-o sipa_eth+ -j HL --hl-inc 1");
+        config.ttl.ipv6 = false;
+        assert!(build_ttl_rules(&config, true).is_empty());
+
+        config.ttl.value = 0;
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_harmful_rules() {
+        let spec = "-P INPUT ACCEPT\n\
+                    -P FORWARD DROP\n\
+                    -N UDX_INPUT\n\
+                    -A INPUT -i usb0 -j DROP\n\
+                    -A INPUT -j UDX_INPUT\n\
+                    -A INPUT -i sipa_eth0 -m comment --comment \"block all\" -j DROP\n\
+                    -A INPUT -i sipa_eth0 -p tcp --dport 23 -j REJECT --reject-with tcp-reset\n\
+                    -A FORWARD -i sipa_eth1 -o usb0 -j REJECT --reject-with icmp-port-unreachable\n\
+                    -A FORWARD -i usb0 -j ACCEPT\n\
+                    -A FORWARD -i wlan0 -j DROP\n\
+                    -A FORWARD -j DROP\n\
+                    -A OUTPUT -o usb0 -j DROP\n\
+                    -A UDX_INPUT -i sipa_eth+ -j DROP\n";
+        let fixes: Vec<String> = harmful_rules(spec, &FirewallConfig::default())
+            .iter()
+            .map(|f| f.join(" "))
+            .collect();
+        assert_eq!(
+            fixes,
+            vec![
+                "-D INPUT -i usb0 -j DROP".to_string(),
+                "-D FORWARD -i sipa_eth1 -o usb0 -j REJECT --reject-with icmp-port-unreachable".to_string(),
+            ]
+        );
+        assert_eq!(split_args("-A X -m comment --comment \"a \\\"b\\\"\""), vec!["-A", "X", "-m", "comment", "--comment", "a \"b\""]);
+        assert!(valid_port_spec("8000:8100") && !valid_port_spec("8100:8000") && !valid_port_spec("0"));
+    }
+}
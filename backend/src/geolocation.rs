@@ -0,0 +1,481 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-15 09:41:26
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-15 09:41:26
+ * @FilePath: /udx710-backend/backend/src/geolocation.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 在线定位模块
+//!
+//! 根据基站定位参数（CellLocationResponse）调用在线定位服务：
+//! - Google Geolocation API
+//! - Unwired Labs LocationAPI
+//! - OpenCellID（仅主服务小区）
+//!
+//! 按配置顺序尝试，请求失败或未到最小请求间隔时使用下一个服务。
+//! 同一组小区的结果在 cache_ttl_secs 内直接返回缓存。
+//!
+//! API Key 不通过接口明文返回，读取配置时只显示末 4 位（见 [`mask_api_keys`]）。
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::{GeolocationConfig, GeolocationProvider, GeolocationProviderKind};
+use crate::utils::{mask_secret, SECRET_MASK_PREFIX};
+use crate::models::{CellLocationInfo, CellLocationResponse, LocationEstimate};
+
+lazy_static::lazy_static! {
+    /// 定位结果缓存（键为小区组合）
+    static ref CACHE: Mutex<HashMap<String, (Instant, LocationEstimate)>> = Mutex::new(HashMap::new());
+    /// 各服务最近一次请求时间（用于限速）
+    static ref LAST_REQUEST: Mutex<HashMap<GeolocationProviderKind, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// 定位服务请求
+#[derive(Debug)]
+pub enum ProviderRequest {
+    Post { url: String, body: Value },
+    Get { url: String, query: Vec<(String, String)> },
+}
+
+impl GeolocationProviderKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GeolocationProviderKind::Google => "google",
+            GeolocationProviderKind::UnwiredLabs => "unwiredlabs",
+            GeolocationProviderKind::OpenCellId => "opencellid",
+        }
+    }
+
+    fn default_url(&self) -> &'static str {
+        match self {
+            GeolocationProviderKind::Google => "https://www.googleapis.com/geolocation/v1/geolocate",
+            GeolocationProviderKind::UnwiredLabs => "https://us1.unwiredlabs.com/v2/process.php",
+            GeolocationProviderKind::OpenCellId => "https://opencellid.org/cell/get",
+        }
+    }
+}
+
+/// 小区组合缓存键：主服务小区 + 邻区 PCI/频点（排序后）
+pub fn cell_set_key(location: &CellLocationResponse) -> Option<String> {
+    let serving = location.cell_info.as_ref()?;
+    let mut neighbors: Vec<String> = location
+        .neighbor_cells
+        .iter()
+        .filter_map(|c| Some(format!("{}@{}", c.pci?, c.arfcn.unwrap_or_default())))
+        .collect();
+    neighbors.sort();
+    neighbors.dedup();
+    Some(format!(
+        "{}:{}:{}:{}:{}|{}",
+        serving.radio_type,
+        serving.mcc,
+        serving.mnc,
+        serving.lac,
+        serving.cid,
+        neighbors.join(",")
+    ))
+}
+
+/// 构造指定服务的请求
+pub fn build_request(
+    provider: &GeolocationProvider,
+    location: &CellLocationResponse,
+) -> Result<ProviderRequest, String> {
+    let serving = location
+        .cell_info
+        .as_ref()
+        .ok_or("No serving cell for geolocation")?;
+    let mcc: u16 = serving.mcc.parse().map_err(|_| format!("Invalid MCC: {}", serving.mcc))?;
+    let mnc: u16 = serving.mnc.parse().map_err(|_| format!("Invalid MNC: {}", serving.mnc))?;
+    let url = if provider.url.is_empty() {
+        provider.kind.default_url().to_string()
+    } else {
+        provider.url.clone()
+    };
+    let radio = serving.radio_type.to_lowercase();
+
+    match provider.kind {
+        GeolocationProviderKind::Google => {
+            // Google 要求提供 Cell ID，邻区只有 PCI 时无法使用
+            let tower = |cell: &CellLocationInfo| {
+                let id_field = if radio == "nr" { "newRadioCellId" } else { "cellId" };
+                json!({
+                    id_field: cell.cid,
+                    "locationAreaCode": cell.lac,
+                    "mobileCountryCode": mcc,
+                    "mobileNetworkCode": mnc,
+                    "signalStrength": cell.signal_strength
+                })
+            };
+            let cell_towers: Vec<Value> = std::iter::once(serving)
+                .chain(location.neighbor_cells.iter().filter(|c| c.cid > 0))
+                .map(tower)
+                .collect();
+            Ok(ProviderRequest::Post {
+                url: format!("{}?key={}", url, provider.api_key),
+                body: json!({
+                    "considerIp": false,
+                    "radioType": radio,
+                    "cellTowers": cell_towers
+                }),
+            })
+        }
+        GeolocationProviderKind::UnwiredLabs => {
+            // 邻区通过 PCI（psc 字段）参与定位
+            let mut cells = vec![json!({
+                "lac": serving.lac,
+                "cid": serving.cid,
+                "signal": serving.signal_strength,
+                "psc": serving.pci
+            })];
+            cells.extend(location.neighbor_cells.iter().filter_map(|c| {
+                Some(json!({
+                    "lac": c.lac,
+                    "psc": c.pci?,
+                    "signal": c.signal_strength
+                }))
+            }));
+            Ok(ProviderRequest::Post {
+                url,
+                body: json!({
+                    "token": provider.api_key,
+                    "radio": radio,
+                    "mcc": mcc,
+                    "mnc": mnc,
+                    "cells": cells,
+                    "address": 0
+                }),
+            })
+        }
+        GeolocationProviderKind::OpenCellId => Ok(ProviderRequest::Get {
+            url,
+            query: vec![
+                ("key".to_string(), provider.api_key.clone()),
+                ("mcc".to_string(), mcc.to_string()),
+                ("mnc".to_string(), mnc.to_string()),
+                ("lac".to_string(), serving.lac.to_string()),
+                ("cellid".to_string(), serving.cid.to_string()),
+                ("radio".to_string(), radio.to_uppercase()),
+                ("format".to_string(), "json".to_string()),
+            ],
+        }),
+    }
+}
+
+/// 解析服务响应
+///
+/// # Returns
+/// (纬度, 经度, 精度半径米)
+pub fn parse_response(kind: GeolocationProviderKind, body: &Value) -> Result<(f64, f64, f64), String> {
+    let number = |v: &Value| v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse().ok()));
+
+    match kind {
+        GeolocationProviderKind::Google => {
+            if let Some(error) = body.get("error") {
+                return Err(format!(
+                    "Google error: {}",
+                    error.get("message").and_then(Value::as_str).unwrap_or("unknown")
+                ));
+            }
+            let location = body.get("location").ok_or("Google response missing location")?;
+            match (number(&location["lat"]), number(&location["lng"])) {
+                (Some(lat), Some(lng)) => Ok((lat, lng, number(&body["accuracy"]).unwrap_or_default())),
+                _ => Err("Google response missing coordinates".to_string()),
+            }
+        }
+        GeolocationProviderKind::UnwiredLabs => {
+            if body["status"].as_str() != Some("ok") {
+                return Err(format!(
+                    "Unwired Labs error: {}",
+                    body["message"].as_str().unwrap_or("unknown")
+                ));
+            }
+            match (number(&body["lat"]), number(&body["lon"])) {
+                (Some(lat), Some(lon)) => Ok((lat, lon, number(&body["accuracy"]).unwrap_or_default())),
+                _ => Err("Unwired Labs response missing coordinates".to_string()),
+            }
+        }
+        GeolocationProviderKind::OpenCellId => {
+            if let Some(error) = body.get("error") {
+                return Err(format!("OpenCellID error: {}", error.as_str().unwrap_or("unknown")));
+            }
+            match (number(&body["lat"]), number(&body["lon"])) {
+                (Some(lat), Some(lon)) => Ok((lat, lon, number(&body["range"]).unwrap_or_default())),
+                _ => Err("OpenCellID response missing coordinates".to_string()),
+            }
+        }
+    }
+}
+
+/// 发送请求并解析结果
+async fn query_provider(
+    client: &reqwest::Client,
+    provider: &GeolocationProvider,
+    location: &CellLocationResponse,
+) -> Result<(f64, f64, f64), String> {
+    let request = match build_request(provider, location)? {
+        ProviderRequest::Post { url, body } => client.post(url).json(&body),
+        ProviderRequest::Get { url, query } => client.get(url).query(&query),
+    };
+    let response = request.send().await.map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status();
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response (HTTP {}): {}", status, e))?;
+    parse_response(provider.kind, &body)
+}
+
+/// 在线定位
+///
+/// # Arguments
+/// * `config` - 在线定位配置
+/// * `location` - 基站定位参数
+/// * `refresh` - 忽略缓存
+///
+/// # Returns
+/// (定位结果, 是否来自缓存)
+pub async fn locate(
+    config: &GeolocationConfig,
+    location: &CellLocationResponse,
+    refresh: bool,
+) -> Result<(LocationEstimate, bool), String> {
+    let key = cell_set_key(location).ok_or("No serving cell for geolocation")?;
+    let ttl = Duration::from_secs(config.cache_ttl_secs);
+
+    {
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        if !refresh {
+            if let Some((_, estimate)) = cache.get(&key) {
+                return Ok((estimate.clone(), true));
+            }
+        }
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let mut failures = Vec::new();
+    for provider in config.providers.iter().filter(|p| p.enabled) {
+        let name = provider.kind.as_str();
+
+        // 限速：未到最小请求间隔则跳过
+        {
+            let mut last = LAST_REQUEST.lock().unwrap();
+            let interval = Duration::from_secs(provider.min_interval_secs);
+            if last.get(&provider.kind).is_some_and(|at| at.elapsed() < interval) {
+                failures.push(format!("{}: rate limited", name));
+                continue;
+            }
+            last.insert(provider.kind, Instant::now());
+        }
+
+        match query_provider(&client, provider, location).await {
+            Ok((latitude, longitude, accuracy_m)) => {
+                info!(provider = name, latitude, longitude, accuracy_m, "Online geolocation succeeded");
+                let estimate = LocationEstimate {
+                    latitude,
+                    longitude,
+                    accuracy_m,
+                    source: name.to_string(),
+                    towers: Vec::new(),
+                };
+                CACHE.lock().unwrap().insert(key, (Instant::now(), estimate.clone()));
+                return Ok((estimate, false));
+            }
+            Err(e) => {
+                warn!(provider = name, error = %e, "Online geolocation failed, trying next provider");
+                failures.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        Err("No geolocation provider enabled".to_string())
+    } else {
+        Err(format!("All geolocation providers failed: {}", failures.join("; ")))
+    }
+}
+
+/// 返回 API Key 已掩码的配置（用于 GET 接口）
+pub fn mask_api_keys(config: &GeolocationConfig) -> GeolocationConfig {
+    let mut masked = config.clone();
+    for provider in &mut masked.providers {
+        provider.api_key = mask_secret(&provider.api_key);
+    }
+    masked
+}
+
+/// 保存配置前还原未修改的 API Key
+///
+/// 客户端回传掩码值或空值时，沿用当前配置中同类服务的 API Key
+pub fn restore_api_keys(config: &mut GeolocationConfig, current: &GeolocationConfig) {
+    for provider in &mut config.providers {
+        let unchanged = provider.api_key.is_empty() || provider.api_key.starts_with(SECRET_MASK_PREFIX);
+        if !unchanged {
+            continue;
+        }
+        if let Some(existing) = current.providers.iter().find(|p| p.kind == provider.kind) {
+            if provider.api_key.is_empty() || provider.api_key == mask_secret(&existing.api_key) {
+                provider.api_key = existing.api_key.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_location() -> CellLocationResponse {
+        let cell = |cid: u32, pci: u32, signal: i32| CellLocationInfo {
+            mcc: "460".to_string(),
+            mnc: "01".to_string(),
+            lac: 6178,
+            cid,
+            signal_strength: signal,
+            radio_type: "lte".to_string(),
+            arfcn: Some(1650),
+            pci: Some(pci),
+            rsrq: None,
+            sinr: None,
+        };
+        CellLocationResponse {
+            available: true,
+            cell_info: Some(cell(123456789, 301, -85)),
+            neighbor_cells: vec![cell(0, 87, -101), cell(0, 12, -96)],
+            usage_hint: String::new(),
+        }
+    }
+
+    fn provider(kind: GeolocationProviderKind) -> GeolocationProvider {
+        GeolocationProvider {
+            kind,
+            enabled: true,
+            api_key: "KEY".to_string(),
+            url: String::new(),
+            min_interval_secs: 30,
+        }
+    }
+
+    #[test]
+    fn test_mask_and_restore_api_keys() {
+        let mut current = GeolocationConfig {
+            providers: vec![provider(GeolocationProviderKind::Google), provider(GeolocationProviderKind::UnwiredLabs)],
+            ..Default::default()
+        };
+        current.providers[0].api_key = "AIzaSyExample1234".to_string();
+
+        let masked = mask_api_keys(&current);
+        assert_eq!(masked.providers[0].api_key, "****1234");
+        assert_eq!(masked.providers[1].api_key, "****");
+
+        // 回传掩码值时保留原 Key，填写新值时替换
+        let mut update = masked.clone();
+        update.providers[1].api_key = "pk.new-token".to_string();
+        restore_api_keys(&mut update, &current);
+        assert_eq!(update.providers[0].api_key, "AIzaSyExample1234");
+        assert_eq!(update.providers[1].api_key, "pk.new-token");
+
+        // 新增的服务没有原 Key，掩码值不会被还原
+        let mut update = masked;
+        update.providers[0].kind = GeolocationProviderKind::OpenCellId;
+        restore_api_keys(&mut update, &current);
+        assert_eq!(update.providers[0].api_key, "****1234");
+    }
+
+    #[test]
+    fn test_build_requests() {
+        let location = sample_location();
+
+        let ProviderRequest::Post { url, body } = build_request(&provider(GeolocationProviderKind::Google), &location).unwrap() else {
+            panic!("google should POST");
+        };
+        assert!(url.ends_with("/geolocate?key=KEY"));
+        // 邻区没有 Cell ID，只提交主服务小区
+        assert_eq!(body["cellTowers"].as_array().unwrap().len(), 1);
+        assert_eq!(body["cellTowers"][0]["cellId"], 123456789);
+        assert_eq!(body["cellTowers"][0]["mobileNetworkCode"], 1);
+
+        let ProviderRequest::Post { body, .. } = build_request(&provider(GeolocationProviderKind::UnwiredLabs), &location).unwrap() else {
+            panic!("unwiredlabs should POST");
+        };
+        assert_eq!(body["token"], "KEY");
+        assert_eq!(body["cells"].as_array().unwrap().len(), 3);
+        assert_eq!(body["cells"][1]["psc"], 87);
+
+        let ProviderRequest::Get { query, .. } = build_request(&provider(GeolocationProviderKind::OpenCellId), &location).unwrap() else {
+            panic!("opencellid should GET");
+        };
+        assert!(query.contains(&("cellid".to_string(), "123456789".to_string())));
+        assert!(query.contains(&("radio".to_string(), "LTE".to_string())));
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let google = json!({ "location": { "lat": 39.9, "lng": 116.4 }, "accuracy": 550.0 });
+        assert_eq!(parse_response(GeolocationProviderKind::Google, &google).unwrap(), (39.9, 116.4, 550.0));
+        let google_error = json!({ "error": { "code": 404, "message": "Not Found" } });
+        assert!(parse_response(GeolocationProviderKind::Google, &google_error).is_err());
+
+        let unwired = json!({ "status": "ok", "lat": 39.9, "lon": 116.4, "accuracy": 300 });
+        assert_eq!(parse_response(GeolocationProviderKind::UnwiredLabs, &unwired).unwrap(), (39.9, 116.4, 300.0));
+        let unwired_error = json!({ "status": "error", "message": "No matches found" });
+        assert!(parse_response(GeolocationProviderKind::UnwiredLabs, &unwired_error).is_err());
+
+        let ocid = json!({ "lat": "39.9", "lon": "116.4", "range": "1000" });
+        assert_eq!(parse_response(GeolocationProviderKind::OpenCellId, &ocid).unwrap(), (39.9, 116.4, 1000.0));
+    }
+
+    #[test]
+    fn test_cell_set_key_ignores_neighbor_order() {
+        let location = sample_location();
+        let mut reordered = sample_location();
+        reordered.neighbor_cells.reverse();
+        assert_eq!(cell_set_key(&location), cell_set_key(&reordered));
+    }
+
+    /// 本地模拟服务：Google 返回错误，Unwired Labs 返回结果
+    #[tokio::test]
+    async fn test_locate_falls_back_and_caches() {
+        use axum::{routing::post, Json, Router};
+
+        let app = Router::new()
+            .route("/google", post(|| async { Json(json!({ "error": { "code": 404, "message": "Not Found" } })) }))
+            .route("/unwired", post(|| async { Json(json!({ "status": "ok", "lat": 39.9, "lon": 116.4, "accuracy": 300 })) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut google = provider(GeolocationProviderKind::Google);
+        google.url = format!("{}/google", base);
+        let mut unwired = provider(GeolocationProviderKind::UnwiredLabs);
+        unwired.url = format!("{}/unwired", base);
+        let config = GeolocationConfig {
+            providers: vec![google, unwired],
+            ..Default::default()
+        };
+
+        let location = sample_location();
+        let (estimate, cached) = locate(&config, &location, true).await.unwrap();
+        assert_eq!((estimate.source.as_str(), estimate.accuracy_m, cached), ("unwiredlabs", 300.0, false));
+
+        // 第二次命中缓存，不受限速影响
+        let (estimate, cached) = locate(&config, &location, false).await.unwrap();
+        assert_eq!((estimate.source.as_str(), cached), ("unwiredlabs", true));
+
+        // 忽略缓存时两个服务都在限速间隔内
+        let err = locate(&config, &location, true).await.unwrap_err();
+        assert!(err.contains("rate limited"));
+    }
+}
@@ -23,17 +23,35 @@ use std::sync::Arc;
 use zbus::Connection;
 
 use crate::{
+    alignment,
+    apn_db,
+    arfcn,
+    band_lock::{
+        apply_band_lock, band_capabilities, discover_band_capabilities, get_rat_name, lock_cell, query_cell_lock_status, resolve_forcefrq_type, unlock_all_cells,
+        unlock_cell,
+    },
+    carrier_aggregation::fetch_carrier_aggregation,
+    cell_tower,
+    config::ConfigManager,
+    data_session::{
+        availability_percent, compute_daily_availability, compute_outages, DataSessionTracker,
+        SessionEndReason,
+    },
     dbus::{
-        get_airplane_mode, get_all_apn_contexts, get_data_connection_status, get_device_info_data,
-        get_network_info_data, get_qos_info_data, get_radio_mode, get_roaming_status, get_serving_cell_info,
-        get_sim_info_data, send_at_command, set_airplane_mode, set_apn_properties, set_data_connection,
-        set_radio_mode, set_roaming_allowed,
+        add_apn_context, auto_configure_apn, find_internet_context, get_airplane_mode, get_all_apn_contexts,
+        get_data_connection_status, get_data_ip_config, get_device_info_data, get_network_info_data, get_qos_info_data,
+        get_radio_mode, get_roaming_status, get_serving_cell_info, get_sim_identity, get_sim_info_data,
+        remove_apn_context, send_at_command, set_airplane_mode, set_apn_properties, set_apn_property,
+        set_data_connection, set_radio_mode, set_roaming_allowed,
     },
-    iptables::flush_iptables,
+    geolocation,
     models::*,
+    safe_lock::SafeLockManager,
+    survey,
+    thermal::read_temperature_sensors,
     usb_switch,
     utils::{
-        bands_to_bitmask, bitmask_to_bands, build_splband_lte_command, build_splband_nr_command,
+        bitmask_to_bands,
         format_uptime, get_active_interfaces, get_cell_command_config,
         parse_at_response_to_2d_vec, parse_neighbor_cells, parse_primary_cell,
         parse_splband_lte_response, parse_splband_nr_response, read_cpu_info, read_cpu_load_sync,
@@ -82,12 +100,12 @@ pub async fn post_at_command(
 /// * `tech` - 网络制式
 ///
 /// # Returns
-/// 解析后的主小区信息
+/// (解析后的主小区信息, 主小区页原始二维数组)
 async fn fetch_primary_cell(
     conn: &Connection,
     cmd: &str,
     tech: &str,
-) -> Result<CellInfo, String> {
+) -> Result<(CellInfo, Vec<Vec<String>>), String> {
     let response = send_at_command(conn, cmd)
         .await
         .map_err(|e| format!("Primary cell AT command failed: {}", e))?;
@@ -95,7 +113,7 @@ async fn fetch_primary_cell(
     let parsed = parse_at_response_to_2d_vec(&response);
     let cell = parse_primary_cell(tech, &parsed);
 
-    Ok(cell)
+    Ok((cell, parsed))
 }
 
 /// 获取邻区信息列表
@@ -135,10 +153,24 @@ async fn fetch_neighbor_cells(
 ///       "cell_id": 12345,
 ///       "tac": 100
 ///     },
-///     "cells": [...]
+///     "cells": [...],
+///     "carrier_aggregation": {
+///       "mode": "endc",
+///       "carriers": [
+///         { "role": "pcell", "tech": "lte", "band": "B3", "arfcn": 1650, "pci": 123, "dl_freq_mhz": 1850.0, ... },
+///         { "role": "scell", "tech": "lte", "band": "B1", "arfcn": 100, "pci": 45, "dl_freq_mhz": 2120.0, ... },
+///         { "role": "pscell", "tech": "nr", "band": "n78", "arfcn": 633984, "pci": 597, "dl_freq_mhz": 3509.76, ... }
+///       ],
+///       "endc": { "anchor_band": "B3", "anchor_arfcn": 1650, "anchor_pci": 123, "nr_band": "n78", "nr_arfcn": 633984, "nr_pci": 597 }
+///     }
 ///   }
 /// }
 /// ```
+///
+/// carrier_aggregation.mode: single / lte_ca / nr_ca / endc
+///
+/// 分量载波取自主小区页的逗号分隔列，辅载波信号模组未上报时为空；主小区页不含载波带宽，
+/// 不返回带宽字段
 pub async fn get_cells(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
     let result = async {
         // 1. 获取服务小区信息（包含网络制式）
@@ -154,16 +186,20 @@ pub async fn get_cells(State(conn): State<Arc<Connection>>) -> impl IntoResponse
 
         // 3. 顺序获取主小区和邻区信息
         // 注意：ofono D-Bus 不支持并发 AT 指令，必须串行执行
-        let primary_cell = fetch_primary_cell(&conn, cmd_config.primary, tech).await?;
+        let (primary_cell, primary_page) = fetch_primary_cell(&conn, cmd_config.primary, tech).await?;
         let neighbor_cells = fetch_neighbor_cells(&conn, cmd_config.neighbor, tech).await?;
 
-        // 4. 合并主小区和邻区
+        // 4. 载波聚合 / EN-DC 分量载波
+        let carrier_aggregation = fetch_carrier_aggregation(&conn, tech, &primary_page).await;
+
+        // 5. 合并主小区和邻区
         let mut all_cells = vec![primary_cell];
         all_cells.extend(neighbor_cells);
 
         Ok::<_, String>(CellsResponse {
             serving_cell,
             cells: all_cells,
+            carrier_aggregation,
         })
     }
     .await;
@@ -180,6 +216,49 @@ pub async fn get_cells(State(conn): State<Arc<Connection>>) -> impl IntoResponse
     }
 }
 
+/// GET /api/arfcn/convert - 频点换算
+///
+/// 根据 3GPP TS 36.101/38.104 将 ARFCN/EARFCN 换算为频段和上下行中心频率
+///
+/// # 查询参数
+/// - `tech`: nr（默认）或 lte
+/// - `arfcn`: 频点号
+/// - `band`: 模组上报的频段（可选，用于区分 n77/n78 等重叠频段）
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "tech": "nr",
+///     "arfcn": 633984,
+///     "band": "n78",
+///     "band_number": 78,
+///     "duplex": "TDD",
+///     "dl_freq_mhz": 3509.76,
+///     "ul_freq_mhz": 3509.76,
+///     "candidates": ["n78", "n77"]
+///   }
+/// }
+/// ```
+pub async fn convert_arfcn_handler(Query(params): Query<ArfcnConvertRequest>) -> impl IntoResponse {
+    let band_hint = params.band.as_deref().and_then(arfcn::parse_band_number);
+    match arfcn::lookup(&params.tech, params.arfcn, band_hint) {
+        Some(info) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", info)),
+        ),
+        None => (
+            StatusCode::OK,
+            Json(ApiResponse::<ArfcnInfo>::error(format!(
+                "Unknown {} ARFCN: {}",
+                params.tech, params.arfcn
+            ))),
+        ),
+    }
+}
+
 /// GET /api/device - 获取设备信息（来自 D-Bus Modem 接口）
 ///
 /// # Response example
@@ -231,19 +310,26 @@ pub async fn get_device_info(State(conn): State<Arc<Connection>>) -> impl IntoRe
 /// ```
 ///
 /// # 说明
-/// 每次切换数据连接状态时，会自动清空 iptables 规则（flush），
+/// 每次切换数据连接状态时，会检查托管防火墙链并删除系统注入的有害规则，
 /// 以确保网络配置处于干净状态
 pub async fn set_data_status(
     State(conn): State<Arc<Connection>>,
+    State(sessions): State<Arc<DataSessionTracker>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
     Json(payload): Json<DataConnectionRequest>,
 ) -> impl IntoResponse {
-    // 1. 先清空 iptables 规则
-    if let Err(_e) = flush_iptables().await {
-        // 清空规则失败不应阻止数据连接操作，静默处理
+    // 1. 先修正防火墙规则
+    if let Err(_e) = firewall.enforce(config_manager.get_firewall()).await {
+        // 修正失败不应阻止数据连接操作，静默处理
     }
 
     // 2. 设置数据连接状态
-    match set_data_connection(&conn, payload.active).await {
+    if !payload.active {
+        sessions.mark_end_reason(SessionEndReason::UserAction);
+    }
+    let watchdog_context = config_manager.get_watchdog_context();
+    match set_data_connection(&conn, watchdog_context.as_deref(), payload.active).await {
         Ok(_) => {
             
             (
@@ -276,8 +362,12 @@ pub async fn set_data_status(
 ///   }
 /// }
 /// ```
-pub async fn get_data_status(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
-    match get_data_connection_status(&conn).await {
+pub async fn get_data_status(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> impl IntoResponse {
+    let watchdog_context = config_manager.get_watchdog_context();
+    match get_data_connection_status(&conn, watchdog_context.as_deref()).await {
         Ok(active) => (
             StatusCode::OK,
             Json(ApiResponse::success_with_message(
@@ -295,6 +385,67 @@ pub async fn get_data_status(State(conn): State<Arc<Connection>>) -> impl IntoRe
     }
 }
 
+/// GET /api/data/ip - Get full IP configuration of the data context
+///
+/// Reads the ofono context `Settings` / `IPv6.Settings` dictionaries and detects
+/// the public IPv4 address to tell whether the connection is behind CGNAT.
+/// Pass `?refresh=true` to bypass the public IP cache.
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "context_path": "/ril_0/context2",
+///     "active": true,
+///     "apn": "cmnet",
+///     "interface": "sipa_eth0",
+///     "ipv4": { "method": "static", "address": "10.123.45.67", "netmask": "255.255.255.0",
+///               "gateway": "10.123.45.1", "dns": ["211.136.17.107"] },
+///     "ipv6": { "address": "2409:8a00:1:2::1", "prefix_length": 64, "prefix": "2409:8a00:1:2::/64",
+///               "gateway": "", "dns": ["2409:8088::a"] },
+///     "has_ipv6": true,
+///     "public_ip": "112.1.2.3",
+///     "public_ip_error": null,
+///     "nat_type": "private",
+///     "behind_cgnat": true
+///   }
+/// }
+/// ```
+pub async fn get_data_ip_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Query(params): Query<DataIpQuery>,
+) -> (StatusCode, Json<ApiResponse<DataIpConfigResponse>>) {
+    let watchdog_context = config_manager.get_watchdog_context();
+    let mut config = match get_data_ip_config(&conn, watchdog_context.as_deref()).await {
+        Ok(config) => config,
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::error(format!("Failed to get IP configuration: {}", e))),
+            )
+        }
+    };
+
+    if config.active {
+        match crate::ip_info::detect_public_ip(&config.ipv4.address, params.refresh).await {
+            Ok(ip) => config.public_ip = Some(ip),
+            Err(e) => config.public_ip_error = Some(e),
+        }
+    }
+    let (nat_type, behind_cgnat) =
+        crate::ip_info::classify_nat(&config.ipv4.address, config.public_ip.as_deref());
+    config.nat_type = nat_type.to_string();
+    config.behind_cgnat = behind_cgnat;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config)),
+    )
+}
+
 /// GET /api/roaming - Get roaming status
 ///
 /// # Response example
@@ -413,8 +564,12 @@ pub async fn set_roaming_status_handler(
 /// ```
 pub async fn set_airplane_mode_handler(
     State(conn): State<Arc<Connection>>,
+    State(sessions): State<Arc<DataSessionTracker>>,
     Json(payload): Json<AirplaneModeRequest>,
 ) -> impl IntoResponse {
+    if payload.enabled {
+        sessions.mark_end_reason(SessionEndReason::UserAction);
+    }
     match set_airplane_mode(&conn, payload.enabled).await {
         Ok(_) => {
             // 读取当前状态确认
@@ -591,45 +746,6 @@ pub async fn get_qos_info(State(conn): State<Arc<Connection>>) -> impl IntoRespo
     }
 }
 
-/// 读取温度传感器数据（内部工具函数）
-fn read_temperature_sensors() -> Vec<ThermalZone> {
-    use std::fs;
-    use std::path::Path;
-
-    let thermal_path = Path::new("/sys/class/thermal");
-    let mut sensors = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(thermal_path) {
-        for entry in entries.flatten() {
-            let file_name = entry.file_name();
-            let name = file_name.to_string_lossy();
-
-            if name.starts_with("thermal_zone") {
-                let zone_path = entry.path();
-                
-                let sensor_type = fs::read_to_string(zone_path.join("type"))
-                    .map(|s| s.trim().to_string())
-                    .unwrap_or_default();
-
-                let temperature = fs::read_to_string(zone_path.join("temp"))
-                    .ok()
-                    .and_then(|s| s.trim().parse::<i32>().ok())
-                    .map(|t| t as f64 / 1000.0)
-                    .unwrap_or(0.0);
-
-                sensors.push(ThermalZone {
-                    zone: name.to_string(),
-                    sensor_type,
-                    temperature,
-                });
-            }
-        }
-    }
-
-    sensors.sort_by(|a, b| a.zone.cmp(&b.zone));
-    sensors
-}
-
 /// 获取USB模式名称
 fn get_mode_name(mode: Option<u8>) -> String {
     match mode {
@@ -760,7 +876,10 @@ pub async fn set_usb_mode(Json(payload): Json<SetUsbModeRequest>) -> impl IntoRe
 /// - macOS 可能需要更长时间识别新设备
 /// - 模式 3 (RNDIS) 在 macOS/Linux 上可能需要额外驱动
 /// - 建议使用模式 1 (NCM) 以获得最佳跨平台兼容性
-pub async fn set_usb_mode_advanced(Json(payload): Json<SetUsbModeRequest>) -> impl IntoResponse {
+pub async fn set_usb_mode_advanced(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(payload): Json<SetUsbModeRequest>,
+) -> impl IntoResponse {
     // 验证模式值
     if !(1..=3).contains(&payload.mode) {
         return (
@@ -772,8 +891,14 @@ pub async fn set_usb_mode_advanced(Json(payload): Json<SetUsbModeRequest>) -> im
     }
     
     // 执行热切换
-    match usb_switch::switch_usb_mode_advanced(payload.mode) {
+    let lan_config = config_manager.get_lan();
+    let wan_interface = config_manager.get_firewall().wan_interface;
+    let shaping_config = config_manager.get_shaping();
+    match usb_switch::switch_usb_mode_advanced(payload.mode, &lan_config, &wan_interface, &shaping_config) {
         Ok(_) => {
+            // 接口重建后重新绑定 DHCP 服务和 IPv6 直通
+            crate::dhcp::reload();
+            crate::ipv6_lan::reload();
             let mode_name = get_mode_name(Some(payload.mode));
             (
                 StatusCode::OK,
@@ -973,70 +1098,63 @@ pub async fn get_system_stats() -> impl IntoResponse {
 /// 
 /// 返回格式化的基站定位参数，可用于调用第三方定位API（如Google Geolocation、OpenCellID等）
 pub async fn get_cell_location_info(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
+    match collect_cell_location(&conn).await {
+        Ok((message, response)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(message, response)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<CellLocationResponse>::error(e)),
+        ),
+    }
+}
+
+/// 收集基站定位参数（主服务小区 + 邻区）
+///
+/// # Returns
+/// (提示信息, 定位参数)，MCC/MNC 不可用时 available 为 false
+async fn collect_cell_location(conn: &Connection) -> Result<(&'static str, CellLocationResponse), String> {
     // 获取网络信息（MCC、MNC）
-    let network_info = match get_network_info_data(&conn).await {
-        Ok(info) => info,
-        Err(e) => {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<CellLocationResponse>::error(format!(
-                    "Failed to get network info: {}",
-                    e
-                ))),
-            );
-        }
-    };
+    let network_info = get_network_info_data(conn)
+        .await
+        .map_err(|e| format!("Failed to get network info: {}", e))?;
 
     // 检查是否有 MCC 和 MNC
     let mcc = match network_info.mcc {
         Some(ref m) if !m.is_empty() => m.clone(),
         _ => {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::success_with_message(
-                    "Cell location unavailable: MCC not available",
-                    CellLocationResponse {
-                        available: false,
-                        cell_info: None,
-                        neighbor_cells: vec![],
-                        usage_hint: "Network not registered or MCC/MNC not available. Please ensure device is connected to cellular network.".to_string(),
-                    },
-                )),
-            );
+            return Ok((
+                "Cell location unavailable: MCC not available",
+                CellLocationResponse {
+                    available: false,
+                    cell_info: None,
+                    neighbor_cells: vec![],
+                    usage_hint: "Network not registered or MCC/MNC not available. Please ensure device is connected to cellular network.".to_string(),
+                },
+            ));
         }
     };
 
     let mnc = match network_info.mnc {
         Some(ref m) if !m.is_empty() => m.clone(),
         _ => {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::success_with_message(
-                    "Cell location unavailable: MNC not available",
-                    CellLocationResponse {
-                        available: false,
-                        cell_info: None,
-                        neighbor_cells: vec![],
-                        usage_hint: "Network not registered or MCC/MNC not available. Please ensure device is connected to cellular network.".to_string(),
-                    },
-                )),
-            );
+            return Ok((
+                "Cell location unavailable: MNC not available",
+                CellLocationResponse {
+                    available: false,
+                    cell_info: None,
+                    neighbor_cells: vec![],
+                    usage_hint: "Network not registered or MCC/MNC not available. Please ensure device is connected to cellular network.".to_string(),
+                },
+            ));
         }
     };
 
     // 获取服务小区信息（TAC、CID）
-    let serving_cell = match get_serving_cell_info(&conn).await {
-        Ok(cell) => cell,
-        Err(e) => {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<CellLocationResponse>::error(format!(
-                    "Failed to get serving cell info: {}",
-                    e
-                ))),
-            );
-        }
-    };
+    let serving_cell = get_serving_cell_info(conn)
+        .await
+        .map_err(|e| format!("Failed to get serving cell info: {}", e))?;
 
     // 获取详细的小区信息（信号强度等）
     let tech = serving_cell.tech.as_str();
@@ -1072,24 +1190,21 @@ pub async fn get_cell_location_info(State(conn): State<Arc<Connection>>) -> impl
                 serving_cell.tac
             );
 
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::success_with_message(
-                    "Success (limited info)",
-                    CellLocationResponse {
-                        available: cell_info.is_some(),
-                        cell_info,
-                        neighbor_cells: vec![],
-                        usage_hint,
-                    },
-                )),
-            );
+            return Ok((
+                "Success (limited info)",
+                CellLocationResponse {
+                    available: cell_info.is_some(),
+                    cell_info,
+                    neighbor_cells: vec![],
+                    usage_hint,
+                },
+            ));
         }
     };
 
     // 获取主小区和邻区详细信息
-    let serving_cell_detail = fetch_primary_cell(&conn, cmd_config.primary, tech).await.ok();
-    let neighbor_cells = fetch_neighbor_cells(&conn, cmd_config.neighbor, tech).await.unwrap_or_default();
+    let serving_cell_detail = fetch_primary_cell(conn, cmd_config.primary, tech).await.ok().map(|(cell, _)| cell);
+    let neighbor_cells = fetch_neighbor_cells(conn, cmd_config.neighbor, tech).await.unwrap_or_default();
 
     // 构建主服务小区定位信息
     let cell_info = if serving_cell.cell_id > 0 {
@@ -1169,1488 +1284,3867 @@ pub async fn get_cell_location_info(State(conn): State<Arc<Connection>>) -> impl
         usage_hint,
     };
 
-    (
-        StatusCode::OK,
-        Json(ApiResponse::success_with_message("Success", response)),
-    )
-}
-
-/// GET /api/network/interfaces - 获取所有网络接口详细信息
-/// 
-/// 返回所有网络接口的详细信息，包括：
-/// - 接口名称、状态、MAC地址、MTU
-/// - IPv4和IPv6地址列表
-/// - 公网/内网地址分类
-/// - 流量统计（接收/发送字节数、包数、错误数）
-pub async fn get_network_interfaces_info() -> impl IntoResponse {
-    let result = tokio::task::spawn_blocking(|| {
-        let interfaces = read_network_interfaces()?;
-        let total_count = interfaces.len();
-        
-        Ok::<_, String>(NetworkInterfacesResponse {
-            interfaces,
-            total_count,
-        })
-    })
-    .await;
-    
-    match result {
-        Ok(Ok(data)) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", data)),
-        ),
-        Ok(Err(msg)) => (
-            StatusCode::OK,
-            Json(ApiResponse::<NetworkInterfacesResponse>::error(msg)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::<NetworkInterfacesResponse>::error(format!(
-                "Task execution failed: {}",
-                e
-            ))),
-        ),
-    }
+    Ok(("Success", response))
 }
 
-/// GET /api/radio-mode - 获取当前射频模式
+/// GET /api/location/estimate - 离线位置估算
 ///
-/// # 返回
+/// 在本地离线基站库中查找主服务小区和邻区，按信号强度加权估算位置，不依赖网络。
+/// 基站库需先通过 /api/location/towers/import 导入。
+///
+/// ## 响应示例
 /// ```json
 /// {
 ///   "status": "ok",
-///   "message": "Success",
+///   "message": "Estimated from 3 towers",
 ///   "data": {
-///     "mode": "auto",
-///     "technology_preference": "NR 5G/LTE auto"
+///     "latitude": 39.9163,
+///     "longitude": 116.3975,
+///     "accuracy_m": 420.0,
+///     "source": "offline",
+///     "towers": [
+///       { "role": "serving", "radio": "LTE", "area": 6178, "cell": 123456789, "pci": 301,
+///         "latitude": 39.9165, "longitude": 116.3971, "range_m": 850, "rsrp_dbm": -85, "weight": 0.82 }
+///     ]
 ///   }
 /// }
 /// ```
-pub async fn get_radio_mode_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
-    match get_radio_mode(&conn).await {
-        Ok(data) => (
+pub async fn get_location_estimate_handler(
+    State(conn): State<Arc<Connection>>,
+    State(db): State<Arc<Database>>,
+) -> impl IntoResponse {
+    let location = match collect_cell_location(&conn).await {
+        Ok((_, location)) if location.available => location,
+        Ok((message, _)) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<LocationEstimate>::error(message.to_string())),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<LocationEstimate>::error(e)),
+            );
+        }
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        let towers = cell_tower::match_towers(&db, &location)?;
+        cell_tower::weighted_estimate(towers)
+            .ok_or_else(|| "No matching towers in offline database".to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Task failed: {}", e)));
+
+    match result {
+        Ok(estimate) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", data)),
+            Json(ApiResponse::success_with_message(
+                format!("Estimated from {} towers", estimate.towers.len()),
+                estimate,
+            )),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::<RadioModeResponse>::error(format!(
-                "Failed to get radio mode: {}",
-                e
-            ))),
+            Json(ApiResponse::<LocationEstimate>::error(e)),
         ),
     }
 }
 
-/// POST /api/radio-mode - 设置射频模式
+/// GET /api/location/online - 在线定位
 ///
-/// # 请求体
+/// 按配置顺序调用在线定位服务（Google / Unwired Labs / OpenCellID），
+/// 失败或限速时使用下一个服务；同一组小区的结果会被缓存
+///
+/// # 查询参数
+/// - `refresh`: 为 true 时忽略缓存
+///
+/// ## 响应示例
 /// ```json
 /// {
-///   "mode": "auto"  // auto | lte | nr
+///   "status": "ok",
+///   "message": "Located by unwiredlabs (cached)",
+///   "data": {
+///     "latitude": 39.9163,
+///     "longitude": 116.3975,
+///     "accuracy_m": 300.0,
+///     "source": "unwiredlabs",
+///     "towers": []
+///   }
 /// }
 /// ```
-///
-/// # 说明
-/// - auto: 4G/5G 自动切换
-/// - lte: 仅 4G LTE
-/// - nr: 仅 5G NR
-pub async fn set_radio_mode_handler(
+pub async fn get_online_location_handler(
     State(conn): State<Arc<Connection>>,
-    Json(payload): Json<RadioModeRequest>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Query(params): Query<GeolocationRequest>,
 ) -> impl IntoResponse {
-    match set_radio_mode(&conn, payload.mode.clone()).await {
-        Ok(_) => {
-            let mode_str = match payload.mode {
-                RadioMode::Auto => "4G/5G Auto",
-                RadioMode::LteOnly => "4G LTE Only",
-                RadioMode::NrOnly => "5G NR Only",
+    // 天线对准期间不占用 AT 通道
+    if alignment::is_active() {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<LocationEstimate>::error("Paused during antenna alignment")),
+        );
+    }
+    let location = match collect_cell_location(&conn).await {
+        Ok((_, location)) if location.available => location,
+        Ok((message, _)) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<LocationEstimate>::error(message.to_string())),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<LocationEstimate>::error(e)),
+            );
+        }
+    };
+
+    let config = config_manager.get_geolocation();
+    match geolocation::locate(&config, &location, params.refresh).await {
+        Ok((estimate, cached)) => {
+            let message = if cached {
+                format!("Located by {} (cached)", estimate.source)
+            } else {
+                format!("Located by {}", estimate.source)
             };
             (
                 StatusCode::OK,
-                Json(ApiResponse::success_with_message(
-                    format!("Radio mode set to {}", mode_str),
-                    json!({}),
-                )),
+                Json(ApiResponse::success_with_message(message, estimate)),
             )
         }
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::<serde_json::Value>::error(format!(
-                "Failed to set radio mode: {}",
-                e
-            ))),
+            Json(ApiResponse::<LocationEstimate>::error(e)),
         ),
     }
 }
 
-/// GET /api/band-lock - 获取当前频段锁定状态
+/// GET /api/location/providers - 获取在线定位服务配置
 ///
-/// # 返回
+/// api_key 只返回末 4 位（如 `****1a2b`，8 位及以下为 `****`），未设置时为空字符串
+pub async fn get_geolocation_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::GeolocationConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            crate::geolocation::mask_api_keys(&config_manager.get_geolocation()),
+        )),
+    )
+}
+
+/// POST /api/location/providers - 设置在线定位服务配置
+///
+/// # 请求体
 /// ```json
 /// {
-///   "status": "ok",
-///   "message": "Success",
-///   "data": {
-///     "locked": true,
-///     "lte_fdd_bands": [1, 3, 8],
-///     "lte_tdd_bands": [38, 40, 41],
-///     "nr_fdd_bands": [1, 28],
-///     "nr_tdd_bands": [41, 77, 78, 79]
-///   }
+///   "providers": [
+///     { "kind": "unwiredlabs", "enabled": true, "api_key": "pk.xxx", "url": "", "min_interval_secs": 30 },
+///     { "kind": "google", "enabled": true, "api_key": "AIza...", "url": "", "min_interval_secs": 60 },
+///     { "kind": "opencellid", "enabled": false, "api_key": "pk.xxx", "url": "", "min_interval_secs": 30 }
+///   ],
+///   "cache_ttl_secs": 86400,
+///   "timeout_secs": 10
 /// }
 /// ```
-pub async fn get_band_lock_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
-    // 读取 LTE 频段锁定状态
-    let lte_result = send_at_command(&conn, "AT+SPLBAND=0").await;
-    let (lte_fdd_mask, lte_tdd_mask, lte_raw) = match lte_result {
-        Ok(response) => {
-            let (fdd, tdd) = parse_splband_lte_response(&response);
-            (fdd, tdd, Some(response))
-        }
-        Err(e) => (0, 0, Some(format!("Error: {}", e))),
-    };
-
-    // 读取 NR 频段锁定状态
-    let nr_result = send_at_command(&conn, "AT+SPLBAND=3").await;
-    let (nr_fdd_mask, nr_tdd_mask, nr_raw) = match nr_result {
-        Ok(response) => {
-            let (fdd, tdd) = parse_splband_nr_response(&response);
-            (fdd, tdd, Some(response))
-        }
-        Err(e) => (0, 0, Some(format!("Error: {}", e))),
-    };
-
-    // UDX710 设备支持的全部频段掩码
-    // LTE: FDD=149 (B1+B3+B5+B8), TDD=320 (B39+B41)
-    // NR: FDD=517 (N1+N3+N28), TDD=912 (N41+N77+N78+N79)
-    const LTE_FDD_ALL: u16 = 149;
-    const LTE_TDD_ALL: u16 = 320;
-    const NR_FDD_ALL: u16 = 517;
-    const NR_TDD_ALL: u16 = 912;
-    
-    // 判断是否有频段锁定
-    // 如果返回的频段等于设备支持的全部频段，则认为"未锁定"（全部可用）
-    // 如果返回 0 或小于全部，则认为"已锁定"（限制了可用频段）
-    let lte_is_all_or_zero = (lte_fdd_mask == LTE_FDD_ALL && lte_tdd_mask == LTE_TDD_ALL) 
-                            || (lte_fdd_mask == 0 && lte_tdd_mask == 0);
-    let nr_is_all_or_zero = (nr_fdd_mask == NR_FDD_ALL && nr_tdd_mask == NR_TDD_ALL)
-                           || (nr_fdd_mask == 0 && nr_tdd_mask == 0);
-    let locked = !(lte_is_all_or_zero && nr_is_all_or_zero);
-    
-    // 将位掩码转换为频段号列表
-    // 未锁定时返回空数组（前端显示为"未锁定模式"）
-    // 已锁定时返回具体频段列表（前端显示为"自定义锁定模式"）
-    let (lte_fdd_bands, lte_tdd_bands, nr_fdd_bands, nr_tdd_bands) = if !locked {
-        // 未锁定：返回空数组
-        (vec![], vec![], vec![], vec![])
-    } else {
-        // 已锁定：返回具体频段
-        (
-            bitmask_to_bands(lte_fdd_mask, 1),    // LTE FDD: B1-B16
-            bitmask_to_bands(lte_tdd_mask, 33),   // LTE TDD: B33-B48
-            bitmask_to_bands(nr_fdd_mask, 100),   // NR FDD: 展锐特殊映射
-            bitmask_to_bands(nr_tdd_mask, 41),    // NR TDD: 展锐特殊映射
-        )
-    };
-
-    // 构建调试信息
-    let raw_response = Some(format!(
-        "LTE(fdd={},tdd={}): {}\nNR(fdd={},tdd={}): {}",
-        lte_fdd_mask, lte_tdd_mask, lte_raw.unwrap_or_default().trim(),
-        nr_fdd_mask, nr_tdd_mask, nr_raw.unwrap_or_default().trim()
-    ));
+///
+/// # 说明
+/// - kind: google / unwiredlabs / opencellid，按数组顺序尝试
+/// - url 为空时使用官方接口地址，可指向自建的兼容服务
+/// - api_key 为空或为 GET 返回的掩码值时，保留该服务已保存的 API Key
+pub async fn set_geolocation_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(mut geolocation_config): Json<crate::config::GeolocationConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    crate::geolocation::restore_api_keys(&mut geolocation_config, &config_manager.get_geolocation());
+    if let Some(p) = geolocation_config
+        .providers
+        .iter()
+        .find(|p| p.enabled && p.api_key.starts_with(crate::utils::SECRET_MASK_PREFIX))
+    {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("API key for provider {:?} must be re-entered", p.kind))),
+        );
+    }
+    if let Some(p) = geolocation_config.providers.iter().find(|p| p.enabled && p.api_key.is_empty()) {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("API key required for provider {:?}", p.kind))),
+        );
+    }
 
-    let status = BandLockStatus {
-        locked,
-        lte_fdd_bands,
-        lte_tdd_bands,
-        nr_fdd_bands,
-        nr_tdd_bands,
-        raw_response,
-    };
+    match config_manager.set_geolocation(geolocation_config) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Geolocation config updated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to update geolocation config: {}", e))),
+        ),
+    }
+}
 
-    (
-        StatusCode::OK,
-        Json(ApiResponse::success_with_message("Success", status)),
-    )
+/// GET /api/location/towers - 获取离线基站库统计和导入状态
+pub async fn get_cell_towers_handler(State(db): State<Arc<Database>>) -> impl IntoResponse {
+    match db.get_cell_tower_counts() {
+        Ok(counts) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                "Success",
+                CellTowerDbInfo {
+                    total: counts.iter().map(|c| c.count).sum(),
+                    counts,
+                    import: cell_tower::import_status(),
+                },
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<CellTowerDbInfo>::error(format!("Failed to get cell towers: {}", e))),
+        ),
+    }
 }
 
-/// POST /api/system/reboot - 系统重启
+/// POST /api/location/towers/import - 导入离线基站库
 ///
-/// # Request body（可选）
+/// 在后台导入设备上的 OpenCellID / Mozilla Location Service CSV 文件（支持 .gz），
+/// 进度通过 GET /api/location/towers 查询。.gz 文件损坏或被截断（gzip 非零退出）时导入失败，
+/// 已写入的记录保留
+///
+/// ## 请求示例
 /// ```json
 /// {
-///   "delay_seconds": 3  // 延迟秒数，默认为3秒
+///   "path": "/mnt/data/cell_towers.csv.gz",
+///   "mcc": [460],
+///   "replace": true
 /// }
 /// ```
+pub async fn import_cell_towers_handler(
+    State(db): State<Arc<Database>>,
+    Json(payload): Json<TowerImportRequest>,
+) -> impl IntoResponse {
+    match cell_tower::start_import(db, payload) {
+        Ok(status) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Cell tower import started", status)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<TowerImportStatus>::error(e)),
+        ),
+    }
+}
+
+// ============ 站点勘测 API ============
+
+/// GET /api/survey - 获取勘测状态和会话列表
 ///
-/// # Response example
+/// ## 响应示例
 /// ```json
 /// {
 ///   "status": "ok",
-///   "message": "System will reboot in 3 seconds"
+///   "message": "Success",
+///   "data": {
+///     "status": { "active": true, "session_id": 3, "interval_ms": 2000, "label": "roof", "rounds": 42, "last_cell_count": 5 },
+///     "sessions": [
+///       { "id": 3, "name": "Roof survey", "started_at": "2025-12-15T10:00:00+00:00", "finished_at": null, "interval_ms": 2000, "sample_count": 210 }
+///     ]
+///   }
 /// }
 /// ```
-pub async fn system_reboot(
-    Json(payload): Json<Option<SystemRebootRequest>>,
-) -> impl IntoResponse {
-    let delay = payload.map(|p| p.delay_seconds).unwrap_or(3);
-    
-    // 使用 tokio 异步执行重启命令
-    tokio::spawn(async move {
-        // 等待指定的延迟时间
-        tokio::time::sleep(tokio::time::Duration::from_secs(delay as u64)).await;
-        
-        // 执行重启命令
-        let _ = Command::new("reboot").output();
-    });
-    
-    (
-        StatusCode::OK,
-        Json(ApiResponse::success_with_message(
-            format!("系统将在 {} 秒后重启", delay),
-            json!({"delay_seconds": delay}),
-        )),
-    )
+pub async fn get_survey_handler(State(db): State<Arc<Database>>) -> impl IntoResponse {
+    match db.get_survey_sessions() {
+        Ok(sessions) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                "Success",
+                json!({ "status": survey::status(), "sessions": sessions }),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Failed to get survey sessions: {}", e))),
+        ),
+    }
 }
 
-/// POST /api/band-lock - 设置频段锁定
+/// POST /api/survey/start - 开始站点勘测
 ///
-/// # 请求体
+/// 按 interval_ms 采样主小区和邻区，直到调用 /api/survey/stop
+///
+/// ## 请求示例
 /// ```json
 /// {
-///   "lte_fdd_bands": [1, 3, 8],
-///   "lte_tdd_bands": [38, 40, 41],
-///   "nr_fdd_bands": [1, 28],
-///   "nr_tdd_bands": [41, 77, 78, 79]
+///   "name": "Roof survey",
+///   "interval_ms": 2000,
+///   "label": "north corner",
+///   "latitude": 39.9163,
+///   "longitude": 116.3975
 /// }
 /// ```
-///
-/// # 说明
-/// - 传入空数组表示不锁定对应类型的频段
-/// - 所有数组都为空时，表示解除所有频段锁定
-/// - LTE FDD: B1-B16, TDD: B33-B48
-/// - NR FDD: N1-N16, TDD: N41-N56 (实际支持 N41-N79)
-pub async fn set_band_lock_handler(
+pub async fn start_survey_handler(
     State(conn): State<Arc<Connection>>,
-    Json(payload): Json<BandLockRequest>,
+    State(db): State<Arc<Database>>,
+    Json(payload): Json<SurveyStartRequest>,
 ) -> impl IntoResponse {
-    // LTE 频段锁定
-    let lte_fdd_mask = bands_to_bitmask(&payload.lte_fdd_bands, 1);
-    let lte_tdd_mask = bands_to_bitmask(&payload.lte_tdd_bands, 33);
-    
-    if lte_fdd_mask != 0 || lte_tdd_mask != 0 {
-        let lte_cmd = build_splband_lte_command(lte_fdd_mask, lte_tdd_mask);
-        if let Err(e) = send_at_command(&conn, &lte_cmd).await {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "Failed to set LTE band lock: {}",
-                    e
-                ))),
-            );
-        }
+    match survey::start(conn, db, payload) {
+        Ok(status) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Survey started", status)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<SurveyStatus>::error(e)),
+        ),
     }
+}
 
-    // NR 频段锁定
-    let nr_fdd_mask = bands_to_bitmask(&payload.nr_fdd_bands, 100); // NR FDD: 展锐特殊映射
-    let nr_tdd_mask = bands_to_bitmask(&payload.nr_tdd_bands, 41);  // NR TDD: 展锐特殊映射
-    
-    if nr_fdd_mask != 0 || nr_tdd_mask != 0 {
-        let nr_cmd = build_splband_nr_command(nr_fdd_mask, nr_tdd_mask);
-        if let Err(e) = send_at_command(&conn, &nr_cmd).await {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "Failed to set NR band lock: {}",
-                    e
-                ))),
-            );
-        }
+/// POST /api/survey/stop - 结束站点勘测
+pub async fn stop_survey_handler(State(db): State<Arc<Database>>) -> impl IntoResponse {
+    match survey::stop(&db) {
+        Ok(status) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Survey stopped", status)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<SurveyStatus>::error(e)),
+        ),
     }
+}
 
-    // 如果所有频段都为空，则解除锁定
-    if payload.lte_fdd_bands.is_empty()
-        && payload.lte_tdd_bands.is_empty()
-        && payload.nr_fdd_bands.is_empty()
-        && payload.nr_tdd_bands.is_empty()
-    {
-        let mut lte_unlocked = false;
-        let mut nr_unlocked = false;
-        
-        // 先读取当前 LTE 锁定状态
-        let lte_result = send_at_command(&conn, "AT+SPLBAND=0").await;
-        if let Ok(lte_response) = lte_result {
-            let (lte_fdd_mask, lte_tdd_mask) = parse_splband_lte_response(&lte_response);
-            
-            // 只有当前有 LTE 锁定时才执行解锁
-            if lte_fdd_mask != 0 || lte_tdd_mask != 0 {
-                // 格式: AT+SPLBAND=1,0,<TDD>,0,<FDD>,0 (6 参数)
-                if let Err(e) = send_at_command(&conn, "AT+SPLBAND=1,0,0,0,0,0").await {
-                    return (
-                        StatusCode::OK,
-                        Json(ApiResponse::<serde_json::Value>::error(format!(
-                            "Failed to unlock LTE bands: {}",
-                            e
-                        ))),
-                    );
-                }
-                lte_unlocked = true;
-            }
-        }
-        
-        // 先读取当前 NR 锁定状态
-        let nr_result = send_at_command(&conn, "AT+SPLBAND=3").await;
-        if let Ok(nr_response) = nr_result {
-            let (nr_fdd_mask, nr_tdd_mask) = parse_splband_nr_response(&nr_response);
-            
-            // 只有当前有 NR 锁定时才执行解锁
-            if nr_fdd_mask != 0 || nr_tdd_mask != 0 {
-                if let Err(e) = send_at_command(&conn, "AT+SPLBAND=2,0,0,0,0").await {
-                    return (
-                        StatusCode::OK,
-                        Json(ApiResponse::<serde_json::Value>::error(format!(
-                            "Failed to unlock NR bands: {}",
-                            e
-                        ))),
-                    );
-                }
-                nr_unlocked = true;
-            }
-        }
-        
-        // 根据实际执行的解锁操作返回友好的提示信息
-        let message = if lte_unlocked || nr_unlocked {
-            if lte_unlocked && nr_unlocked {
-                "已解除所有频段锁定（LTE + NR）"
-            } else if lte_unlocked {
-                "已解除 LTE 频段锁定（NR 未锁定）"
-            } else {
-                "已解除 NR 频段锁定（LTE 未锁定）"
-            }
-        } else {
-            "当前没有锁定的频段，无需解锁"
-        };
-        
-        return (
+/// POST /api/survey/tag - 更新位置标签
+///
+/// 之后的采样都会带上此标签和坐标（坐标可省略）
+///
+/// ## 请求示例
+/// ```json
+/// {
+///   "label": "balcony",
+///   "latitude": 39.9164,
+///   "longitude": 116.3978
+/// }
+/// ```
+pub async fn tag_survey_handler(Json(payload): Json<SurveyTagRequest>) -> impl IntoResponse {
+    match survey::tag(payload) {
+        Ok(status) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                message,
-                json!({}),
-            )),
-        );
+            Json(ApiResponse::success_with_message("Survey location updated", status)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<SurveyStatus>::error(e)),
+        ),
     }
-
-    // 生成友好的提示信息
-    let has_lte = lte_fdd_mask != 0 || lte_tdd_mask != 0;
-    let has_nr = nr_fdd_mask != 0 || nr_tdd_mask != 0;
-    let message = if has_lte && has_nr {
-        "已同时锁定 LTE 和 NR 频段"
-    } else if has_lte {
-        "LTE 频段锁定已应用"
-    } else {
-        "NR 频段锁定已应用"
-    };
-
-    (
-        StatusCode::OK,
-        Json(ApiResponse::success_with_message(
-            message,
-            json!({}),
-        )),
-    )
 }
 
-// ============ 小区锁定 API ============
-// 使用 AT+SPFORCEFRQ 指令实现小区锁定
-// 发现来源：通过 dbus-monitor 监听实际锁频操作
-
-use crate::models::{CellLockStatusResponse, CellLockRatStatus, CellLockRequest, CellUnlockRequest};
-
-/// SPFORCEFRQ 网络类型常量
-const FORCEFRQ_TYPE_LTE: u8 = 12;
-const FORCEFRQ_TYPE_NR: u8 = 16;
+/// 读取勘测会话和采样
+fn load_survey(db: &Database, id: i64) -> Result<(crate::db::SurveySession, Vec<crate::db::SurveySample>), String> {
+    let session = db
+        .get_survey_session(id)
+        .map_err(|e| format!("Failed to get survey session: {}", e))?
+        .ok_or_else(|| format!("Survey session {} not found", id))?;
+    let samples = db
+        .get_survey_samples(id)
+        .map_err(|e| format!("Failed to get survey samples: {}", e))?;
+    Ok((session, samples))
+}
 
-/// 获取 RAT 类型名称
-fn get_rat_name(rat: u8) -> String {
-    match rat {
-        12 => "LTE".to_string(),
-        16 => "NR".to_string(),
-        _ => format!("Unknown({})", rat),
+/// GET /api/survey/{id} - 获取勘测会话的小区汇总
+///
+/// 每个小区（制式 + 频点 + PCI）的观测次数、最好/平均/最差 RSRP（dBm）、
+/// 平均 SINR 以及观测到最好信号的位置标签，按最好 RSRP 降序
+pub async fn get_survey_detail_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        let (session, samples) = load_survey(&db, id)?;
+        Ok::<_, String>(SurveyDetail {
+            session,
+            cells: survey::summarize(&samples),
+        })
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Task failed: {}", e)));
+
+    match result {
+        Ok(detail) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", detail)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<SurveyDetail>::error(e)),
+        ),
     }
 }
 
-/// 解析 AT+SPFORCEFRQ 查询响应
-/// 
-/// 响应格式:
-/// - 未锁定: +SPFORCEFRQ: 16,3
-/// - 已锁定: +SPFORCEFRQ: 16,3,633984,597
-fn parse_spforcefrq_query_response(response: &str, rat: u8) -> CellLockRatStatus {
-    let prefix = format!("+SPFORCEFRQ: {},3", rat);
-    
-    if let Some(line) = response.lines().find(|l| l.starts_with(&prefix)) {
-        let data = line.strip_prefix(&format!("+SPFORCEFRQ: {},3", rat)).unwrap_or("");
-        let data = data.trim_start_matches(',');
-        
-        if data.is_empty() {
-            // 未锁定
-            CellLockRatStatus {
-                rat,
-                rat_name: get_rat_name(rat),
-                enabled: false,
-                lock_type: 0,
-                pci: None,
-                arfcn: None,
-            }
-        } else {
-            // 已锁定，解析 arfcn,pci
-            let parts: Vec<&str> = data.split(',').collect();
-            let arfcn = parts.first().and_then(|s| s.trim().parse::<u32>().ok());
-            let pci = parts.get(1).and_then(|s| s.trim().parse::<u16>().ok());
-            
-            CellLockRatStatus {
-                rat,
-                rat_name: get_rat_name(rat),
-                enabled: arfcn.is_some() && pci.is_some(),
-                lock_type: 3,
-                pci,
-                arfcn,
+/// GET /api/survey/{id}/export - 导出勘测数据
+///
+/// # 查询参数
+/// - `format`: csv（默认，全部采样）或 kml（只包含带坐标的采样，按小区分文件夹）
+pub async fn export_survey_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Query(params): Query<SurveyExportRequest>,
+) -> impl IntoResponse {
+    let format = params.format.to_lowercase();
+    let result = tokio::task::spawn_blocking(move || {
+        let (session, samples) = load_survey(&db, id)?;
+        match format.as_str() {
+            "csv" => Ok(("text/csv; charset=utf-8", "csv", survey::to_csv(&samples))),
+            "kml" => Ok(("application/vnd.google-earth.kml+xml", "kml", survey::to_kml(&session, &samples))),
+            other => Err(format!("Unsupported export format: {}", other)),
+        }
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Task failed: {}", e)));
+
+    let mut headers = HeaderMap::new();
+    match result {
+        Ok((content_type, extension, body)) => {
+            headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            if let Ok(disposition) = HeaderValue::from_str(&format!("attachment; filename=\"survey_{}.{}\"", id, extension)) {
+                headers.insert(axum::http::header::CONTENT_DISPOSITION, disposition);
             }
+            (StatusCode::OK, headers, body)
         }
-    } else {
-        // 解析失败，返回未锁定状态
-        CellLockRatStatus {
-            rat,
-            rat_name: get_rat_name(rat),
-            enabled: false,
-            lock_type: 0,
-            pci: None,
-            arfcn: None,
+        Err(e) => {
+            headers.insert(
+                axum::http::header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            (StatusCode::BAD_REQUEST, headers, e)
         }
     }
 }
 
-/// GET /api/cell-lock - 获取小区锁定状态
-/// 
-/// 使用 AT+SPFORCEFRQ=<type>,3 查询锁定状态
-/// 
+/// DELETE /api/survey/{id} - 删除勘测会话
+pub async fn delete_survey_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    if survey::status().session_id == Some(id) {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error("Cannot delete a running survey, stop it first")),
+        );
+    }
+
+    match db.delete_survey_session(id) {
+        Ok(0) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Survey session {} not found", id))),
+        ),
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Survey session deleted", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to delete survey session: {}", e))),
+        ),
+    }
+}
+
+// ============ 天线对准 API ============
+
+/// GET /api/alignment - 获取天线对准状态
+///
 /// ## 响应示例
 /// ```json
 /// {
 ///   "status": "ok",
 ///   "message": "Success",
 ///   "data": {
-///     "rat_status": [
-///       { "rat": 16, "rat_name": "NR", "enabled": true, "lock_type": 3, "pci": 597, "arfcn": 633984 }
-///     ],
-///     "any_locked": true
+///     "active": true, "started_at": "2025-12-15T14:00:00+00:00", "interval_ms": 200, "timeout_secs": 300,
+///     "direction": "NE 45°", "samples": 812,
+///     "best": { "direction": "NE 45°", "rsrp_avg": -88.4, "sinr_avg": 14.2, "timestamp": "2025-12-15T14:02:10+00:00" }
 ///   }
 /// }
 /// ```
-pub async fn get_cell_lock_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
-    let mut rat_status = Vec::new();
-    let mut any_locked = false;
-    
-    // 查询 NR 锁定状态
-    let nr_cmd = format!("AT+SPFORCEFRQ={},3", FORCEFRQ_TYPE_NR);
-    if let Ok(response) = send_at_command(&conn, &nr_cmd).await {
-        let status = parse_spforcefrq_query_response(&response, FORCEFRQ_TYPE_NR);
-        if status.enabled {
-            any_locked = true;
-        }
-        rat_status.push(status);
-    } else {
-        rat_status.push(CellLockRatStatus {
-            rat: FORCEFRQ_TYPE_NR,
-            rat_name: "NR".to_string(),
-            enabled: false,
-            lock_type: 0,
-            pci: None,
-            arfcn: None,
-        });
-    }
-    
-    // 查询 LTE 锁定状态
-    let lte_cmd = format!("AT+SPFORCEFRQ={},3", FORCEFRQ_TYPE_LTE);
-    if let Ok(response) = send_at_command(&conn, &lte_cmd).await {
-        let status = parse_spforcefrq_query_response(&response, FORCEFRQ_TYPE_LTE);
-        if status.enabled {
-            any_locked = true;
-        }
-        rat_status.push(status);
-    } else {
-        rat_status.push(CellLockRatStatus {
-            rat: FORCEFRQ_TYPE_LTE,
-            rat_name: "LTE".to_string(),
-            enabled: false,
-            lock_type: 0,
-            pci: None,
-            arfcn: None,
-        });
-    }
-    
-    let response = CellLockStatusResponse {
-        rat_status,
-        any_locked,
-    };
-    
+pub async fn get_alignment_handler() -> impl IntoResponse {
     (
         StatusCode::OK,
-        Json(ApiResponse::success_with_message("Success", response)),
+        Json(ApiResponse::success_with_message("Success", alignment::status())),
     )
 }
 
-/// POST /api/cell-lock - 设置小区锁定
-/// 
-/// 使用 AT+SPFORCEFRQ 指令锁定到指定小区
-/// 
-/// ## 锁定流程
-/// 1. AT+SFUN=5 - 进入工程模式
-/// 2. AT+SPFORCEFRQ=16,0 - 清空 NR 锁定
-/// 3. AT+SPFORCEFRQ=12,0 - 清空 LTE 锁定
-/// 4. AT+SPFORCEFRQ=<type>,2,<arfcn>,<pci> - 设置锁定
-/// 5. AT+SFUN=4 - 恢复正常模式
-/// 
-/// ## 请求示例
-/// ```json
-/// {
-///   "rat": 16,        // 16=NR, 12=LTE
-///   "enable": true,
-///   "pci": 599,
-///   "arfcn": 633984
-/// }
-/// ```
-pub async fn set_cell_lock_handler(
+/// GET /api/alignment/ws - 天线对准 WebSocket
+///
+/// 连接期间以 interval_ms 轮询服务小区并推送采样，后台轮询暂停；
+/// 到达 timeout_secs 或客户端断开时结束。同一时间只允许一个连接。
+///
+/// # 查询参数
+/// - `interval_ms`: 采样间隔（默认 200，最小 100）
+/// - `timeout_secs`: 自动结束时间（默认 300，最长 1800）
+/// - `window`: 滑动平均窗口（默认 10）
+///
+/// # 服务端消息
+/// - `{"type":"start", ...}` / `{"type":"sample", ...}` / `{"type":"error","message":..}` / `{"type":"end","reason":"timeout","best":{..}}`
+///
+/// # 客户端消息
+/// - `{"direction":"NE 45°"}`: 标记当前朝向
+/// - `{"reset":true}`: 清除峰值保持和最佳朝向
+pub async fn alignment_ws_handler(
     State(conn): State<Arc<Connection>>,
-    Json(payload): Json<CellLockRequest>,
-) -> impl IntoResponse {
-    // 确定网络类型
-    let forcefrq_type = if payload.rat == FORCEFRQ_TYPE_LTE || payload.rat == FORCEFRQ_TYPE_NR {
-        payload.rat
-    } else {
-        // 根据 rat 值推断类型
-        match payload.rat {
-            1 | 2 => FORCEFRQ_TYPE_LTE,  // LTE FDD/TDD
-            5 | 6 | 7 => FORCEFRQ_TYPE_NR, // NR SA/NSA
-            _ => FORCEFRQ_TYPE_NR,
+    Query(params): Query<AlignmentRequest>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> axum::response::Response {
+    let (request, guard) = match alignment::begin(&params) {
+        Ok(session) => session,
+        Err(e) => {
+            return (StatusCode::CONFLICT, Json(ApiResponse::<serde_json::Value>::error(e))).into_response();
         }
     };
-    
-    if payload.enable {
-        // 锁定小区需要 ARFCN 和 PCI
-        let (arfcn, pci) = match (payload.arfcn, payload.pci) {
-            (Some(a), Some(p)) => (a, p),
-            _ => {
-                return (
-                    StatusCode::OK,
-                    Json(ApiResponse::<serde_json::Value>::error(
-                        "锁定小区需要同时提供 arfcn 和 pci 参数"
-                    )),
-                );
-            }
-        };
-        
-        // 执行锁定流程
-        let steps = vec![
-            ("AT+SFUN=5", "进入工程模式"),
-            ("AT+SPFORCEFRQ=16,0", "清空 NR 锁定"),
-            ("AT+SPFORCEFRQ=12,0", "清空 LTE 锁定"),
-        ];
-        
-        for (cmd, desc) in &steps {
-            if let Err(e) = send_at_command(&conn, cmd).await {
-                // 恢复正常模式
-                let _ = send_at_command(&conn, "AT+SFUN=4").await;
-                return (
-                    StatusCode::OK,
-                    Json(ApiResponse::<serde_json::Value>::error(format!(
-                        "{}失败: {}",
-                        desc, e
-                    ))),
-                );
-            }
-        }
-        
-        // 设置锁定
-        let lock_cmd = format!("AT+SPFORCEFRQ={},2,{},{}", forcefrq_type, arfcn, pci);
-        if let Err(e) = send_at_command(&conn, &lock_cmd).await {
-            // 恢复正常模式
-            let _ = send_at_command(&conn, "AT+SFUN=4").await;
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "设置锁定失败: {}",
-                    e
-                ))),
-            );
-        }
-        
-        // 恢复正常模式
-        if let Err(e) = send_at_command(&conn, "AT+SFUN=4").await {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "恢复正常模式失败: {}",
-                    e
-                ))),
-            );
-        }
-        
-        (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("{} 小区锁定已设置 (ARFCN={}, PCI={})", get_rat_name(forcefrq_type), arfcn, pci),
-                json!({
-                    "locked": true,
-                    "tech": get_rat_name(forcefrq_type),
-                    "arfcn": arfcn,
-                    "pci": pci
-                }),
-            )),
-        )
-    } else {
-        // 解锁：清空指定类型的锁定
-        // 1. 进入工程模式
-        if let Err(e) = send_at_command(&conn, "AT+SFUN=5").await {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "进入工程模式失败: {}",
-                    e
-                ))),
-            );
-        }
-        
-        // 2. 清空锁定
-        let clear_cmd = format!("AT+SPFORCEFRQ={},0", forcefrq_type);
-        if let Err(e) = send_at_command(&conn, &clear_cmd).await {
-            // 恢复正常模式
-            let _ = send_at_command(&conn, "AT+SFUN=4").await;
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "清空锁定失败: {}",
-                    e
-                ))),
-            );
-        }
-        
-        // 3. 恢复正常模式
-        if let Err(e) = send_at_command(&conn, "AT+SFUN=4").await {
-            return (
-                StatusCode::OK,
-                Json(ApiResponse::<serde_json::Value>::error(format!(
-                    "恢复正常模式失败: {}",
-                    e
-                ))),
-            );
-        }
-        
-        (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("{} 小区锁定已解除", get_rat_name(forcefrq_type)),
-                json!({
-                    "locked": false,
-                    "tech": get_rat_name(forcefrq_type)
-                }),
-            )),
-        )
-    }
+    ws.on_upgrade(move |socket| alignment::run(conn, socket, request, guard))
 }
 
-/// POST /api/cell-lock/unlock-all - 解除所有小区锁定
+/// GET /api/network/interfaces - 获取所有网络接口详细信息
 /// 
-/// 清除 NR 和 LTE 的小区锁定
-pub async fn unlock_all_cells_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(_payload): Json<CellUnlockRequest>,
-) -> impl IntoResponse {
-    // 完整的解锁流程
-    let steps = vec![
-        ("AT+SFUN=5", "进入工程模式"),
-        ("AT+SPFORCEFRQ=16,0", "清空 NR 锁定"),
-        ("AT+SPFORCEFRQ=12,0", "清空 LTE 锁定"),
-        ("AT+SFUN=4", "恢复正常模式"),
-    ];
-    
-    let mut success_steps = Vec::new();
-    let mut errors = Vec::new();
-    
-    for (cmd, desc) in &steps {
-        match send_at_command(&conn, cmd).await {
-            Ok(_) => success_steps.push(*desc),
-            Err(e) => {
-                errors.push(format!("{}: {}", desc, e));
-                // 尝试恢复正常模式
-                if !cmd.contains("SFUN=4") {
-                    let _ = send_at_command(&conn, "AT+SFUN=4").await;
-                }
-                break;
-            }
-        }
-    }
+/// 返回所有网络接口的详细信息，包括：
+/// - 接口名称、状态、MAC地址、MTU
+/// - IPv4和IPv6地址列表
+/// - 公网/内网地址分类
+/// - 流量统计（接收/发送字节数、包数、错误数）
+/// - LAN 侧 IPv6 直通状态（ipv6_passthrough：模式、通告的前缀、DNS、RA / DHCPv6 计数）
+pub async fn get_network_interfaces_info() -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(|| {
+        let interfaces = read_network_interfaces()?;
+        let total_count = interfaces.len();
+        
+        Ok::<_, String>(NetworkInterfacesResponse {
+            interfaces,
+            total_count,
+            ipv6_passthrough: crate::ipv6_lan::status(),
+        })
+    })
+    .await;
     
-    if errors.is_empty() {
-        (
+    match result {
+        Ok(Ok(data)) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                "已解除所有小区锁定 (NR + LTE)",
-                json!({
-                    "success": true,
-                    "steps": success_steps
-                }),
-            )),
-        )
-    } else {
-        (
+            Json(ApiResponse::success_with_message("Success", data)),
+        ),
+        Ok(Err(msg)) => (
             StatusCode::OK,
-            Json(ApiResponse::<serde_json::Value>::error(format!(
-                "解锁失败: {}",
-                errors.join("; ")
+            Json(ApiResponse::<NetworkInterfacesResponse>::error(msg)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<NetworkInterfacesResponse>::error(format!(
+                "Task execution failed: {}",
+                e
             ))),
-        )
+        ),
+    }
+}
+
+/// GET /api/radio-mode - 获取当前射频模式
+///
+/// # 返回
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "mode": "auto",
+///     "technology_preference": "NR 5G/LTE auto"
+///   }
+/// }
+/// ```
+pub async fn get_radio_mode_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
+    match get_radio_mode(&conn).await {
+        Ok(data) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", data)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<RadioModeResponse>::error(format!(
+                "Failed to get radio mode: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+/// POST /api/radio-mode - 设置射频模式
+///
+/// # 请求体
+/// ```json
+/// {
+///   "mode": "auto"  // auto | lte | nr
+/// }
+/// ```
+///
+/// # 说明
+/// - auto: 4G/5G 自动切换
+/// - lte: 仅 4G LTE
+/// - nr: 仅 5G NR
+pub async fn set_radio_mode_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(payload): Json<RadioModeRequest>,
+) -> impl IntoResponse {
+    match set_radio_mode(&conn, payload.mode.clone()).await {
+        Ok(_) => {
+            config_manager.clear_active_lock_profile();
+            let mode_str = match payload.mode {
+                RadioMode::Auto => "4G/5G Auto",
+                RadioMode::LteOnly => "4G LTE Only",
+                RadioMode::NrOnly => "5G NR Only",
+            };
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    format!("Radio mode set to {}", mode_str),
+                    json!({}),
+                )),
+            )
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!(
+                "Failed to set radio mode: {}",
+                e
+            ))),
+        ),
+    }
+}
+
+/// GET /api/band-lock - 获取当前频段锁定状态
+///
+/// # 返回
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "locked": true,
+///     "lte_fdd_bands": [1, 3, 8],
+///     "lte_tdd_bands": [38, 40, 41],
+///     "nr_fdd_bands": [1, 28],
+///     "nr_tdd_bands": [41, 77, 78, 79]
+///   }
+/// }
+/// ```
+pub async fn get_band_lock_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
+    // 读取 LTE 频段锁定状态
+    let lte_result = send_at_command(&conn, "AT+SPLBAND=0").await;
+    let (lte_fdd_mask, lte_tdd_mask, lte_raw) = match lte_result {
+        Ok(response) => {
+            let (fdd, tdd) = parse_splband_lte_response(&response);
+            (fdd, tdd, Some(response))
+        }
+        Err(e) => (0, 0, Some(format!("Error: {}", e))),
+    };
+
+    // 读取 NR 频段锁定状态
+    let nr_result = send_at_command(&conn, "AT+SPLBAND=3").await;
+    let (nr_fdd_mask, nr_tdd_mask, nr_raw) = match nr_result {
+        Ok(response) => {
+            let (fdd, tdd) = parse_splband_nr_response(&response);
+            (fdd, tdd, Some(response))
+        }
+        Err(e) => (0, 0, Some(format!("Error: {}", e))),
+    };
+
+    // 模组支持的全部频段掩码（启动时通过 AT+SPLBAND=4/5 查询并缓存）
+    let caps = band_capabilities(&conn).await;
+    
+    // 判断是否有频段锁定
+    // 如果返回的频段等于设备支持的全部频段，则认为"未锁定"（全部可用）
+    // 如果返回 0 或小于全部，则认为"已锁定"（限制了可用频段）
+    let lte_is_all_or_zero = (lte_fdd_mask == caps.lte_fdd_mask && lte_tdd_mask == caps.lte_tdd_mask) 
+                            || (lte_fdd_mask == 0 && lte_tdd_mask == 0);
+    let nr_is_all_or_zero = (nr_fdd_mask == caps.nr_fdd_mask && nr_tdd_mask == caps.nr_tdd_mask)
+                           || (nr_fdd_mask == 0 && nr_tdd_mask == 0);
+    let locked = !(lte_is_all_or_zero && nr_is_all_or_zero);
+    
+    // 将位掩码转换为频段号列表
+    // 未锁定时返回空数组（前端显示为"未锁定模式"）
+    // 已锁定时返回具体频段列表（前端显示为"自定义锁定模式"）
+    let (lte_fdd_bands, lte_tdd_bands, nr_fdd_bands, nr_tdd_bands) = if !locked {
+        // 未锁定：返回空数组
+        (vec![], vec![], vec![], vec![])
+    } else {
+        // 已锁定：返回具体频段
+        (
+            bitmask_to_bands(lte_fdd_mask, 1),    // LTE FDD: B1-B16
+            bitmask_to_bands(lte_tdd_mask, 33),   // LTE TDD: B33-B48
+            bitmask_to_bands(nr_fdd_mask, 100),   // NR FDD: 展锐特殊映射
+            bitmask_to_bands(nr_tdd_mask, 41),    // NR TDD: 展锐特殊映射
+        )
+    };
+
+    // 构建调试信息
+    let raw_response = Some(format!(
+        "LTE(fdd={},tdd={}): {}\nNR(fdd={},tdd={}): {}",
+        lte_fdd_mask, lte_tdd_mask, lte_raw.unwrap_or_default().trim(),
+        nr_fdd_mask, nr_tdd_mask, nr_raw.unwrap_or_default().trim()
+    ));
+
+    let status = BandLockStatus {
+        locked,
+        lte_fdd_bands,
+        lte_tdd_bands,
+        nr_fdd_bands,
+        nr_tdd_bands,
+        raw_response,
+    };
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", status)),
+    )
+}
+
+/// POST /api/system/reboot - 系统重启
+///
+/// # Request body（可选）
+/// ```json
+/// {
+///   "delay_seconds": 3  // 延迟秒数，默认为3秒
+/// }
+/// ```
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "System will reboot in 3 seconds"
+/// }
+/// ```
+pub async fn system_reboot(
+    Json(payload): Json<Option<SystemRebootRequest>>,
+) -> impl IntoResponse {
+    let delay = payload.map(|p| p.delay_seconds).unwrap_or(3);
+    
+    // 使用 tokio 异步执行重启命令
+    tokio::spawn(async move {
+        // 等待指定的延迟时间
+        tokio::time::sleep(tokio::time::Duration::from_secs(delay as u64)).await;
+        
+        // 执行重启命令
+        let _ = Command::new("reboot").output();
+    });
+    
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            format!("系统将在 {} 秒后重启", delay),
+            json!({"delay_seconds": delay}),
+        )),
+    )
+}
+
+/// GET /api/band-lock/capabilities - 获取模组支持的频段
+///
+/// 默认返回缓存的 AT+SPLBAND=4/5 查询结果，`?refresh=true` 重新查询
+///
+/// 只缓存查询成功的结果；尚未查询成功时每次请求都重新查询，失败时返回默认能力（source 为 default）
+///
+/// # 返回
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "lte_fdd_bands": [1, 3, 5, 8],
+///     "lte_tdd_bands": [39, 41],
+///     "nr_fdd_bands": [1, 3, 28],
+///     "nr_tdd_bands": [41, 77, 78, 79],
+///     "lte_fdd_mask": 149,
+///     "lte_tdd_mask": 320,
+///     "nr_fdd_mask": 517,
+///     "nr_tdd_mask": 912,
+///     "source": "modem",
+///     "discovered_at": "2025-12-14T10:00:00+00:00"
+///   }
+/// }
+/// ```
+pub async fn get_band_capabilities_handler(
+    State(conn): State<Arc<Connection>>,
+    Query(params): Query<BandCapabilitiesRequest>,
+) -> impl IntoResponse {
+    let caps = if params.refresh {
+        discover_band_capabilities(&conn).await
+    } else {
+        band_capabilities(&conn).await
+    };
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", caps)),
+    )
+}
+
+/// POST /api/band-lock - 设置频段锁定
+///
+/// # 请求体
+/// ```json
+/// {
+///   "lte_fdd_bands": [1, 3, 8],
+///   "lte_tdd_bands": [38, 40, 41],
+///   "nr_fdd_bands": [1, 28],
+///   "nr_tdd_bands": [41, 77, 78, 79]
+/// }
+/// ```
+///
+/// # 说明
+/// - 传入空数组表示不锁定对应类型的频段
+/// - 只指定 LTE 或 NR 频段时，解除另一制式原有的频段锁定
+/// - 所有数组都为空时，表示解除所有频段锁定
+/// - 包含模组不支持的频段（见 /api/band-lock/capabilities）时返回错误
+/// - `safe: true` 启用安全锁定：`grace_secs`（默认 90）内未驻留到请求的频段，
+///   或 `require_data`（默认 true）时数据连接未恢复，则自动恢复原锁定（见 /api/lock/safe）
+/// - LTE FDD: B1-B16, TDD: B33-B48
+/// - NR FDD: N1-N16, TDD: N41-N56 (实际支持 N41-N79)
+pub async fn set_band_lock_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(safe_lock): State<Arc<SafeLockManager>>,
+    Json(payload): Json<BandLockRequest>,
+) -> impl IntoResponse {
+    let unlock = payload.lte_fdd_bands.is_empty()
+        && payload.lte_tdd_bands.is_empty()
+        && payload.nr_fdd_bands.is_empty()
+        && payload.nr_tdd_bands.is_empty();
+
+    // 安全锁定：观察期内未驻留到请求的频段或数据未恢复时自动恢复
+    if payload.safe_lock.safe && !unlock {
+        return match safe_lock.lock_bands(Arc::clone(&conn), &payload).await {
+            Ok((message, pending)) => {
+                config_manager.clear_active_lock_profile();
+                (
+                    StatusCode::OK,
+                    Json(ApiResponse::success_with_message(
+                        format!("{}，将在 {} 前确认，否则自动恢复", message, pending.deadline),
+                        json!({ "safe_lock": pending }),
+                    )),
+                )
+            }
+            Err(e) => (
+                StatusCode::OK,
+                Json(ApiResponse::<serde_json::Value>::error(e)),
+            ),
+        };
+    }
+
+    safe_lock.supersede().await;
+    match apply_band_lock(&conn, &payload).await {
+        Ok(message) => {
+            config_manager.clear_active_lock_profile();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(message, json!({}))),
+            )
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(e)),
+        ),
+    }
+}
+
+// ============ 小区锁定 API ============
+// 使用 AT+SPFORCEFRQ 指令实现小区锁定，具体流程见 band_lock 模块
+
+use crate::models::{CellLockRequest, CellUnlockRequest};
+
+/// GET /api/cell-lock - 获取小区锁定状态
+/// 
+/// 使用 AT+SPFORCEFRQ=<type>,3 查询锁定状态
+/// 
+/// ## 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "rat_status": [
+///       { "rat": 16, "rat_name": "NR", "enabled": true, "lock_type": 3, "pci": 597, "arfcn": 633984 }
+///     ],
+///     "any_locked": true
+///   }
+/// }
+/// ```
+pub async fn get_cell_lock_handler(State(conn): State<Arc<Connection>>) -> impl IntoResponse {
+    let response = query_cell_lock_status(&conn).await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", response)),
+    )
+}
+
+/// POST /api/cell-lock - 设置小区锁定
+/// 
+/// 使用 AT+SPFORCEFRQ 指令锁定到指定小区
+/// 
+/// ## 锁定流程
+/// 1. AT+SFUN=5 - 进入工程模式
+/// 2. AT+SPFORCEFRQ=16,0 - 清空 NR 锁定
+/// 3. AT+SPFORCEFRQ=12,0 - 清空 LTE 锁定
+/// 4. AT+SPFORCEFRQ=<type>,2,<arfcn>,<pci> - 设置锁定
+/// 5. AT+SFUN=4 - 恢复正常模式
+/// 
+/// ## 请求示例
+/// ```json
+/// {
+///   "rat": 16,        // 16=NR, 12=LTE
+///   "enable": true,
+///   "pci": 599,
+///   "arfcn": 633984,
+///   "safe": true,     // 可选：安全锁定，观察期内未驻留/数据未恢复则自动恢复
+///   "grace_secs": 90
+/// }
+/// ```
+pub async fn set_cell_lock_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(safe_lock): State<Arc<SafeLockManager>>,
+    Json(payload): Json<CellLockRequest>,
+) -> impl IntoResponse {
+    // 确定网络类型
+    let forcefrq_type = resolve_forcefrq_type(payload.rat);
+    
+    if payload.enable {
+        // 锁定小区需要 ARFCN 和 PCI
+        let (arfcn, pci) = match (payload.arfcn, payload.pci) {
+            (Some(a), Some(p)) => (a, p),
+            _ => {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::<serde_json::Value>::error(
+                        "锁定小区需要同时提供 arfcn 和 pci 参数"
+                    )),
+                );
+            }
+        };
+        
+        // 安全锁定：观察期内未驻留到目标小区或数据未恢复时自动恢复
+        if payload.safe_lock.safe {
+            return match safe_lock
+                .lock_cell(Arc::clone(&conn), forcefrq_type, arfcn, pci, &payload.safe_lock)
+                .await
+            {
+                Ok(pending) => {
+                    config_manager.clear_active_lock_profile();
+                    (
+                        StatusCode::OK,
+                        Json(ApiResponse::success_with_message(
+                            format!(
+                                "{} 小区锁定已设置 (ARFCN={}, PCI={})，将在 {} 前确认，否则自动恢复",
+                                get_rat_name(forcefrq_type), arfcn, pci, pending.deadline
+                            ),
+                            json!({
+                                "locked": true,
+                                "tech": get_rat_name(forcefrq_type),
+                                "arfcn": arfcn,
+                                "pci": pci,
+                                "safe_lock": pending
+                            }),
+                        )),
+                    )
+                }
+                Err(e) => (
+                    StatusCode::OK,
+                    Json(ApiResponse::<serde_json::Value>::error(e)),
+                ),
+            };
+        }
+        
+        safe_lock.supersede().await;
+        if let Err(e) = lock_cell(&conn, forcefrq_type, arfcn, pci).await {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<serde_json::Value>::error(e)),
+            );
+        }
+        config_manager.clear_active_lock_profile();
+        
+        (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("{} 小区锁定已设置 (ARFCN={}, PCI={})", get_rat_name(forcefrq_type), arfcn, pci),
+                json!({
+                    "locked": true,
+                    "tech": get_rat_name(forcefrq_type),
+                    "arfcn": arfcn,
+                    "pci": pci
+                }),
+            )),
+        )
+    } else {
+        // 解锁：清空指定类型的锁定
+        safe_lock.supersede().await;
+        if let Err(e) = unlock_cell(&conn, forcefrq_type).await {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<serde_json::Value>::error(e)),
+            );
+        }
+        config_manager.clear_active_lock_profile();
+        
+        (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("{} 小区锁定已解除", get_rat_name(forcefrq_type)),
+                json!({
+                    "locked": false,
+                    "tech": get_rat_name(forcefrq_type)
+                }),
+            )),
+        )
+    }
+}
+
+/// POST /api/cell-lock/unlock-all - 解除所有小区锁定
+/// 
+/// 清除 NR 和 LTE 的小区锁定
+pub async fn unlock_all_cells_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(safe_lock): State<Arc<SafeLockManager>>,
+    Json(_payload): Json<CellUnlockRequest>,
+) -> impl IntoResponse {
+    safe_lock.supersede().await;
+    match unlock_all_cells(&conn).await {
+        Ok(()) => {
+            config_manager.clear_active_lock_profile();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    "已解除所有小区锁定 (NR + LTE)",
+                    json!({
+                        "success": true,
+                        "steps": ["进入工程模式", "清空 NR 锁定", "清空 LTE 锁定", "恢复正常模式"]
+                    }),
+                )),
+            )
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("解锁失败: {}", e))),
+        ),
+    }
+}
+
+// ============ 安全锁定 API ============
+
+/// GET /api/lock/safe - 获取正在观察中的安全锁定
+///
+/// 没有观察中的锁定时 data 为 null
+///
+/// ## 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "id": 3,
+///     "kind": "cell",
+///     "started_at": "2025-12-14T10:00:00+00:00",
+///     "deadline": "2025-12-14T10:01:30+00:00",
+///     "require_data": true,
+///     "last_check": "Camped on nr 633984/597, data connection not active"
+///   }
+/// }
+/// ```
+pub async fn get_safe_lock_handler(
+    State(safe_lock): State<Arc<SafeLockManager>>,
+) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", json!(safe_lock.pending()))),
+    )
+}
+
+/// GET /api/lock/history - 获取安全锁定记录
+///
+/// outcome: pending / confirmed / reverted / revert_failed / superseded / interrupted
+///
+/// # 查询参数
+/// - `limit`: 每页数量（默认 50）
+/// - `offset`: 偏移量（默认 0）
+pub async fn get_lock_history_handler(
+    State(db): State<Arc<Database>>,
+    Query(params): Query<LockHistoryRequest>,
+) -> impl IntoResponse {
+    match db.get_lock_history(params.limit, params.offset) {
+        Ok(records) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", json!(records))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Failed to get lock history: {}", e))),
+        ),
+    }
+}
+
+// ============ 最佳小区优化 API ============
+
+use crate::cell_optimizer::CellOptimizer;
+
+/// GET /api/cell-optimizer - 获取最佳小区优化任务状态和结果
+///
+/// ## 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "state": "completed",
+///     "tested": 3,
+///     "total": 3,
+///     "results": [
+///       { "tech": "nr", "band": "n78", "arfcn": 633984, "pci": 597, "registered": true,
+///         "avg_rsrp": -85.5, "avg_sinr": 18.2, "throughput_mbps": null }
+///     ],
+///     "applied": false,
+///     "restored": true,
+///     "message": "Best cell nr 633984/597, previous lock state restored"
+///   }
+/// }
+/// ```
+pub async fn get_cell_optimizer_handler(
+    State(optimizer): State<Arc<CellOptimizer>>,
+) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", optimizer.status())),
+    )
+}
+
+/// POST /api/cell-optimizer/start - 启动最佳小区优化任务
+///
+/// 逐个试锁定候选小区（主小区 + 邻区），等待注册后采样信号并排序。
+/// 任务期间网络会多次中断；失败、取消或 `apply_best=false` 时恢复原锁定状态。
+/// 开始试锁定前结束正在观察的安全锁定（见 /api/lock/safe）；取消会立即中断等待和测速。
+///
+/// ## 请求示例
+/// ```json
+/// {
+///   "max_candidates": 5,
+///   "settle_secs": 30,
+///   "samples": 3,
+///   "sample_interval_secs": 2,
+///   "throughput_url": null,
+///   "throughput_secs": 10,
+///   "apply_best": true
+/// }
+/// ```
+pub async fn start_cell_optimizer_handler(
+    State(conn): State<Arc<Connection>>,
+    State(optimizer): State<Arc<CellOptimizer>>,
+    Json(payload): Json<CellOptimizeRequest>,
+) -> impl IntoResponse {
+    match optimizer.start(conn, payload) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Optimizer started", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(e)),
+        ),
+    }
+}
+
+/// POST /api/cell-optimizer/cancel - 取消正在运行的优化任务（会恢复原锁定状态）
+pub async fn cancel_cell_optimizer_handler(
+    State(optimizer): State<Arc<CellOptimizer>>,
+) -> impl IntoResponse {
+    if optimizer.cancel() {
+        (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Cancel requested", json!({}))),
+        )
+    } else {
+        (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error("Optimizer is not running")),
+        )
+    }
+}
+
+// ============ 锁定配置方案 API ============
+
+use crate::band_lock::{apply_lock_profile, validate_bands};
+use crate::config::LockProfile;
+
+/// GET /api/band-lock/profiles - 获取锁定配置方案列表
+///
+/// ## 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "profiles": [
+///       { "name": "n78 only", "description": "", "lte_fdd_bands": [], "lte_tdd_bands": [],
+///         "nr_fdd_bands": [], "nr_tdd_bands": [78], "radio_mode": "nr", "cell_lock": null }
+///     ],
+///     "active": "n78 only",
+///     "applied_at": "2025-12-14T10:00:00+00:00"
+///   }
+/// }
+/// ```
+pub async fn get_lock_profiles_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_lock_profiles())),
+    )
+}
+
+/// POST /api/band-lock/profiles - 新增或更新锁定配置方案（按名称覆盖）
+///
+/// ## 请求示例
+/// ```json
+/// {
+///   "name": "B3+B8 rural",
+///   "description": "郊区覆盖优先",
+///   "lte_fdd_bands": [3, 8],
+///   "radio_mode": "lte",
+///   "cell_lock": null
+/// }
+/// ```
+///
+/// - 所有频段数组为空表示解除频段锁定
+/// - `radio_mode` 为空表示不修改射频模式
+/// - `cell_lock` 为空表示应用时解除小区锁定
+pub async fn save_lock_profile_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(mut profile): Json<LockProfile>,
+) -> impl IntoResponse {
+    profile.name = profile.name.trim().to_string();
+    if profile.name.is_empty() {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error("方案名称不能为空")),
+        );
+    }
+    if let Some(cell) = &profile.cell_lock {
+        if !matches!(cell.rat, 1 | 2 | 5..=7 | 12 | 16) {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<serde_json::Value>::error(format!(
+                    "不支持的小区锁定制式: {}",
+                    cell.rat
+                ))),
+            );
+        }
+    }
+
+    let bands = BandLockRequest {
+        lte_fdd_bands: profile.lte_fdd_bands.clone(),
+        lte_tdd_bands: profile.lte_tdd_bands.clone(),
+        nr_fdd_bands: profile.nr_fdd_bands.clone(),
+        nr_tdd_bands: profile.nr_tdd_bands.clone(),
+        ..Default::default()
+    };
+    if let Err(e) = validate_bands(&band_capabilities(&conn).await, &bands) {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(e)),
+        );
+    }
+
+    let mut profiles = config_manager.get_lock_profiles();
+    // 修改当前生效的方案后，设备状态与方案不再一致
+    if profiles.active.as_deref() == Some(profile.name.as_str()) {
+        profiles.active = None;
+        profiles.applied_at = None;
+    }
+    let created = match profiles.profiles.iter_mut().find(|p| p.name == profile.name) {
+        Some(existing) => {
+            *existing = profile.clone();
+            false
+        }
+        None => {
+            profiles.profiles.push(profile.clone());
+            true
+        }
+    };
+
+    match config_manager.set_lock_profiles(profiles) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                if created { "Profile created" } else { "Profile updated" },
+                json!(profile),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Failed to save profile: {}", e))),
+        ),
+    }
+}
+
+/// DELETE /api/band-lock/profiles/{name} - 删除锁定配置方案
+pub async fn delete_lock_profile_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let mut profiles = config_manager.get_lock_profiles();
+    let before = profiles.profiles.len();
+    profiles.profiles.retain(|p| p.name != name);
+    if profiles.profiles.len() == before {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Profile not found: {}", name))),
+        );
+    }
+    if profiles.active.as_deref() == Some(name.as_str()) {
+        profiles.active = None;
+        profiles.applied_at = None;
+    }
+
+    match config_manager.set_lock_profiles(profiles) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Profile deleted", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Failed to delete profile: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/band-lock/profiles/{name}/apply - 一键应用锁定配置方案
+///
+/// 依次设置射频模式、频段锁定和小区锁定，全部成功后记录为当前生效方案。
+/// 方案中未包含的制式会解除原有频段锁定，生效方案即模组的实际锁定状态。
+///
+/// ## 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Profile applied: n78 only",
+///   "data": {
+///     "active": "n78 only",
+///     "applied_at": "2025-12-14T10:00:00+00:00",
+///     "steps": ["radio mode: NR 5G only", "NR 频段锁定已应用，已解除 LTE 频段锁定"]
+///   }
+/// }
+/// ```
+pub async fn apply_lock_profile_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(safe_lock): State<Arc<SafeLockManager>>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let Some(profile) = config_manager
+        .get_lock_profiles()
+        .profiles
+        .into_iter()
+        .find(|p| p.name == name)
+    else {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<serde_json::Value>::error(format!("Profile not found: {}", name))),
+        );
+    };
+
+    // 应用过程中设备状态已经改变，先清除旧的生效标记
+    config_manager.clear_active_lock_profile();
+    safe_lock.supersede().await;
+
+    let steps = match apply_lock_profile(&conn, &profile).await {
+        Ok(steps) => steps,
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::<serde_json::Value>::error(format!(
+                    "Failed to apply profile {}: {}",
+                    name, e
+                ))),
+            );
+        }
+    };
+
+    let applied_at = chrono::Utc::now().to_rfc3339();
+    let mut profiles = config_manager.get_lock_profiles();
+    profiles.active = Some(name.clone());
+    profiles.applied_at = Some(applied_at.clone());
+    if let Err(e) = config_manager.set_lock_profiles(profiles) {
+        tracing::warn!(error = %e, "Failed to record active lock profile");
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            format!("Profile applied: {}", name),
+            json!({
+                "active": name,
+                "applied_at": applied_at,
+                "steps": steps
+            }),
+        )),
+    )
+}
+
+// ============ 电话相关 API ============
+
+use crate::db::Database;
+
+/// GET /api/calls - 获取当前通话列表
+pub async fn get_calls_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<CallListResponse>>) {
+    // 获取 VoiceCallManager 接口下的所有通话
+    match crate::dbus::get_active_calls(&conn).await {
+        Ok(calls) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", CallListResponse { calls })),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get calls: {}", e))),
+        ),
+    }
+}
+
+
+/// POST /api/call/dial - 拨打电话
+pub async fn dial_call_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<MakeCallRequest>,
+) -> (StatusCode, Json<ApiResponse<CallInfo>>) {
+    match crate::dbus::dial_call(&conn, &req.phone_number).await {
+        Ok(call_info) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call initiated", call_info)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to dial: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/hangup - 挂断电话
+pub async fn hangup_call_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<HangupCallRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::hangup_call(&conn, &req.path).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call ended", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to hangup: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/hangup-all - 挂断所有电话
+pub async fn hangup_all_calls_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::hangup_all_calls(&conn).await {
+        Ok(count) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("Ended {} call(s)", count),
+                json!({ "count": count }),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to hangup all: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/answer - 接听来电
+pub async fn answer_call_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<HangupCallRequest>, // 复用结构，只需要 path
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::answer_call(&conn, &req.path).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call answered", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to answer: {}", e))),
+        ),
+    }
+}
+
+// ============ 短信相关 API ============
+
+/// POST /api/sms/send - 发送短信
+pub async fn send_sms_handler(
+    State((conn, db)): State<(Arc<Connection>, Arc<Database>)>,
+    Json(req): Json<SendSmsRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    // 发送短信
+    match crate::dbus::send_sms(&conn, &req.phone_number, &req.content).await {
+        Ok(message_path) => {
+            // 存储到数据库
+            match db.insert_sms("outgoing", &req.phone_number, &req.content, "sent", None) {
+                Ok(id) => (
+                    StatusCode::OK,
+                    Json(ApiResponse::success_with_message(
+                        "SMS sent successfully",
+                        json!({
+                            "message_path": message_path,
+                            "db_id": id,
+                        }),
+                    )),
+                ),
+                Err(_e) => {
+                    (
+                        StatusCode::OK,
+                        Json(ApiResponse::success_with_message(
+                            "SMS sent but failed to save to database",
+                            json!({ "message_path": message_path }),
+                        )),
+                    )
+                }
+            }
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to send SMS: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/sms/list - 获取短信列表
+pub async fn get_sms_list_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Query(req): axum::extract::Query<SmsListRequest>,
+) -> (StatusCode, Json<ApiResponse<Vec<crate::db::SmsMessage>>>) {
+    match db.get_sms_messages(req.limit, req.offset) {
+        Ok(messages) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("Retrieved {} messages", messages.len()),
+                messages,
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get messages: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/sms/conversation - 获取与特定号码的对话历史
+pub async fn get_sms_conversation_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Query(req): axum::extract::Query<SmsConversationRequest>,
+) -> (StatusCode, Json<ApiResponse<Vec<crate::db::SmsMessage>>>) {
+    match db.get_sms_conversation(&req.phone_number, req.limit) {
+        Ok(messages) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("Retrieved {} messages", messages.len()),
+                messages,
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get conversation: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/sms/stats - 获取短信统计
+pub async fn get_sms_stats_handler(
+    State(db): State<Arc<Database>>,
+) -> (StatusCode, Json<ApiResponse<crate::db::SmsStats>>) {
+    match db.get_sms_stats() {
+        Ok(stats) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", stats)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get stats: {}", e))),
+        ),
+    }
+}
+
+impl Default for crate::db::SmsStats {
+    fn default() -> Self {
+        Self {
+            total: 0,
+            incoming: 0,
+            outgoing: 0,
+        }
+    }
+}
+
+/// DELETE /api/sms/clear - 清空所有短信
+pub async fn clear_sms_handler(
+    State(db): State<Arc<Database>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match db.clear_all_sms() {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("All messages cleared", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to clear messages: {}", e))),
+        ),
+    }
+}
+
+// ============ 新增功能 API ============
+
+/// GET /api/device/imeisv - 获取 IMEISV（软件版本号）
+pub async fn get_imeisv_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::ImeisvResponse>>) {
+    match crate::dbus::get_imeisv(&conn).await {
+        Ok(imeisv) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", imeisv)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get IMEISV: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/network/signal-strength - 获取信号强度详细信息
+pub async fn get_signal_strength_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::SignalStrengthResponse>>) {
+    match crate::dbus::get_signal_strength(&conn).await {
+        Ok(signal) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", signal)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get signal strength: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/network/nitz - 获取 NITZ 网络时间
+pub async fn get_nitz_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::NitzTimeResponse>>) {
+    match crate::dbus::get_nitz_time(&conn).await {
+        Ok(nitz) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", nitz)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get NITZ time: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/ims/status - 获取 IMS 状态
+pub async fn get_ims_status_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::ImsStatusResponse>>) {
+    match crate::dbus::get_ims_status(&conn).await {
+        Ok(ims) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", ims)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get IMS status: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/call/volume - 获取通话音量
+pub async fn get_call_volume_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::CallVolumeResponse>>) {
+    match crate::dbus::get_call_volume(&conn).await {
+        Ok(volume) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", volume)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get call volume: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/volume - 设置通话音量
+pub async fn set_call_volume_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<crate::models::SetCallVolumeRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::set_call_volume(&conn, req.speaker_volume, req.microphone_volume, req.muted).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call volume updated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to set call volume: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/voicemail/status - 获取语音留言状态
+pub async fn get_voicemail_status_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::VoicemailStatusResponse>>) {
+    match crate::dbus::get_voicemail_status(&conn).await {
+        Ok(voicemail) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", voicemail)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get voicemail status: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/network/operators - 获取运营商列表（快速）
+pub async fn get_operators_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::OperatorListResponse>>) {
+    match crate::dbus::get_operators(&conn).await {
+        Ok(operators) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", operators)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get operators: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/network/operators/scan - 扫描所有运营商（慢，120秒）
+pub async fn scan_operators_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::OperatorListResponse>>) {
+    match crate::dbus::scan_operators(&conn).await {
+        Ok(operators) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Scan completed", operators)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to scan operators: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/network/register-manual - 手动注册运营商
+pub async fn register_operator_manual_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<crate::models::ManualRegisterRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::register_operator_manual(&conn, &req.mccmnc).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("Registered to operator {}", req.mccmnc),
+                json!({}),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to register operator: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/network/register-auto - 自动注册运营商
+pub async fn register_operator_auto_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::register_operator_auto(&conn).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Automatic registration initiated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to register automatically: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/call/forwarding - 获取呼叫转移设置
+pub async fn get_call_forwarding_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::CallForwardingResponse>>) {
+    match crate::dbus::get_call_forwarding(&conn).await {
+        Ok(forwarding) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", forwarding)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get call forwarding: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/forwarding - 设置呼叫转移
+pub async fn set_call_forwarding_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<crate::models::SetCallForwardingRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::set_call_forwarding(&conn, &req.forward_type, &req.number, req.timeout).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call forwarding updated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to set call forwarding: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/call/settings - 获取通话设置
+pub async fn get_call_settings_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::CallSettingsResponse>>) {
+    match crate::dbus::get_call_settings(&conn).await {
+        Ok(settings) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", settings)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get call settings: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/call/settings - 设置通话设置
+pub async fn set_call_settings_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<crate::models::SetCallSettingRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::set_call_setting(&conn, &req.property, &req.value).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Call settings updated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to set call settings: {}", e))),
+        ),
+    }
+}
+
+// ============ SIM 卡槽功能 ============
+
+/// GET /api/sim/slot - 获取 SIM 卡槽信息
+pub async fn get_sim_slot_handler(
+    State(conn): State<Arc<Connection>>,
+) -> (StatusCode, Json<ApiResponse<crate::models::SimSlotResponse>>) {
+    match crate::dbus::get_sim_slot(&conn).await {
+        Ok(slot_info) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Success", slot_info)),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get SIM slot info: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/sim/slot/switch - 切换 SIM 卡槽
+pub async fn switch_sim_slot_handler(
+    State(conn): State<Arc<Connection>>,
+    Json(req): Json<crate::models::SwitchSimSlotRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match crate::dbus::switch_sim_slot(&conn, req.slot).await {
+        Ok(response) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                format!("Switched to SIM slot {}", req.slot),
+                json!({"response": response}),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to switch SIM slot: {}", e))),
+        ),
+    }
+}
+
+// ============ APN 管理功能 ============
+
+/// GET /api/apn - 获取 APN 列表
+///
+/// 默认返回 internet 类型的 APN context 配置
+///
+/// # 查询参数
+/// - `type`: internet（默认）/ mms / ims / wap / all
+pub async fn get_apn_list_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Query(params): Query<ApnListQuery>,
+) -> (StatusCode, Json<ApiResponse<ApnListResponse>>) {
+    let context_type = params.context_type.unwrap_or_else(|| "internet".to_string());
+    let filter = (context_type != "all").then_some(context_type.as_str());
+    let selected = config_manager.get_watchdog_context();
+    match get_all_apn_contexts(&conn, filter, selected.as_deref()).await {
+        Ok(contexts) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                "Success",
+                ApnListResponse {
+                    contexts,
+                    watchdog_context: find_internet_context(&conn, selected.as_deref()).await.unwrap_or_default(),
+                },
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to get APN list: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/apn - 设置 APN 配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "context_path": "/ril_0/context2",
+///   "apn": "cbnet",
+///   "protocol": "dual",
+///   "username": "",
+///   "password": "",
+///   "auth_method": "chap"
+/// }
+/// ```
+pub async fn set_apn_handler(
+    State(conn): State<Arc<Connection>>,
+    State(sessions): State<Arc<DataSessionTracker>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(req): Json<SetApnRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    // 验证 context_path
+    if req.context_path.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("context_path is required")),
+        );
+    }
+    
+    // 修改已激活 context 的 APN 会先断开连接
+    sessions.mark_end_reason(SessionEndReason::UserAction);
+    
+    // 调用 D-Bus 设置 APN 属性
+    match set_apn_properties(
+        &conn,
+        &req.context_path,
+        req.apn.as_deref(),
+        req.protocol.as_deref(),
+        req.username.as_deref(),
+        req.password.as_deref(),
+        req.auth_method.as_deref(),
+    ).await {
+        Ok(_) => {
+            // 获取更新后的 APN 配置
+            let selected = config_manager.get_watchdog_context();
+            match get_all_apn_contexts(&conn, None, selected.as_deref()).await {
+                Ok(contexts) => {
+                    // 找到刚刚修改的 context
+                    let updated_context = contexts
+                        .iter()
+                        .find(|c| c.path == req.context_path)
+                        .cloned();
+                    
+                    (
+                        StatusCode::OK,
+                        Json(ApiResponse::success_with_message(
+                            "APN configuration updated successfully",
+                            json!({
+                                "updated_context": updated_context,
+                            }),
+                        )),
+                    )
+                }
+                Err(_) => (
+                    StatusCode::OK,
+                    Json(ApiResponse::success_with_message(
+                        "APN configuration updated successfully",
+                        json!({}),
+                    )),
+                ),
+            }
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to set APN: {}", e))),
+        ),
+    }
+}
+
+/// 支持创建的 context 类型
+const APN_CONTEXT_TYPES: [&str; 4] = ["internet", "mms", "ims", "wap"];
+
+/// POST /api/apn/contexts - 创建 APN context
+///
+/// # 请求体
+/// ```json
+/// {
+///   "type": "internet",
+///   "name": "Backup",
+///   "apn": "iot.example",
+///   "protocol": "dual",
+///   "username": "user",
+///   "password": "pass",
+///   "auth_method": "chap",
+///   "watchdog": true
+/// }
+/// ```
+///
+/// # 说明
+/// - type: internet / mms / ims / wap
+/// - watchdog: 设为 watchdog 保持激活的 context（仅 internet 类型）
+pub async fn add_apn_context_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(req): Json<AddApnContextRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    if !APN_CONTEXT_TYPES.contains(&req.context_type.as_str()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "Invalid context type: {}, expected one of {:?}",
+                req.context_type, APN_CONTEXT_TYPES
+            ))),
+        );
+    }
+    if req.watchdog && req.context_type != "internet" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("Only internet contexts can be kept active by the watchdog")),
+        );
+    }
+    if let Some(method) = req.auth_method.as_deref().filter(|m| !["none", "pap", "chap"].contains(m)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("Invalid auth method: {}", method))),
+        );
+    }
+
+    let context_path = match add_apn_context(&conn, &req.context_type).await {
+        Ok(path) => path,
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::error(format!("Failed to add context: {}", e))),
+            )
+        }
+    };
+
+    // 新建的 context 未激活，可以直接设置属性
+    let mut result = match req.name.as_deref().filter(|n| !n.is_empty()) {
+        Some(name) => set_apn_property(&conn, &context_path, "Name", name).await,
+        None => Ok(()),
+    };
+    if result.is_ok() {
+        result = set_apn_properties(
+            &conn,
+            &context_path,
+            req.apn.as_deref(),
+            req.protocol.as_deref(),
+            req.username.as_deref(),
+            req.password.as_deref(),
+            req.auth_method.as_deref(),
+        )
+        .await;
+    }
+    if let Err(e) = result {
+        let _ = remove_apn_context(&conn, &context_path).await;
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to configure context: {}", e))),
+        );
+    }
+
+    if req.watchdog {
+        if let Err(e) = config_manager.set_watchdog_context(Some(context_path.clone())) {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::error(format!("Context created but failed to save watchdog context: {}", e))),
+            );
+        }
+    }
+
+    let selected = config_manager.get_watchdog_context();
+    let context = get_all_apn_contexts(&conn, None, selected.as_deref())
+        .await
+        .ok()
+        .and_then(|contexts| contexts.into_iter().find(|c| c.path == context_path));
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "APN context created",
+            json!({ "context_path": context_path, "context": context }),
+        )),
+    )
+}
+
+/// DELETE /api/apn/contexts/{id} - 删除 APN context
+///
+/// id 为 context 路径的最后一段，如 /ril_0/context3 对应 context3
+pub async fn remove_apn_context_handler(
+    State(conn): State<Arc<Connection>>,
+    State(sessions): State<Arc<DataSessionTracker>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("Invalid context id: {}", id))),
+        );
+    }
+    let context_path = format!("/ril_0/{}", id);
+
+    let selected = config_manager.get_watchdog_context();
+    let was_watchdog = selected.as_deref() == Some(context_path.as_str());
+    if was_watchdog
+        || find_internet_context(&conn, selected.as_deref()).await.ok().as_deref() == Some(context_path.as_str())
+    {
+        sessions.mark_end_reason(SessionEndReason::UserAction);
+    }
+
+    match remove_apn_context(&conn, &context_path).await {
+        Ok(()) => {
+            // 删除的是手动指定的 watchdog context 时恢复自动选择
+            if was_watchdog {
+                let _ = config_manager.set_watchdog_context(None);
+            }
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    "APN context removed",
+                    json!({ "context_path": context_path, "watchdog_reset": was_watchdog }),
+                )),
+            )
+        }
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to remove context: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/apn/watchdog - 指定 watchdog 保持激活的 context
+///
+/// # 请求体
+/// ```json
+/// { "context_path": "/ril_0/context3" }
+/// ```
+/// context_path 为空时恢复自动选择（第一个配置了 APN 的 internet context）。
+/// 数据连接开关、状态查询和数据会话记录都使用该 context。
+pub async fn set_watchdog_context_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(req): Json<SetWatchdogContextRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let context_path = req.context_path.filter(|p| !p.is_empty());
+
+    if let Some(path) = &context_path {
+        match get_all_apn_contexts(&conn, None, None).await {
+            Ok(contexts) => match contexts.iter().find(|c| &c.path == path) {
+                Some(c) if c.context_type == "internet" => {}
+                Some(c) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(ApiResponse::error(format!(
+                            "Context {} is of type {}, only internet contexts can be selected",
+                            path, c.context_type
+                        ))),
+                    )
+                }
+                None => {
+                    return (
+                        StatusCode::OK,
+                        Json(ApiResponse::error(format!("Context {} not found", path))),
+                    )
+                }
+            },
+            Err(e) => {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::error(format!("Failed to get APN contexts: {}", e))),
+                )
+            }
+        }
+    }
+
+    match config_manager.set_watchdog_context(context_path.clone()) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                "Watchdog context updated",
+                json!({
+                    "watchdog_context": find_internet_context(&conn, context_path.as_deref())
+                        .await
+                        .unwrap_or_default()
+                }),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to save watchdog context: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/apn/database - 查询 APN 数据库
+///
+/// 不带参数时按当前 SIM 卡（归属运营商 MCC/MNC、SPN、ICCID）查询，并给出自动配置将使用的条目
+///
+/// # 查询参数
+/// - `mcc` / `mnc`: 指定运营商（可选）
+pub async fn get_apn_database_handler(
+    State(conn): State<Arc<Connection>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Query(params): Query<ApnDatabaseQuery>,
+) -> (StatusCode, Json<ApiResponse<ApnDatabaseResponse>>) {
+    let sim = match (params.mcc, params.mnc) {
+        (Some(mcc), Some(mnc)) => ApnSimIdentity {
+            mcc,
+            mnc,
+            ..Default::default()
+        },
+        (None, None) => match get_sim_identity(&conn).await {
+            Ok(sim) if !sim.mcc.is_empty() => sim,
+            Ok(_) => {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::error("SIM MCC/MNC not available, specify mcc and mnc")),
+                )
+            }
+            Err(e) => {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::error(format!("Failed to get SIM info: {}", e))),
+                )
+            }
+        },
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("mcc and mnc must be specified together")),
+            )
+        }
+    };
+
+    let custom = config_manager.get_apn().entries;
+    let response = ApnDatabaseResponse {
+        recommended: apn_db::recommend(&custom, &sim),
+        entries: apn_db::lookup(&custom, &sim.mcc, &sim.mnc),
+        builtin_count: apn_db::builtin().len(),
+        sim: Some(sim),
+    };
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", response)),
+    )
+}
+
+/// GET /api/apn/config - 获取 APN 自动配置设置
+pub async fn get_apn_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::ApnConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_apn())),
+    )
+}
+
+/// POST /api/apn/config - 设置 APN 自动配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "auto_provision": true,
+///   "entries": [
+///     { "carrier": "My IoT", "mcc": "234", "mnc": "10", "apn": "iot.example", "type": "default",
+///       "protocol": "IPV4V6", "user": "", "password": "", "authtype": 0, "mtu": 1400,
+///       "mvno_type": "iccid", "mvno_match_data": "894411" }
+///   ]
+/// }
+/// ```
+///
+/// # 说明
+/// - entries 字段与 Android apns-conf 一致，优先于内置数据库
+/// - mvno_type: spn（全匹配）/ iccid / imsi（前缀匹配，imsi 支持 x 通配）
+pub async fn set_apn_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(apn_config): Json<crate::config::ApnConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    if let Some(e) = apn_config
+        .entries
+        .iter()
+        .find(|e| e.mcc.is_empty() || e.mnc.is_empty() || e.apn.is_empty())
+    {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!(
+                "APN entry requires mcc, mnc and apn: {:?}",
+                e.carrier
+            ))),
+        );
+    }
+
+    match config_manager.set_apn(apn_config) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("APN config updated", json!({}))),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to update APN config: {}", e))),
+        ),
+    }
+}
+
+/// POST /api/apn/provision - 立即按 APN 数据库配置当前 SIM 卡
+///
+/// # 请求体
+/// ```json
+/// { "context_path": "/ril_0/context2" }
+/// ```
+/// context_path 为空时配置 watchdog 管理的 internet context
+pub async fn provision_apn_handler(
+    State(conn): State<Arc<Connection>>,
+    State(sessions): State<Arc<DataSessionTracker>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(req): Json<ApnProvisionRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let context_path = match req.context_path.filter(|p| !p.is_empty()) {
+        Some(path) => path,
+        None => match find_internet_context(&conn, config_manager.get_watchdog_context().as_deref()).await {
+            Ok(path) => path,
+            Err(e) => {
+                return (
+                    StatusCode::OK,
+                    Json(ApiResponse::error(format!("Failed to find internet context: {}", e))),
+                )
+            }
+        },
+    };
+
+    sessions.mark_end_reason(SessionEndReason::UserAction);
+    match auto_configure_apn(&conn, &context_path, &config_manager).await {
+        Ok(message) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message(
+                message,
+                json!({ "context_path": context_path, "provisioned": config_manager.get_apn().provisioned }),
+            )),
+        ),
+        Err(e) => (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to provision APN: {}", e))),
+        ),
+    }
+}
+
+/// GET /api/connectivity - 联网检测
+///
+/// 通过 ping 检测 IPv4 和 IPv6 连通性
+pub async fn get_connectivity_check() -> (StatusCode, Json<ApiResponse<ConnectivityCheckResponse>>) {
+    let ipv4_result = ping_host("223.5.5.5", false);
+    let ipv6_result = ping_host("2400:3200::1", true);
+    
+    let response = ConnectivityCheckResponse {
+        ipv4: ipv4_result,
+        ipv6: ipv6_result,
+    };
+    
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Connectivity check completed", response)),
+    )
+}
+
+/// 执行 ping 检测
+fn ping_host(target: &str, is_ipv6: bool) -> PingResult {
+    let cmd = if is_ipv6 { "ping6" } else { "ping" };
+    
+    // 使用 -c 1 只发一个包，-W 2 设置超时 2 秒
+    let output = Command::new(cmd)
+        .args(["-c", "1", "-W", "2", target])
+        .output();
+    
+    match output {
+        Ok(result) => {
+            if result.status.success() {
+                // 解析延迟时间
+                let stdout = String::from_utf8_lossy(&result.stdout);
+                let latency = parse_ping_latency(&stdout);
+                
+                PingResult {
+                    success: true,
+                    latency_ms: latency,
+                    target: target.to_string(),
+                    error: None,
+                }
+            } else {
+                let stderr = String::from_utf8_lossy(&result.stderr);
+                PingResult {
+                    success: false,
+                    latency_ms: None,
+                    target: target.to_string(),
+                    error: Some(if stderr.is_empty() {
+                        "Host unreachable".to_string()
+                    } else {
+                        stderr.trim().to_string()
+                    }),
+                }
+            }
+        }
+        Err(e) => PingResult {
+            success: false,
+            latency_ms: None,
+            target: target.to_string(),
+            error: Some(format!("Failed to execute ping: {}", e)),
+        },
     }
 }
 
-// ============ 电话相关 API ============
-
-use crate::db::Database;
-
-/// GET /api/calls - 获取当前通话列表
-pub async fn get_calls_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<CallListResponse>>) {
-    // 获取 VoiceCallManager 接口下的所有通话
-    match crate::dbus::get_active_calls(&conn).await {
-        Ok(calls) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", CallListResponse { calls })),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get calls: {}", e))),
-        ),
+/// 从 ping 输出中解析延迟时间
+fn parse_ping_latency(output: &str) -> Option<f64> {
+    // 匹配 "time=XX.XX ms" 或 "time=XX ms"
+    for line in output.lines() {
+        if let Some(time_pos) = line.find("time=") {
+            let after_time = &line[time_pos + 5..];
+            // 找到数字部分
+            let num_str: String = after_time
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            if let Ok(latency) = num_str.parse::<f64>() {
+                return Some(latency);
+            }
+        }
     }
+    None
 }
 
+// ============ 通话记录 API ============
 
-/// POST /api/call/dial - 拨打电话
-pub async fn dial_call_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<MakeCallRequest>,
-) -> (StatusCode, Json<ApiResponse<CallInfo>>) {
-    match crate::dbus::dial_call(&conn, &req.phone_number).await {
-        Ok(call_info) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call initiated", call_info)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to dial: {}", e))),
-        ),
-    }
-}
+use crate::webhook::WebhookSender;
 
-/// POST /api/call/hangup - 挂断电话
-pub async fn hangup_call_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<HangupCallRequest>,
-) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::hangup_call(&conn, &req.path).await {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call ended", json!({}))),
-        ),
+/// GET /api/call/history - 获取通话记录
+pub async fn get_call_history_handler(
+    State(db): State<Arc<Database>>,
+    Query(params): Query<crate::models::CallHistoryRequest>,
+) -> (StatusCode, Json<ApiResponse<crate::models::CallHistoryResponse>>) {
+    let limit = if params.limit > 0 { params.limit } else { 50 };
+    let offset = if params.offset >= 0 { params.offset } else { 0 };
+    
+    match db.get_call_history(limit, offset) {
+        Ok(records) => {
+            let stats = db.get_call_stats().unwrap_or_default();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    "Success",
+                    crate::models::CallHistoryResponse { records, stats },
+                )),
+            )
+        }
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to hangup: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get call history: {}", e))),
         ),
     }
 }
 
-/// POST /api/call/hangup-all - 挂断所有电话
-pub async fn hangup_all_calls_handler(
-    State(conn): State<Arc<Connection>>,
+/// DELETE /api/call/history/{id} - 删除单条通话记录
+pub async fn delete_call_history_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::hangup_all_calls(&conn).await {
-        Ok(count) => (
+    match db.delete_call(id) {
+        Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("Ended {} call(s)", count),
-                json!({ "count": count }),
-            )),
+            Json(ApiResponse::success_with_message("Call record deleted", json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to hangup all: {}", e))),
+            Json(ApiResponse::error(format!("Failed to delete call record: {}", e))),
         ),
     }
 }
 
-/// POST /api/call/answer - 接听来电
-pub async fn answer_call_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<HangupCallRequest>, // 复用结构，只需要 path
+/// POST /api/call/history/clear - 清空所有通话记录
+pub async fn clear_call_history_handler(
+    State(db): State<Arc<Database>>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::answer_call(&conn, &req.path).await {
+    match db.clear_all_calls() {
         Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call answered", json!({}))),
+            Json(ApiResponse::success_with_message("All call records cleared", json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to answer: {}", e))),
+            Json(ApiResponse::error(format!("Failed to clear call history: {}", e))),
         ),
     }
 }
 
-// ============ 短信相关 API ============
+// ============ 数据会话 API ============
 
-/// POST /api/sms/send - 发送短信
-pub async fn send_sms_handler(
-    State((conn, db)): State<(Arc<Connection>, Arc<Database>)>,
-    Json(req): Json<SendSmsRequest>,
-) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    // 发送短信
-    match crate::dbus::send_sms(&conn, &req.phone_number, &req.content).await {
-        Ok(message_path) => {
-            // 存储到数据库
-            match db.insert_sms("outgoing", &req.phone_number, &req.content, "sent", None) {
-                Ok(id) => (
-                    StatusCode::OK,
-                    Json(ApiResponse::success_with_message(
-                        "SMS sent successfully",
-                        json!({
-                            "message_path": message_path,
-                            "db_id": id,
-                        }),
-                    )),
-                ),
-                Err(_e) => {
-                    (
-                        StatusCode::OK,
-                        Json(ApiResponse::success_with_message(
-                            "SMS sent but failed to save to database",
-                            json!({ "message_path": message_path }),
-                        )),
-                    )
-                }
-            }
-        }
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to send SMS: {}", e))),
-        ),
-    }
-}
+/// 统计天数上限（与会话保留天数一致）
+const DATA_STATS_MAX_DAYS: i64 = 90;
 
-/// GET /api/sms/list - 获取短信列表
-pub async fn get_sms_list_handler(
+/// GET /api/data/sessions - 获取数据会话记录
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Retrieved 1 sessions",
+///   "data": [
+///     {
+///       "id": 12,
+///       "start_time": "2025-12-14T02:00:00+00:00",
+///       "end_time": null,
+///       "apn": "cmnet",
+///       "interface": "sipa_eth0",
+///       "ipv4_address": "10.12.34.56",
+///       "ipv6_address": "2409:8a00::1",
+///       "rx_bytes": 10485760,
+///       "tx_bytes": 1048576,
+///       "end_reason": null
+///     }
+///   ]
+/// }
+/// ```
+pub async fn get_data_sessions_handler(
     State(db): State<Arc<Database>>,
-    axum::extract::Query(req): axum::extract::Query<SmsListRequest>,
-) -> (StatusCode, Json<ApiResponse<Vec<crate::db::SmsMessage>>>) {
-    match db.get_sms_messages(req.limit, req.offset) {
-        Ok(messages) => (
+    Query(params): Query<DataSessionListRequest>,
+) -> (StatusCode, Json<ApiResponse<Vec<crate::db::DataSessionRecord>>>) {
+    let limit = if params.limit > 0 { params.limit } else { 50 };
+    let offset = if params.offset >= 0 { params.offset } else { 0 };
+    
+    match db.get_data_sessions(limit, offset) {
+        Ok(sessions) => (
             StatusCode::OK,
             Json(ApiResponse::success_with_message(
-                format!("Retrieved {} messages", messages.len()),
-                messages,
+                format!("Retrieved {} sessions", sessions.len()),
+                sessions,
             )),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get messages: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get data sessions: {}", e))),
         ),
     }
 }
 
-/// GET /api/sms/conversation - 获取与特定号码的对话历史
-pub async fn get_sms_conversation_handler(
-    State(db): State<Arc<Database>>,
-    axum::extract::Query(req): axum::extract::Query<SmsConversationRequest>,
-) -> (StatusCode, Json<ApiResponse<Vec<crate::db::SmsMessage>>>) {
-    match db.get_sms_conversation(&req.phone_number, req.limit) {
-        Ok(messages) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("Retrieved {} messages", messages.len()),
-                messages,
-            )),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get conversation: {}", e))),
-        ),
-    }
+/// 读取统计窗口内的会话并计算中断，返回 (会话列表, 中断列表)
+fn load_data_outages(
+    db: &Database,
+    window_start: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> rusqlite::Result<(Vec<crate::db::DataSessionRecord>, Vec<DataOutage>)> {
+    let sessions = db.get_data_sessions_since(&window_start.to_rfc3339())?;
+    let outages = compute_outages(&sessions, window_start, now);
+    Ok((sessions, outages))
 }
 
-/// GET /api/sms/stats - 获取短信统计
-pub async fn get_sms_stats_handler(
+/// GET /api/data/outages?days=7 - 获取数据中断区间
+///
+/// 中断由相邻数据会话之间的空档计算得出，`reason` 为导致中断的会话结束原因：
+/// `network_deregistered` / `context_deactivated` / `watchdog_restore` / `user_action` / `service_restart`
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "days": 7,
+///     "count": 1,
+///     "total_outage_secs": 35,
+///     "outages": [
+///       {
+///         "start_time": "2025-12-14T01:59:25+00:00",
+///         "end_time": "2025-12-14T02:00:00+00:00",
+///         "duration_secs": 35,
+///         "reason": "network_deregistered",
+///         "ongoing": false
+///       }
+///     ]
+///   }
+/// }
+/// ```
+pub async fn get_data_outages_handler(
     State(db): State<Arc<Database>>,
-) -> (StatusCode, Json<ApiResponse<crate::db::SmsStats>>) {
-    match db.get_sms_stats() {
-        Ok(stats) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", stats)),
-        ),
+    Query(params): Query<DataStatsRangeRequest>,
+) -> (StatusCode, Json<ApiResponse<DataOutagesResponse>>) {
+    let days = params.days.clamp(1, DATA_STATS_MAX_DAYS);
+    let now = chrono::Utc::now();
+    let window_start = now - chrono::Duration::days(days);
+    
+    match load_data_outages(&db, window_start, now) {
+        Ok((_, mut outages)) => {
+            outages.reverse();
+            let total_outage_secs = outages.iter().map(|o| o.duration_secs).sum();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    "Success",
+                    DataOutagesResponse {
+                        days,
+                        count: outages.len(),
+                        total_outage_secs,
+                        outages,
+                    },
+                )),
+            )
+        }
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get stats: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get data outages: {}", e))),
         ),
     }
 }
 
-impl Default for crate::db::SmsStats {
-    fn default() -> Self {
-        Self {
-            total: 0,
-            incoming: 0,
-            outgoing: 0,
-        }
-    }
-}
-
-/// DELETE /api/sms/clear - 清空所有短信
-pub async fn clear_sms_handler(
+/// GET /api/data/availability?days=7 - 获取每日数据连接可用率
+///
+/// 可用率 = 在线时长 / (在线时长 + 中断时长)，按设备本地时区划分自然日，
+/// 没有任何记录的时间段不计入统计。
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "days": 7,
+///     "overall_percent": 99.95,
+///     "daily": [
+///       {
+///         "date": "2025-12-14",
+///         "connected_secs": 86365,
+///         "outage_secs": 35,
+///         "outage_count": 1,
+///         "availability_percent": 99.96
+///       }
+///     ]
+///   }
+/// }
+/// ```
+pub async fn get_data_availability_handler(
     State(db): State<Arc<Database>>,
-) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match db.clear_all_sms() {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("All messages cleared", json!({}))),
-        ),
+    Query(params): Query<DataStatsRangeRequest>,
+) -> (StatusCode, Json<ApiResponse<DataAvailabilityResponse>>) {
+    let days = params.days.clamp(1, DATA_STATS_MAX_DAYS);
+    let now = chrono::Utc::now();
+    let window_start = now - chrono::Duration::days(days);
+    
+    match load_data_outages(&db, window_start, now) {
+        Ok((sessions, outages)) => {
+            let daily = compute_daily_availability(&sessions, &outages, window_start, now, &chrono::Local);
+            let connected: i64 = daily.iter().map(|d| d.connected_secs).sum();
+            let outage: i64 = daily.iter().map(|d| d.outage_secs).sum();
+            (
+                StatusCode::OK,
+                Json(ApiResponse::success_with_message(
+                    "Success",
+                    DataAvailabilityResponse {
+                        days,
+                        overall_percent: availability_percent(connected, connected + outage),
+                        daily,
+                    },
+                )),
+            )
+        }
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to clear messages: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get data availability: {}", e))),
         ),
     }
 }
 
-// ============ 新增功能 API ============
+// ============ 温度监控 API ============
 
-/// GET /api/device/imeisv - 获取 IMEISV（软件版本号）
-pub async fn get_imeisv_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::ImeisvResponse>>) {
-    match crate::dbus::get_imeisv(&conn).await {
-        Ok(imeisv) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", imeisv)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get IMEISV: {}", e))),
-        ),
-    }
+use crate::thermal::{ThermalMonitor, ThermalStatus};
+
+/// GET /api/thermal - 获取温度监控状态
+///
+/// # Response example
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "enabled": true,
+///     "level": "warning",
+///     "max_temperature": 78.5,
+///     "hottest_zone": "thermal_zone0",
+///     "zones": [{"zone": "thermal_zone0", "type": "soc-thmzone", "temperature": 78.5}],
+///     "active_actions": [],
+///     "last_update": "2025-12-14T07:20:00+00:00",
+///     "events": [
+///       {
+///         "timestamp": "2025-12-14T07:18:00+00:00",
+///         "level": "warning",
+///         "temperature": 76.2,
+///         "zone": "thermal_zone0",
+///         "message": "thermal_zone0 76.2°C (normal -> warning)"
+///       }
+///     ]
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - active_actions 为当前生效的保护动作；降温后恢复失败的动作仍保留在其中，每次采样时重试恢复
+pub async fn get_thermal_status_handler(
+    State(monitor): State<Arc<ThermalMonitor>>,
+) -> (StatusCode, Json<ApiResponse<ThermalStatus>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", monitor.status())),
+    )
 }
 
-/// GET /api/network/signal-strength - 获取信号强度详细信息
-pub async fn get_signal_strength_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::SignalStrengthResponse>>) {
-    match crate::dbus::get_signal_strength(&conn).await {
-        Ok(signal) => (
+/// GET /api/thermal/history?hours=24&zone=thermal_zone0 - 获取温度历史
+///
+/// 不指定 `zone` 时返回所有传感器的历史，按时间升序排列
+pub async fn get_thermal_history_handler(
+    State(db): State<Arc<Database>>,
+    Query(params): Query<ThermalHistoryRequest>,
+) -> (StatusCode, Json<ApiResponse<Vec<crate::db::ThermalRecord>>>) {
+    let hours = params.hours.clamp(1, 24 * 30);
+    let since = (chrono::Utc::now() - chrono::Duration::hours(hours)).to_rfc3339();
+    
+    match db.get_thermal_history(params.zone.as_deref(), &since) {
+        Ok(records) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", signal)),
+            Json(ApiResponse::success_with_message(
+                format!("Retrieved {} records", records.len()),
+                records,
+            )),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get signal strength: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get thermal history: {}", e))),
         ),
     }
 }
 
-/// GET /api/network/nitz - 获取 NITZ 网络时间
-pub async fn get_nitz_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::NitzTimeResponse>>) {
-    match crate::dbus::get_nitz_time(&conn).await {
-        Ok(nitz) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", nitz)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get NITZ time: {}", e))),
-        ),
-    }
+/// GET /api/thermal/config - 获取温度监控配置
+pub async fn get_thermal_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::ThermalConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_thermal())),
+    )
 }
 
-/// GET /api/ims/status - 获取 IMS 状态
-pub async fn get_ims_status_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::ImsStatusResponse>>) {
-    match crate::dbus::get_ims_status(&conn).await {
-        Ok(ims) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", ims)),
-        ),
-        Err(e) => (
+/// POST /api/thermal/config - 设置温度监控配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "enabled": true,
+///   "interval_secs": 60,
+///   "warning_temp": 75.0,
+///   "critical_temp": 85.0,
+///   "recovery_temp": 70.0,
+///   "zones": [],
+///   "action_force_lte": true,
+///   "action_single_carrier": false,
+///   "action_webhook": true,
+///   "history_retention_hours": 72
+/// }
+/// ```
+///
+/// # 说明
+/// - 达到 `warning_temp` 进入告警，达到 `critical_temp` 执行保护动作
+/// - 温度降到 `recovery_temp` 及以下才恢复正常并撤销保护动作
+/// - 执行失败的保护动作在过温期间每次采样时重试
+/// - 保护期间射频模式或频段锁定被手动修改时，撤销保护动作不会覆盖新的设置
+pub async fn set_thermal_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(thermal_config): Json<crate::config::ThermalConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    if thermal_config.recovery_temp >= thermal_config.warning_temp
+        || thermal_config.warning_temp >= thermal_config.critical_temp
+    {
+        return (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get IMS status: {}", e))),
-        ),
+            Json(ApiResponse::error(
+                "Thresholds must satisfy recovery_temp < warning_temp < critical_temp",
+            )),
+        );
     }
-}
-
-/// GET /api/call/volume - 获取通话音量
-pub async fn get_call_volume_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::CallVolumeResponse>>) {
-    match crate::dbus::get_call_volume(&conn).await {
-        Ok(volume) => (
+    
+    match config_manager.set_thermal(thermal_config) {
+        Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", volume)),
+            Json(ApiResponse::success_with_message("Thermal config updated", json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get call volume: {}", e))),
+            Json(ApiResponse::error(format!("Failed to update thermal config: {}", e))),
         ),
     }
 }
 
-/// POST /api/call/volume - 设置通话音量
-pub async fn set_call_volume_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<crate::models::SetCallVolumeRequest>,
+// ============ LAN / DHCP API ============
+
+/// GET /api/lan/config - 获取 USB 网卡（LAN）配置
+pub async fn get_lan_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::LanConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_lan())),
+    )
+}
+
+/// POST /api/lan/config - 设置 USB 网卡（LAN）配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "interface": "usb0",
+///   "ip_address": "192.168.8.1",
+///   "prefix_len": 24,
+///   "mac_address": "CC:E8:AC:C0:00:00",
+///   "dhcp_enabled": true,
+///   "dhcp_start": "192.168.8.100",
+///   "dhcp_end": "192.168.8.200",
+///   "lease_time_secs": 43200,
+///   "dns_servers": [],
+///   "ipv6": {
+///     "mode": "relay",
+///     "ra_interval_secs": 60,
+///     "dhcpv6": true,
+///     "ndp_proxy": false,
+///     "dns_servers": []
+///   },
+///   "client_accounting": false
+/// }
+/// ```
+///
+/// # 说明
+/// - 立即生效，已连接的客户端需要重新获取地址
+/// - client_accounting（默认关闭）：开启后按客户端统计流量（/api/clients）。SFP 硬件加速的流量不经过 netfilter，
+///   统计期间必须关闭 SFP，转发吞吐下降、CPU 占用升高；关闭后卸载计数链并恢复 SFP
+/// - dhcp_enabled=true 时使用内置 DHCP 服务并关闭 connman tethering，false（默认）时沿用 connman tethering
+/// - dns_servers 为空时下发运营商 DNS
+/// - ipv6.mode=relay 时在 LAN 上通告运营商分配的 /64 前缀（SLAAC + RDNSS），ipv6.dns_servers 为空时使用运营商 DNS
+/// - IPv6 转发流量受防火墙 IPv6 规则约束，WAN → LAN 按 wan_forward（默认放行，设为 drop 时只放行已建立的连接）
+pub async fn set_lan_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(lan_config): Json<crate::config::LanConfig>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::set_call_volume(&conn, req.speaker_volume, req.microphone_volume, req.muted).await {
+    if let Err(e) = crate::lan::validate(&lan_config) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e)));
+    }
+    if let Err(e) = config_manager.set_lan(lan_config.clone()) {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to save LAN config: {}", e))),
+        );
+    }
+    let interface = lan_config.interface.clone();
+    let result = crate::lan::apply(lan_config, config_manager.get_firewall().wan_interface).await;
+    // 接口可能改名，重新下发限速（失败记录在限速状态中）
+    let _ = crate::shaping::apply(config_manager.get_shaping(), interface).await;
+    match result {
         Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call volume updated", json!({}))),
+            Json(ApiResponse::success_with_message("LAN config updated", json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to set call volume: {}", e))),
+            Json(ApiResponse::error(format!("LAN config saved but failed to apply: {}", e))),
         ),
     }
 }
 
-/// GET /api/voicemail/status - 获取语音留言状态
-pub async fn get_voicemail_status_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::VoicemailStatusResponse>>) {
-    match crate::dbus::get_voicemail_status(&conn).await {
-        Ok(voicemail) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", voicemail)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get voicemail status: {}", e))),
-        ),
-    }
+/// GET /api/lan/leases - 获取 DHCP 租约列表
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "dhcp_enabled": true,
+///     "leases": [
+///       {
+///         "mac": "aa:bb:cc:dd:ee:01",
+///         "ip": "192.168.66.100",
+///         "hostname": "laptop",
+///         "expires_at": "2025-12-18T03:00:00+00:00",
+///         "remaining_secs": 40210,
+///         "connected": true
+///       }
+///     ]
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - connected 表示 ARP 表中存在该客户端的有效条目
+pub async fn get_lan_leases_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(db): State<Arc<Database>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let lan_config = config_manager.get_lan();
+    let records = match db.get_dhcp_leases() {
+        Ok(records) => records,
+        Err(e) => {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::error(format!("Failed to get leases: {}", e))),
+            )
+        }
+    };
+    let arp = crate::lan::read_arp_table(&lan_config.interface);
+    let leases = crate::lan::leases_with_status(records, &arp, chrono::Utc::now().timestamp());
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            json!({
+                "dhcp_enabled": lan_config.dhcp_enabled,
+                "leases": leases,
+            }),
+        )),
+    )
 }
 
-/// GET /api/network/operators - 获取运营商列表（快速）
-pub async fn get_operators_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::OperatorListResponse>>) {
-    match crate::dbus::get_operators(&conn).await {
-        Ok(operators) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", operators)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get operators: {}", e))),
-        ),
-    }
+// ============ 客户端流量 API ============
+
+/// GET /api/clients - 获取已连接客户端及流量
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "sample_interval_secs": 10,
+///     "clients": [
+///       {
+///         "mac": "aa:bb:cc:dd:ee:01",
+///         "hostname": "laptop",
+///         "ipv4": "192.168.66.100",
+///         "ipv6": ["2409:8a00:1:2::10"],
+///         "connected": true,
+///         "last_seen": "2025-12-18T03:00:00+00:00",
+///         "download_rate": 524288,
+///         "upload_rate": 32768,
+///         "today_download_bytes": 1073741824,
+///         "today_upload_bytes": 52428800
+///       }
+///     ]
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - 速率单位为字节/秒，按最近一个采样周期计算
+/// - 需要在 LAN 配置中开启 client_accounting（默认关闭，开启期间关闭 SFP 硬件加速）；关闭时速率和今日流量不再增长
+/// - 只统计经设备转发的流量（访问设备本身的流量不计入）
+/// - 今日流量按设备本地时区划分自然日，在线优先，再按今日流量降序
+pub async fn get_clients_handler() -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            json!({
+                "sample_interval_secs": crate::clients::SAMPLE_INTERVAL_SECS,
+                "clients": crate::clients::snapshot(),
+            }),
+        )),
+    )
 }
 
-/// GET /api/network/operators/scan - 扫描所有运营商（慢，120秒）
-pub async fn scan_operators_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::OperatorListResponse>>) {
-    match crate::dbus::scan_operators(&conn).await {
-        Ok(operators) => (
+/// GET /api/clients/{mac}?days=7 - 获取客户端每日流量历史
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "mac": "aa:bb:cc:dd:ee:01",
+///     "days": 7,
+///     "client": { "mac": "aa:bb:cc:dd:ee:01", "hostname": "laptop", "connected": true },
+///     "download_bytes": 3221225472,
+///     "upload_bytes": 157286400,
+///     "daily": [
+///       { "mac": "aa:bb:cc:dd:ee:01", "date": "2025-12-18", "download_bytes": 1073741824, "upload_bytes": 52428800 }
+///     ]
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - days 默认 7，最大 90；client 为当前状态，服务启动后未出现过的客户端为 null
+pub async fn get_client_history_handler(
+    State(db): State<Arc<Database>>,
+    axum::extract::Path(mac): axum::extract::Path<String>,
+    Query(params): Query<DataStatsRangeRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let Some(mac) = crate::lan::parse_mac(&mac).map(|m| crate::lan::format_mac(&m)) else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Invalid MAC address")));
+    };
+    let days = params.days.clamp(1, DATA_STATS_MAX_DAYS);
+    let since = (chrono::Local::now() - chrono::Duration::days(days - 1))
+        .format("%Y-%m-%d")
+        .to_string();
+    match db.get_client_traffic_history(&mac, &since) {
+        Ok(daily) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Scan completed", operators)),
+            Json(ApiResponse::success_with_message(
+                "Success",
+                json!({
+                    "mac": mac,
+                    "days": days,
+                    "client": crate::clients::get(&mac),
+                    "download_bytes": daily.iter().map(|d| d.download_bytes).sum::<i64>(),
+                    "upload_bytes": daily.iter().map(|d| d.upload_bytes).sum::<i64>(),
+                    "daily": daily,
+                }),
+            )),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to scan operators: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get client traffic history: {}", e))),
         ),
     }
 }
 
-/// POST /api/network/register-manual - 手动注册运营商
-pub async fn register_operator_manual_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<crate::models::ManualRegisterRequest>,
+// ============ 限速 API ============
+
+/// GET /api/shaping - 获取限速配置和实时队列统计
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "config": {
+///       "enabled": true,
+///       "download_kbps": 50000,
+///       "upload_kbps": 0,
+///       "clients": [
+///         {"mac": "aa:bb:cc:dd:ee:01", "name": "tv", "enabled": true, "download_kbps": 8000, "upload_kbps": 2000}
+///       ]
+///     },
+///     "status": {
+///       "enabled": true,
+///       "interface": "usb0",
+///       "download_active": true,
+///       "upload_active": true,
+///       "last_applied_at": "2025-12-18T08:00:00+00:00",
+///       "last_error": null,
+///       "download": [
+///         {
+///           "class_id": "1:10",
+///           "target": "aa:bb:cc:dd:ee:01",
+///           "name": "tv",
+///           "rate_kbps": 8000,
+///           "sent_bytes": 98765,
+///           "sent_packets": 321,
+///           "dropped": 7,
+///           "overlimits": 40,
+///           "backlog_bytes": 0,
+///           "backlog_packets": 0
+///         }
+///       ],
+///       "upload": []
+///     }
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - target：total 为接口总限速，default 为未单独限速的客户端，其余为客户端 MAC
+pub async fn get_shaping_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let shaping_config = config_manager.get_shaping();
+    let status = crate::shaping::status(shaping_config.clone(), config_manager.get_lan().interface).await;
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            json!({
+                "config": shaping_config,
+                "status": status,
+            }),
+        )),
+    )
+}
+
+/// 校验、下发并保存限速配置
+async fn save_shaping_config(
+    config_manager: &ConfigManager,
+    shaping_config: crate::config::ShapingConfig,
+    message: &str,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::register_operator_manual(&conn, &req.mccmnc).await {
+    if let Err(e) = crate::shaping::validate(&shaping_config) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e)));
+    }
+    let interface = config_manager.get_lan().interface;
+    if let Err(e) = crate::shaping::apply(shaping_config.clone(), interface.clone()).await {
+        // 下发失败时恢复原有限速
+        let _ = crate::shaping::apply(config_manager.get_shaping(), interface).await;
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to apply traffic shaping: {}", e))),
+        );
+    }
+    match config_manager.set_shaping(shaping_config) {
         Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("Registered to operator {}", req.mccmnc),
-                json!({}),
-            )),
+            Json(ApiResponse::success_with_message(message, json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to register operator: {}", e))),
+            Json(ApiResponse::error(format!("Failed to save shaping config: {}", e))),
         ),
     }
 }
 
-/// POST /api/network/register-auto - 自动注册运营商
-pub async fn register_operator_auto_handler(
-    State(conn): State<Arc<Connection>>,
+/// POST /api/shaping - 设置限速配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "enabled": true,
+///   "download_kbps": 50000,
+///   "upload_kbps": 0,
+///   "clients": [
+///     {"mac": "aa:bb:cc:dd:ee:01", "name": "tv", "download_kbps": 8000, "upload_kbps": 2000}
+///   ]
+/// }
+/// ```
+///
+/// # 说明
+/// - 速率单位 kbit/s，0 表示不限；客户端限速受总限速约束
+/// - 按 MAC 匹配，IPv4 / IPv6 均生效；限速生效期间关闭 SFP 硬件转发加速
+pub async fn set_shaping_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(shaping_config): Json<crate::config::ShapingConfig>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::register_operator_auto(&conn).await {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Automatic registration initiated", json!({}))),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to register automatically: {}", e))),
-        ),
+    save_shaping_config(&config_manager, shaping_config, "Shaping config updated").await
+}
+
+/// POST /api/shaping/clients - 添加或替换客户端限速（同一 MAC 则替换）
+///
+/// # 请求体
+/// ```json
+/// {"mac": "aa:bb:cc:dd:ee:01", "name": "tv", "download_kbps": 8000, "upload_kbps": 2000}
+/// ```
+pub async fn add_client_limit_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(mut limit): Json<crate::config::ClientLimit>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let Some(mac) = crate::lan::parse_mac(&limit.mac) else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Invalid MAC address")));
+    };
+    limit.mac = crate::lan::format_mac(&mac);
+    let mut shaping_config = config_manager.get_shaping();
+    match shaping_config
+        .clients
+        .iter_mut()
+        .find(|c| crate::lan::parse_mac(&c.mac) == Some(mac))
+    {
+        Some(existing) => *existing = limit,
+        None => shaping_config.clients.push(limit),
     }
+    save_shaping_config(&config_manager, shaping_config, "Client limit saved").await
 }
 
-/// GET /api/call/forwarding - 获取呼叫转移设置
-pub async fn get_call_forwarding_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::CallForwardingResponse>>) {
-    match crate::dbus::get_call_forwarding(&conn).await {
-        Ok(forwarding) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", forwarding)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get call forwarding: {}", e))),
-        ),
+/// DELETE /api/shaping/clients/{mac} - 删除客户端限速
+pub async fn delete_client_limit_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    axum::extract::Path(mac): axum::extract::Path<String>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let Some(parsed) = crate::lan::parse_mac(&mac) else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Invalid MAC address")));
+    };
+    let mut shaping_config = config_manager.get_shaping();
+    let before = shaping_config.clients.len();
+    shaping_config.clients.retain(|c| crate::lan::parse_mac(&c.mac) != Some(parsed));
+    if shaping_config.clients.len() == before {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Client limit not found: {}", mac))),
+        );
     }
+    save_shaping_config(&config_manager, shaping_config, "Client limit deleted").await
 }
 
-/// POST /api/call/forwarding - 设置呼叫转移
-pub async fn set_call_forwarding_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<crate::models::SetCallForwardingRequest>,
+// ============ 防火墙 API ============
+
+/// GET /api/firewall - 获取防火墙状态
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "enabled": true,
+///     "active_ipv4": true,
+///     "active_ipv6": true,
+///     "ipv4_rules": 9,
+///     "ipv6_rules": 10,
+///     "managed_rules": 1,
+///     "port_forwards": 2,
+///     "dmz_host": null,
+///     "nat_active": true,
+///     "ttl": {"enabled": true, "mode": "set", "value": 65, "active_ipv4": true, "active_ipv6": true, "error": null},
+///     "last_applied_at": "2025-12-17T02:00:00+00:00",
+///     "last_error": null,
+///     "recent_fixes": [
+///       {"timestamp": "2025-12-17T02:10:00+00:00", "detail": "IPv4: -D FORWARD -i usb0 -j DROP"}
+///     ]
+///   }
+/// }
+/// ```
+pub async fn get_firewall_status_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::firewall::FirewallStatus>>) {
+    let status = firewall.status(config_manager.get_firewall()).await;
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", status)),
+    )
+}
+
+/// GET /api/firewall/config - 获取防火墙配置（含规则列表）
+pub async fn get_firewall_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::FirewallConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_firewall())),
+    )
+}
+
+/// 校验、下发并保存防火墙配置
+async fn save_firewall_config(
+    config_manager: &ConfigManager,
+    firewall: &Arc<crate::firewall::FirewallManager>,
+    firewall_config: crate::config::FirewallConfig,
+    message: &str,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::set_call_forwarding(&conn, &req.forward_type, &req.number, req.timeout).await {
+    if let Err(e) = crate::firewall::validate(&firewall_config) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e)));
+    }
+    if let Err(e) = firewall.apply(firewall_config.clone()).await {
+        // 下发失败时恢复原有规则
+        let _ = firewall.apply(config_manager.get_firewall()).await;
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error(format!("Failed to apply firewall rules: {}", e))),
+        );
+    }
+    match config_manager.set_firewall(firewall_config) {
         Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call forwarding updated", json!({}))),
+            Json(ApiResponse::success_with_message(message, json!({}))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to set call forwarding: {}", e))),
+            Json(ApiResponse::error(format!("Failed to save firewall config: {}", e))),
         ),
     }
 }
 
-/// GET /api/call/settings - 获取通话设置
-pub async fn get_call_settings_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::CallSettingsResponse>>) {
-    match crate::dbus::get_call_settings(&conn).await {
-        Ok(settings) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", settings)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get call settings: {}", e))),
-        ),
+/// POST /api/firewall/config - 设置防火墙配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "enabled": true,
+///   "lan_interface": "usb0",
+///   "wan_interface": "sipa_eth+",
+///   "lan_input": "accept",
+///   "wan_input": "drop",
+///   "lan_forward": "accept",
+///   "wan_forward": "drop",
+///   "rules": [
+///     {"name": "ssh-office", "enabled": true, "zone": "wan", "chain": "input", "family": "ipv4",
+///      "protocol": "tcp", "source": "203.0.113.0/24", "destination": "", "port": "22", "action": "accept"}
+///   ],
+///   "remove_harmful_rules": true
+/// }
+/// ```
+///
+/// # 说明
+/// - 策略和规则动作：accept / drop / reject；区域：lan / wan；链：input / forward
+/// - 规则按顺序匹配，先于默认策略；已建立的连接始终放行
+/// - 配置立即下发（IPv4 和 IPv6），启动时自动重新下发
+/// - 默认策略均为 accept，与启用托管链前的转发行为一致；需要阻止 WAN 主动访问 LAN 时将 wan_forward 设为 drop
+/// - remove_harmful_rules（默认开启）：删除内置 INPUT / FORWARD 链中只按 LAN / WAN 接口匹配的 DROP / REJECT 规则
+/// - 端口转发、DMZ 和 TTL 通过 /api/firewall/port-forwards、/api/firewall/dmz、/api/firewall/ttl 管理，此处保持不变
+pub async fn set_firewall_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    Json(mut firewall_config): Json<crate::config::FirewallConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let current = config_manager.get_firewall();
+    firewall_config.port_forwards = current.port_forwards;
+    firewall_config.dmz_host = current.dmz_host;
+    firewall_config.ttl = current.ttl;
+    save_firewall_config(&config_manager, &firewall, firewall_config, "Firewall config updated").await
+}
+
+/// POST /api/firewall/rules - 添加防火墙规则（追加到末尾，同名规则则替换）
+///
+/// # 请求体
+/// ```json
+/// {"name": "block-telnet", "zone": "lan", "chain": "input", "protocol": "tcp", "port": "23", "action": "reject"}
+/// ```
+pub async fn add_firewall_rule_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    Json(rule): Json<crate::config::FirewallRule>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let mut firewall_config = config_manager.get_firewall();
+    match firewall_config.rules.iter_mut().find(|r| r.name == rule.name) {
+        Some(existing) => *existing = rule,
+        None => firewall_config.rules.push(rule),
     }
+    save_firewall_config(&config_manager, &firewall, firewall_config, "Firewall rule saved").await
 }
 
-/// POST /api/call/settings - 设置通话设置
-pub async fn set_call_settings_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<crate::models::SetCallSettingRequest>,
+/// DELETE /api/firewall/rules/{name} - 删除防火墙规则
+pub async fn delete_firewall_rule_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    axum::extract::Path(name): axum::extract::Path<String>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::set_call_setting(&conn, &req.property, &req.value).await {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call settings updated", json!({}))),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to set call settings: {}", e))),
-        ),
+    let mut firewall_config = config_manager.get_firewall();
+    let before = firewall_config.rules.len();
+    firewall_config.rules.retain(|r| r.name != name);
+    if firewall_config.rules.len() == before {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Rule not found: {}", name))),
+        );
     }
+    save_firewall_config(&config_manager, &firewall, firewall_config, "Firewall rule deleted").await
 }
 
-// ============ SIM 卡槽功能 ============
+/// GET /api/firewall/port-forwards - 获取端口转发规则和 DMZ 主机
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "port_forwards": [
+///       {"name": "web", "enabled": true, "protocol": "tcp", "external_port": "8080",
+///        "internal_host": "192.168.66.10", "internal_port": "80", "source": ""}
+///     ],
+///     "dmz_host": ""
+///   }
+/// }
+/// ```
+pub async fn get_port_forwards_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let firewall_config = config_manager.get_firewall();
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            json!({
+                "port_forwards": firewall_config.port_forwards,
+                "dmz_host": firewall_config.dmz_host,
+            }),
+        )),
+    )
+}
 
-/// GET /api/sim/slot - 获取 SIM 卡槽信息
-pub async fn get_sim_slot_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<crate::models::SimSlotResponse>>) {
-    match crate::dbus::get_sim_slot(&conn).await {
-        Ok(slot_info) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Success", slot_info)),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get SIM slot info: {}", e))),
-        ),
+/// POST /api/firewall/port-forwards - 添加端口转发规则（同名规则则替换）
+///
+/// # 请求体
+/// ```json
+/// {"name": "web", "protocol": "tcp", "external_port": "8080", "internal_host": "192.168.66.10", "internal_port": "80"}
+/// ```
+///
+/// # 说明
+/// - protocol：tcp / udp / both
+/// - 端口支持范围（8000:8100），internal_port 为空表示与外部端口相同
+/// - 仅 IPv4；source 可限制来源地址或网段
+pub async fn add_port_forward_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    Json(forward): Json<crate::config::PortForward>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let mut firewall_config = config_manager.get_firewall();
+    match firewall_config.port_forwards.iter_mut().find(|f| f.name == forward.name) {
+        Some(existing) => *existing = forward,
+        None => firewall_config.port_forwards.push(forward),
     }
+    save_firewall_config(&config_manager, &firewall, firewall_config, "Port forward saved").await
 }
 
-/// POST /api/sim/slot/switch - 切换 SIM 卡槽
-pub async fn switch_sim_slot_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<crate::models::SwitchSimSlotRequest>,
+/// DELETE /api/firewall/port-forwards/{name} - 删除端口转发规则
+pub async fn delete_port_forward_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    axum::extract::Path(name): axum::extract::Path<String>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match crate::dbus::switch_sim_slot(&conn, req.slot).await {
-        Ok(response) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                format!("Switched to SIM slot {}", req.slot),
-                json!({"response": response}),
-            )),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to switch SIM slot: {}", e))),
-        ),
+    let mut firewall_config = config_manager.get_firewall();
+    let before = firewall_config.port_forwards.len();
+    firewall_config.port_forwards.retain(|f| f.name != name);
+    if firewall_config.port_forwards.len() == before {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Port forward not found: {}", name))),
+        );
     }
+    save_firewall_config(&config_manager, &firewall, firewall_config, "Port forward deleted").await
 }
 
-// ============ APN 管理功能 ============
+/// POST /api/firewall/dmz - 设置 DMZ 主机
+///
+/// # 请求体
+/// ```json
+/// {"host": "192.168.66.10"}
+/// ```
+///
+/// # 说明
+/// - 未匹配端口转发的 WAN 入站连接（ICMP 除外）全部转发到该主机
+/// - host 为空表示关闭 DMZ
+pub async fn set_dmz_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    Json(payload): Json<SetDmzRequest>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let mut firewall_config = config_manager.get_firewall();
+    firewall_config.dmz_host = payload.host.trim().to_string();
+    let message = if firewall_config.dmz_host.is_empty() { "DMZ disabled" } else { "DMZ host updated" };
+    save_firewall_config(&config_manager, &firewall, firewall_config, message).await
+}
 
-/// GET /api/apn - 获取 APN 列表
+/// GET /api/firewall/ttl - 获取 TTL / Hop Limit 规范化配置和状态
 ///
-/// 返回所有 internet 类型的 APN context 配置
-pub async fn get_apn_list_handler(
-    State(conn): State<Arc<Connection>>,
-) -> (StatusCode, Json<ApiResponse<ApnListResponse>>) {
-    match get_all_apn_contexts(&conn).await {
-        Ok(contexts) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message(
-                "Success",
-                ApnListResponse { contexts },
-            )),
-        ),
-        Err(e) => (
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "config": {"enabled": true, "mode": "set", "value": 65, "ipv6": true},
+///     "status": {"enabled": true, "mode": "set", "value": 65, "active_ipv4": true, "active_ipv6": true, "error": null}
+///   }
+/// }
+/// ```
+pub async fn get_ttl_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let firewall_config = config_manager.get_firewall();
+    let ttl = firewall_config.ttl.clone();
+    let status = firewall.status(firewall_config).await;
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            json!({
+                "config": ttl,
+                "status": status.ttl,
+            }),
+        )),
+    )
+}
+
+/// POST /api/firewall/ttl - 设置 TTL / Hop Limit 规范化
+///
+/// # 请求体
+/// ```json
+/// {"enabled": true, "mode": "set", "value": 65, "ipv6": true}
+/// ```
+///
+/// # 说明
+/// - mode：set（设为 value）/ increment（增加 value）
+/// - 只修改经 WAN 接口转发出去的报文（mangle 表 FORWARD），设备自身流量不受影响
+/// - 规则由防火墙管理，watchdog 检查时被清除或下发失败会自动重新下发
+/// - 开启期间关闭 SFP 硬件加速（加速的流量不经过 iptables，TTL 不会被修改）
+pub async fn set_ttl_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    State(firewall): State<Arc<crate::firewall::FirewallManager>>,
+    Json(ttl): Json<crate::config::TtlConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let mut firewall_config = config_manager.get_firewall();
+    firewall_config.ttl = ttl;
+    let response = save_firewall_config(&config_manager, &firewall, firewall_config, "TTL config updated").await;
+    if response.1.status != "ok" {
+        return response;
+    }
+    // TTL 下发失败不会回滚其他防火墙规则，单独报告
+    match firewall.status(config_manager.get_firewall()).await.ttl.error {
+        Some(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get APN list: {}", e))),
+            Json(ApiResponse::error(format!("TTL config saved but failed to apply: {}", e))),
         ),
+        None => response,
     }
 }
 
-/// POST /api/apn - 设置 APN 配置
+// ============ 数据连接 Watchdog API ============
+
+/// GET /api/watchdog - 获取数据连接 watchdog 状态
+///
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "enabled": true,
+///     "health": "down",
+///     "last_status": "Context inactive (APN: cmnet)",
+///     "last_check": "2025-12-16T08:00:05+00:00",
+///     "consecutive_failures": 7,
+///     "down_since": "2025-12-16T07:59:30+00:00",
+///     "current_step": "airplane_toggle",
+///     "step_attempts": 1,
+///     "next_attempt_at": "2025-12-16T08:00:35+00:00",
+///     "reboots_last_24h": 0,
+///     "max_reboots_per_day": 1,
+///     "probe_failures": 0,
+///     "last_probe_at": null,
+///     "probe_results": [],
+///     "recent_actions": [
+///       {"id": 3, "timestamp": "2025-12-16T08:00:05+00:00", "action": "airplane_toggle",
+///        "failures": 6, "success": true, "detail": "Modem toggled offline for 5s"}
+///     ]
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - health 为 unregistered 时（SIM 卡在位但未注册网络）只执行到重新注册 / 重启 ofono，不重启系统
+/// - health 为 paused 时小区优化或安全锁定观察正在进行，恢复阶梯暂停
+pub async fn get_watchdog_status_handler(
+    State(db): State<Arc<Database>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::watchdog::WatchdogStatus>>) {
+    let status = crate::watchdog::status(&db, &config_manager.get_watchdog());
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", status)),
+    )
+}
+
+/// GET /api/watchdog/config - 获取数据连接 watchdog 配置
+pub async fn get_watchdog_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::WatchdogConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("Success", config_manager.get_watchdog())),
+    )
+}
+
+/// POST /api/watchdog/config - 设置数据连接 watchdog 配置
 ///
 /// # 请求体
 /// ```json
 /// {
-///   "context_path": "/ril_0/context2",
-///   "apn": "cbnet",
-///   "protocol": "dual",
-///   "username": "",
-///   "password": "",
-///   "auth_method": "chap"
+///   "enabled": true,
+///   "interval_secs": 5,
+///   "steps": [
+///     {"action": "reactivate", "enabled": true, "failure_threshold": 1, "max_attempts": 3, "backoff_secs": 10},
+///     {"action": "airplane_toggle", "enabled": true, "failure_threshold": 6, "max_attempts": 2, "backoff_secs": 30},
+///     {"action": "reregister", "enabled": true, "failure_threshold": 12, "max_attempts": 2, "backoff_secs": 60},
+///     {"action": "restart_ofono", "enabled": true, "failure_threshold": 24, "max_attempts": 2, "backoff_secs": 120},
+///     {"action": "reboot", "enabled": true, "failure_threshold": 120, "max_attempts": 1, "backoff_secs": 600}
+///   ],
+///   "max_backoff_secs": 1800,
+///   "max_reboots_per_day": 1,
+///   "airplane_toggle_secs": 5,
+///   "ofono_restart_method": "systemctl",
+///   "probe": {
+///     "enabled": true,
+///     "interval_secs": 30,
+///     "timeout_secs": 5,
+///     "failure_threshold": 3,
+///     "targets": [
+///       {"kind": "icmp", "target": "223.5.5.5"},
+///       {"kind": "dns", "target": "www.baidu.com"},
+///       {"kind": "http", "target": "http://connect.rom.miui.com/generate_204"}
+///     ]
+///   }
 /// }
 /// ```
-pub async fn set_apn_handler(
-    State(conn): State<Arc<Connection>>,
-    Json(req): Json<SetApnRequest>,
+///
+/// # 说明
+/// - 连续失败检查次数达到 `failure_threshold` 后执行该步，尝试 `max_attempts` 次后升级到下一步
+/// - 每次尝试后等待 `backoff_secs`，同一步重复尝试时翻倍，不超过 `max_backoff_secs`
+/// - 24 小时内重启系统次数达到 `max_reboots_per_day` 后跳过重启步骤
+/// - `ofono_restart_method`：systemctl（systemctl restart ofono）/ init_script（/etc/init.d/ofono restart）
+/// - 启用 `probe` 时，context 激活但连续 `failure_threshold` 轮所有目标都探测失败同样视为断开
+/// - dns 目标通过数据网络接口直接向运营商 DNS 查询 A 记录（不经过系统解析器和缓存）
+pub async fn set_watchdog_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(watchdog_config): Json<crate::config::WatchdogConfig>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    // 验证 context_path
-    if req.context_path.is_empty() {
+    if !(1..=3600).contains(&watchdog_config.interval_secs) {
         return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::error("context_path is required")),
+            StatusCode::OK,
+            Json(ApiResponse::error("interval_secs must be between 1 and 3600")),
+        );
+    }
+    if watchdog_config
+        .steps
+        .windows(2)
+        .any(|w| w[1].failure_threshold < w[0].failure_threshold)
+    {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::error("Step failure_threshold must not decrease along the ladder")),
         );
     }
     
-    // 调用 D-Bus 设置 APN 属性
-    match set_apn_properties(
-        &conn,
-        &req.context_path,
-        req.apn.as_deref(),
-        req.protocol.as_deref(),
-        req.username.as_deref(),
-        req.password.as_deref(),
-        req.auth_method.as_deref(),
-    ).await {
-        Ok(_) => {
-            // 获取更新后的 APN 配置
-            match get_all_apn_contexts(&conn).await {
-                Ok(contexts) => {
-                    // 找到刚刚修改的 context
-                    let updated_context = contexts
-                        .iter()
-                        .find(|c| c.path == req.context_path)
-                        .cloned();
-                    
-                    (
-                        StatusCode::OK,
-                        Json(ApiResponse::success_with_message(
-                            "APN configuration updated successfully",
-                            json!({
-                                "updated_context": updated_context,
-                            }),
-                        )),
-                    )
-                }
-                Err(_) => (
-                    StatusCode::OK,
-                    Json(ApiResponse::success_with_message(
-                        "APN configuration updated successfully",
-                        json!({}),
-                    )),
-                ),
+    for target in &watchdog_config.probe.targets {
+        let valid = match target.kind {
+            crate::config::ProbeKind::Icmp => target.target.parse::<std::net::IpAddr>().is_ok(),
+            crate::config::ProbeKind::Dns => crate::probe::build_dns_query(0, &target.target).is_ok(),
+            crate::config::ProbeKind::Http => {
+                target.target.starts_with("http://") || target.target.starts_with("https://")
             }
+        };
+        if !valid {
+            return (
+                StatusCode::OK,
+                Json(ApiResponse::error(format!("Invalid probe target: {}", target.target))),
+            );
         }
+    }
+    
+    match config_manager.set_watchdog(watchdog_config) {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::success_with_message("Watchdog config updated", json!({}))),
+        ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to set APN: {}", e))),
+            Json(ApiResponse::error(format!("Failed to update watchdog config: {}", e))),
         ),
     }
 }
 
-/// GET /api/connectivity - 联网检测
+// ============ DDNS API ============
+
+/// GET /api/ddns - 获取 DDNS 状态
 ///
-/// 通过 ping 检测 IPv4 和 IPv6 连通性
-pub async fn get_connectivity_check() -> (StatusCode, Json<ApiResponse<ConnectivityCheckResponse>>) {
-    let ipv4_result = ping_host("223.5.5.5", false);
-    let ipv6_result = ping_host("2400:3200::1", true);
-    
-    let response = ConnectivityCheckResponse {
-        ipv4: ipv4_result,
-        ipv6: ipv6_result,
-    };
-    
+/// # 响应示例
+/// ```json
+/// {
+///   "status": "ok",
+///   "message": "Success",
+///   "data": {
+///     "enabled": true,
+///     "provider": "cloudflare",
+///     "domain": "home.example.com",
+///     "last_check_at": "2025-12-19T02:00:00+00:00",
+///     "last_error": null,
+///     "consecutive_failures": 0,
+///     "next_retry_at": null,
+///     "ipv4": {
+///       "record_type": "A",
+///       "enabled": true,
+///       "current_ip": null,
+///       "published_ip": null,
+///       "last_update_at": null,
+///       "last_error": null,
+///       "skipped": "IPv4 address 10.12.34.56 is behind carrier NAT"
+///     },
+///     "ipv6": {
+///       "record_type": "AAAA",
+///       "enabled": true,
+///       "current_ip": "2409:8a00:1:2::1",
+///       "published_ip": "2409:8a00:1:2::1",
+///       "last_update_at": "2025-12-19T01:30:00+00:00",
+///       "last_error": null,
+///       "skipped": null
+///     },
+///     "recent_updates": [
+///       {"id": 5, "timestamp": "2025-12-19T01:30:00+00:00", "provider": "cloudflare", "domain": "home.example.com",
+///        "record_type": "AAAA", "ip": "2409:8a00:1:2::1", "success": true, "detail": "Record updated"}
+///     ]
+///   }
+/// }
+/// ```
+pub async fn get_ddns_status_handler(
+    State(db): State<Arc<Database>>,
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::ddns::DdnsStatus>>) {
+    let status = crate::ddns::status(&db, &config_manager.get_ddns());
     (
         StatusCode::OK,
-        Json(ApiResponse::success_with_message("Connectivity check completed", response)),
+        Json(ApiResponse::success_with_message("Success", status)),
     )
 }
 
-/// 执行 ping 检测
-fn ping_host(target: &str, is_ipv6: bool) -> PingResult {
-    let cmd = if is_ipv6 { "ping6" } else { "ping" };
-    
-    // 使用 -c 1 只发一个包，-W 2 设置超时 2 秒
-    let output = Command::new(cmd)
-        .args(["-c", "1", "-W", "2", target])
-        .output();
-    
-    match output {
-        Ok(result) => {
-            if result.status.success() {
-                // 解析延迟时间
-                let stdout = String::from_utf8_lossy(&result.stdout);
-                let latency = parse_ping_latency(&stdout);
-                
-                PingResult {
-                    success: true,
-                    latency_ms: latency,
-                    target: target.to_string(),
-                    error: None,
-                }
-            } else {
-                let stderr = String::from_utf8_lossy(&result.stderr);
-                PingResult {
-                    success: false,
-                    latency_ms: None,
-                    target: target.to_string(),
-                    error: Some(if stderr.is_empty() {
-                        "Host unreachable".to_string()
-                    } else {
-                        stderr.trim().to_string()
-                    }),
-                }
-            }
-        }
-        Err(e) => PingResult {
-            success: false,
-            latency_ms: None,
-            target: target.to_string(),
-            error: Some(format!("Failed to execute ping: {}", e)),
-        },
-    }
+/// GET /api/ddns/config - 获取 DDNS 配置
+///
+/// secret 只返回末 4 位（如 `****1a2b`，8 位及以下为 `****`），未设置时为空字符串
+pub async fn get_ddns_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+) -> (StatusCode, Json<ApiResponse<crate::config::DdnsConfig>>) {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message(
+            "Success",
+            crate::ddns::mask_secret(&config_manager.get_ddns()),
+        )),
+    )
 }
 
-/// 从 ping 输出中解析延迟时间
-fn parse_ping_latency(output: &str) -> Option<f64> {
-    // 匹配 "time=XX.XX ms" 或 "time=XX ms"
-    for line in output.lines() {
-        if let Some(time_pos) = line.find("time=") {
-            let after_time = &line[time_pos + 5..];
-            // 找到数字部分
-            let num_str: String = after_time
-                .chars()
-                .take_while(|c| c.is_ascii_digit() || *c == '.')
-                .collect();
-            if let Ok(latency) = num_str.parse::<f64>() {
-                return Some(latency);
-            }
-        }
+/// POST /api/ddns/config - 设置 DDNS 配置
+///
+/// # 请求体
+/// ```json
+/// {
+///   "enabled": true,
+///   "provider": "cloudflare",
+///   "domain": "home.example.com",
+///   "zone": "023e105f4ecef8ad9ca31a8372d0c353",
+///   "key_id": "",
+///   "secret": "cloudflare-api-token",
+///   "ipv4": true,
+///   "ipv6": true,
+///   "ttl": 600,
+///   "check_interval_secs": 60,
+///   "force_update_hours": 24,
+///   "custom": {
+///     "method": "GET",
+///     "url": "https://dyn.example.com/update?hostname={{domain}}&myip={{ip}}",
+///     "body": "",
+///     "headers": {},
+///     "success_keyword": ""
+///   }
+/// }
+/// ```
+///
+/// # 说明
+/// - provider：custom / cloudflare / duckdns / dnspod / aliyun
+/// - cloudflare：zone 为 Zone ID，secret 为 API Token（需要 DNS 编辑权限）
+/// - duckdns：domain 可只填子域名，secret 为 Token
+/// - dnspod：key_id / secret 为 API ID / Token；aliyun：key_id / secret 为 AccessKey ID / Secret；
+///   zone 为主域名，为空时取域名后两段
+/// - custom：url / body / headers 支持 {{ip}}、{{type}}、{{domain}}、{{ipv4}}、{{ipv6}}、{{key_id}}、{{secret}}，
+///   每条记录（A / AAAA）请求一次
+/// - 数据连接 IPv4 位于运营商 NAT 后时不更新 A 记录
+/// - secret 为空或为 GET 返回的掩码值时，保留已保存的 secret
+/// - 保存后立即按新配置检查并更新
+pub async fn set_ddns_config_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
+    Json(mut ddns_config): Json<crate::config::DdnsConfig>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    crate::ddns::restore_secret(&mut ddns_config, &config_manager.get_ddns());
+    if ddns_config.secret.starts_with(crate::utils::SECRET_MASK_PREFIX) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("secret must be re-entered")));
     }
-    None
-}
-
-// ============ 通话记录 API ============
-
-use crate::config::ConfigManager;
-use crate::webhook::WebhookSender;
-
-/// GET /api/call/history - 获取通话记录
-pub async fn get_call_history_handler(
-    State(db): State<Arc<Database>>,
-    Query(params): Query<crate::models::CallHistoryRequest>,
-) -> (StatusCode, Json<ApiResponse<crate::models::CallHistoryResponse>>) {
-    let limit = if params.limit > 0 { params.limit } else { 50 };
-    let offset = if params.offset >= 0 { params.offset } else { 0 };
-    
-    match db.get_call_history(limit, offset) {
-        Ok(records) => {
-            let stats = db.get_call_stats().unwrap_or_default();
+    if let Err(e) = crate::ddns::validate(&ddns_config) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e)));
+    }
+    match config_manager.set_ddns(ddns_config) {
+        Ok(_) => {
+            crate::ddns::reload();
             (
                 StatusCode::OK,
-                Json(ApiResponse::success_with_message(
-                    "Success",
-                    crate::models::CallHistoryResponse { records, stats },
-                )),
+                Json(ApiResponse::success_with_message("DDNS config updated", json!({}))),
             )
         }
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to get call history: {}", e))),
+            Json(ApiResponse::error(format!("Failed to save DDNS config: {}", e))),
         ),
     }
 }
 
-/// DELETE /api/call/history/{id} - 删除单条通话记录
-pub async fn delete_call_history_handler(
-    State(db): State<Arc<Database>>,
-    axum::extract::Path(id): axum::extract::Path<i64>,
+/// POST /api/ddns/update - 立即更新（忽略退避和地址缓存）
+///
+/// 更新在后台执行，结果通过 GET /api/ddns 查看
+pub async fn ddns_update_now_handler(
+    State(config_manager): State<Arc<ConfigManager>>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match db.delete_call(id) {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::success_with_message("Call record deleted", json!({}))),
-        ),
-        Err(e) => (
-            StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to delete call record: {}", e))),
-        ),
+    if !config_manager.get_ddns().enabled {
+        return (StatusCode::OK, Json(ApiResponse::error("DDNS is not enabled")));
     }
+    crate::ddns::update_now();
+    (
+        StatusCode::OK,
+        Json(ApiResponse::success_with_message("DDNS update scheduled", json!({}))),
+    )
 }
 
-/// POST /api/call/history/clear - 清空所有通话记录
-pub async fn clear_call_history_handler(
+/// GET /api/ddns/history - 获取 DDNS 更新记录
+///
+/// # 查询参数
+/// - `limit`: 每页数量（默认 50）
+/// - `offset`: 偏移量（默认 0）
+pub async fn get_ddns_history_handler(
     State(db): State<Arc<Database>>,
+    Query(params): Query<DdnsHistoryRequest>,
 ) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
-    match db.clear_all_calls() {
-        Ok(_) => (
+    match db.get_ddns_history(params.limit, params.offset) {
+        Ok(records) => (
             StatusCode::OK,
-            Json(ApiResponse::success_with_message("All call records cleared", json!({}))),
+            Json(ApiResponse::success_with_message("Success", json!(records))),
         ),
         Err(e) => (
             StatusCode::OK,
-            Json(ApiResponse::error(format!("Failed to clear call history: {}", e))),
+            Json(ApiResponse::error(format!("Failed to get DDNS history: {}", e))),
         ),
     }
 }
@@ -0,0 +1,147 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-16 10:05:12
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-16 10:05:12
+ * @FilePath: /udx710-backend/backend/src/ip_info.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! IP 配置辅助模块
+//!
+//! - 公网 IPv4 检测：依次请求多个回显服务，以数据连接地址为源地址发起请求；
+//!   结果按数据连接地址缓存 CACHE_TTL，地址变化或强制刷新时重新检测
+//! - NAT 类型判断：比较数据连接地址与公网 IP，识别运营商级 NAT（100.64.0.0/10）
+//! - IPv6 前缀计算
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// 公网 IP 回显服务（返回纯文本地址）
+const PUBLIC_IP_SERVICES: [&str; 3] = [
+    "https://api.ipify.org",
+    "https://ipv4.icanhazip.com",
+    "https://ifconfig.me/ip",
+];
+
+/// 公网 IP 缓存时间
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// 公网 IP 缓存（数据连接地址, 检测时间, 公网 IP）
+static CACHE: Mutex<Option<(String, Instant, String)>> = Mutex::new(None);
+
+/// 检测公网 IPv4 地址
+///
+/// # Arguments
+/// * `wan_address` - 数据连接 IPv4 地址（用作源地址和缓存键，可为空）
+/// * `refresh` - 忽略缓存
+pub async fn detect_public_ip(wan_address: &str, refresh: bool) -> Result<String, String> {
+    if !refresh {
+        if let Ok(cache) = CACHE.lock() {
+            if let Some((addr, at, ip)) = cache.as_ref() {
+                if addr == wan_address && at.elapsed() < CACHE_TTL {
+                    return Ok(ip.clone());
+                }
+            }
+        }
+    }
+
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(5));
+    if let Ok(addr) = wan_address.parse::<Ipv4Addr>() {
+        builder = builder.local_address(IpAddr::V4(addr));
+    }
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let mut last_error = String::from("No public IP service available");
+    for url in PUBLIC_IP_SERVICES {
+        let result = match client.get(url).send().await {
+            Ok(resp) if resp.status().is_success() => resp.text().await.map_err(|e| e.to_string()),
+            Ok(resp) => Err(format!("HTTP {}", resp.status())),
+            Err(e) => Err(e.to_string()),
+        };
+        match result.map(|body| body.trim().parse::<Ipv4Addr>()) {
+            Ok(Ok(ip)) => {
+                let ip = ip.to_string();
+                if let Ok(mut cache) = CACHE.lock() {
+                    *cache = Some((wan_address.to_string(), Instant::now(), ip.clone()));
+                }
+                return Ok(ip);
+            }
+            Ok(Err(_)) => last_error = format!("{}: invalid response", url),
+            Err(e) => last_error = format!("{}: {}", url, e),
+        }
+        warn!(error = %last_error, "Public IP detection failed, trying next service");
+    }
+    Err(last_error)
+}
+
+/// 判断 NAT 类型
+///
+/// # Returns
+/// (nat_type, behind_cgnat)：
+/// - `cgnat`：数据连接地址位于 100.64.0.0/10
+/// - `private`：数据连接地址为 RFC 1918 私网地址（运营商 NAT）
+/// - `none`：数据连接地址即公网 IP
+/// - `nat`：数据连接为公网地址但与检测到的公网 IP 不一致
+/// - `unknown`：无数据连接地址，或未检测到公网 IP 无法比较
+pub fn classify_nat(wan_address: &str, public_ip: Option<&str>) -> (&'static str, Option<bool>) {
+    let Ok(wan) = wan_address.parse::<Ipv4Addr>() else {
+        return ("unknown", None);
+    };
+    let octets = wan.octets();
+    if octets[0] == 100 && (octets[1] & 0xc0) == 64 {
+        return ("cgnat", Some(true));
+    }
+    if wan.is_private() {
+        return ("private", Some(true));
+    }
+    match public_ip.and_then(|ip| ip.parse::<Ipv4Addr>().ok()) {
+        Some(public) if public == wan => ("none", Some(false)),
+        Some(_) => ("nat", Some(true)),
+        None => ("unknown", None),
+    }
+}
+
+/// 由 IPv6 地址和前缀长度计算前缀（如 2409:8a00:1:2::/64）
+pub fn ipv6_prefix(address: &str, prefix_length: u8) -> Option<String> {
+    let addr: Ipv6Addr = address.split('/').next()?.parse().ok()?;
+    if prefix_length > 128 {
+        return None;
+    }
+    let mask = u128::MAX.checked_shl(128 - prefix_length as u32).unwrap_or(0);
+    let prefix = Ipv6Addr::from(u128::from(addr) & mask);
+    Some(format!("{}/{}", prefix, prefix_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_nat() {
+        assert_eq!(classify_nat("100.72.3.4", None), ("cgnat", Some(true)));
+        assert_eq!(classify_nat("100.128.0.1", Some("100.128.0.1")), ("none", Some(false)));
+        assert_eq!(classify_nat("10.20.30.40", Some("1.2.3.4")), ("private", Some(true)));
+        assert_eq!(classify_nat("8.8.4.4", Some("8.8.4.4")), ("none", Some(false)));
+        assert_eq!(classify_nat("8.8.4.4", Some("1.1.1.1")), ("nat", Some(true)));
+        assert_eq!(classify_nat("8.8.4.4", None), ("unknown", None));
+        assert_eq!(classify_nat("", Some("1.1.1.1")), ("unknown", None));
+    }
+
+    #[test]
+    fn test_ipv6_prefix() {
+        assert_eq!(
+            ipv6_prefix("2409:8a00:1234:5678:abcd::1", 64).as_deref(),
+            Some("2409:8a00:1234:5678::/64")
+        );
+        assert_eq!(ipv6_prefix("2001:db8::1", 0).as_deref(), Some("::/0"));
+        assert_eq!(ipv6_prefix("2001:db8::1", 128).as_deref(), Some("2001:db8::1/128"));
+        assert!(ipv6_prefix("invalid", 64).is_none());
+        assert!(ipv6_prefix("2001:db8::1", 129).is_none());
+    }
+}
@@ -10,7 +10,8 @@
  */
 //! iptables 操作模块
 //!
-//! 提供 iptables 规则检查和清空功能
+//! 提供 iptables 命令执行、规则统计和 iptables-restore 原子替换链的功能。
+//! 托管规则链见 [`crate::firewall`]。
 
 use std::process::Command;
 use tokio::task;
@@ -22,15 +23,31 @@ pub struct IptablesRuleCount {
     pub ipv6_rules: usize,
 }
 
-impl IptablesRuleCount {
-    /// 是否有任何规则
-    pub fn has_rules(&self) -> bool {
-        self.ipv4_rules > 0 || self.ipv6_rules > 0
-    }
-    
-    /// 总规则数
-    pub fn total(&self) -> usize {
-        self.ipv4_rules + self.ipv6_rules
+/// 执行 iptables / ip6tables 命令（阻塞）
+///
+/// # Arguments
+/// * `ipv6` - 是否使用 ip6tables
+/// * `args` - 命令参数
+///
+/// # Returns
+/// * `Ok(String)` - 标准输出
+/// * `Err(String)` - 命令失败时的标准错误输出
+pub fn run_iptables<S: AsRef<str>>(ipv6: bool, args: &[S]) -> Result<String, String> {
+    let cmd = if ipv6 { "ip6tables" } else { "iptables" };
+    let output = Command::new(cmd)
+        .args(args.iter().map(|a| a.as_ref()))
+        .output()
+        .map_err(|e| format!("Failed to execute {}: {}", cmd, e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let args: Vec<&str> = args.iter().map(|a| a.as_ref()).collect();
+        Err(format!(
+            "{} {} failed: {}",
+            cmd,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
     }
 }
 
@@ -74,86 +91,96 @@ pub async fn get_iptables_rule_count() -> Result<IptablesRuleCount, String> {
     .map_err(|e| format!("Task execution failed: {}", e))?
 }
 
-/// 清空所有 iptables 规则
-///
-/// 执行等同于 `iptables -F` 的操作，清空 filter 表的所有链
-///
-/// # Returns
-/// * `Ok(())` - 成功清空规则
-/// * `Err(String)` - 操作失败的错误信息
+/// 生成 iptables-restore 输入
 ///
-/// # 说明
-/// 此函数会清空以下链的规则：
-/// - INPUT 链
-/// - FORWARD 链
-/// - OUTPUT 链
-pub async fn flush_iptables() -> Result<(), String> {
-    task::spawn_blocking(|| {
-        // 清空 filter 表的所有规则
-        let outputv4 = Command::new("iptables")
-            .arg("-F")
-            .output()
-            .map_err(|e| format!("Failed to execute ip6tables: {}", e))?;
-        if !outputv4.status.success()  {
-            let stderr = String::from_utf8_lossy(&outputv4.stderr);
-            return Err(format!("iptables -F failed: {}", stderr));
-        }
-        let outputv6 = Command::new("ip6tables")
-            .arg("-F")
-            .output()
-            .map_err(|e| format!("Failed to execute ip6tables: {}", e))?;
-        if !outputv6.status.success()  {
-            let stderr = String::from_utf8_lossy(&outputv6.stderr);
-            return Err(format!("ip6tables -F failed: {}", stderr));
+/// # Arguments
+/// * `table` - 表名
+/// * `chains` - 需要替换的链（声明后由 --noflush 模式清空，不存在时创建）
+/// * `lines` - 规则命令参数（如 `-A CHAIN ...`、`-I INPUT 1 -j CHAIN`）
+pub fn build_restore_input<S: AsRef<str>>(table: &str, chains: &[&str], lines: &[Vec<S>]) -> String {
+    let quote = |arg: &str| {
+        if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"') {
+            arg.to_string()
+        } else {
+            format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
         }
-
-        Ok(())
-    })
-    .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    };
+    let mut input = format!("*{}\n", table);
+    for chain in chains {
+        input.push_str(&format!(":{} - [0:0]\n", chain));
+    }
+    for line in lines {
+        let args: Vec<String> = line.iter().map(|a| quote(a.as_ref())).collect();
+        input.push_str(&args.join(" "));
+        input.push('\n');
+    }
+    input.push_str("COMMIT\n");
+    input
 }
 
-/// 清空所有 iptables 规则（包括 nat 和 mangle 表）
+/// 通过 `iptables-restore --noflush` 原子替换指定链（阻塞）
 ///
-/// 执行更完整的清空操作，清空 filter、nat、mangle 表的所有规则
+/// 整个表在一次提交中生效，不会出现链已清空但规则尚未下发完的窗口；
+/// 未声明的链保持不变。
 ///
-/// # Returns
-/// * `Ok(())` - 成功清空规则
-/// * `Err(String)` - 操作失败的错误信息
-#[allow(dead_code)]
-pub async fn flush_all_iptables() -> Result<(), String> {
-    task::spawn_blocking(|| {
-        let tables = ["filter", "nat", "mangle"];
-        
-        for table in &tables {
-            let output = Command::new("iptables")
-                .arg("-t")
-                .arg(table)
-                .arg("-F")
-                .output()
-                .map_err(|e| format!("Failed to execute iptables for table {}: {}", table, e))?;
-
-            if !output.status.success() {
-                // 如果表不存在或不支持，继续处理下一个表（某些表可能不存在）
-                // 静默处理，不输出警告
-            }
-        }
+/// # Arguments
+/// * `ipv6` - 是否使用 ip6tables-restore
+/// * `table` - 表名
+/// * `chains` - 需要替换的链
+/// * `lines` - 规则命令参数
+pub fn restore_chains<S: AsRef<str>>(ipv6: bool, table: &str, chains: &[&str], lines: &[Vec<S>]) -> Result<(), String> {
+    use std::io::Write;
+    use std::process::Stdio;
 
+    let cmd = if ipv6 { "ip6tables-restore" } else { "iptables-restore" };
+    let input = build_restore_input(table, chains, lines);
+    let mut child = Command::new(cmd)
+        .arg("--noflush")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to execute {}: {}", cmd, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|e| format!("Failed to write to {}: {}", cmd, e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait for {}: {}", cmd, e))?;
+    if output.status.success() {
         Ok(())
-    })
-    .await
-    .map_err(|e| format!("Task execution failed: {}", e))?
+    } else {
+        Err(format!(
+            "{} ({} table) failed: {}",
+            cmd,
+            table,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    #[ignore] // 需要 root 权限，默认忽略
-    async fn test_flush_iptables() {
-        let result = flush_iptables().await;
-        assert!(result.is_ok());
+    #[test]
+    fn test_build_restore_input() {
+        let lines = vec![
+            vec!["-A", "UDX_INPUT", "-i", "lo", "-j", "ACCEPT"],
+            vec!["-A", "UDX_INPUT", "-m", "comment", "--comment", "say \"hi\"", "-j", "DROP"],
+            vec!["-I", "INPUT", "1", "-j", "UDX_INPUT"],
+        ];
+        assert_eq!(
+            build_restore_input("filter", &["UDX_INPUT", "UDX_FORWARD"], &lines),
+            "*filter\n\
+             :UDX_INPUT - [0:0]\n\
+             :UDX_FORWARD - [0:0]\n\
+             -A UDX_INPUT -i lo -j ACCEPT\n\
+             -A UDX_INPUT -m comment --comment \"say \\\"hi\\\"\" -j DROP\n\
+             -I INPUT 1 -j UDX_INPUT\n\
+             COMMIT\n"
+        );
     }
 }
-
@@ -0,0 +1,728 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-18 10:05:37
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-18 10:05:37
+ * @FilePath: /udx710-backend/backend/src/ipv6_lan.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! LAN 侧 IPv6 直通模块
+//!
+//! 运营商把整个 /64 前缀路由给模组（3GPP），relay 模式下：
+//! 1. 从数据网络接口的全局地址得到 /64 前缀，路由到 LAN 接口（metric 低于 WAN 侧的前缀路由）
+//! 2. 开启 IPv6 转发，WAN 接口 accept_ra=2，转发开启后仍接受运营商 RA
+//! 3. 在 LAN 上周期发送 RA（前缀信息、MTU、RDNSS），并响应 RS，客户端通过 SLAAC 获取地址
+//! 4. 可选无状态 DHCPv6（RA 置 O 标志）下发 DNS
+//! 5. 可选在 WAN 接口为 LAN 主机代理邻居发现
+//!
+//! 转发流量经过防火墙的 IPv6 托管链：LAN → WAN 按 lan_forward，WAN → LAN 按 wan_forward
+//! （默认放行，wan_forward 设为 drop 时只放行已建立的连接）。前缀变化或数据断开时发送生存期为 0 的 RA 撤销旧前缀。
+
+use std::collections::HashSet;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::config::{ConfigManager, Ipv6LanConfig, Ipv6LanMode, LanConfig};
+use crate::models::Ipv6PassthroughStatus;
+
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+
+const DHCPV6_SERVER_PORT: u16 = 547;
+const DHCPV6_REPLY: u8 = 7;
+const DHCPV6_INFORMATION_REQUEST: u8 = 11;
+const DHCPV6_OPT_CLIENTID: u16 = 1;
+const DHCPV6_OPT_SERVERID: u16 = 2;
+const DHCPV6_OPT_DNS_SERVERS: u16 = 23;
+
+/// 全部节点 / 全部路由器 / 全部 DHCP 服务器组播地址
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+const ALL_DHCP_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+/// 前缀有效 / 首选生存期（秒）
+const PREFIX_VALID_LIFETIME: u32 = 7200;
+const PREFIX_PREFERRED_LIFETIME: u32 = 3600;
+
+/// 前缀路由的 metric（低于内核为 WAN 前缀添加的 256）
+const PREFIX_ROUTE_METRIC: &str = "128";
+
+/// 上游状态检查间隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 两次响应 RS 的最小间隔
+const MIN_RS_REPLY_INTERVAL: Duration = Duration::from_secs(3);
+
+/// 配置变更通知
+static RELOAD: Notify = Notify::const_new();
+
+/// 运行状态
+static STATUS: Mutex<Option<Ipv6PassthroughStatus>> = Mutex::new(None);
+
+/// 通知 IPv6 直通按最新配置重新启动
+pub fn reload() {
+    RELOAD.notify_one();
+}
+
+/// 获取 IPv6 直通状态
+pub fn status() -> Ipv6PassthroughStatus {
+    STATUS.lock().ok().and_then(|s| s.clone()).unwrap_or_else(|| Ipv6PassthroughStatus {
+        mode: "disabled".to_string(),
+        ..Default::default()
+    })
+}
+
+fn update_status(f: impl FnOnce(&mut Ipv6PassthroughStatus)) {
+    if let Ok(mut guard) = STATUS.lock() {
+        let status = guard.get_or_insert_with(Default::default);
+        f(status);
+    }
+}
+
+/// 从 /proc/net/if_inet6 中找出接口的全局地址（跳过 tentative / deprecated）
+pub fn parse_global_ipv6(content: &str, interface: &str) -> Option<Ipv6Addr> {
+    content.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 || fields[5] != interface || fields[3] != "00" {
+            return None;
+        }
+        let flags = u8::from_str_radix(fields[4], 16).ok()?;
+        if flags & 0x60 != 0 {
+            return None;
+        }
+        u128::from_str_radix(fields[0], 16).ok().map(Ipv6Addr::from)
+    })
+}
+
+/// 地址所在的 /64 前缀
+pub fn prefix64(addr: Ipv6Addr) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(addr) & (u128::MAX << 64))
+}
+
+/// 是否为全局单播地址
+fn is_global_unicast(addr: &Ipv6Addr) -> bool {
+    // 2000::/3
+    addr.segments()[0] & 0xe000 == 0x2000
+}
+
+/// RA 报文参数
+#[derive(Debug, Clone)]
+pub struct RaParams {
+    pub mac: [u8; 6],
+    pub prefix: Option<Ipv6Addr>,
+    /// 撤销前缀和默认路由（生存期为 0）
+    pub withdraw: bool,
+    pub mtu: Option<u32>,
+    pub dns_servers: Vec<Ipv6Addr>,
+    /// O 标志：通过 DHCPv6 获取其他配置
+    pub other_config: bool,
+    pub interval_secs: u32,
+}
+
+/// 构造 RA 报文（校验和由内核计算）
+pub fn build_ra(params: &RaParams) -> Vec<u8> {
+    let router_lifetime: u16 = if params.withdraw {
+        0
+    } else {
+        (params.interval_secs * 3).clamp(30, 9000) as u16
+    };
+    let mut buf = vec![ICMPV6_ROUTER_ADVERT, 0, 0, 0, 64];
+    buf.push(if params.other_config { 0x40 } else { 0 });
+    buf.extend_from_slice(&router_lifetime.to_be_bytes());
+    buf.extend_from_slice(&[0; 8]); // Reachable Time / Retrans Timer
+
+    // 源链路层地址
+    buf.extend_from_slice(&[1, 1]);
+    buf.extend_from_slice(&params.mac);
+
+    if let Some(mtu) = params.mtu {
+        buf.extend_from_slice(&[5, 1, 0, 0]);
+        buf.extend_from_slice(&mtu.to_be_bytes());
+    }
+
+    if let Some(prefix) = params.prefix {
+        let (valid, preferred) = if params.withdraw {
+            (0, 0)
+        } else {
+            (PREFIX_VALID_LIFETIME, PREFIX_PREFERRED_LIFETIME)
+        };
+        // L（在链路上）+ A（自动配置）
+        buf.extend_from_slice(&[3, 4, 64, 0xc0]);
+        buf.extend_from_slice(&valid.to_be_bytes());
+        buf.extend_from_slice(&preferred.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&prefix.octets());
+    }
+
+    if !params.dns_servers.is_empty() {
+        let lifetime: u32 = if params.withdraw { 0 } else { params.interval_secs * 3 };
+        buf.extend_from_slice(&[25, 1 + 2 * params.dns_servers.len() as u8, 0, 0]);
+        buf.extend_from_slice(&lifetime.to_be_bytes());
+        for dns in &params.dns_servers {
+            buf.extend_from_slice(&dns.octets());
+        }
+    }
+    buf
+}
+
+/// 读取 DHCPv6 选项
+fn dhcpv6_options(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut options = Vec::new();
+    let mut i = 4;
+    while i + 4 <= buf.len() {
+        let code = u16::from_be_bytes([buf[i], buf[i + 1]]);
+        let len = u16::from_be_bytes([buf[i + 2], buf[i + 3]]) as usize;
+        let Some(value) = buf.get(i + 4..i + 4 + len) else {
+            break;
+        };
+        options.push((code, value));
+        i += 4 + len;
+    }
+    options
+}
+
+/// 服务器 DUID（DUID-LL）
+fn server_duid(mac: &[u8; 6]) -> Vec<u8> {
+    let mut duid = vec![0, 3, 0, 1];
+    duid.extend_from_slice(mac);
+    duid
+}
+
+/// 构造无状态 DHCPv6 应答（只处理 INFORMATION-REQUEST）
+pub fn build_dhcpv6_reply(request: &[u8], mac: &[u8; 6], dns_servers: &[Ipv6Addr]) -> Option<Vec<u8>> {
+    if request.len() < 4 || request[0] != DHCPV6_INFORMATION_REQUEST {
+        return None;
+    }
+    let duid = server_duid(mac);
+    let options = dhcpv6_options(request);
+    // 指定了其他服务器
+    if options
+        .iter()
+        .any(|(code, value)| *code == DHCPV6_OPT_SERVERID && *value != duid.as_slice())
+    {
+        return None;
+    }
+
+    let mut reply = vec![DHCPV6_REPLY, request[1], request[2], request[3]];
+    let mut option = |code: u16, value: &[u8]| {
+        reply.extend_from_slice(&code.to_be_bytes());
+        reply.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        reply.extend_from_slice(value);
+    };
+    if let Some((_, client_id)) = options.iter().find(|(code, _)| *code == DHCPV6_OPT_CLIENTID) {
+        option(DHCPV6_OPT_CLIENTID, client_id);
+    }
+    option(DHCPV6_OPT_SERVERID, &duid);
+    let dns: Vec<u8> = dns_servers.iter().flat_map(|d| d.octets()).collect();
+    option(DHCPV6_OPT_DNS_SERVERS, &dns);
+    Some(reply)
+}
+
+/// 上游（数据连接）信息
+#[derive(Debug, Clone, PartialEq)]
+struct Upstream {
+    wan_interface: String,
+    prefix: Ipv6Addr,
+    dns_servers: Vec<Ipv6Addr>,
+    mtu: Option<u32>,
+}
+
+/// 执行 ip -6 命令
+fn run_ip6(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("ip")
+        .arg("-6")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute ip: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "ip -6 {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn write_sysctl(path: &str, value: &str) -> Result<(), String> {
+    std::fs::write(path, value).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+fn setsockopt(fd: i32, level: libc::c_int, name: libc::c_int, value: &[u8]) -> std::io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value.as_ptr() as *const libc::c_void,
+            value.len() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// 创建绑定到 LAN 接口的 ICMPv6 原始套接字（发送 RA、接收 RS）
+fn open_icmpv6_socket(interface: &str, ifindex: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET6,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_ICMPV6,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    // NDP 报文要求跳数限制为 255
+    let hops = 255i32.to_ne_bytes();
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_BINDTODEVICE, interface.as_bytes())?;
+    setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS, &hops)?;
+    setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, &hops)?;
+    setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_IF, &ifindex.to_ne_bytes())?;
+    let mreq = libc::ipv6_mreq {
+        ipv6mr_multiaddr: libc::in6_addr { s6_addr: ALL_ROUTERS.octets() },
+        ipv6mr_interface: ifindex,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_ADD_MEMBERSHIP,
+            &mreq as *const libc::ipv6_mreq as *const libc::c_void,
+            std::mem::size_of::<libc::ipv6_mreq>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    AsyncFd::new(owned)
+}
+
+/// 创建绑定到 LAN 接口的 DHCPv6 服务端套接字
+fn open_dhcpv6_socket(interface: &str, ifindex: u32) -> std::io::Result<UdpSocket> {
+    let socket = std::net::UdpSocket::bind((Ipv6Addr::UNSPECIFIED, DHCPV6_SERVER_PORT))?;
+    setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BINDTODEVICE, interface.as_bytes())?;
+    socket.join_multicast_v6(&ALL_DHCP_SERVERS, ifindex)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+/// 向 ff02::1 发送报文
+fn send_multicast(socket: &AsyncFd<OwnedFd>, ifindex: u32, packet: &[u8]) -> std::io::Result<()> {
+    let addr = libc::sockaddr_in6 {
+        sin6_family: libc::AF_INET6 as libc::sa_family_t,
+        sin6_port: 0,
+        sin6_flowinfo: 0,
+        sin6_addr: libc::in6_addr { s6_addr: ALL_NODES.octets() },
+        sin6_scope_id: ifindex,
+    };
+    let ret = unsafe {
+        libc::sendto(
+            socket.get_ref().as_raw_fd(),
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// 读取原始套接字中的一个报文
+async fn recv_icmpv6(socket: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let mut guard = socket.readable().await?;
+        let result = guard.try_io(|inner| {
+            let ret = unsafe {
+                libc::recv(
+                    inner.get_ref().as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if ret < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(ret as usize)
+            }
+        });
+        if let Ok(result) = result {
+            return result;
+        }
+    }
+}
+
+/// 读取接口 sysfs 属性
+fn read_interface_attr(interface: &str, attr: &str) -> Option<String> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/{}", interface, attr))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// 查询上游：数据连接激活且数据接口有全局 IPv6 地址时返回前缀
+async fn query_upstream(
+    conn: &Connection,
+    watchdog_context: Option<&str>,
+    config: &Ipv6LanConfig,
+) -> Result<Upstream, String> {
+    let ip = crate::dbus::get_data_ip_config(conn, watchdog_context)
+        .await
+        .map_err(|e| format!("Failed to get data context: {}", e))?;
+    if !ip.active || ip.interface.is_empty() {
+        return Err("Data connection is not active".to_string());
+    }
+    let if_inet6 = std::fs::read_to_string("/proc/net/if_inet6").unwrap_or_default();
+    let address = parse_global_ipv6(&if_inet6, &ip.interface)
+        .or_else(|| ip.ipv6.address.split('/').next()?.parse().ok().filter(is_global_unicast))
+        .ok_or_else(|| "No global IPv6 address on the data connection".to_string())?;
+
+    let dns_source = if config.dns_servers.is_empty() { &ip.ipv6.dns } else { &config.dns_servers };
+    Ok(Upstream {
+        prefix: prefix64(address),
+        dns_servers: dns_source.iter().filter_map(|d| d.parse().ok()).collect(),
+        mtu: read_interface_attr(&ip.interface, "mtu").and_then(|m| m.parse().ok()),
+        wan_interface: ip.interface,
+    })
+}
+
+/// LAN 上已解析的全局地址邻居
+fn lan_neighbors(interface: &str, prefix: Ipv6Addr) -> HashSet<Ipv6Addr> {
+    let output = Command::new("ip")
+        .args(["-6", "neigh", "show", "dev", interface])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).to_string())
+        .unwrap_or_default();
+    output
+        .lines()
+        .filter(|line| !line.contains("FAILED") && !line.contains("INCOMPLETE"))
+        .filter_map(|line| line.split_whitespace().next()?.parse::<Ipv6Addr>().ok())
+        .filter(|addr| prefix64(*addr) == prefix)
+        .collect()
+}
+
+/// relay 运行状态
+struct Relay {
+    lan_interface: String,
+    ifindex: u32,
+    mac: [u8; 6],
+    config: Ipv6LanConfig,
+    upstream: Option<Upstream>,
+    proxies: HashSet<Ipv6Addr>,
+    last_ra: Option<Instant>,
+}
+
+impl Relay {
+    fn ra_params(&self, upstream: &Upstream, withdraw: bool) -> RaParams {
+        RaParams {
+            mac: self.mac,
+            prefix: Some(upstream.prefix),
+            withdraw,
+            mtu: upstream.mtu,
+            dns_servers: upstream.dns_servers.clone(),
+            other_config: self.config.dhcpv6,
+            interval_secs: self.config.ra_interval_secs,
+        }
+    }
+
+    fn send_ra(&mut self, socket: &AsyncFd<OwnedFd>) {
+        let Some(upstream) = &self.upstream else {
+            return;
+        };
+        let packet = build_ra(&self.ra_params(upstream, false));
+        match send_multicast(socket, self.ifindex, &packet) {
+            Ok(_) => {
+                self.last_ra = Some(Instant::now());
+                update_status(|s| {
+                    s.ra_sent += 1;
+                    s.last_ra_at = Some(chrono::Utc::now().to_rfc3339());
+                });
+            }
+            Err(e) => warn!(error = %e, "IPv6: failed to send RA"),
+        }
+    }
+
+    /// 撤销当前前缀
+    fn withdraw(&mut self, socket: &AsyncFd<OwnedFd>) {
+        let Some(upstream) = self.upstream.take() else {
+            return;
+        };
+        let packet = build_ra(&self.ra_params(&upstream, true));
+        let _ = send_multicast(socket, self.ifindex, &packet);
+        let prefix = format!("{}/64", upstream.prefix);
+        let _ = run_ip6(&["route", "del", &prefix, "dev", &self.lan_interface, "metric", PREFIX_ROUTE_METRIC]);
+        for addr in self.proxies.drain() {
+            let _ = run_ip6(&["neigh", "del", "proxy", &addr.to_string(), "dev", &upstream.wan_interface]);
+        }
+        info!(prefix = %prefix, "IPv6: prefix withdrawn from LAN");
+    }
+
+    /// 启用新前缀
+    fn activate(&mut self, upstream: Upstream, socket: &AsyncFd<OwnedFd>) -> Result<(), String> {
+        write_sysctl("/proc/sys/net/ipv6/conf/all/forwarding", "1")?;
+        // 开启转发后内核默认忽略 RA，WAN 侧需要继续接受运营商 RA
+        write_sysctl(
+            &format!("/proc/sys/net/ipv6/conf/{}/accept_ra", upstream.wan_interface),
+            "2",
+        )?;
+        if self.config.ndp_proxy {
+            write_sysctl(
+                &format!("/proc/sys/net/ipv6/conf/{}/proxy_ndp", upstream.wan_interface),
+                "1",
+            )?;
+        }
+        let prefix = format!("{}/64", upstream.prefix);
+        run_ip6(&["route", "replace", &prefix, "dev", &self.lan_interface, "metric", PREFIX_ROUTE_METRIC])?;
+        info!(prefix = %prefix, wan = %upstream.wan_interface, "IPv6: prefix relayed to LAN");
+        self.upstream = Some(upstream);
+        self.send_ra(socket);
+        Ok(())
+    }
+
+    /// 同步 WAN 接口上的 NDP 代理条目
+    fn sync_proxies(&mut self) {
+        let Some(upstream) = &self.upstream else {
+            return;
+        };
+        let current = lan_neighbors(&self.lan_interface, upstream.prefix);
+        for addr in current.difference(&self.proxies) {
+            if let Err(e) = run_ip6(&["neigh", "replace", "proxy", &addr.to_string(), "dev", &upstream.wan_interface]) {
+                warn!(error = %e, "IPv6: failed to add NDP proxy entry");
+            }
+        }
+        for addr in self.proxies.difference(&current) {
+            let _ = run_ip6(&["neigh", "del", "proxy", &addr.to_string(), "dev", &upstream.wan_interface]);
+        }
+        self.proxies = current;
+    }
+
+    /// 检查上游变化
+    async fn refresh(&mut self, conn: &Connection, watchdog_context: Option<&str>, socket: &AsyncFd<OwnedFd>) {
+        let result = query_upstream(conn, watchdog_context, &self.config).await;
+        let error = match result {
+            Ok(upstream) if self.upstream.as_ref() == Some(&upstream) => None,
+            Ok(upstream) => {
+                self.withdraw(socket);
+                self.activate(upstream, socket).err()
+            }
+            Err(e) => {
+                self.withdraw(socket);
+                Some(e)
+            }
+        };
+        if let Some(e) = &error {
+            warn!(error = %e, "IPv6: relay inactive");
+        }
+
+        if self.config.ndp_proxy {
+            self.sync_proxies();
+        }
+        let due = self
+            .last_ra
+            .is_none_or(|at| at.elapsed() >= Duration::from_secs(self.config.ra_interval_secs as u64));
+        if due {
+            self.send_ra(socket);
+        }
+
+        let upstream = self.upstream.clone();
+        let proxies = self.proxies.len();
+        update_status(|s| {
+            s.active = upstream.is_some();
+            s.wan_interface = upstream.as_ref().map(|u| u.wan_interface.clone()).unwrap_or_default();
+            s.prefix = upstream.as_ref().map(|u| format!("{}/64", u.prefix));
+            s.dns_servers = upstream
+                .as_ref()
+                .map(|u| u.dns_servers.iter().map(|d| d.to_string()).collect())
+                .unwrap_or_default();
+            s.mtu = upstream.as_ref().and_then(|u| u.mtu);
+            s.ndp_proxy_entries = proxies;
+            s.error = error;
+        });
+    }
+}
+
+/// 打开套接字并运行 relay，收到 [`reload`] 时撤销前缀并返回
+async fn run_relay(conn: &Connection, config_manager: &ConfigManager, config: &LanConfig) -> Result<(), String> {
+    let ifindex: u32 = read_interface_attr(&config.interface, "ifindex")
+        .and_then(|i| i.parse().ok())
+        .ok_or_else(|| format!("Interface {} not found", config.interface))?;
+    let mac = read_interface_attr(&config.interface, "address")
+        .and_then(|m| crate::lan::parse_mac(&m))
+        .ok_or_else(|| format!("Failed to read MAC of {}", config.interface))?;
+    let icmp = open_icmpv6_socket(&config.interface, ifindex)
+        .map_err(|e| format!("Failed to open ICMPv6 socket: {}", e))?;
+    let dhcp = if config.ipv6.dhcpv6 {
+        Some(open_dhcpv6_socket(&config.interface, ifindex).map_err(|e| format!("Failed to open DHCPv6 socket: {}", e))?)
+    } else {
+        None
+    };
+
+    let mut relay = Relay {
+        lan_interface: config.interface.clone(),
+        ifindex,
+        mac,
+        config: config.ipv6.clone(),
+        upstream: None,
+        proxies: HashSet::new(),
+        last_ra: None,
+    };
+    let mut last_rs_reply: Option<Instant> = None;
+    let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+    let mut icmp_buf = [0u8; 1500];
+    let mut dhcp_buf = [0u8; 1500];
+    info!(interface = %config.interface, dhcpv6 = config.ipv6.dhcpv6, "IPv6 relay started");
+
+    loop {
+        let dhcp_recv = async {
+            match &dhcp {
+                Some(socket) => socket.recv_from(&mut dhcp_buf).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = RELOAD.notified() => break,
+            _ = ticker.tick() => {
+                relay.refresh(conn, config_manager.get_watchdog_context().as_deref(), &icmp).await
+            }
+            received = recv_icmpv6(&icmp, &mut icmp_buf) => {
+                if let Ok(len) = received {
+                    if len > 0 && icmp_buf[0] == ICMPV6_ROUTER_SOLICIT {
+                        update_status(|s| s.rs_received += 1);
+                        if last_rs_reply.is_none_or(|at| at.elapsed() >= MIN_RS_REPLY_INTERVAL) {
+                            last_rs_reply = Some(Instant::now());
+                            relay.send_ra(&icmp);
+                        }
+                    }
+                }
+            }
+            received = dhcp_recv => {
+                if let (Ok((len, SocketAddr::V6(peer))), Some(socket), Some(upstream)) = (received, &dhcp, &relay.upstream) {
+                    if let Some(reply) = build_dhcpv6_reply(&dhcp_buf[..len], &relay.mac, &upstream.dns_servers) {
+                        if socket.send_to(&reply, peer).await.is_ok() {
+                            update_status(|s| s.dhcpv6_replies += 1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    relay.withdraw(&icmp);
+    Ok(())
+}
+
+/// IPv6 直通主循环（模式为 disabled 时等待 [`reload`]）
+pub async fn run(conn: Arc<Connection>, config_manager: Arc<ConfigManager>) {
+    loop {
+        let config = config_manager.get_lan();
+        update_status(|s| {
+            *s = Ipv6PassthroughStatus {
+                mode: if config.ipv6.mode == Ipv6LanMode::Relay { "relay" } else { "disabled" }.to_string(),
+                lan_interface: config.interface.clone(),
+                ..Default::default()
+            }
+        });
+        if config.ipv6.mode == Ipv6LanMode::Disabled {
+            RELOAD.notified().await;
+            continue;
+        }
+
+        if let Err(e) = run_relay(&conn, &config_manager, &config).await {
+            // USB 未枚举时接口可能不存在，稍后重试
+            warn!(error = %e, "IPv6 relay failed, retrying");
+            update_status(|s| s.error = Some(e));
+            tokio::select! {
+                _ = RELOAD.notified() => {}
+                _ = tokio::time::sleep(Duration::from_secs(10)) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_global_ipv6() {
+        let content = "fe800000000000000000000000000001 05 40 20 80 sipa_eth0\n\
+                       24098a00123456780000000000000001 05 40 00 c0 sipa_eth0\n\
+                       24098a00123456780000000000000002 05 40 00 80 sipa_eth0\n\
+                       20010db8000000000000000000000001 06 40 00 80 usb0\n";
+        assert_eq!(
+            parse_global_ipv6(content, "sipa_eth0"),
+            Some("2409:8a00:1234:5678::2".parse().unwrap())
+        );
+        assert_eq!(parse_global_ipv6(content, "sipa_eth1"), None);
+        assert_eq!(
+            prefix64("2409:8a00:1234:5678:abcd::1".parse().unwrap()),
+            "2409:8a00:1234:5678::".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_ra() {
+        let mut params = RaParams {
+            mac: [0xcc, 0xe8, 0xac, 0xc0, 0, 0],
+            prefix: Some("2409:8a00:1234:5678::".parse().unwrap()),
+            withdraw: false,
+            mtu: Some(1400),
+            dns_servers: vec!["2409:8888::8".parse().unwrap()],
+            other_config: true,
+            interval_secs: 60,
+        };
+        let ra = build_ra(&params);
+        // 头部 16 + 源地址 8 + MTU 8 + 前缀 32 + RDNSS 24
+        assert_eq!(ra.len(), 88);
+        assert_eq!((ra[0], ra[4], ra[5]), (ICMPV6_ROUTER_ADVERT, 64, 0x40));
+        assert_eq!(u16::from_be_bytes([ra[6], ra[7]]), 180);
+        assert_eq!(&ra[32..36], &[3, 4, 64, 0xc0]);
+        assert_eq!(&ra[48..56], &[0x24, 0x09, 0x8a, 0x00, 0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(&ra[64..66], &[25, 3]);
+
+        params.withdraw = true;
+        let ra = build_ra(&params);
+        assert_eq!(u16::from_be_bytes([ra[6], ra[7]]), 0);
+        assert_eq!(&ra[36..44], &[0; 8]);
+    }
+
+    #[test]
+    fn test_dhcpv6_reply() {
+        let mac = [0xcc, 0xe8, 0xac, 0xc0, 0, 0];
+        let dns: Vec<Ipv6Addr> = vec!["2409:8888::8".parse().unwrap()];
+        let request = [DHCPV6_INFORMATION_REQUEST, 1, 2, 3, 0, 1, 0, 4, 0xde, 0xad, 0xbe, 0xef, 0, 6, 0, 2, 0, 23];
+        let reply = build_dhcpv6_reply(&request, &mac, &dns).unwrap();
+        assert_eq!(&reply[..4], &[DHCPV6_REPLY, 1, 2, 3]);
+        let options = dhcpv6_options(&reply);
+        assert_eq!(options[0], (DHCPV6_OPT_CLIENTID, &[0xde, 0xad, 0xbe, 0xef][..]));
+        assert_eq!(options[1].1, server_duid(&mac).as_slice());
+        assert_eq!(options[2], (DHCPV6_OPT_DNS_SERVERS, &dns[0].octets()[..]));
+
+        // 发给其他服务器的请求不应答
+        let other = [DHCPV6_INFORMATION_REQUEST, 1, 2, 3, 0, 2, 0, 2, 0, 1];
+        assert!(build_dhcpv6_reply(&other, &mac, &dns).is_none());
+        // SOLICIT（有状态地址分配）不处理
+        assert!(build_dhcpv6_reply(&[1, 1, 2, 3], &mac, &dns).is_none());
+    }
+}
@@ -0,0 +1,328 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-17 15:12:08
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-17 15:12:08
+ * @FilePath: /udx710-backend/backend/src/lan.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! USB 网卡（LAN）配置模块
+//!
+//! 按 [`LanConfig`] 配置 LAN 接口地址和 MAC。启用内置 DHCP 服务时关闭
+//! connman gadget tethering，由本模块开启 IPv4 转发，并在 nat 表中维护
+//! UDX_POSTROUTING 链对 LAN 子网做 MASQUERADE；关闭时沿用 connman tethering。
+
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::process::Command;
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::config::LanConfig;
+use crate::db::DhcpLeaseRecord;
+use crate::iptables::run_iptables;
+
+/// 托管的 MASQUERADE 链（nat 表）
+pub const POSTROUTING_CHAIN: &str = "UDX_POSTROUTING";
+
+/// ARP 表路径
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// 租约及在线状态
+#[derive(Debug, Clone, Serialize)]
+pub struct LanLease {
+    pub mac: String,
+    pub ip: String,
+    pub hostname: String,
+    pub expires_at: String,
+    /// 剩余租期（秒），已过期为 0
+    pub remaining_secs: i64,
+    /// ARP 表中存在该 MAC 的有效条目
+    pub connected: bool,
+}
+
+/// 解析 MAC 地址（冒号或短横线分隔）
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let parts: Vec<&str> = mac.split([':', '-']).collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut bytes = [0u8; 6];
+    for (byte, part) in bytes.iter_mut().zip(parts) {
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// 格式化 MAC 地址（小写，冒号分隔）
+pub fn format_mac(mac: &[u8]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// 前缀长度对应的子网掩码
+pub fn netmask(prefix_len: u8) -> Ipv4Addr {
+    let bits = if prefix_len == 0 { 0 } else { u32::MAX << (32 - prefix_len.min(32) as u32) };
+    Ipv4Addr::from(bits)
+}
+
+/// 网络地址和广播地址
+pub fn subnet(ip: Ipv4Addr, prefix_len: u8) -> (Ipv4Addr, Ipv4Addr) {
+    let mask = u32::from(netmask(prefix_len));
+    let network = u32::from(ip) & mask;
+    (Ipv4Addr::from(network), Ipv4Addr::from(network | !mask))
+}
+
+/// 校验 LAN 配置
+pub fn validate(config: &LanConfig) -> Result<(), String> {
+    if !crate::firewall::valid_interface(&config.interface) || config.interface.contains('+') {
+        return Err(format!("Invalid interface: {:?}", config.interface));
+    }
+    if !(8..=30).contains(&config.prefix_len) {
+        return Err(format!("Invalid prefix length: {}", config.prefix_len));
+    }
+    let ip: Ipv4Addr = config
+        .ip_address
+        .parse()
+        .map_err(|_| format!("Invalid IP address: {}", config.ip_address))?;
+    let (network, broadcast) = subnet(ip, config.prefix_len);
+    if ip == network || ip == broadcast || ip.is_loopback() || ip.is_multicast() || ip.is_unspecified() {
+        return Err(format!("Invalid IP address: {}", config.ip_address));
+    }
+    match parse_mac(&config.mac_address) {
+        // 组播位置位或全零的地址不能作为网卡地址
+        Some(mac) if mac[0] & 0x01 == 0 && mac != [0; 6] => {}
+        _ => return Err(format!("Invalid MAC address: {}", config.mac_address)),
+    }
+
+    let start: Ipv4Addr = config
+        .dhcp_start
+        .parse()
+        .map_err(|_| format!("Invalid DHCP start: {}", config.dhcp_start))?;
+    let end: Ipv4Addr = config
+        .dhcp_end
+        .parse()
+        .map_err(|_| format!("Invalid DHCP end: {}", config.dhcp_end))?;
+    for addr in [start, end] {
+        if subnet(addr, config.prefix_len).0 != network || addr == network || addr == broadcast {
+            return Err(format!("DHCP range {} is outside {}/{}", addr, network, config.prefix_len));
+        }
+    }
+    if u32::from(start) > u32::from(end) {
+        return Err("DHCP start must not be after DHCP end".to_string());
+    }
+    if config.lease_time_secs < 120 {
+        return Err("Lease time must be at least 120 seconds".to_string());
+    }
+    for dns in &config.dns_servers {
+        if dns.parse::<Ipv4Addr>().is_err() {
+            return Err(format!("Invalid DNS server: {}", dns));
+        }
+    }
+    if !(10..=1800).contains(&config.ipv6.ra_interval_secs) {
+        return Err("RA interval must be between 10 and 1800 seconds".to_string());
+    }
+    for dns in &config.ipv6.dns_servers {
+        if dns.parse::<std::net::Ipv6Addr>().is_err() {
+            return Err(format!("Invalid IPv6 DNS server: {}", dns));
+        }
+    }
+    Ok(())
+}
+
+/// 执行 ip 命令
+fn run_ip(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute ip: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "ip {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// nat 表中执行 iptables 命令
+fn run_nat(args: &[&str]) -> Result<String, String> {
+    let mut full = vec!["-t", "nat"];
+    full.extend_from_slice(args);
+    run_iptables(false, &full)
+}
+
+/// 下发 LAN 子网的 MASQUERADE 链
+fn apply_masquerade(config: &LanConfig, wan_interface: &str) -> Result<(), String> {
+    let ip: Ipv4Addr = config.ip_address.parse().map_err(|_| "Invalid IP address".to_string())?;
+    let source = format!("{}/{}", subnet(ip, config.prefix_len).0, config.prefix_len);
+
+    let _ = run_nat(&["-N", POSTROUTING_CHAIN]);
+    run_nat(&["-F", POSTROUTING_CHAIN])?;
+    run_nat(&["-A", POSTROUTING_CHAIN, "-s", &source, "-o", wan_interface, "-j", "MASQUERADE"])?;
+    if run_nat(&["-C", "POSTROUTING", "-j", POSTROUTING_CHAIN]).is_err() {
+        run_nat(&["-A", "POSTROUTING", "-j", POSTROUTING_CHAIN])?;
+    }
+    Ok(())
+}
+
+/// 卸载 MASQUERADE 链（交还给 connman tethering）
+fn remove_masquerade() {
+    while run_nat(&["-D", "POSTROUTING", "-j", POSTROUTING_CHAIN]).is_ok() {}
+    let _ = run_nat(&["-F", POSTROUTING_CHAIN]);
+    let _ = run_nat(&["-X", POSTROUTING_CHAIN]);
+}
+
+/// 配置 LAN 接口（同步执行，USB 模式切换后也会调用）
+///
+/// # Arguments
+/// * `config` - LAN 配置
+/// * `wan_interface` - WAN 接口（MASQUERADE 出接口）
+pub fn apply_blocking(config: &LanConfig, wan_interface: &str) -> Result<(), String> {
+    validate(config)?;
+    let iface = config.interface.as_str();
+
+    if config.dhcp_enabled {
+        // connman tethering 自带 DHCP 服务，与内置服务冲突
+        let _ = Command::new("connmanctl").args(["tether", "gadget", "off"]).output();
+    }
+
+    // 修改 MAC 需要先关闭接口，MAC 未变化时不重启链路
+    let current_mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", iface)).unwrap_or_default();
+    let mac_changed = parse_mac(current_mac.trim()) != parse_mac(&config.mac_address);
+    if mac_changed {
+        run_ip(&["link", "set", "dev", iface, "down"])?;
+        if let Err(e) = run_ip(&["link", "set", "dev", iface, "address", &config.mac_address]) {
+            warn!(error = %e, "LAN: failed to set MAC address");
+        }
+    }
+    // 只清除 IPv4 地址，保留链路本地地址（RA 以它为源地址）
+    run_ip(&["-4", "addr", "flush", "dev", iface])?;
+    run_ip(&[
+        "addr",
+        "add",
+        &format!("{}/{}", config.ip_address, config.prefix_len),
+        "dev",
+        iface,
+    ])?;
+    run_ip(&["link", "set", "dev", iface, "up"])?;
+
+    if config.dhcp_enabled {
+        std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")
+            .map_err(|e| format!("Failed to enable IPv4 forwarding: {}", e))?;
+        apply_masquerade(config, wan_interface)?;
+    } else {
+        remove_masquerade();
+    }
+
+    info!(
+        interface = %iface,
+        address = %format!("{}/{}", config.ip_address, config.prefix_len),
+        dhcp = config.dhcp_enabled,
+        "LAN configured"
+    );
+    Ok(())
+}
+
+/// 配置 LAN 接口并通知 DHCP 服务和 IPv6 直通重新加载
+pub async fn apply(config: LanConfig, wan_interface: String) -> Result<(), String> {
+    let result = task::spawn_blocking(move || apply_blocking(&config, &wan_interface))
+        .await
+        .map_err(|e| format!("Task execution failed: {}", e))?;
+    crate::dhcp::reload();
+    crate::ipv6_lan::reload();
+    result
+}
+
+/// 读取 ARP 表中指定接口的有效条目
+///
+/// # Returns
+/// (IP, MAC) 列表，MAC 为小写
+pub fn read_arp_table(interface: &str) -> Vec<(String, String)> {
+    std::fs::read_to_string(ARP_TABLE_PATH)
+        .map(|content| parse_arp_table(&content, interface))
+        .unwrap_or_default()
+}
+
+fn parse_arp_table(content: &str, interface: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 || fields[5] != interface {
+                return None;
+            }
+            // Flags 0x2 = ATF_COM（已解析）
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+            (flags & 0x2 != 0).then(|| (fields[0].to_string(), fields[3].to_lowercase()))
+        })
+        .collect()
+}
+
+/// 合并租约和 ARP 表
+pub fn leases_with_status(records: Vec<DhcpLeaseRecord>, arp: &[(String, String)], now: i64) -> Vec<LanLease> {
+    records
+        .into_iter()
+        .map(|record| LanLease {
+            connected: arp.iter().any(|(_, mac)| *mac == record.mac),
+            remaining_secs: (record.expires_at - now).max(0),
+            expires_at: chrono::DateTime::from_timestamp(record.expires_at, 0)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            mac: record.mac,
+            ip: record.ip,
+            hostname: record.hostname,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut config = LanConfig::default();
+        assert!(validate(&config).is_ok());
+
+        config.ip_address = "10.10.0.1".to_string();
+        assert!(validate(&config).unwrap_err().contains("outside"));
+        config.dhcp_start = "10.10.0.50".to_string();
+        config.dhcp_end = "10.10.0.99".to_string();
+        assert!(validate(&config).is_ok());
+
+        config.mac_address = "01:00:5e:00:00:01".to_string();
+        assert!(validate(&config).is_err());
+        config.mac_address = "cc-e8-ac-c0-00-01".to_string();
+        assert!(validate(&config).is_ok());
+
+        config.prefix_len = 31;
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_subnet_and_arp() {
+        assert_eq!(netmask(24), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(
+            subnet(Ipv4Addr::new(192, 168, 66, 1), 23),
+            (Ipv4Addr::new(192, 168, 66, 0), Ipv4Addr::new(192, 168, 67, 255))
+        );
+        assert_eq!(format_mac(&parse_mac("CC:E8:AC:C0:00:00").unwrap()), "cc:e8:ac:c0:00:00");
+
+        let arp = "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   192.168.66.100   0x1         0x2         AA:BB:CC:DD:EE:01     *        usb0\n\
+                   192.168.66.101   0x1         0x0         00:00:00:00:00:00     *        usb0\n\
+                   10.0.0.1         0x1         0x2         AA:BB:CC:DD:EE:02     *        sipa_eth0\n";
+        assert_eq!(
+            parse_arp_table(arp, "usb0"),
+            vec![("192.168.66.100".to_string(), "aa:bb:cc:dd:ee:01".to_string())]
+        );
+    }
+}
@@ -36,25 +36,52 @@ use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 use zbus::Connection;
 
+mod alignment;
+mod apn_db;
+mod arfcn;
+mod band_lock;
+mod carrier_aggregation;
+mod cell_optimizer;
+mod cell_tower;
+mod clients;
 mod config;
+mod data_session;
 mod db;
 mod dbus;
+mod ddns;
+mod dhcp;
+mod firewall;
+mod geolocation;
 mod handlers;
+mod ip_info;
 mod iptables;
+mod ipv6_lan;
+mod lan;
 mod models;
 mod ota;
+mod probe;
+mod safe_lock;
 mod serial;
+mod shaping;
 mod sms_listener;
 mod state;
+mod survey;
+mod thermal;
 mod usb_switch;
 mod utils;
+mod watchdog;
 mod webhook;
 
+use cell_optimizer::CellOptimizer;
 use config::{ConfigManager, get_default_config_path};
+use data_session::DataSessionTracker;
 use dbus::init_data_connection;
 use handlers::*;
 use db::Database;
+use firewall::FirewallManager;
+use safe_lock::SafeLockManager;
 use state::AppState;
+use thermal::ThermalMonitor;
 use webhook::WebhookSender;
 
 /// 获取二进制文件同级目录下的 www 目录路径
@@ -172,6 +199,58 @@ async fn main() -> Result<()> {
     info!(path = ?config_path, "Loading config");
     let config_manager = Arc::new(ConfigManager::new(config_path));
     
+    // 下发托管防火墙规则
+    let firewall_manager = Arc::new(FirewallManager::new());
+    if let Err(e) = firewall_manager.apply(config_manager.get_firewall()).await {
+        tracing::warn!(error = %e, "Failed to apply firewall rules");
+    }
+    
+    // 按 LAN 配置设置 USB 网卡并启动内置 DHCP 服务
+    if let Err(e) = lan::apply(config_manager.get_lan(), config_manager.get_firewall().wan_interface).await {
+        tracing::warn!(error = %e, "Failed to configure LAN interface");
+    }
+    {
+        let conn_clone = Arc::clone(&dbus_conn);
+        let config_clone = Arc::clone(&config_manager);
+        let db_clone = Arc::clone(&app_db);
+        tokio::spawn(async move {
+            dhcp::run_dhcp_server(conn_clone, config_clone, db_clone).await;
+        });
+    }
+    
+    // 启动 LAN 侧 IPv6 直通（RA / 无状态 DHCPv6）
+    {
+        let conn_clone = Arc::clone(&dbus_conn);
+        let config_clone = Arc::clone(&config_manager);
+        tokio::spawn(async move {
+            ipv6_lan::run(conn_clone, config_clone).await;
+        });
+    }
+    
+    // 下发 LAN 限速
+    if let Err(e) = shaping::apply(config_manager.get_shaping(), config_manager.get_lan().interface).await {
+        tracing::warn!(error = %e, "Failed to apply traffic shaping");
+    }
+    
+    // 启动客户端发现与流量统计
+    {
+        let config_clone = Arc::clone(&config_manager);
+        let db_clone = Arc::clone(&app_db);
+        tokio::spawn(async move {
+            clients::run(config_clone, db_clone).await;
+        });
+    }
+    
+    // 启动 DDNS 更新
+    {
+        let conn_clone = Arc::clone(&dbus_conn);
+        let config_clone = Arc::clone(&config_manager);
+        let db_clone = Arc::clone(&app_db);
+        tokio::spawn(async move {
+            ddns::run(conn_clone, config_clone, db_clone).await;
+        });
+    }
+    
     // 初始化 Webhook 发送器
     let webhook_sender = Arc::new(WebhookSender::new(Arc::clone(&config_manager)));
     
@@ -198,25 +277,89 @@ async fn main() -> Result<()> {
     // 自动初始化数据连接
     {
         let conn_clone = Arc::clone(&dbus_conn);
+        let watchdog_context = config_manager.get_watchdog_context();
         tokio::spawn(async move {
             // 等待 2 秒让 modem 完全初始化
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-            let result = init_data_connection(&conn_clone).await;
+            let result = init_data_connection(&conn_clone, watchdog_context.as_deref()).await;
             tracing::info!(result = %result, "Auto-connect completed");
         });
     }
     
-    // 启动数据连接 Watchdog（每 15 秒检查一次）
+    // 查询模组频段能力（AT+SPLBAND=4/5），成功时缓存，失败时由首次使用时重试
+    {
+        let conn_clone = Arc::clone(&dbus_conn);
+        tokio::spawn(async move {
+            // 等待 modem 初始化完成
+            tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+            band_lock::discover_band_capabilities(&conn_clone).await;
+        });
+    }
+    
+    // 安全锁定管理器（锁定后观察注册和数据连接，失败自动恢复）
+    let safe_lock = Arc::new(SafeLockManager::new(Arc::clone(&app_db), Arc::clone(&config_manager)));
+
+    {
+        let safe_lock_clone = Arc::clone(&safe_lock);
+        let conn_clone = Arc::clone(&dbus_conn);
+        tokio::spawn(async move {
+            // 等待 modem 初始化完成后恢复观察期内被中断的锁定
+            tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+            safe_lock_clone.recover_interrupted(&conn_clone).await;
+        });
+    }
+
+    // 最佳小区优化器（按需通过 API 启动任务）
+    let cell_optimizer = Arc::new(CellOptimizer::new(Arc::clone(&config_manager), Arc::clone(&safe_lock)));
+
+    // 数据会话追踪器（由 watchdog 采样，API 主动断开前标记结束原因）
+    let session_tracker = Arc::new(DataSessionTracker::new(Arc::clone(&app_db)));
+
+    // 启动数据连接 Watchdog（按配置间隔检查并逐级恢复，同时记录数据会话）
     {
         let conn_clone = Arc::clone(&dbus_conn);
+        let db_clone = Arc::clone(&app_db);
+        let config_clone = Arc::clone(&config_manager);
+        let firewall_clone = Arc::clone(&firewall_manager);
+        let optimizer_clone = Arc::clone(&cell_optimizer);
+        let safe_lock_clone = Arc::clone(&safe_lock);
+        let sessions_clone = Arc::clone(&session_tracker);
         tokio::spawn(async move {
             // 初始延迟 5 秒，等待系统稳定
             tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            tracing::info!(interval = 15, "Watchdog started");
-            dbus::data_connection_watchdog(conn_clone, 5).await;
+            tracing::info!(interval = config_clone.get_watchdog().interval_secs, "Watchdog started");
+            dbus::data_connection_watchdog(
+                conn_clone,
+                db_clone,
+                config_clone,
+                firewall_clone,
+                optimizer_clone,
+                safe_lock_clone,
+                sessions_clone,
+            )
+            .await;
+        });
+    }
+
+    // 启动温度监控（采集历史、过温保护）
+    let thermal_monitor = Arc::new(ThermalMonitor::new(
+        Arc::clone(&app_db),
+        Arc::clone(&config_manager),
+        Arc::clone(&webhook_sender),
+    ));
+    {
+        let monitor_clone = Arc::clone(&thermal_monitor);
+        let conn_clone = Arc::clone(&dbus_conn);
+        tokio::spawn(async move {
+            monitor_clone.run(conn_clone).await;
         });
     }
 
+    // 站点勘测：上次服务退出时未结束的会话标记为已结束
+    if let Err(e) = app_db.close_open_survey_sessions() {
+        warn!(error = %e, "Failed to close open survey sessions");
+    }
+
     // CORS 配置：允许前端开发服务器跨域访问
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -229,6 +372,11 @@ async fn main() -> Result<()> {
         app_db,
         config_manager,
         webhook_sender,
+        thermal_monitor,
+        cell_optimizer,
+        safe_lock,
+        firewall_manager,
+        session_tracker,
     );
 
     // Build routes - 使用统一的 AppState
@@ -253,20 +401,56 @@ async fn main() -> Result<()> {
         .route("/api/network/register-auto", post(register_operator_auto_handler).options(options_handler))
         // ========== 小区信息接口 ==========
         .route("/api/cells", get(get_cells).options(options_handler))
+        .route("/api/arfcn/convert", get(convert_arfcn_handler).options(options_handler))
         .route("/api/location/cell-info", get(get_cell_location_info).options(options_handler))
+        .route("/api/location/estimate", get(get_location_estimate_handler).options(options_handler))
+        .route("/api/location/online", get(get_online_location_handler).options(options_handler))
+        .route("/api/location/providers", get(get_geolocation_config_handler).post(set_geolocation_config_handler).options(options_handler))
+        .route("/api/location/towers", get(get_cell_towers_handler).options(options_handler))
+        .route("/api/location/towers/import", post(import_cell_towers_handler).options(options_handler))
+        
+        // ========== 站点勘测接口 ==========
+        .route("/api/survey", get(get_survey_handler).options(options_handler))
+        .route("/api/survey/start", post(start_survey_handler).options(options_handler))
+        .route("/api/survey/stop", post(stop_survey_handler).options(options_handler))
+        .route("/api/survey/tag", post(tag_survey_handler).options(options_handler))
+        .route("/api/survey/{id}", get(get_survey_detail_handler).delete(delete_survey_handler).options(options_handler))
+        .route("/api/survey/{id}/export", get(export_survey_handler).options(options_handler))
+        // ========== 天线对准接口 ==========
+        .route("/api/alignment", get(get_alignment_handler).options(options_handler))
+        .route("/api/alignment/ws", get(alignment_ws_handler))
         // ========== QoS 接口 ==========
         .route("/api/qos", get(get_qos_info).options(options_handler))
         // ========== 数据连接接口 ==========
         .route("/api/data", get(get_data_status).post(set_data_status).options(options_handler))
+        .route("/api/data/ip", get(get_data_ip_handler).options(options_handler))
+        .route("/api/data/sessions", get(get_data_sessions_handler).options(options_handler))
+        .route("/api/data/outages", get(get_data_outages_handler).options(options_handler))
+        .route("/api/data/availability", get(get_data_availability_handler).options(options_handler))
         .route("/api/roaming", get(get_roaming_status_handler).post(set_roaming_status_handler).options(options_handler))
         .route("/api/airplane-mode", get(get_airplane_mode_handler).post(set_airplane_mode_handler).options(options_handler))
         // ========== 射频模式接口 ==========
         .route("/api/radio-mode", get(get_radio_mode_handler).post(set_radio_mode_handler).options(options_handler))
         .route("/api/band-lock", get(get_band_lock_handler).post(set_band_lock_handler).options(options_handler))
+        .route("/api/band-lock/capabilities", get(get_band_capabilities_handler).options(options_handler))
+        .route("/api/band-lock/profiles", get(get_lock_profiles_handler).post(save_lock_profile_handler).options(options_handler))
+        .route("/api/band-lock/profiles/{name}", axum::routing::delete(delete_lock_profile_handler).options(options_handler))
+        .route("/api/band-lock/profiles/{name}/apply", post(apply_lock_profile_handler).options(options_handler))
         .route("/api/cell-lock", get(get_cell_lock_handler).post(set_cell_lock_handler).options(options_handler))
         .route("/api/cell-lock/unlock-all", post(unlock_all_cells_handler).options(options_handler))
+        .route("/api/lock/safe", get(get_safe_lock_handler).options(options_handler))
+        .route("/api/lock/history", get(get_lock_history_handler).options(options_handler))
+        .route("/api/cell-optimizer", get(get_cell_optimizer_handler).options(options_handler))
+        .route("/api/cell-optimizer/start", post(start_cell_optimizer_handler).options(options_handler))
+        .route("/api/cell-optimizer/cancel", post(cancel_cell_optimizer_handler).options(options_handler))
         // ========== APN 管理接口 ==========
         .route("/api/apn", get(get_apn_list_handler).post(set_apn_handler).options(options_handler))
+        .route("/api/apn/database", get(get_apn_database_handler).options(options_handler))
+        .route("/api/apn/config", get(get_apn_config_handler).post(set_apn_config_handler).options(options_handler))
+        .route("/api/apn/provision", post(provision_apn_handler).options(options_handler))
+        .route("/api/apn/contexts", post(add_apn_context_handler).options(options_handler))
+        .route("/api/apn/contexts/{id}", axum::routing::delete(remove_apn_context_handler).options(options_handler))
+        .route("/api/apn/watchdog", post(set_watchdog_context_handler).options(options_handler))
         // ========== 电话功能接口 ==========
         .route("/api/calls", get(get_calls_handler).options(options_handler))
         .route("/api/call/dial", post(dial_call_handler).options(options_handler))
@@ -294,6 +478,30 @@ async fn main() -> Result<()> {
         // ========== 系统接口 ==========
         .route("/api/stats", get(get_system_stats).options(options_handler))
         .route("/api/stats/cpu", get(get_cpu_info).options(options_handler))
+        .route("/api/thermal", get(get_thermal_status_handler).options(options_handler))
+        .route("/api/thermal/history", get(get_thermal_history_handler).options(options_handler))
+        .route("/api/thermal/config", get(get_thermal_config_handler).post(set_thermal_config_handler).options(options_handler))
+        .route("/api/watchdog", get(get_watchdog_status_handler).options(options_handler))
+        .route("/api/watchdog/config", get(get_watchdog_config_handler).post(set_watchdog_config_handler).options(options_handler))
+        .route("/api/lan/config", get(get_lan_config_handler).post(set_lan_config_handler).options(options_handler))
+        .route("/api/lan/leases", get(get_lan_leases_handler).options(options_handler))
+        .route("/api/clients", get(get_clients_handler).options(options_handler))
+        .route("/api/clients/{mac}", get(get_client_history_handler).options(options_handler))
+        .route("/api/shaping", get(get_shaping_handler).post(set_shaping_handler).options(options_handler))
+        .route("/api/shaping/clients", post(add_client_limit_handler).options(options_handler))
+        .route("/api/shaping/clients/{mac}", axum::routing::delete(delete_client_limit_handler).options(options_handler))
+        .route("/api/firewall", get(get_firewall_status_handler).options(options_handler))
+        .route("/api/firewall/config", get(get_firewall_config_handler).post(set_firewall_config_handler).options(options_handler))
+        .route("/api/firewall/rules", post(add_firewall_rule_handler).options(options_handler))
+        .route("/api/firewall/rules/{name}", axum::routing::delete(delete_firewall_rule_handler).options(options_handler))
+        .route("/api/firewall/port-forwards", get(get_port_forwards_handler).post(add_port_forward_handler).options(options_handler))
+        .route("/api/firewall/port-forwards/{name}", axum::routing::delete(delete_port_forward_handler).options(options_handler))
+        .route("/api/firewall/dmz", post(set_dmz_handler).options(options_handler))
+        .route("/api/firewall/ttl", get(get_ttl_handler).post(set_ttl_handler).options(options_handler))
+        .route("/api/ddns", get(get_ddns_status_handler).options(options_handler))
+        .route("/api/ddns/config", get(get_ddns_config_handler).post(set_ddns_config_handler).options(options_handler))
+        .route("/api/ddns/update", post(ddns_update_now_handler).options(options_handler))
+        .route("/api/ddns/history", get(get_ddns_history_handler).options(options_handler))
         .route("/api/connectivity", get(get_connectivity_check).options(options_handler))
         .route("/api/system/reboot", post(system_reboot).options(options_handler))
         .route("/api/health", get(health_check))
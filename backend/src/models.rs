@@ -90,6 +90,49 @@ pub struct CellInfo {
     pub rsrq: String,
     /// 信噪比（SINR）原始值×100，实际单位dB，前端需除以100
     pub sinr: String,
+    /// 下行中心频率（MHz），由 ARFCN 换算
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dl_freq_mhz: Option<f64>,
+    /// 上行中心频率（MHz），仅下行频段或无法推算时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ul_freq_mhz: Option<f64>,
+}
+
+/// 频点换算结果
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct ArfcnInfo {
+    /// 网络制式：nr, lte
+    pub tech: String,
+    /// 频点号（NR-ARFCN / EARFCN）
+    pub arfcn: u32,
+    /// 频段名称（如 n78、B3）
+    pub band: String,
+    /// 频段号
+    pub band_number: u16,
+    /// 双工方式：FDD, TDD, SDL
+    pub duplex: String,
+    /// 下行中心频率（MHz）
+    pub dl_freq_mhz: f64,
+    /// 上行中心频率（MHz）
+    pub ul_freq_mhz: Option<f64>,
+    /// 覆盖该频点的所有频段（NR 存在重叠频段）
+    pub candidates: Vec<String>,
+}
+
+/// 频点换算请求参数
+#[derive(Debug, Deserialize)]
+pub struct ArfcnConvertRequest {
+    /// 网络制式：nr（默认）或 lte
+    #[serde(default = "default_arfcn_tech")]
+    pub tech: String,
+    /// 频点号
+    pub arfcn: u32,
+    /// 模组上报的频段（可选，用于区分重叠频段，如 77 或 n77）
+    pub band: Option<String>,
+}
+
+fn default_arfcn_tech() -> String {
+    "nr".to_string()
 }
 
 /// 小区信息响应
@@ -100,6 +143,56 @@ pub struct CellsResponse {
     pub serving_cell: ServingCell,
     /// 所有小区列表（包含主小区和邻区）
     pub cells: Vec<CellInfo>,
+    /// 载波聚合 / EN-DC 分量载波
+    pub carrier_aggregation: CarrierAggregation,
+}
+
+/// 分量载波（Component Carrier）
+///
+/// 信号强度字段与 CellInfo 相同，均为原始值（×100）
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct ComponentCarrier {
+    /// 载波角色：pcell（主载波）、scell（辅载波）、pscell（EN-DC 中 NR 侧主载波）
+    pub role: String,
+    /// 网络制式：nr, lte
+    pub tech: String,
+    /// 频段编号
+    pub band: String,
+    /// 绝对频点号（ARFCN/EARFCN）
+    pub arfcn: u32,
+    /// 物理小区标识（PCI）
+    pub pci: u16,
+    /// 下行中心频率（MHz），由 ARFCN 换算
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dl_freq_mhz: Option<f64>,
+    pub rsrp: String,
+    pub rsrq: String,
+    pub sinr: String,
+}
+
+/// EN-DC 锚点与 NR 辅连接的对应关系
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct EnDcLink {
+    /// LTE 锚点小区频段
+    pub anchor_band: String,
+    pub anchor_arfcn: u32,
+    pub anchor_pci: u16,
+    /// NR 辅连接主载波（PSCell）频段
+    pub nr_band: String,
+    pub nr_arfcn: u32,
+    pub nr_pci: u16,
+}
+
+/// 载波聚合 / EN-DC 状态
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct CarrierAggregation {
+    /// 连接模式：single（单载波）、lte_ca、nr_ca、endc
+    pub mode: String,
+    /// 分量载波列表（主载波在前，EN-DC 时 LTE 在前、NR 在后）
+    pub carriers: Vec<ComponentCarrier>,
+    /// EN-DC 锚点关系，非 EN-DC 时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endc: Option<EnDcLink>,
 }
 
 /// 设备信息响应（来自 D-Bus Modem 接口）
@@ -134,6 +227,71 @@ pub struct DataConnectionResponse {
     pub active: bool,
 }
 
+/// 数据连接 IPv4 配置（ofono context 的 Settings 字典）
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DataIpv4Settings {
+    /// 地址获取方式（static / dhcp）
+    pub method: String,
+    /// IPv4 地址
+    pub address: String,
+    /// 子网掩码
+    pub netmask: String,
+    /// 网关
+    pub gateway: String,
+    /// 运营商下发的 DNS 服务器
+    pub dns: Vec<String>,
+}
+
+/// 数据连接 IPv6 配置（ofono context 的 IPv6.Settings 字典）
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DataIpv6Settings {
+    /// IPv6 地址
+    pub address: String,
+    /// 前缀长度
+    pub prefix_length: Option<u8>,
+    /// 前缀（如 2409:8a00:1234:5678::/64）
+    pub prefix: String,
+    /// 网关
+    pub gateway: String,
+    /// 运营商下发的 DNS 服务器
+    pub dns: Vec<String>,
+}
+
+/// 数据连接 IP 配置查询参数
+#[derive(Debug, Deserialize, Default)]
+pub struct DataIpQuery {
+    /// 忽略缓存，重新检测公网 IP
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// 数据连接 IP 配置响应
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DataIpConfigResponse {
+    /// context 路径
+    pub context_path: String,
+    /// 是否激活
+    pub active: bool,
+    /// APN 名称
+    pub apn: String,
+    /// 数据网络接口名
+    pub interface: String,
+    /// IPv4 配置
+    pub ipv4: DataIpv4Settings,
+    /// IPv6 配置
+    pub ipv6: DataIpv6Settings,
+    /// 是否获得 IPv6 地址
+    pub has_ipv6: bool,
+    /// 检测到的公网 IPv4 地址
+    pub public_ip: Option<String>,
+    /// 公网 IP 检测失败原因
+    pub public_ip_error: Option<String>,
+    /// NAT 类型：none（公网直连）/ cgnat（100.64.0.0/10）/ private（运营商私网地址）/ nat（地址与公网 IP 不一致）/ unknown
+    pub nat_type: String,
+    /// 是否处于运营商级 NAT 之后（无法判断时为 null）
+    pub behind_cgnat: Option<bool>,
+}
+
 /// 漫游设置请求
 #[derive(Debug, Deserialize)]
 pub struct RoamingRequest {
@@ -574,6 +732,34 @@ pub struct NetworkInterfacesResponse {
     pub interfaces: Vec<NetworkInterfaceInfo>,
     /// 接口总数
     pub total_count: usize,
+    /// LAN 侧 IPv6 直通状态
+    pub ipv6_passthrough: Ipv6PassthroughStatus,
+}
+
+/// LAN 侧 IPv6 直通状态
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct Ipv6PassthroughStatus {
+    /// 配置的模式：disabled / relay
+    pub mode: String,
+    /// 是否正在 LAN 上通告前缀
+    pub active: bool,
+    pub lan_interface: String,
+    /// 数据网络接口
+    pub wan_interface: String,
+    /// 通告的前缀（如 2409:8a00:1234:5678::/64）
+    pub prefix: Option<String>,
+    /// 通告的 DNS 服务器
+    pub dns_servers: Vec<String>,
+    /// 通告的 MTU（取自数据网络接口）
+    pub mtu: Option<u32>,
+    pub ra_sent: u64,
+    pub last_ra_at: Option<String>,
+    pub rs_received: u64,
+    pub dhcpv6_replies: u64,
+    /// WAN 接口上的 NDP 代理条目数
+    pub ndp_proxy_entries: usize,
+    /// 未激活的原因或最近一次错误
+    pub error: Option<String>,
 }
 
 /// 射频模式枚举
@@ -645,8 +831,55 @@ pub struct BandLockStatus {
     pub raw_response: Option<String>,
 }
 
-/// 频段锁定请求
+/// 模组频段能力（AT+SPLBAND=4/5 查询结果）
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct BandCapabilities {
+    /// 支持的 LTE FDD 频段
+    pub lte_fdd_bands: Vec<u8>,
+    /// 支持的 LTE TDD 频段
+    pub lte_tdd_bands: Vec<u8>,
+    /// 支持的 NR FDD 频段
+    pub nr_fdd_bands: Vec<u8>,
+    /// 支持的 NR TDD 频段
+    pub nr_tdd_bands: Vec<u8>,
+    /// 原始位掩码
+    pub lte_fdd_mask: u16,
+    pub lte_tdd_mask: u16,
+    pub nr_fdd_mask: u16,
+    pub nr_tdd_mask: u16,
+    /// 数据来源：modem=模组查询结果，default=查询失败时使用的 UDX710 默认能力
+    pub source: String,
+    /// 查询时间（RFC3339）
+    pub discovered_at: Option<String>,
+}
+
+/// 频段能力查询参数
 #[derive(Debug, Deserialize)]
+pub struct BandCapabilitiesRequest {
+    /// 是否重新向模组查询（默认使用启动时缓存）
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// 安全锁定选项
+///
+/// 启用后在观察期内检查是否驻留到目标小区/频段以及数据连接是否恢复，
+/// 未满足时自动恢复锁定前的状态
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct SafeLockOptions {
+    /// 是否启用安全锁定
+    #[serde(default)]
+    pub safe: bool,
+    /// 观察期（秒，默认 90）
+    #[serde(default)]
+    pub grace_secs: Option<u64>,
+    /// 是否要求数据连接恢复（默认 true）
+    #[serde(default)]
+    pub require_data: Option<bool>,
+}
+
+/// 频段锁定请求
+#[derive(Debug, Deserialize, Default)]
 pub struct BandLockRequest {
     /// LTE FDD 频段列表（如 [1, 3, 8]）
     #[serde(default)]
@@ -660,6 +893,9 @@ pub struct BandLockRequest {
     /// NR TDD 频段列表（如 [41, 77, 78, 79]）
     #[serde(default)]
     pub nr_tdd_bands: Vec<u8>,
+    /// 安全锁定选项
+    #[serde(flatten)]
+    pub safe_lock: SafeLockOptions,
 }
 
 // ============ 小区锁定模型 ============
@@ -668,7 +904,7 @@ pub struct BandLockRequest {
 // 操作: 0=清除, 2=设置
 
 /// 单个 RAT 的小区锁定状态
-#[derive(Debug, Serialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct CellLockRatStatus {
     /// RAT 类型 (12=LTE, 16=NR)
     pub rat: u8,
@@ -685,7 +921,7 @@ pub struct CellLockRatStatus {
 }
 
 /// 小区锁定状态响应
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct CellLockStatusResponse {
     /// 各 RAT 的锁定状态列表
     pub rat_status: Vec<CellLockRatStatus>,
@@ -720,6 +956,9 @@ pub struct CellLockRequest {
     /// ARFCN（绝对频点号），锁定时必填
     #[serde(default)]
     pub arfcn: Option<u32>,
+    /// 安全锁定选项（仅锁定时有效）
+    #[serde(flatten)]
+    pub safe_lock: SafeLockOptions,
 }
 
 fn default_nr_rat() -> u8 {
@@ -1022,8 +1261,10 @@ pub struct ApnContext {
     pub password: String,
     /// 认证方式: none/pap/chap
     pub auth_method: String,
-    /// 类型: internet/mms/ims
+    /// 类型: internet/mms/ims/wap
     pub context_type: String,
+    /// 是否为 watchdog 保持激活的 context（手动指定）
+    pub watchdog: bool,
 }
 
 /// APN 列表响应
@@ -1031,6 +1272,49 @@ pub struct ApnContext {
 pub struct ApnListResponse {
     /// APN context 列表
     pub contexts: Vec<ApnContext>,
+    /// watchdog 当前管理的 context 路径（未手动指定时为自动选择的 internet context）
+    pub watchdog_context: String,
+}
+
+/// APN 列表请求
+#[derive(Debug, Deserialize, Default)]
+pub struct ApnListQuery {
+    /// context 类型过滤（默认 internet，all 表示全部）
+    #[serde(rename = "type", default)]
+    pub context_type: Option<String>,
+}
+
+/// 创建 APN context 请求
+#[derive(Debug, Deserialize)]
+pub struct AddApnContextRequest {
+    /// 类型: internet/mms/ims/wap
+    #[serde(rename = "type")]
+    pub context_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub apn: Option<String>,
+    /// 协议: ip/ipv6/dual
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// 认证方式: none/pap/chap
+    #[serde(default)]
+    pub auth_method: Option<String>,
+    /// 设为 watchdog 保持激活的 context（仅 internet 类型）
+    #[serde(default)]
+    pub watchdog: bool,
+}
+
+/// 指定 watchdog context 请求
+#[derive(Debug, Deserialize)]
+pub struct SetWatchdogContextRequest {
+    /// context 路径，为空恢复自动选择
+    #[serde(default)]
+    pub context_path: Option<String>,
 }
 
 /// 设置 APN 请求
@@ -1055,6 +1339,55 @@ pub struct SetApnRequest {
     pub auth_method: Option<String>,
 }
 
+/// SIM 卡标识（APN 数据库匹配使用归属运营商而非当前注册网络）
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ApnSimIdentity {
+    pub mcc: String,
+    pub mnc: String,
+    /// 运营商名称（SPN）
+    pub spn: String,
+    pub iccid: String,
+    pub imsi: String,
+}
+
+/// APN 数据库查询请求（mcc/mnc 为空时使用当前 SIM 卡）
+#[derive(Debug, Deserialize, Default)]
+pub struct ApnDatabaseQuery {
+    #[serde(default)]
+    pub mcc: Option<String>,
+    #[serde(default)]
+    pub mnc: Option<String>,
+}
+
+/// APN 数据库条目
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ApnDatabaseEntry {
+    /// custom（配置文件）/ builtin（内置数据库）
+    pub source: String,
+    #[serde(flatten)]
+    pub entry: crate::config::ApnEntry,
+}
+
+/// APN 数据库查询响应
+#[derive(Debug, Serialize, Default)]
+pub struct ApnDatabaseResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sim: Option<ApnSimIdentity>,
+    /// 自动配置将使用的条目
+    pub recommended: Option<ApnDatabaseEntry>,
+    /// 该 MCC/MNC 下的全部条目
+    pub entries: Vec<ApnDatabaseEntry>,
+    pub builtin_count: usize,
+}
+
+/// 立即自动配置 APN 请求
+#[derive(Debug, Deserialize, Default)]
+pub struct ApnProvisionRequest {
+    /// 要配置的 context 路径，为空使用 watchdog 管理的 internet context
+    #[serde(default)]
+    pub context_path: Option<String>,
+}
+
 // ============ 通话记录模型 ============
 
 /// 通话记录列表请求
@@ -1084,6 +1417,144 @@ pub struct DeleteCallRequest {
     pub id: i64,
 }
 
+// ============ 数据会话模型 ============
+
+/// 数据连接上下文快照（watchdog 每次轮询采集）
+#[derive(Debug, Clone, Default)]
+pub struct DataContextSnapshot {
+    /// 网络注册状态（registered / roaming / searching 等）
+    pub network_status: String,
+    /// context 是否激活
+    pub active: bool,
+    /// APN 名称
+    pub apn: String,
+    /// 数据网络接口名
+    pub interface: String,
+    /// IPv4 地址
+    pub ipv4_address: String,
+    /// IPv6 地址
+    pub ipv6_address: String,
+    /// 运营商下发的 DNS 服务器（IPv4 在前）
+    pub dns_servers: Vec<String>,
+}
+
+/// 数据会话列表请求
+#[derive(Debug, Deserialize)]
+pub struct DataSessionListRequest {
+    /// 每页数量（默认 50）
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    /// 偏移量（默认 0）
+    #[serde(default)]
+    pub offset: i64,
+}
+
+/// 中断/可用率统计请求
+#[derive(Debug, Deserialize)]
+pub struct DataStatsRangeRequest {
+    /// 统计最近多少天（默认 7，最大 90）
+    #[serde(default = "default_stats_days")]
+    pub days: i64,
+}
+
+fn default_stats_days() -> i64 {
+    7
+}
+
+/// 数据中断区间（由相邻会话之间的空档计算得出）
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct DataOutage {
+    /// 中断开始时间 ISO 8601
+    pub start_time: String,
+    /// 中断结束时间 ISO 8601（仍在中断中为 None）
+    pub end_time: Option<String>,
+    /// 中断时长（秒，进行中的按当前时间计算）
+    pub duration_secs: i64,
+    /// 导致中断的会话结束原因
+    pub reason: Option<String>,
+    /// 是否仍在中断中
+    pub ongoing: bool,
+}
+
+/// 数据中断列表响应
+#[derive(Debug, Serialize, Default)]
+pub struct DataOutagesResponse {
+    /// 统计天数
+    pub days: i64,
+    /// 中断次数
+    pub count: usize,
+    /// 总中断时长（秒）
+    pub total_outage_secs: i64,
+    /// 中断列表（最新在前）
+    pub outages: Vec<DataOutage>,
+}
+
+/// 单日可用率统计
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct DailyAvailability {
+    /// 日期（本地时区，YYYY-MM-DD）
+    pub date: String,
+    /// 在线时长（秒）
+    pub connected_secs: i64,
+    /// 中断时长（秒）
+    pub outage_secs: i64,
+    /// 当天开始的中断次数
+    pub outage_count: u32,
+    /// 可用率百分比（无观测数据时为 None）
+    pub availability_percent: Option<f64>,
+}
+
+/// 可用率统计响应
+#[derive(Debug, Serialize, Default)]
+pub struct DataAvailabilityResponse {
+    /// 统计天数
+    pub days: i64,
+    /// 整个统计区间的可用率百分比
+    pub overall_percent: Option<f64>,
+    /// 每日统计（按日期升序）
+    pub daily: Vec<DailyAvailability>,
+}
+
+// ============ 温度监控模型 ============
+
+/// 温度历史查询请求
+#[derive(Debug, Deserialize)]
+pub struct ThermalHistoryRequest {
+    /// 查询最近多少小时（默认 24）
+    #[serde(default = "default_thermal_hours")]
+    pub hours: i64,
+    /// 传感器名称（可选，如 thermal_zone0）
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+fn default_thermal_hours() -> i64 {
+    24
+}
+
+// ============ 防火墙模型 ============
+
+/// 设置 DMZ 主机请求
+#[derive(Debug, Deserialize)]
+pub struct SetDmzRequest {
+    /// DMZ 主机 IPv4 地址，为空关闭 DMZ
+    #[serde(default)]
+    pub host: String,
+}
+
+// ============ DDNS 模型 ============
+
+/// DDNS 更新记录查询请求
+#[derive(Debug, Deserialize)]
+pub struct DdnsHistoryRequest {
+    /// 每页数量（默认 50）
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    /// 偏移量（默认 0）
+    #[serde(default)]
+    pub offset: i64,
+}
+
 // ============ Webhook 配置模型 ============
 
 /// Webhook 测试结果
@@ -1164,3 +1635,337 @@ pub struct OtaApplyRequest {
     pub restart_now: bool,
 }
 
+
+// ============ 最佳小区优化模型 ============
+
+/// 最佳小区优化任务请求
+#[derive(Debug, Deserialize, Clone)]
+pub struct CellOptimizeRequest {
+    /// 最多测试的候选小区数（按初始 RSRP 排序，默认 5）
+    #[serde(default = "default_optimize_candidates")]
+    pub max_candidates: usize,
+    /// 锁定后等待注册的最长时间（秒，默认 30）
+    #[serde(default = "default_optimize_settle_secs")]
+    pub settle_secs: u64,
+    /// 每个小区的信号采样次数（默认 3）
+    #[serde(default = "default_optimize_samples")]
+    pub samples: u32,
+    /// 采样间隔（秒，默认 2）
+    #[serde(default = "default_optimize_sample_interval")]
+    pub sample_interval_secs: u64,
+    /// 测速下载地址（可选，为空则不测速）
+    #[serde(default)]
+    pub throughput_url: Option<String>,
+    /// 单次测速时长（秒，默认 10）
+    #[serde(default = "default_optimize_throughput_secs")]
+    pub throughput_secs: u64,
+    /// 完成后是否锁定到最佳小区（false 则只报告结果并恢复原状态）
+    #[serde(default)]
+    pub apply_best: bool,
+}
+
+fn default_optimize_candidates() -> usize {
+    5
+}
+
+fn default_optimize_settle_secs() -> u64 {
+    30
+}
+
+fn default_optimize_samples() -> u32 {
+    3
+}
+
+fn default_optimize_sample_interval() -> u64 {
+    2
+}
+
+fn default_optimize_throughput_secs() -> u64 {
+    10
+}
+
+// ============ 安全锁定模型 ============
+
+/// 安全锁定记录查询请求
+#[derive(Debug, Deserialize)]
+pub struct LockHistoryRequest {
+    /// 每页数量（默认 50）
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    /// 偏移量（默认 0）
+    #[serde(default)]
+    pub offset: i64,
+}
+
+// ============ 离线基站定位模型 ============
+
+/// 离线基站库导入请求
+#[derive(Debug, Deserialize)]
+pub struct TowerImportRequest {
+    /// 设备上的 CSV 文件路径（OpenCellID / MLS 格式，支持 .gz）
+    pub path: String,
+    /// 只导入这些 MCC（为空则全部导入）
+    #[serde(default)]
+    pub mcc: Vec<u16>,
+    /// 导入前先删除这些 MCC 的已有数据（mcc 为空时清空全部）
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// 离线基站库导入状态
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct TowerImportStatus {
+    /// 是否正在导入
+    pub running: bool,
+    pub path: String,
+    pub started_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    /// 已读取行数
+    pub lines_read: u64,
+    /// 已导入基站数
+    pub imported: u64,
+    /// 被过滤或格式错误的行数
+    pub skipped: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 离线基站库信息
+#[derive(Debug, Serialize, Default)]
+pub struct CellTowerDbInfo {
+    /// 基站总数
+    pub total: i64,
+    /// 按制式和 MCC 分组的数量
+    pub counts: Vec<crate::db::CellTowerCount>,
+    /// 最近一次导入状态
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import: Option<TowerImportStatus>,
+}
+
+/// 在线定位请求
+#[derive(Debug, Deserialize, Default)]
+pub struct GeolocationRequest {
+    /// 忽略缓存，重新请求定位服务
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// 参与定位的基站
+#[derive(Debug, Serialize, Clone)]
+pub struct TowerMatch {
+    /// serving（主服务小区）/ neighbor（邻区）
+    pub role: String,
+    pub radio: String,
+    pub area: u32,
+    pub cell: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pci: Option<u32>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// 基站覆盖半径（米）
+    pub range_m: u32,
+    /// 信号强度（RSRP，dBm）
+    pub rsrp_dbm: i32,
+    /// 归一化权重（总和为 1）
+    pub weight: f64,
+}
+
+/// 位置估算结果
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct LocationEstimate {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// 精度半径（米）
+    pub accuracy_m: f64,
+    /// 数据来源：offline 或在线定位服务名称（google / unwiredlabs / opencellid）
+    pub source: String,
+    /// 参与定位的基站
+    pub towers: Vec<TowerMatch>,
+}
+
+// ============ 站点勘测模型 ============
+
+/// 开始站点勘测请求
+#[derive(Debug, Deserialize, Clone)]
+pub struct SurveyStartRequest {
+    /// 会话名称（为空则使用开始时间）
+    #[serde(default)]
+    pub name: String,
+    /// 采样间隔（毫秒，默认 2000，最小 1000）
+    #[serde(default = "default_survey_interval_ms")]
+    pub interval_ms: u64,
+    /// 初始位置标签
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+}
+
+fn default_survey_interval_ms() -> u64 {
+    2000
+}
+
+/// 更新位置标签请求（应用到之后的采样）
+#[derive(Debug, Deserialize, Clone)]
+pub struct SurveyTagRequest {
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+}
+
+/// 站点勘测状态
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct SurveyStatus {
+    /// 是否正在勘测
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<i64>,
+    pub interval_ms: u64,
+    /// 当前位置标签
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// 已完成的采样轮数
+    pub rounds: u64,
+    /// 最近一次采样观测到的小区数
+    pub last_cell_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// 单个小区的勘测汇总
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct SurveyCellSummary {
+    pub tech: String,
+    pub band: String,
+    pub arfcn: u32,
+    pub pci: u16,
+    /// 观测次数
+    pub samples: u64,
+    /// 作为主服务小区的次数
+    pub serving_samples: u64,
+    /// RSRP 最好/平均/最差（dBm）
+    pub best_rsrp: Option<f64>,
+    pub avg_rsrp: Option<f64>,
+    pub worst_rsrp: Option<f64>,
+    /// 平均 SINR（dB）
+    pub avg_sinr: Option<f64>,
+    /// 观测到最好 RSRP 的位置标签
+    pub best_label: String,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// 站点勘测会话详情
+#[derive(Debug, Serialize, Default)]
+pub struct SurveyDetail {
+    pub session: crate::db::SurveySession,
+    /// 按最好 RSRP 排序的小区汇总
+    pub cells: Vec<SurveyCellSummary>,
+}
+
+/// 站点勘测导出请求
+#[derive(Debug, Deserialize)]
+pub struct SurveyExportRequest {
+    /// csv（默认）或 kml
+    #[serde(default = "default_survey_export_format")]
+    pub format: String,
+}
+
+fn default_survey_export_format() -> String {
+    "csv".to_string()
+}
+
+// ============ 天线对准模型 ============
+
+/// 天线对准参数（WebSocket 连接的查询参数）
+#[derive(Debug, Deserialize, Clone)]
+pub struct AlignmentRequest {
+    /// 采样间隔（毫秒，默认 200，最小 100）
+    #[serde(default = "default_alignment_interval_ms")]
+    pub interval_ms: u64,
+    /// 自动结束时间（秒，默认 300，最长 1800）
+    #[serde(default = "default_alignment_timeout_secs")]
+    pub timeout_secs: u64,
+    /// 滑动平均窗口（采样数，默认 10）
+    #[serde(default = "default_alignment_window")]
+    pub window: usize,
+}
+
+fn default_alignment_interval_ms() -> u64 {
+    200
+}
+
+fn default_alignment_timeout_secs() -> u64 {
+    300
+}
+
+fn default_alignment_window() -> usize {
+    10
+}
+
+/// 客户端发送的对准指令（JSON 文本消息）
+#[derive(Debug, Deserialize, Default)]
+pub struct AlignmentCommand {
+    /// 当前朝向标签（如 "N", "120°"），之后的采样归属于该朝向
+    #[serde(default)]
+    pub direction: Option<String>,
+    /// 清除峰值保持和最佳朝向
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// 最佳朝向标记（滑动平均 RSRP 最高的时刻）
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct AlignmentBest {
+    pub direction: String,
+    pub rsrp_avg: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sinr_avg: Option<f64>,
+    pub timestamp: String,
+}
+
+/// 对准采样（dBm/dB，滑动平均和峰值已计算）
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AlignmentSample {
+    pub timestamp: String,
+    pub seq: u64,
+    pub direction: String,
+    pub tech: String,
+    pub band: String,
+    pub pci: String,
+    pub rsrp: Option<f64>,
+    pub rsrq: Option<f64>,
+    pub sinr: Option<f64>,
+    pub rsrp_avg: Option<f64>,
+    pub sinr_avg: Option<f64>,
+    /// 峰值保持
+    pub rsrp_peak: Option<f64>,
+    pub sinr_peak: Option<f64>,
+    pub best: Option<AlignmentBest>,
+    /// 本次采样刷新了最佳朝向
+    pub new_best: bool,
+}
+
+/// 天线对准状态
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AlignmentStatus {
+    /// 是否正在对准（对准期间暂停后台轮询）
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    pub interval_ms: u64,
+    pub timeout_secs: u64,
+    pub direction: String,
+    pub samples: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best: Option<AlignmentBest>,
+}
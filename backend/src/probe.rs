@@ -0,0 +1,339 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-16 17:08:45
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-16 17:08:45
+ * @FilePath: /udx710-backend/backend/src/probe.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 连通性探测模块
+//!
+//! context 处于激活状态但没有流量通过时，ofono 仍然报告 Active=true。
+//! watchdog 按配置周期探测端到端连通性：
+//! - ICMP：通过数据网络接口 ping 目标地址
+//! - DNS：通过绑定数据网络接口的 UDP 套接字直接向运营商 DNS 查询 A 记录，
+//!   不经过系统解析器和本地缓存
+//! - HTTP：以数据连接地址为源地址请求 URL，期望返回 204
+//!
+//! 一轮中任一目标成功即视为连通，连续多轮全部失败时 watchdog 触发恢复。
+
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+
+use crate::config::{ProbeConfig, ProbeKind, ProbeTarget};
+
+/// 单个目标的探测结果
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub kind: ProbeKind,
+    pub target: String,
+    pub success: bool,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// 探测单个目标
+///
+/// # Arguments
+/// * `target` - 探测目标
+/// * `interface` - 数据网络接口（ICMP 探测绑定该接口，可为空）
+/// * `source` - 数据连接 IPv4 地址（HTTP 探测的源地址，可为空）
+/// * `dns_servers` - 运营商 DNS 服务器（DNS 探测的查询对象）
+/// * `timeout` - 超时时间
+async fn probe_target(
+    target: &ProbeTarget,
+    interface: &str,
+    source: &str,
+    dns_servers: &[String],
+    timeout: Duration,
+) -> ProbeResult {
+    let started = Instant::now();
+    let outcome = match target.kind {
+        ProbeKind::Icmp => probe_icmp(&target.target, interface, timeout).await,
+        ProbeKind::Dns => probe_dns(&target.target, interface, dns_servers, timeout).await,
+        ProbeKind::Http => probe_http(&target.target, source, timeout).await,
+    };
+    let latency_ms = (started.elapsed().as_secs_f64() * 1000.0 * 10.0).round() / 10.0;
+    ProbeResult {
+        kind: target.kind,
+        target: target.target.clone(),
+        success: outcome.is_ok(),
+        latency_ms: outcome.is_ok().then_some(latency_ms),
+        error: outcome.err(),
+    }
+}
+
+async fn probe_icmp(target: &str, interface: &str, timeout: Duration) -> Result<(), String> {
+    let addr: IpAddr = target.parse().map_err(|_| format!("Invalid IP address: {}", target))?;
+    let mut cmd = Command::new(if addr.is_ipv6() { "ping6" } else { "ping" });
+    cmd.args(["-c", "1", "-W", &timeout.as_secs().max(1).to_string()]);
+    if !interface.is_empty() {
+        cmd.args(["-I", interface]);
+    }
+    let output = cmd
+        .arg(target)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute ping: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(if stderr.is_empty() { "Host unreachable".to_string() } else { stderr })
+    }
+}
+
+/// 构造 A 记录查询报文
+///
+/// # Returns
+/// 域名不合法（标签为空或超过 63 字节、总长超过 253 字节）时返回错误
+pub fn build_dns_query(id: u16, host: &str) -> Result<Vec<u8>, String> {
+    let name = host.trim().trim_end_matches('.');
+    if name.is_empty() || name.len() > 253 {
+        return Err(format!("Invalid domain name: {}", host));
+    }
+    // 头部：ID，RD=1，QDCOUNT=1
+    let mut packet = id.to_be_bytes().to_vec();
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid domain name: {}", host));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    // QTYPE=A，QCLASS=IN
+    packet.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01]);
+    Ok(packet)
+}
+
+/// 检查查询应答：ID 匹配、RCODE 为 NOERROR 且包含应答记录
+pub fn check_dns_response(id: u16, packet: &[u8]) -> Result<(), String> {
+    if packet.len() < 12 || packet[..2] != id.to_be_bytes() || packet[2] & 0x80 == 0 {
+        return Err("Invalid DNS response".to_string());
+    }
+    match packet[3] & 0x0f {
+        0 => {}
+        3 => return Err("NXDOMAIN".to_string()),
+        rcode => return Err(format!("DNS error (RCODE {})", rcode)),
+    }
+    if u16::from_be_bytes([packet[6], packet[7]]) == 0 {
+        return Err("No address returned".to_string());
+    }
+    Ok(())
+}
+
+/// 通过绑定接口的套接字向单个 DNS 服务器查询
+async fn query_dns(server: IpAddr, host: &str, interface: &str) -> Result<(), String> {
+    let local: SocketAddr = if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }
+        .parse()
+        .unwrap();
+    let socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+    if !interface.is_empty() {
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                interface.as_ptr() as *const libc::c_void,
+                interface.len() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(format!("Failed to bind {}: {}", interface, std::io::Error::last_os_error()));
+        }
+    }
+    socket
+        .connect(SocketAddr::new(server, 53))
+        .await
+        .map_err(|e| format!("{}: {}", server, e))?;
+
+    let id = (chrono::Utc::now().timestamp_subsec_nanos() & 0xffff) as u16;
+    let query = build_dns_query(id, host)?;
+    socket.send(&query).await.map_err(|e| format!("{}: {}", server, e))?;
+    let mut buf = [0u8; 512];
+    // 丢弃 ID 不匹配的报文，继续等待
+    loop {
+        let len = socket.recv(&mut buf).await.map_err(|e| format!("{}: {}", server, e))?;
+        match check_dns_response(id, &buf[..len]) {
+            Err(e) if e == "Invalid DNS response" => continue,
+            result => return result.map_err(|e| format!("{}: {}", server, e)),
+        }
+    }
+}
+
+/// 并行查询所有运营商 DNS，任一返回有效应答即成功
+async fn probe_dns(host: &str, interface: &str, dns_servers: &[String], timeout: Duration) -> Result<(), String> {
+    let servers: Vec<IpAddr> = dns_servers.iter().filter_map(|s| s.parse().ok()).collect();
+    if servers.is_empty() {
+        return Err("No carrier DNS servers".to_string());
+    }
+    let queries = servers.into_iter().map(|server| Box::pin(query_dns(server, host, interface)));
+    match tokio::time::timeout(timeout, futures_util::future::select_ok(queries)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err("Timed out".to_string()),
+    }
+}
+
+async fn probe_http(url: &str, source: &str, timeout: Duration) -> Result<(), String> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Ok(addr) = source.parse::<Ipv4Addr>() {
+        builder = builder.local_address(IpAddr::V4(addr));
+    }
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let resp = client.get(url).send().await.map_err(|e| e.to_string())?;
+    // 返回其他状态码通常是被运营商劫持到门户页面
+    if resp.status() == reqwest::StatusCode::NO_CONTENT {
+        Ok(())
+    } else {
+        Err(format!("Unexpected HTTP {}", resp.status()))
+    }
+}
+
+/// 连通性探测状态（由 watchdog 循环持有）
+#[derive(Debug, Default)]
+pub struct ProbeTracker {
+    consecutive_failures: u32,
+    last_probe: Option<Instant>,
+    last_probe_at: Option<String>,
+    results: Vec<ProbeResult>,
+}
+
+impl ProbeTracker {
+    /// 到达探测间隔时并行探测所有目标
+    ///
+    /// # Returns
+    /// 连续失败轮数达到阈值时返回失败描述
+    pub async fn check(
+        &mut self,
+        config: &ProbeConfig,
+        interface: &str,
+        source: &str,
+        dns_servers: &[String],
+    ) -> Option<String> {
+        let due = self
+            .last_probe
+            .is_none_or(|at| at.elapsed() >= Duration::from_secs(config.interval_secs));
+        if due && !config.targets.is_empty() {
+            let timeout = Duration::from_secs(config.timeout_secs.max(1));
+            let probes = config
+                .targets
+                .iter()
+                .map(|target| probe_target(target, interface, source, dns_servers, timeout));
+            let results = futures_util::future::join_all(probes).await;
+            self.record(results);
+        }
+        self.failing(config.failure_threshold)
+    }
+
+    /// 记录一轮探测结果
+    fn record(&mut self, results: Vec<ProbeResult>) {
+        if results.iter().any(|r| r.success) {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        }
+        self.last_probe = Some(Instant::now());
+        self.last_probe_at = Some(chrono::Utc::now().to_rfc3339());
+        self.results = results;
+    }
+
+    /// 连续失败轮数是否达到阈值
+    fn failing(&self, threshold: u32) -> Option<String> {
+        (self.consecutive_failures >= threshold.max(1)).then(|| {
+            let errors: Vec<String> = self
+                .results
+                .iter()
+                .map(|r| format!("{}: {}", r.target, r.error.as_deref().unwrap_or("failed")))
+                .collect();
+            format!(
+                "Connectivity probes failed {} times ({})",
+                self.consecutive_failures,
+                errors.join("; ")
+            )
+        })
+    }
+
+    /// 下一次检查立即探测（恢复动作执行后调用）
+    pub fn probe_now(&mut self) {
+        self.last_probe = None;
+    }
+
+    /// context 未激活时清空探测状态
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    pub fn last_probe_at(&self) -> Option<String> {
+        self.last_probe_at.clone()
+    }
+
+    pub fn results(&self) -> &[ProbeResult] {
+        &self.results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(success: bool) -> ProbeResult {
+        ProbeResult {
+            kind: ProbeKind::Icmp,
+            target: "223.5.5.5".to_string(),
+            success,
+            latency_ms: None,
+            error: (!success).then(|| "Host unreachable".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_dns_query_and_response() {
+        let query = build_dns_query(0x1234, "www.baidu.com").unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x03www\x05baidu\x03com\x00\x00\x01\x00\x01");
+        assert!(build_dns_query(1, "bad..name").is_err());
+        assert!(build_dns_query(1, &"a".repeat(64)).is_err());
+
+        let mut response = query.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        assert_eq!(check_dns_response(0x1234, &response).unwrap_err(), "No address returned");
+        response[7] = 1;
+        assert!(check_dns_response(0x1234, &response).is_ok());
+        assert_eq!(check_dns_response(0x4321, &response).unwrap_err(), "Invalid DNS response");
+        response[3] = 0x83;
+        assert_eq!(check_dns_response(0x1234, &response).unwrap_err(), "NXDOMAIN");
+    }
+
+    #[test]
+    fn test_probe_tracker() {
+        let mut tracker = ProbeTracker::default();
+        tracker.record(vec![result(false), result(false)]);
+        tracker.record(vec![result(false)]);
+        assert!(tracker.failing(3).is_none());
+        tracker.record(vec![result(false)]);
+        let failure = tracker.failing(3).unwrap();
+        assert!(failure.contains("failed 3 times") && failure.contains("Host unreachable"));
+
+        // 任一目标成功即清零
+        tracker.record(vec![result(false), result(true)]);
+        assert_eq!(tracker.consecutive_failures(), 0);
+        assert!(tracker.failing(0).is_none());
+    }
+}
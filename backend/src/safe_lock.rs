@@ -0,0 +1,424 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 21:02:18
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 21:02:18
+ * @FilePath: /udx710-backend/backend/src/safe_lock.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 安全锁定模块
+//!
+//! 小区/频段锁定后在观察期内检查网络注册、驻留的小区/频段和数据连接，
+//! 观察期结束仍未满足条件时自动恢复锁定前的状态，避免远程设备因错误锁定而永久离线。
+//! 每次安全锁定的结果都会写入数据库。观察期内服务重启或系统重启时，
+//! 启动后按数据库中保存的锁定前状态恢复（见 [`SafeLockManager::recover_interrupted`]）。
+
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::arfcn::parse_band_number;
+use crate::band_lock::{
+    apply_band_lock, fetch_primary_cell, get_rat_name, is_registered, lock_cell, parse_first,
+    query_cell_lock_status, read_band_masks, restore_band_masks, restore_cell_lock, SavedBandMasks,
+    FORCEFRQ_TYPE_NR,
+};
+use crate::config::ConfigManager;
+use crate::db::Database;
+use crate::dbus::{get_data_connection_status, get_serving_cell_info};
+use crate::models::{BandLockRequest, CellLockStatusResponse, SafeLockOptions};
+
+/// 默认观察期（秒）
+const DEFAULT_GRACE_SECS: u64 = 90;
+/// 观察期范围（秒）
+const MIN_GRACE_SECS: u64 = 15;
+const MAX_GRACE_SECS: u64 = 900;
+/// 检查间隔（秒）
+const CHECK_INTERVAL_SECS: u64 = 5;
+/// 启动时恢复中断锁定的尝试次数（Modem 可能尚未就绪）
+const RECOVER_ATTEMPTS: u32 = 3;
+
+/// 安全锁定目标
+#[derive(Debug, Clone)]
+enum SafeLockTarget {
+    Cell { forcefrq_type: u8, arfcn: u32, pci: u16 },
+    Band { lte_bands: Vec<u8>, nr_bands: Vec<u8> },
+}
+
+/// 锁定前的状态
+enum SavedLockState {
+    Cell(CellLockStatusResponse),
+    Band(SavedBandMasks),
+}
+
+/// 正在观察中的安全锁定
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct PendingSafeLock {
+    /// 记录 ID（对应 /api/lock/history）
+    pub id: i64,
+    /// 锁定类型：cell / band
+    pub kind: String,
+    pub started_at: String,
+    /// 观察期截止时间，届时仍未确认则自动恢复
+    pub deadline: String,
+    pub require_data: bool,
+    /// 最近一次检查结果
+    pub last_check: String,
+}
+
+/// 观察任务
+struct ActiveWatch {
+    pending: PendingSafeLock,
+    /// 已开始恢复锁定前状态，此时不能中止任务
+    reverting: bool,
+    task: AbortHandle,
+}
+
+/// 安全锁定管理器（同一时间只观察一个锁定）
+pub struct SafeLockManager {
+    db: Arc<Database>,
+    config_manager: Arc<ConfigManager>,
+    pending: Mutex<Option<ActiveWatch>>,
+    /// 观察任务恢复期间持有
+    revert_lock: tokio::sync::Mutex<()>,
+}
+
+impl SafeLockManager {
+    /// 创建管理器
+    ///
+    /// 上次服务退出时未结束的记录由 [`Self::recover_interrupted`] 恢复
+    pub fn new(db: Arc<Database>, config_manager: Arc<ConfigManager>) -> Self {
+        Self {
+            db,
+            config_manager,
+            pending: Mutex::new(None),
+            revert_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// 恢复上次服务退出时仍在观察期内的锁定（启动时调用）
+    ///
+    /// 服务或系统在观察期内重启时观察任务丢失，试验锁定会一直生效；
+    /// 此时按记录中保存的锁定前状态恢复，再结束记录。恢复期间新的锁定操作等待恢复完成。
+    pub async fn recover_interrupted(&self, conn: &Connection) {
+        let _revert = self.revert_lock.lock().await;
+        let records = match self.db.get_pending_lock_records() {
+            Ok(records) => records,
+            Err(e) => {
+                warn!(error = %e, "Safe lock: failed to read pending records");
+                return;
+            }
+        };
+        // 记录按时间倒序，最早的锁定前状态最后恢复
+        for record in records {
+            let saved = match record.kind.as_str() {
+                "cell" => serde_json::from_str(&record.previous).map(SavedLockState::Cell),
+                _ => serde_json::from_str(&record.previous).map(SavedLockState::Band),
+            };
+            let saved = match saved {
+                Ok(saved) => saved,
+                Err(e) => {
+                    self.complete(
+                        record.id,
+                        "interrupted",
+                        &format!("Service restarted; previous state unreadable: {}", e),
+                    );
+                    continue;
+                }
+            };
+
+            let mut result = Err(String::new());
+            for attempt in 0..RECOVER_ATTEMPTS {
+                if attempt > 0 {
+                    tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+                }
+                result = match &saved {
+                    SavedLockState::Cell(previous) => restore_cell_lock(conn, previous).await,
+                    SavedLockState::Band(masks) => restore_band_masks(conn, masks).await,
+                };
+                if result.is_ok() {
+                    break;
+                }
+            }
+            match result {
+                Ok(()) => self.complete(record.id, "reverted", "Service restarted during grace period"),
+                Err(e) => self.complete(
+                    record.id,
+                    "revert_failed",
+                    &format!("Service restarted during grace period; revert failed: {}", e),
+                ),
+            }
+        }
+    }
+
+    /// 获取正在观察中的安全锁定
+    pub fn pending(&self) -> Option<PendingSafeLock> {
+        self.pending.lock().unwrap().as_ref().map(|w| w.pending.clone())
+    }
+
+    /// 新的锁定操作覆盖正在观察中的安全锁定（不再自动恢复）
+    ///
+    /// 观察任务已开始恢复时不中止，等待恢复完成后返回，避免 Modem 停留在
+    /// 工程模式或只恢复了一半的锁定状态。
+    pub async fn supersede(&self) {
+        let superseded = {
+            let mut guard = self.pending.lock().unwrap();
+            match guard.as_ref() {
+                Some(watch) if !watch.reverting => guard.take(),
+                _ => None,
+            }
+        };
+        if let Some(watch) = superseded {
+            watch.task.abort();
+            info!(id = watch.pending.id, "Safe lock superseded by a new lock change");
+            if let Err(e) = self.db.finish_lock_record(watch.pending.id, "superseded", "Superseded by a new lock change") {
+                warn!(error = %e, "Safe lock: failed to record outcome");
+            }
+        }
+        // 正在恢复时等待恢复完成
+        drop(self.revert_lock.lock().await);
+    }
+
+    /// 安全锁定到指定小区
+    pub async fn lock_cell(
+        self: &Arc<Self>,
+        conn: Arc<Connection>,
+        forcefrq_type: u8,
+        arfcn: u32,
+        pci: u16,
+        options: &SafeLockOptions,
+    ) -> Result<PendingSafeLock, String> {
+        self.supersede().await;
+        let previous = query_cell_lock_status(&conn).await;
+        lock_cell(&conn, forcefrq_type, arfcn, pci).await?;
+
+        let target_json = json!({
+            "tech": get_rat_name(forcefrq_type),
+            "arfcn": arfcn,
+            "pci": pci
+        });
+        let previous_json = serde_json::to_string(&previous).unwrap_or_default();
+        let id = self.record("cell", &target_json.to_string(), &previous_json)?;
+        Ok(self.watch(
+            conn,
+            id,
+            "cell",
+            SafeLockTarget::Cell { forcefrq_type, arfcn, pci },
+            SavedLockState::Cell(previous),
+            options,
+        ))
+    }
+
+    /// 安全应用频段锁定，返回频段锁定提示信息和观察状态
+    pub async fn lock_bands(
+        self: &Arc<Self>,
+        conn: Arc<Connection>,
+        bands: &BandLockRequest,
+    ) -> Result<(String, PendingSafeLock), String> {
+        self.supersede().await;
+        let previous = read_band_masks(&conn).await?;
+        let message = apply_band_lock(&conn, bands).await?;
+
+        let target_json = json!({
+            "lte_fdd_bands": bands.lte_fdd_bands,
+            "lte_tdd_bands": bands.lte_tdd_bands,
+            "nr_fdd_bands": bands.nr_fdd_bands,
+            "nr_tdd_bands": bands.nr_tdd_bands
+        });
+        let previous_json = serde_json::to_string(&previous).unwrap_or_default();
+        let id = self.record("band", &target_json.to_string(), &previous_json)?;
+        let target = SafeLockTarget::Band {
+            lte_bands: [bands.lte_fdd_bands.as_slice(), bands.lte_tdd_bands.as_slice()].concat(),
+            nr_bands: [bands.nr_fdd_bands.as_slice(), bands.nr_tdd_bands.as_slice()].concat(),
+        };
+        let pending = self.watch(
+            conn,
+            id,
+            "band",
+            target,
+            SavedLockState::Band(previous),
+            &bands.safe_lock,
+        );
+        Ok((message, pending))
+    }
+
+    /// 写入 pending 状态的锁定记录
+    fn record(&self, kind: &str, target_json: &str, previous_json: &str) -> Result<i64, String> {
+        self.db
+            .insert_lock_record(kind, target_json, previous_json)
+            .map_err(|e| format!("Failed to record safe lock: {}", e))
+    }
+
+    /// 启动观察任务
+    fn watch(
+        self: &Arc<Self>,
+        conn: Arc<Connection>,
+        id: i64,
+        kind: &str,
+        target: SafeLockTarget,
+        saved: SavedLockState,
+        options: &SafeLockOptions,
+    ) -> PendingSafeLock {
+        let grace_secs = options
+            .grace_secs
+            .unwrap_or(DEFAULT_GRACE_SECS)
+            .clamp(MIN_GRACE_SECS, MAX_GRACE_SECS);
+        let require_data = options.require_data.unwrap_or(true);
+
+        let now = Utc::now();
+        let pending = PendingSafeLock {
+            id,
+            kind: kind.to_string(),
+            started_at: now.to_rfc3339(),
+            deadline: (now + ChronoDuration::seconds(grace_secs as i64)).to_rfc3339(),
+            require_data,
+            last_check: "Waiting for registration".to_string(),
+        };
+
+        let manager = Arc::clone(self);
+        let task = tokio::spawn(async move {
+            manager.run_watch(&conn, id, target, saved, grace_secs, require_data).await;
+        });
+        *self.pending.lock().unwrap() = Some(ActiveWatch {
+            pending: pending.clone(),
+            reverting: false,
+            task: task.abort_handle(),
+        });
+
+        info!(id, kind, grace_secs, require_data, "Safe lock applied, watching");
+        pending
+    }
+
+    /// 观察循环：条件满足则确认，超时则恢复
+    async fn run_watch(
+        &self,
+        conn: &Connection,
+        id: i64,
+        target: SafeLockTarget,
+        saved: SavedLockState,
+        grace_secs: u64,
+        require_data: bool,
+    ) {
+        let mut elapsed = 0;
+        let mut last_reason = String::new();
+
+        while elapsed < grace_secs {
+            tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL_SECS)).await;
+            elapsed += CHECK_INTERVAL_SECS;
+
+            let watchdog_context = self.config_manager.get_watchdog_context();
+            match check_target(conn, &target, require_data, watchdog_context.as_deref()).await {
+                Ok(detail) => {
+                    self.complete(id, "confirmed", &format!("{} after {}s", detail, elapsed));
+                    return;
+                }
+                Err(reason) => {
+                    if let Some(watch) = self.pending.lock().unwrap().as_mut() {
+                        if watch.pending.id == id {
+                            watch.pending.last_check = reason.clone();
+                        }
+                    }
+                    last_reason = reason;
+                }
+            }
+        }
+
+        // 与 supersede 互斥：已被覆盖则不再恢复，否则标记为恢复中，之后不再被中止
+        let _revert = self.revert_lock.lock().await;
+        {
+            let mut guard = self.pending.lock().unwrap();
+            match guard.as_mut() {
+                Some(watch) if watch.pending.id == id => {
+                    watch.reverting = true;
+                    watch.pending.last_check = format!("Reverting: {}", last_reason);
+                }
+                _ => return,
+            }
+        }
+
+        warn!(id, reason = %last_reason, "Safe lock not confirmed, reverting");
+        let result = match &saved {
+            SavedLockState::Cell(previous) => restore_cell_lock(conn, previous).await,
+            SavedLockState::Band(masks) => restore_band_masks(conn, masks).await,
+        };
+        match result {
+            Ok(()) => self.complete(id, "reverted", &format!("Not confirmed within {}s: {}", grace_secs, last_reason)),
+            Err(e) => self.complete(
+                id,
+                "revert_failed",
+                &format!("Not confirmed within {}s: {}; revert failed: {}", grace_secs, last_reason, e),
+            ),
+        }
+    }
+
+    /// 记录结果并清除观察状态
+    fn complete(&self, id: i64, outcome: &str, detail: &str) {
+        info!(id, outcome, detail, "Safe lock finished");
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.as_ref().is_some_and(|w| w.pending.id == id) {
+                *pending = None;
+            }
+        }
+        if let Err(e) = self.db.finish_lock_record(id, outcome, detail) {
+            warn!(error = %e, "Safe lock: failed to record outcome");
+        }
+    }
+}
+
+/// 检查是否已驻留到目标小区/频段且数据连接正常
+///
+/// # Returns
+/// 满足条件时返回说明，否则返回未满足的原因
+async fn check_target(
+    conn: &Connection,
+    target: &SafeLockTarget,
+    require_data: bool,
+    watchdog_context: Option<&str>,
+) -> Result<String, String> {
+    if !is_registered(conn).await {
+        return Err("Network not registered".to_string());
+    }
+
+    let detail = match target {
+        SafeLockTarget::Cell { forcefrq_type, arfcn, pci } => {
+            let tech = if *forcefrq_type == FORCEFRQ_TYPE_NR { "nr" } else { "lte" };
+            let cell = fetch_primary_cell(conn, tech).await?;
+            let camped = (parse_first::<u32>(&cell.arfcn), parse_first::<u16>(&cell.pci));
+            if camped != (Some(*arfcn), Some(*pci)) {
+                return Err(format!(
+                    "Camped on {} {}/{}, expected {}/{}",
+                    tech, cell.arfcn, cell.pci, arfcn, pci
+                ));
+            }
+            format!("Camped on {} {}/{}", tech, arfcn, pci)
+        }
+        SafeLockTarget::Band { lte_bands, nr_bands } => {
+            let serving = get_serving_cell_info(conn)
+                .await
+                .map_err(|e| format!("Failed to get serving cell info: {}", e))?;
+            let tech = serving.tech.as_str();
+            let cell = fetch_primary_cell(conn, tech).await?;
+            let band = parse_band_number(&cell.band);
+            let allowed = if tech == "nr" { nr_bands } else { lte_bands };
+            // 只约束请求中指定了频段的制式
+            if !allowed.is_empty() && !band.is_some_and(|b| allowed.iter().any(|a| *a as u16 == b)) {
+                return Err(format!("Camped on {} band {}, not in requested bands", tech, cell.band));
+            }
+            format!("Camped on {} band {}", tech, cell.band)
+        }
+    };
+
+    if require_data && !matches!(get_data_connection_status(conn, watchdog_context).await, Ok(true)) {
+        return Err(format!("{}, data connection not active", detail));
+    }
+
+    Ok(detail)
+}
@@ -0,0 +1,529 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-18 16:05:12
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-18 16:05:12
+ * @FilePath: /udx710-backend/backend/src/shaping.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! LAN 接口限速（tc HTB）
+//!
+//! 下行在 LAN 接口出方向挂载 HTB，上行通过 ingress + mirred 重定向到 ifb0
+//! 后挂载 HTB。两个方向结构相同：1:1 为总限速，1:2 为未单独限速客户端的
+//! 默认类，每个客户端一个子类，u32 过滤器按以太网头中的 MAC 分类，因此
+//! IPv4 / IPv6 均生效。
+//!
+//! SFP 硬件转发加速会绕过 qdisc，限速生效期间关闭，取消后重新开启。
+
+use serde::Serialize;
+use std::process::Command;
+use std::sync::Mutex;
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::config::ShapingConfig;
+use crate::lan::{format_mac, parse_mac};
+
+/// 上行整形使用的 IFB 设备
+pub const IFB_DEVICE: &str = "ifb0";
+
+/// 单项限速上限（kbit/s）
+const MAX_RATE_KBPS: u32 = 10_000_000;
+
+/// 不限总速率时 1:1 使用的速率（kbit/s）
+const LINK_RATE_KBPS: u32 = 1_000_000;
+
+/// 客户端限速条数上限
+const MAX_CLIENTS: usize = 200;
+
+/// 客户端子类起始编号（1:10 起，十六进制）
+const CLIENT_CLASS_BASE: usize = 0x10;
+
+/// 串行化下发，避免并发修改 qdisc
+static APPLY_LOCK: Mutex<()> = Mutex::new(());
+
+/// 运行状态
+static RUNTIME: Mutex<ShapingRuntime> = Mutex::new(ShapingRuntime {
+    interface: None,
+    last_applied_at: None,
+    last_error: None,
+});
+
+struct ShapingRuntime {
+    /// 最近一次下发使用的 LAN 接口（接口名变化时清理旧接口）
+    interface: Option<String>,
+    last_applied_at: Option<String>,
+    last_error: Option<String>,
+}
+
+/// 单个 HTB 类的队列统计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStats {
+    pub class_id: String,
+    /// total（总限速）/ default（未单独限速的客户端）/ 客户端 MAC
+    pub target: String,
+    pub name: String,
+    /// 配置的限速（kbit/s），0 表示不限
+    pub rate_kbps: u32,
+    pub sent_bytes: u64,
+    pub sent_packets: u64,
+    pub dropped: u64,
+    pub overlimits: u64,
+    pub backlog_bytes: u64,
+    pub backlog_packets: u64,
+}
+
+/// 限速状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ShapingStatus {
+    pub enabled: bool,
+    pub interface: String,
+    /// 下行 HTB 是否已挂载
+    pub download_active: bool,
+    /// 上行 HTB 是否已挂载（ifb0）
+    pub upload_active: bool,
+    pub last_applied_at: Option<String>,
+    pub last_error: Option<String>,
+    pub download: Vec<QueueStats>,
+    pub upload: Vec<QueueStats>,
+}
+
+/// 校验限速配置
+pub fn validate(config: &ShapingConfig) -> Result<(), String> {
+    if config.download_kbps > MAX_RATE_KBPS || config.upload_kbps > MAX_RATE_KBPS {
+        return Err(format!("Rate must not exceed {} kbit/s", MAX_RATE_KBPS));
+    }
+    if config.clients.len() > MAX_CLIENTS {
+        return Err(format!("At most {} client limits are supported", MAX_CLIENTS));
+    }
+    let mut seen = Vec::new();
+    for client in &config.clients {
+        let mac = parse_mac(&client.mac).ok_or_else(|| format!("Invalid MAC address: {}", client.mac))?;
+        if seen.contains(&mac) {
+            return Err(format!("Duplicate client limit: {}", client.mac));
+        }
+        seen.push(mac);
+        if client.download_kbps > MAX_RATE_KBPS || client.upload_kbps > MAX_RATE_KBPS {
+            return Err(format!("Rate must not exceed {} kbit/s: {}", MAX_RATE_KBPS, client.mac));
+        }
+    }
+    Ok(())
+}
+
+/// HTB 类
+struct HtbClass {
+    class_id: String,
+    /// total / default / 客户端 MAC
+    target: String,
+    name: String,
+    /// 0 表示不限
+    rate_kbps: u32,
+    /// 按 MAC 分类的客户端类
+    mac: Option<[u8; 6]>,
+}
+
+/// 一个方向的 HTB 类（未启用或无任何限速时为空）
+fn direction_classes(config: &ShapingConfig, upload: bool) -> Vec<HtbClass> {
+    let total = if upload { config.upload_kbps } else { config.download_kbps };
+    let clients: Vec<HtbClass> = config
+        .clients
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.enabled)
+        .filter_map(|(i, c)| {
+            let rate_kbps = if upload { c.upload_kbps } else { c.download_kbps };
+            let mac = parse_mac(&c.mac)?;
+            (rate_kbps > 0).then(|| HtbClass {
+                class_id: format!("1:{:x}", CLIENT_CLASS_BASE + i),
+                target: format_mac(&mac),
+                name: c.name.clone(),
+                rate_kbps,
+                mac: Some(mac),
+            })
+        })
+        .collect();
+    if !config.enabled || (total == 0 && clients.is_empty()) {
+        return Vec::new();
+    }
+    let shared = |class_id: &str, target: &str| HtbClass {
+        class_id: class_id.to_string(),
+        target: target.to_string(),
+        name: String::new(),
+        rate_kbps: total,
+        mac: None,
+    };
+    let mut classes = vec![shared("1:1", "total"), shared("1:2", "default")];
+    classes.extend(clients);
+    classes
+}
+
+/// u32 匹配以太网头中的 MAC（相对网络层头部的负偏移，按 2 / 4 字节对齐拆分）
+fn mac_match(mac: &[u8; 6], source: bool) -> Vec<String> {
+    let keys: [(&str, &[u8], &str); 2] = if source {
+        // 源 MAC 位于 -8..-2
+        [("u32", &mac[0..4], "-8"), ("u16", &mac[4..6], "-4")]
+    } else {
+        // 目的 MAC 位于 -14..-8
+        [("u16", &mac[0..2], "-14"), ("u32", &mac[2..6], "-12")]
+    };
+    keys.iter()
+        .flat_map(|(kind, bytes, at)| {
+            let value: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let mask = if *kind == "u32" { "0xffffffff" } else { "0xffff" };
+            to_args(&["match", kind, &format!("0x{}", value), mask, "at", at])
+        })
+        .collect()
+}
+
+fn to_args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+/// 生成一个方向的 HTB 命令
+fn build_htb(device: &str, config: &ShapingConfig, upload: bool) -> Vec<Vec<String>> {
+    let classes = direction_classes(config, upload);
+    if classes.is_empty() {
+        return Vec::new();
+    }
+    let mut commands = vec![to_args(&["qdisc", "add", "dev", device, "root", "handle", "1:", "htb", "default", "2"])];
+    for class in &classes {
+        let parent = if class.class_id == "1:1" { "1:" } else { "1:1" };
+        let rate = format!("{}kbit", if class.rate_kbps == 0 { LINK_RATE_KBPS } else { class.rate_kbps });
+        commands.push(to_args(&[
+            "class", "add", "dev", device, "parent", parent, "classid", &class.class_id, "htb", "rate", &rate, "ceil",
+            &rate,
+        ]));
+        if let Some(mac) = &class.mac {
+            let mut filter = to_args(&["filter", "add", "dev", device, "parent", "1:", "protocol", "all", "prio", "1", "u32"]);
+            filter.extend(mac_match(mac, upload));
+            filter.extend(to_args(&["flowid", &class.class_id]));
+            commands.push(filter);
+        }
+    }
+    commands
+}
+
+/// 生成 tc 命令（假定接口上没有已挂载的 qdisc）
+pub fn build_commands(config: &ShapingConfig, interface: &str) -> Vec<Vec<String>> {
+    let mut commands = build_htb(interface, config, false);
+    let upload = build_htb(IFB_DEVICE, config, true);
+    if !upload.is_empty() {
+        commands.push(to_args(&["qdisc", "add", "dev", interface, "handle", "ffff:", "ingress"]));
+        commands.push(to_args(&[
+            "filter", "add", "dev", interface, "parent", "ffff:", "protocol", "all", "prio", "1", "u32", "match",
+            "u32", "0", "0", "action", "mirred", "egress", "redirect", "dev", IFB_DEVICE,
+        ]));
+        commands.extend(upload);
+    }
+    commands
+}
+
+fn run_tc<S: AsRef<str>>(args: &[S]) -> Result<String, String> {
+    let output = Command::new("tc")
+        .args(args.iter().map(|a| a.as_ref()))
+        .output()
+        .map_err(|e| format!("Failed to execute tc: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let args: Vec<&str> = args.iter().map(|a| a.as_ref()).collect();
+        Err(format!(
+            "tc {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// 卸载接口上的 HTB、ingress 和 ifb0 上的 HTB
+fn teardown(interface: &str) {
+    let _ = run_tc(&["qdisc", "del", "dev", interface, "root"]);
+    let _ = run_tc(&["qdisc", "del", "dev", interface, "ingress"]);
+    let _ = run_tc(&["qdisc", "del", "dev", IFB_DEVICE, "root"]);
+}
+
+/// 创建并启用 ifb0
+fn ensure_ifb() -> Result<(), String> {
+    let exists = |dev: &str| std::path::Path::new(&format!("/sys/class/net/{}", dev)).exists();
+    if !exists(IFB_DEVICE) {
+        let _ = Command::new("modprobe").args(["ifb", "numifbs=1"]).output();
+        if !exists(IFB_DEVICE) {
+            let _ = Command::new("ip").args(["link", "add", IFB_DEVICE, "type", "ifb"]).output();
+        }
+    }
+    let output = Command::new("ip")
+        .args(["link", "set", IFB_DEVICE, "up"])
+        .output()
+        .map_err(|e| format!("Failed to execute ip: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to bring up {}: {}",
+            IFB_DEVICE,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn install(config: &ShapingConfig, interface: &str) -> Result<bool, String> {
+    let commands = build_commands(config, interface);
+    if commands.is_empty() {
+        return Ok(false);
+    }
+    if !direction_classes(config, true).is_empty() {
+        ensure_ifb()?;
+    }
+    for args in &commands {
+        run_tc(args)?;
+    }
+    Ok(true)
+}
+
+/// 下发限速规则（重建 qdisc），未启用时卸载
+pub fn apply_blocking(config: &ShapingConfig, interface: &str) -> Result<(), String> {
+    let _guard = APPLY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let previous = RUNTIME.lock().ok().and_then(|r| r.interface.clone());
+    if let Some(previous) = previous.filter(|p| p != interface) {
+        teardown(&previous);
+    }
+    teardown(interface);
+
+    let result = install(config, interface);
+    if result.is_err() {
+        teardown(interface);
+    }
+    let active = matches!(result, Ok(true));
+    crate::usb_switch::inhibit_sfp(crate::usb_switch::SfpInhibitor::Shaping, active);
+
+    if let Ok(mut runtime) = RUNTIME.lock() {
+        runtime.interface = Some(interface.to_string());
+        runtime.last_applied_at = Some(chrono::Utc::now().to_rfc3339());
+        runtime.last_error = result.as_ref().err().cloned();
+    }
+    match &result {
+        Ok(true) => info!(interface, clients = config.clients.len(), "Traffic shaping applied"),
+        Ok(false) => info!(interface, "Traffic shaping disabled"),
+        Err(e) => warn!(interface, error = %e, "Failed to apply traffic shaping"),
+    }
+    result.map(|_| ())
+}
+
+/// 异步下发限速规则
+pub async fn apply(config: ShapingConfig, interface: String) -> Result<(), String> {
+    task::spawn_blocking(move || apply_blocking(&config, &interface))
+        .await
+        .map_err(|e| format!("Task execution failed: {}", e))?
+}
+
+/// 解析 tc 大小（如 0b、1514b、12Kb、3Mb）
+fn parse_size(value: &str) -> u64 {
+    let value = value.trim_end_matches('b');
+    let (number, multiplier) = match value.chars().last() {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().unwrap_or(0) * multiplier
+}
+
+/// 解析 `tc -s class show` 输出（类编号 → 统计）
+fn parse_class_stats(content: &str) -> Vec<QueueStats> {
+    let mut result: Vec<QueueStats> = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line
+            .split_whitespace()
+            .map(|f| f.trim_matches(|c| c == '(' || c == ')' || c == ','))
+            .collect();
+        let number = |key: &str| {
+            fields
+                .iter()
+                .position(|f| *f == key)
+                .and_then(|i| fields.get(i + 1))
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        match fields.first() {
+            Some(&"class") if fields.len() > 2 => result.push(QueueStats {
+                class_id: fields[2].to_string(),
+                ..Default::default()
+            }),
+            Some(&"Sent") => {
+                if let Some(stats) = result.last_mut() {
+                    stats.sent_bytes = number("Sent").unwrap_or(0);
+                    stats.sent_packets = fields.get(3).and_then(|v| v.parse().ok()).unwrap_or(0);
+                    stats.dropped = number("dropped").unwrap_or(0);
+                    stats.overlimits = number("overlimits").unwrap_or(0);
+                }
+            }
+            Some(&"backlog") => {
+                if let Some(stats) = result.last_mut() {
+                    stats.backlog_bytes = fields.get(1).map(|v| parse_size(v)).unwrap_or(0);
+                    stats.backlog_packets = fields
+                        .get(2)
+                        .and_then(|v| v.trim_end_matches('p').parse().ok())
+                        .unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// 读取一个方向的队列统计并按配置标注
+fn direction_stats(device: &str, config: &ShapingConfig, upload: bool) -> Vec<QueueStats> {
+    let Ok(output) = run_tc(&["-s", "class", "show", "dev", device]) else {
+        return Vec::new();
+    };
+    let classes = direction_classes(config, upload);
+    parse_class_stats(&output)
+        .into_iter()
+        .map(|mut stats| {
+            if let Some(class) = classes.iter().find(|c| c.class_id == stats.class_id) {
+                stats.target = class.target.clone();
+                stats.name = class.name.clone();
+                stats.rate_kbps = class.rate_kbps;
+            }
+            stats
+        })
+        .collect()
+}
+
+/// 获取限速状态和实时队列统计
+pub async fn status(config: ShapingConfig, interface: String) -> ShapingStatus {
+    let (download, upload) = {
+        let config = config.clone();
+        let interface = interface.clone();
+        task::spawn_blocking(move || {
+            (
+                direction_stats(&interface, &config, false),
+                direction_stats(IFB_DEVICE, &config, true),
+            )
+        })
+        .await
+        .unwrap_or_default()
+    };
+    let runtime = RUNTIME.lock().ok();
+    ShapingStatus {
+        enabled: config.enabled,
+        interface,
+        download_active: !download.is_empty(),
+        upload_active: !upload.is_empty(),
+        last_applied_at: runtime.as_ref().and_then(|r| r.last_applied_at.clone()),
+        last_error: runtime.as_ref().and_then(|r| r.last_error.clone()),
+        download,
+        upload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientLimit;
+
+    fn sample_config() -> ShapingConfig {
+        ShapingConfig {
+            enabled: true,
+            download_kbps: 50000,
+            upload_kbps: 0,
+            clients: vec![
+                ClientLimit {
+                    mac: "AA:BB:CC:DD:EE:01".to_string(),
+                    name: "tv".to_string(),
+                    download_kbps: 8000,
+                    upload_kbps: 2000,
+                    ..Default::default()
+                },
+                ClientLimit {
+                    mac: "aa:bb:cc:dd:ee:02".to_string(),
+                    download_kbps: 1000,
+                    enabled: false,
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&sample_config()).is_ok());
+
+        let mut config = sample_config();
+        config.clients[1].mac = "aa-bb-cc-dd-ee-01".to_string();
+        assert!(validate(&config).is_err());
+
+        let mut config = sample_config();
+        config.clients[0].mac = "not-a-mac".to_string();
+        assert!(validate(&config).is_err());
+
+        let mut config = sample_config();
+        config.upload_kbps = MAX_RATE_KBPS + 1;
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_build_commands() {
+        let commands: Vec<String> = build_commands(&sample_config(), "usb0")
+            .iter()
+            .map(|c| c.join(" "))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "qdisc add dev usb0 root handle 1: htb default 2",
+                "class add dev usb0 parent 1: classid 1:1 htb rate 50000kbit ceil 50000kbit",
+                "class add dev usb0 parent 1:1 classid 1:2 htb rate 50000kbit ceil 50000kbit",
+                "class add dev usb0 parent 1:1 classid 1:10 htb rate 8000kbit ceil 8000kbit",
+                "filter add dev usb0 parent 1: protocol all prio 1 u32 match u16 0xaabb 0xffff at -14 \
+                 match u32 0xccddee01 0xffffffff at -12 flowid 1:10",
+                "qdisc add dev usb0 handle ffff: ingress",
+                "filter add dev usb0 parent ffff: protocol all prio 1 u32 match u32 0 0 \
+                 action mirred egress redirect dev ifb0",
+                "qdisc add dev ifb0 root handle 1: htb default 2",
+                "class add dev ifb0 parent 1: classid 1:1 htb rate 1000000kbit ceil 1000000kbit",
+                "class add dev ifb0 parent 1:1 classid 1:2 htb rate 1000000kbit ceil 1000000kbit",
+                "class add dev ifb0 parent 1:1 classid 1:10 htb rate 2000kbit ceil 2000kbit",
+                "filter add dev ifb0 parent 1: protocol all prio 1 u32 match u32 0xaabbccdd 0xffffffff at -8 \
+                 match u16 0xee01 0xffff at -4 flowid 1:10",
+            ]
+        );
+
+        let mut config = sample_config();
+        config.enabled = false;
+        assert!(build_commands(&config, "usb0").is_empty());
+
+        let mut config = sample_config();
+        config.download_kbps = 0;
+        config.clients.clear();
+        assert!(build_commands(&config, "usb0").is_empty());
+    }
+
+    #[test]
+    fn test_parse_class_stats() {
+        let output = "\
+class htb 1:1 root rate 50Mbit ceil 50Mbit burst 1600b cburst 1600b
+ Sent 123456 bytes 789 pkt (dropped 0, overlimits 12 requeues 0)
+ backlog 0b 0p requeues 0
+ lended: 0 borrowed: 0 giants: 0
+ tokens: 4000 ctokens: 4000
+
+class htb 1:10 parent 1:1 prio 0 rate 8Mbit ceil 8Mbit burst 1600b cburst 1600b
+ Sent 98765 bytes 321 pkt (dropped 7, overlimits 40 requeues 0)
+ backlog 3Kb 2p requeues 0
+";
+        let stats = parse_class_stats(output);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].class_id, "1:1");
+        assert_eq!(stats[0].sent_bytes, 123456);
+        assert_eq!(stats[0].sent_packets, 789);
+        assert_eq!(stats[0].overlimits, 12);
+        assert_eq!(stats[1].class_id, "1:10");
+        assert_eq!(stats[1].dropped, 7);
+        assert_eq!(stats[1].backlog_bytes, 3072);
+        assert_eq!(stats[1].backlog_packets, 2);
+    }
+}
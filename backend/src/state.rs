@@ -16,8 +16,13 @@ use std::sync::Arc;
 use axum::extract::FromRef;
 use zbus::Connection;
 
+use crate::cell_optimizer::CellOptimizer;
 use crate::config::ConfigManager;
+use crate::data_session::DataSessionTracker;
 use crate::db::Database;
+use crate::firewall::FirewallManager;
+use crate::safe_lock::SafeLockManager;
+use crate::thermal::ThermalMonitor;
 use crate::webhook::WebhookSender;
 
 /// 应用全局状态
@@ -33,21 +38,42 @@ pub struct AppState {
     pub config_manager: Arc<ConfigManager>,
     /// Webhook 发送器（用于转发 SMS 和通话通知）
     pub webhook_sender: Arc<WebhookSender>,
+    /// 温度监控器（用于查询温度告警状态）
+    pub thermal_monitor: Arc<ThermalMonitor>,
+    /// 最佳小区优化器（用于启动/取消优化任务）
+    pub cell_optimizer: Arc<CellOptimizer>,
+    /// 安全锁定管理器（锁定后观察并自动恢复）
+    pub safe_lock: Arc<SafeLockManager>,
+    /// 防火墙管理器（下发规则、查询状态）
+    pub firewall: Arc<FirewallManager>,
+    /// 数据会话追踪器（主动断开前标记结束原因）
+    pub sessions: Arc<DataSessionTracker>,
 }
 
 impl AppState {
     /// 创建新的应用状态
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         dbus_conn: Arc<Connection>,
         database: Arc<Database>,
         config_manager: Arc<ConfigManager>,
         webhook_sender: Arc<WebhookSender>,
+        thermal_monitor: Arc<ThermalMonitor>,
+        cell_optimizer: Arc<CellOptimizer>,
+        safe_lock: Arc<SafeLockManager>,
+        firewall: Arc<FirewallManager>,
+        sessions: Arc<DataSessionTracker>,
     ) -> Self {
         Self {
             dbus_conn,
             database,
             config_manager,
             webhook_sender,
+            thermal_monitor,
+            cell_optimizer,
+            safe_lock,
+            firewall,
+            sessions,
         }
     }
 }
@@ -79,6 +105,36 @@ impl FromRef<AppState> for Arc<WebhookSender> {
     }
 }
 
+impl FromRef<AppState> for Arc<ThermalMonitor> {
+    fn from_ref(state: &AppState) -> Self {
+        state.thermal_monitor.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<CellOptimizer> {
+    fn from_ref(state: &AppState) -> Self {
+        state.cell_optimizer.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<SafeLockManager> {
+    fn from_ref(state: &AppState) -> Self {
+        state.safe_lock.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<FirewallManager> {
+    fn from_ref(state: &AppState) -> Self {
+        state.firewall.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<DataSessionTracker> {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
+}
+
 // 支持 (Arc<Connection>, Arc<Database>) 元组类型
 impl FromRef<AppState> for (Arc<Connection>, Arc<Database>) {
     fn from_ref(state: &AppState) -> Self {
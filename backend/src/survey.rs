@@ -0,0 +1,421 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-15 11:20:48
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-15 11:20:48
+ * @FilePath: /udx710-backend/backend/src/survey.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 站点勘测模块
+//!
+//! 安装时走动/驾车寻找最佳安装位置：按较高频率采样主小区和邻区，
+//! 每个观测到的小区连同信号和时间写入数据库，并可附带用户输入的位置标签或坐标。
+//! 会话结束后可按小区汇总最好/平均/最差信号，并导出为 CSV 或 KML。
+
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::band_lock::parse_first;
+use crate::db::{Database, SurveySample, SurveySession};
+use crate::dbus::{get_serving_cell_info, send_at_command};
+use crate::models::{CellInfo, SurveyCellSummary, SurveyStartRequest, SurveyStatus, SurveyTagRequest};
+use crate::utils::{get_cell_command_config, parse_at_response_to_2d_vec, parse_neighbor_cells, parse_primary_cell};
+
+/// 最小采样间隔（毫秒）
+const MIN_INTERVAL_MS: u64 = 1000;
+
+/// 正在进行的勘测
+static ACTIVE: Mutex<Option<(SurveyStatus, AbortHandle)>> = Mutex::new(None);
+
+/// 获取勘测状态
+pub fn status() -> SurveyStatus {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(status, _)| status.clone())
+        .unwrap_or_default()
+}
+
+/// 开始勘测（同一时间只能有一个勘测会话）
+pub fn start(conn: Arc<Connection>, db: Arc<Database>, request: SurveyStartRequest) -> Result<SurveyStatus, String> {
+    let mut active = ACTIVE.lock().unwrap();
+    if active.is_some() {
+        return Err("A survey is already running".to_string());
+    }
+
+    let interval_ms = request.interval_ms.max(MIN_INTERVAL_MS);
+    let name = if request.name.trim().is_empty() {
+        format!("Survey {}", Utc::now().format("%Y-%m-%d %H:%M"))
+    } else {
+        request.name.trim().to_string()
+    };
+    let session_id = db
+        .insert_survey_session(&name, interval_ms as i64)
+        .map_err(|e| format!("Failed to create survey session: {}", e))?;
+
+    let status = SurveyStatus {
+        active: true,
+        session_id: Some(session_id),
+        interval_ms,
+        label: request.label,
+        latitude: request.latitude,
+        longitude: request.longitude,
+        ..Default::default()
+    };
+
+    let task = tokio::spawn(run(conn, db, session_id, interval_ms));
+    *active = Some((status.clone(), task.abort_handle()));
+
+    info!(session_id, interval_ms, name = %name, "Site survey started");
+    Ok(status)
+}
+
+/// 结束勘测
+pub fn stop(db: &Database) -> Result<SurveyStatus, String> {
+    let (mut status, handle) = ACTIVE.lock().unwrap().take().ok_or("No survey is running")?;
+    handle.abort();
+
+    if let Some(session_id) = status.session_id {
+        db.finish_survey_session(session_id)
+            .map_err(|e| format!("Failed to finish survey session: {}", e))?;
+        info!(session_id, rounds = status.rounds, "Site survey stopped");
+    }
+    status.active = false;
+    Ok(status)
+}
+
+/// 更新位置标签（应用到之后的采样）
+pub fn tag(request: SurveyTagRequest) -> Result<SurveyStatus, String> {
+    let mut active = ACTIVE.lock().unwrap();
+    let (status, _) = active.as_mut().ok_or("No survey is running")?;
+    status.label = request.label;
+    status.latitude = request.latitude;
+    status.longitude = request.longitude;
+    Ok(status.clone())
+}
+
+/// 采样循环
+async fn run(conn: Arc<Connection>, db: Arc<Database>, session_id: i64, interval_ms: u64) {
+    let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        // 天线对准期间暂停采样
+        if crate::alignment::is_active() {
+            continue;
+        }
+
+        let result = sample_cells(&conn).await;
+        let timestamp = Utc::now().to_rfc3339();
+
+        let mut active = ACTIVE.lock().unwrap();
+        let Some((status, _)) = active.as_mut().filter(|(s, _)| s.session_id == Some(session_id)) else {
+            return;
+        };
+        status.rounds += 1;
+
+        match result {
+            Ok(cells) => {
+                let samples: Vec<SurveySample> = cells
+                    .iter()
+                    .filter_map(|cell| to_sample(cell, &timestamp, status))
+                    .collect();
+                status.last_cell_count = samples.len();
+                status.last_error = None;
+                if let Err(e) = db.insert_survey_samples(session_id, &samples) {
+                    warn!(error = %e, "Survey: failed to store samples");
+                    status.last_error = Some(format!("Failed to store samples: {}", e));
+                }
+            }
+            Err(e) => {
+                status.last_cell_count = 0;
+                status.last_error = Some(e);
+            }
+        }
+    }
+}
+
+/// 查询主小区和邻区
+async fn sample_cells(conn: &Connection) -> Result<Vec<CellInfo>, String> {
+    let serving = get_serving_cell_info(conn)
+        .await
+        .map_err(|e| format!("Failed to get serving cell info: {}", e))?;
+    let tech = serving.tech.as_str();
+    let cmd_config = get_cell_command_config(tech)
+        .ok_or_else(|| format!("Unsupported network type: {}", tech))?;
+
+    // 注意：AT 指令必须串行执行
+    let primary = send_at_command(conn, cmd_config.primary)
+        .await
+        .map_err(|e| format!("Primary cell AT command failed: {}", e))?;
+    let neighbor = send_at_command(conn, cmd_config.neighbor)
+        .await
+        .map_err(|e| format!("Neighbor cell AT command failed: {}", e))?;
+
+    let mut cells = vec![parse_primary_cell(tech, &parse_at_response_to_2d_vec(&primary))];
+    cells.extend(parse_neighbor_cells(tech, &parse_at_response_to_2d_vec(&neighbor)));
+    Ok(cells)
+}
+
+/// 将小区信息转换为采样记录（信号原始值 ×100 换算为 dBm/dB）
+fn to_sample(cell: &CellInfo, timestamp: &str, status: &SurveyStatus) -> Option<SurveySample> {
+    let arfcn = parse_first::<u32>(&cell.arfcn).filter(|a| *a != 0)?;
+    let scaled = |raw: &str| parse_first::<i32>(raw).map(|v| v as f64 / 100.0);
+    Some(SurveySample {
+        timestamp: timestamp.to_string(),
+        label: status.label.clone(),
+        latitude: status.latitude,
+        longitude: status.longitude,
+        tech: cell.tech.clone(),
+        band: cell.band.split(',').next().unwrap_or_default().to_string(),
+        arfcn,
+        pci: parse_first(&cell.pci)?,
+        is_serving: cell.is_serving,
+        rsrp: scaled(&cell.rsrp),
+        rsrq: scaled(&cell.rsrq),
+        sinr: scaled(&cell.sinr),
+    })
+}
+
+/// 按小区（制式 + 频点 + PCI）汇总，按最好 RSRP 降序排列
+pub fn summarize(samples: &[SurveySample]) -> Vec<SurveyCellSummary> {
+    struct Acc {
+        summary: SurveyCellSummary,
+        rsrp_sum: f64,
+        rsrp_count: u64,
+        sinr_sum: f64,
+        sinr_count: u64,
+    }
+
+    let mut cells: HashMap<(String, u32, u16), Acc> = HashMap::new();
+    for s in samples {
+        let acc = cells.entry((s.tech.clone(), s.arfcn, s.pci)).or_insert_with(|| Acc {
+            summary: SurveyCellSummary {
+                tech: s.tech.clone(),
+                arfcn: s.arfcn,
+                pci: s.pci,
+                first_seen: s.timestamp.clone(),
+                ..Default::default()
+            },
+            rsrp_sum: 0.0,
+            rsrp_count: 0,
+            sinr_sum: 0.0,
+            sinr_count: 0,
+        });
+        let summary = &mut acc.summary;
+        summary.samples += 1;
+        summary.serving_samples += s.is_serving as u64;
+        summary.last_seen = s.timestamp.clone();
+        if !s.band.is_empty() {
+            summary.band = s.band.clone();
+        }
+        if let Some(rsrp) = s.rsrp {
+            if summary.best_rsrp.is_none_or(|best| rsrp > best) {
+                summary.best_rsrp = Some(rsrp);
+                summary.best_label = s.label.clone();
+            }
+            if summary.worst_rsrp.is_none_or(|worst| rsrp < worst) {
+                summary.worst_rsrp = Some(rsrp);
+            }
+            acc.rsrp_sum += rsrp;
+            acc.rsrp_count += 1;
+        }
+        if let Some(sinr) = s.sinr {
+            acc.sinr_sum += sinr;
+            acc.sinr_count += 1;
+        }
+    }
+
+    let round1 = |v: f64| (v * 10.0).round() / 10.0;
+    let mut result: Vec<SurveyCellSummary> = cells
+        .into_values()
+        .map(|acc| {
+            let mut summary = acc.summary;
+            summary.avg_rsrp = (acc.rsrp_count > 0).then(|| round1(acc.rsrp_sum / acc.rsrp_count as f64));
+            summary.avg_sinr = (acc.sinr_count > 0).then(|| round1(acc.sinr_sum / acc.sinr_count as f64));
+            summary
+        })
+        .collect();
+
+    result.sort_by(|a, b| {
+        b.best_rsrp
+            .unwrap_or(f64::MIN)
+            .total_cmp(&a.best_rsrp.unwrap_or(f64::MIN))
+            .then_with(|| b.samples.cmp(&a.samples))
+    });
+    result
+}
+
+/// CSV 字段转义
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// 导出为 CSV（每个小区观测一行）
+pub fn to_csv(samples: &[SurveySample]) -> String {
+    let opt = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let mut out = String::from("timestamp,label,latitude,longitude,tech,band,arfcn,pci,serving,rsrp_dbm,rsrq_db,sinr_db\n");
+    for s in samples {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            s.timestamp,
+            csv_field(&s.label),
+            opt(s.latitude),
+            opt(s.longitude),
+            s.tech,
+            s.band,
+            s.arfcn,
+            s.pci,
+            s.is_serving as u8,
+            opt(s.rsrp),
+            opt(s.rsrq),
+            opt(s.sinr)
+        ));
+    }
+    out
+}
+
+/// XML 转义
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// 按 RSRP 选择 KML 样式
+fn kml_style(rsrp: Option<f64>) -> &'static str {
+    match rsrp {
+        Some(r) if r >= -90.0 => "#good",
+        Some(r) if r >= -105.0 => "#fair",
+        _ => "#poor",
+    }
+}
+
+/// 导出为 KML（只包含带坐标的采样，每个小区一个文件夹）
+pub fn to_kml(session: &SurveySession, samples: &[SurveySample]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    out.push_str(&format!("<name>{}</name>\n", xml_escape(&session.name)));
+    // 颜色格式为 aabbggrr
+    for (id, color) in [("good", "ff00c000"), ("fair", "ff00c0ff"), ("poor", "ff0000ff")] {
+        out.push_str(&format!(
+            "<Style id=\"{}\"><IconStyle><color>{}</color></IconStyle></Style>\n",
+            id, color
+        ));
+    }
+
+    for cell in summarize(samples) {
+        let points: Vec<&SurveySample> = samples
+            .iter()
+            .filter(|s| s.tech == cell.tech && s.arfcn == cell.arfcn && s.pci == cell.pci)
+            .filter(|s| s.latitude.is_some() && s.longitude.is_some())
+            .collect();
+        if points.is_empty() {
+            continue;
+        }
+
+        out.push_str(&format!(
+            "<Folder>\n<name>{} {} {}/{}</name>\n",
+            cell.tech.to_uppercase(),
+            xml_escape(&cell.band),
+            cell.arfcn,
+            cell.pci
+        ));
+        for s in points {
+            let rsrp = s.rsrp.map(|r| format!("{} dBm", r)).unwrap_or_else(|| "-".to_string());
+            out.push_str(&format!(
+                "<Placemark><name>{}</name><description>{} {}{}</description><TimeStamp><when>{}</when></TimeStamp><styleUrl>{}</styleUrl><Point><coordinates>{},{},0</coordinates></Point></Placemark>\n",
+                rsrp,
+                xml_escape(&s.label),
+                if s.is_serving { "serving" } else { "neighbor" },
+                s.sinr.map(|v| format!(", SINR {} dB", v)).unwrap_or_default(),
+                s.timestamp,
+                kml_style(s.rsrp),
+                s.longitude.unwrap_or_default(),
+                s.latitude.unwrap_or_default()
+            ));
+        }
+        out.push_str("</Folder>\n");
+    }
+
+    out.push_str("</Document>\n</kml>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(label: &str, pci: u16, serving: bool, rsrp: f64, coords: Option<(f64, f64)>) -> SurveySample {
+        SurveySample {
+            timestamp: "2025-12-15T10:00:00+00:00".to_string(),
+            label: label.to_string(),
+            latitude: coords.map(|c| c.0),
+            longitude: coords.map(|c| c.1),
+            tech: "nr".to_string(),
+            band: "n78".to_string(),
+            arfcn: 633984,
+            pci,
+            is_serving: serving,
+            rsrp: Some(rsrp),
+            rsrq: Some(-11.0),
+            sinr: serving.then_some(15.0),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let samples = vec![
+            sample("roof", 597, true, -88.0, None),
+            sample("balcony", 597, true, -101.0, None),
+            sample("balcony", 12, false, -95.0, None),
+            sample("window", 597, true, -92.5, None),
+        ];
+        let cells = summarize(&samples);
+        assert_eq!(cells.len(), 2);
+
+        let best = &cells[0];
+        assert_eq!((best.pci, best.samples, best.serving_samples), (597, 3, 3));
+        assert_eq!((best.best_rsrp, best.worst_rsrp, best.avg_rsrp), (Some(-88.0), Some(-101.0), Some(-93.8)));
+        assert_eq!(best.best_label, "roof");
+        assert_eq!(best.avg_sinr, Some(15.0));
+        assert_eq!(cells[1].avg_sinr, None);
+    }
+
+    #[test]
+    fn test_exports() {
+        let samples = vec![
+            sample("roof, east", 597, true, -88.0, Some((39.9, 116.4))),
+            sample("", 12, false, -110.0, None),
+        ];
+        let csv = to_csv(&samples);
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.contains("\"roof, east\",39.9,116.4,nr,n78,633984,597,1,-88,-11,15"));
+
+        let session = SurveySession {
+            name: "Site <A>".to_string(),
+            ..Default::default()
+        };
+        let kml = to_kml(&session, &samples);
+        assert!(kml.contains("<name>Site &lt;A&gt;</name>"));
+        assert!(kml.contains("<coordinates>116.4,39.9,0</coordinates>"));
+        assert_eq!(kml.matches("<Placemark>").count(), 1);
+        assert!(kml.contains("<styleUrl>#good</styleUrl>"));
+    }
+}
@@ -0,0 +1,614 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-14 15:20:41
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-14 15:20:41
+ * @FilePath: /udx710-backend/backend/src/thermal.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 温度监控模块
+//!
+//! 周期采集 /sys/class/thermal 温度并写入历史，按阈值判断告警等级（带滞回），
+//! 过温时可选执行保护动作（强制 LTE only、锁定单一频段减少载波聚合、Webhook 事件），
+//! 降温后自动恢复原有设置。执行或恢复失败的动作在之后每次采样时重试，直到成功。
+//! 保护期间射频模式或频段锁定被用户、锁定方案或小区优化修改过时，不再覆盖为保护前的设置。
+
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::config::{ConfigManager, ThermalConfig};
+use crate::db::Database;
+use crate::dbus::{get_radio_mode, get_serving_cell_info, send_at_command, set_radio_mode};
+use crate::models::{RadioMode, ThermalZone};
+use crate::utils::{
+    bands_to_bitmask, build_splband_lte_command, build_splband_nr_command, get_cell_command_config,
+    parse_at_response_to_2d_vec, parse_splband_lte_response, parse_splband_nr_response,
+};
+use crate::webhook::WebhookSender;
+
+/// 内存中保留的最近事件数
+const MAX_RECENT_EVENTS: usize = 50;
+
+/// 最小采样间隔（秒）
+const MIN_INTERVAL_SECS: u64 = 5;
+
+/// 读取温度传感器数据
+pub fn read_temperature_sensors() -> Vec<ThermalZone> {
+    use std::fs;
+    use std::path::Path;
+
+    let thermal_path = Path::new("/sys/class/thermal");
+    let mut sensors = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(thermal_path) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let name = file_name.to_string_lossy();
+
+            if name.starts_with("thermal_zone") {
+                let zone_path = entry.path();
+
+                let sensor_type = fs::read_to_string(zone_path.join("type"))
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_default();
+
+                let temperature = fs::read_to_string(zone_path.join("temp"))
+                    .ok()
+                    .and_then(|s| s.trim().parse::<i32>().ok())
+                    .map(|t| t as f64 / 1000.0)
+                    .unwrap_or(0.0);
+
+                sensors.push(ThermalZone {
+                    zone: name.to_string(),
+                    sensor_type,
+                    temperature,
+                });
+            }
+        }
+    }
+
+    sensors.sort_by(|a, b| a.zone.cmp(&b.zone));
+    sensors
+}
+
+/// 温度告警等级
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThermalLevel {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl ThermalLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ThermalLevel::Normal => "normal",
+            ThermalLevel::Warning => "warning",
+            ThermalLevel::Critical => "critical",
+        }
+    }
+}
+
+/// 根据当前等级和最高温度计算新等级
+///
+/// 进入告警/过温后，只有温度降到 `recovery_temp` 及以下才回到正常，避免在阈值附近反复切换。
+pub fn evaluate_level(current: ThermalLevel, temperature: f64, config: &ThermalConfig) -> ThermalLevel {
+    let cooled_down = temperature <= config.recovery_temp;
+
+    if temperature >= config.critical_temp || (current == ThermalLevel::Critical && !cooled_down) {
+        ThermalLevel::Critical
+    } else if temperature >= config.warning_temp || (current == ThermalLevel::Warning && !cooled_down) {
+        ThermalLevel::Warning
+    } else {
+        ThermalLevel::Normal
+    }
+}
+
+/// 温度事件
+#[derive(Debug, Clone, Serialize)]
+pub struct ThermalEvent {
+    /// 事件时间 ISO 8601
+    pub timestamp: String,
+    /// 变化后的等级
+    pub level: ThermalLevel,
+    /// 触发时的最高温度
+    pub temperature: f64,
+    /// 最高温度所在传感器
+    pub zone: String,
+    /// 事件说明（含执行的保护动作）
+    pub message: String,
+}
+
+/// 单频段保护动作保存的原频段锁定状态
+#[derive(Debug, Clone, Copy)]
+struct SavedBandLock {
+    /// 被修改的制式（lte / nr）
+    is_nr: bool,
+    fdd_mask: u16,
+    tdd_mask: u16,
+    /// 保护动作写入的频段掩码（恢复前用于判断锁定是否已被其他操作修改）
+    applied_fdd_mask: u16,
+    applied_tdd_mask: u16,
+}
+
+/// 已执行的保护动作（用于降温后恢复）
+#[derive(Debug, Default)]
+struct ActiveActions {
+    /// 强制 LTE 前的射频模式
+    saved_radio_mode: Option<RadioMode>,
+    /// 锁定单一频段前的频段锁定状态
+    saved_band_lock: Option<SavedBandLock>,
+    /// 最近一次执行保护动作时有动作失败，过温期间继续重试
+    apply_failed: bool,
+}
+
+/// 温度监控状态响应
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ThermalStatus {
+    /// 是否启用监控
+    pub enabled: bool,
+    /// 当前告警等级
+    pub level: ThermalLevel,
+    /// 参与判断的最高温度
+    pub max_temperature: f64,
+    /// 最高温度所在传感器
+    pub hottest_zone: String,
+    /// 最近一次采样的全部传感器
+    pub zones: Vec<ThermalZone>,
+    /// 当前生效的保护动作（force_lte / single_carrier）
+    pub active_actions: Vec<String>,
+    /// 最近一次采样时间
+    pub last_update: Option<String>,
+    /// 最近的等级变化事件（最新在前）
+    pub events: Vec<ThermalEvent>,
+}
+
+/// 温度监控器
+pub struct ThermalMonitor {
+    db: Arc<Database>,
+    config_manager: Arc<ConfigManager>,
+    webhook: Arc<WebhookSender>,
+    status: RwLock<ThermalStatus>,
+    events: RwLock<VecDeque<ThermalEvent>>,
+    actions: tokio::sync::Mutex<ActiveActions>,
+}
+
+impl ThermalMonitor {
+    /// 创建温度监控器
+    pub fn new(db: Arc<Database>, config_manager: Arc<ConfigManager>, webhook: Arc<WebhookSender>) -> Self {
+        Self {
+            db,
+            config_manager,
+            webhook,
+            status: RwLock::new(ThermalStatus::default()),
+            events: RwLock::new(VecDeque::new()),
+            actions: tokio::sync::Mutex::new(ActiveActions::default()),
+        }
+    }
+
+    /// 获取当前状态
+    pub fn status(&self) -> ThermalStatus {
+        let mut status = self.status.read().unwrap().clone();
+        status.enabled = self.config_manager.get_thermal().enabled;
+        status.events = self.events.read().unwrap().iter().cloned().collect();
+        status
+    }
+
+    /// 后台监控循环
+    pub async fn run(self: Arc<Self>, conn: Arc<Connection>) {
+        let mut last_cleanup = std::time::Instant::now();
+
+        loop {
+            let config = self.config_manager.get_thermal();
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                config.interval_secs.max(MIN_INTERVAL_SECS),
+            ))
+            .await;
+
+            // 天线对准期间暂停，把 AT 通道让给对准采样
+            if crate::alignment::is_active() {
+                continue;
+            }
+
+            if !config.enabled {
+                // 监控关闭时撤销仍在生效的保护动作
+                if self.status.read().unwrap().level != ThermalLevel::Normal || self.restore_pending() {
+                    self.restore_actions(&conn).await;
+                    self.status.write().unwrap().level = ThermalLevel::Normal;
+                }
+                continue;
+            }
+
+            // 已恢复正常但上次恢复失败的保护动作，继续重试
+            if self.status.read().unwrap().level == ThermalLevel::Normal && self.restore_pending() {
+                let restored = self.restore_actions(&conn).await;
+                if !restored.is_empty() {
+                    info!(restored = %restored.join(", "), "Thermal: pending actions restored");
+                }
+            }
+
+            let zones = read_temperature_sensors();
+            if zones.is_empty() {
+                continue;
+            }
+
+            // 写入历史
+            let samples: Vec<(String, String, f64)> = zones
+                .iter()
+                .map(|z| (z.zone.clone(), z.sensor_type.clone(), z.temperature))
+                .collect();
+            if let Err(e) = self.db.insert_thermal_samples(&samples) {
+                warn!(error = %e, "Thermal: failed to store samples");
+            }
+
+            // 每小时清理一次过期历史
+            if last_cleanup.elapsed().as_secs() >= 3600 {
+                let cutoff = (Utc::now() - Duration::hours(config.history_retention_hours as i64)).to_rfc3339();
+                if let Err(e) = self.db.cleanup_thermal_history(&cutoff) {
+                    warn!(error = %e, "Thermal: history cleanup failed");
+                }
+                last_cleanup = std::time::Instant::now();
+            }
+
+            // 取参与判断的最高温度
+            let hottest = zones
+                .iter()
+                .filter(|z| {
+                    config.zones.is_empty()
+                        || config.zones.iter().any(|name| name == &z.zone || name == &z.sensor_type)
+                })
+                .max_by(|a, b| a.temperature.total_cmp(&b.temperature))
+                .cloned();
+
+            let Some(hottest) = hottest else {
+                continue;
+            };
+
+            let previous = self.status.read().unwrap().level;
+            let level = evaluate_level(previous, hottest.temperature, &config);
+
+            {
+                let mut status = self.status.write().unwrap();
+                status.level = level;
+                status.max_temperature = hottest.temperature;
+                status.hottest_zone = hottest.zone.clone();
+                status.zones = zones;
+                status.last_update = Some(Utc::now().to_rfc3339());
+            }
+
+            if level != previous {
+                self.handle_transition(&conn, &config, previous, level, &hottest).await;
+            } else if level == ThermalLevel::Critical && self.apply_pending().await {
+                // 仍处于过温状态，上次执行失败的保护动作继续重试
+                let applied = self.apply_actions(&conn, &config).await;
+                if !applied.is_empty() {
+                    info!(applied = %applied.join(", "), "Thermal: pending actions applied");
+                }
+            }
+        }
+    }
+
+    /// 处理等级变化
+    async fn handle_transition(
+        &self,
+        conn: &Connection,
+        config: &ThermalConfig,
+        previous: ThermalLevel,
+        level: ThermalLevel,
+        hottest: &ThermalZone,
+    ) {
+        let mut message = format!(
+            "{} {:.1}°C ({} -> {})",
+            hottest.zone,
+            hottest.temperature,
+            previous.as_str(),
+            level.as_str()
+        );
+
+        match level {
+            ThermalLevel::Critical => {
+                warn!(zone = %hottest.zone, temperature = hottest.temperature, "Thermal: critical temperature");
+                let applied = self.apply_actions(conn, config).await;
+                if !applied.is_empty() {
+                    message = format!("{}, actions: {}", message, applied.join(", "));
+                }
+            }
+            ThermalLevel::Warning => {
+                warn!(zone = %hottest.zone, temperature = hottest.temperature, "Thermal: warning temperature");
+            }
+            ThermalLevel::Normal => {
+                info!(zone = %hottest.zone, temperature = hottest.temperature, "Thermal: temperature back to normal");
+                let restored = self.restore_actions(conn).await;
+                if !restored.is_empty() {
+                    message = format!("{}, restored: {}", message, restored.join(", "));
+                }
+            }
+        }
+
+        {
+            let mut events = self.events.write().unwrap();
+            events.push_front(ThermalEvent {
+                timestamp: Utc::now().to_rfc3339(),
+                level,
+                temperature: hottest.temperature,
+                zone: hottest.zone.clone(),
+                message: message.clone(),
+            });
+            events.truncate(MAX_RECENT_EVENTS);
+        }
+
+        if config.action_webhook {
+            let event = match level {
+                ThermalLevel::Normal => "thermal_recovered",
+                ThermalLevel::Warning => "thermal_warning",
+                ThermalLevel::Critical => "thermal_critical",
+            };
+            if let Err(e) = self.webhook.forward_event(event, &message).await {
+                warn!(error = %e, "Thermal: webhook event failed");
+            }
+        }
+    }
+
+    /// 是否有尚未恢复的保护动作
+    fn restore_pending(&self) -> bool {
+        !self.status.read().unwrap().active_actions.is_empty()
+    }
+
+    /// 是否有执行失败、需要重试的保护动作
+    async fn apply_pending(&self) -> bool {
+        self.actions.lock().await.apply_failed
+    }
+
+    /// 执行过温保护动作，返回已执行的动作名称
+    ///
+    /// 已生效的动作不会重复执行；执行失败时记录 apply_failed，由监控循环在过温期间重试
+    async fn apply_actions(&self, conn: &Connection, config: &ThermalConfig) -> Vec<String> {
+        let mut actions = self.actions.lock().await;
+        let mut applied = Vec::new();
+        let mut failed = false;
+
+        if config.action_force_lte && actions.saved_radio_mode.is_none() {
+            match get_radio_mode(conn).await {
+                Ok(current) => {
+                    let previous = RadioMode::from_ofono_value(&current.technology_preference)
+                        .unwrap_or(RadioMode::Auto);
+                    if previous == RadioMode::LteOnly {
+                        // 已经是 LTE only，无需修改
+                    } else if let Err(e) = set_radio_mode(conn, RadioMode::LteOnly).await {
+                        warn!(error = %e, "Thermal: failed to force LTE only");
+                        failed = true;
+                    } else {
+                        actions.saved_radio_mode = Some(previous);
+                        applied.push("force_lte".to_string());
+                    }
+                }
+                Err(e) => {
+                    warn!(error = %e, "Thermal: failed to read radio mode");
+                    failed = true;
+                }
+            }
+        }
+
+        if config.action_single_carrier && actions.saved_band_lock.is_none() {
+            match lock_serving_band(conn).await {
+                Ok(saved) => {
+                    actions.saved_band_lock = Some(saved);
+                    applied.push("single_carrier".to_string());
+                }
+                Err(e) => {
+                    warn!(error = %e, "Thermal: failed to lock serving band");
+                    failed = true;
+                }
+            }
+        }
+
+        actions.apply_failed = failed;
+        self.status.write().unwrap().active_actions = active_action_names(&actions);
+        applied
+    }
+
+    /// 撤销保护动作，返回已恢复的动作名称
+    ///
+    /// 恢复失败的动作保留在 [`ActiveActions`] 中（状态中仍显示为生效），由监控循环重试。
+    /// 设置已被其他操作修改时保留当前设置，只结束该动作
+    async fn restore_actions(&self, conn: &Connection) -> Vec<String> {
+        let mut actions = self.actions.lock().await;
+        let mut restored = Vec::new();
+        actions.apply_failed = false;
+
+        if let Some(saved) = actions.saved_band_lock {
+            match restore_band_lock(conn, saved).await {
+                Ok(true) => {
+                    actions.saved_band_lock = None;
+                    restored.push("single_carrier".to_string());
+                }
+                Ok(false) => {
+                    info!("Thermal: band lock changed during protection, keeping it");
+                    actions.saved_band_lock = None;
+                }
+                Err(e) => warn!(error = %e, "Thermal: failed to restore band lock"),
+            }
+        }
+
+        if let Some(mode) = actions.saved_radio_mode.clone() {
+            match restore_radio_mode(conn, mode).await {
+                Ok(true) => {
+                    actions.saved_radio_mode = None;
+                    restored.push("force_lte".to_string());
+                }
+                Ok(false) => {
+                    info!("Thermal: radio mode changed during protection, keeping it");
+                    actions.saved_radio_mode = None;
+                }
+                Err(e) => warn!(error = %e, "Thermal: failed to restore radio mode"),
+            }
+        }
+
+        self.status.write().unwrap().active_actions = active_action_names(&actions);
+        restored
+    }
+}
+
+fn active_action_names(actions: &ActiveActions) -> Vec<String> {
+    let mut names = Vec::new();
+    if actions.saved_radio_mode.is_some() {
+        names.push("force_lte".to_string());
+    }
+    if actions.saved_band_lock.is_some() {
+        names.push("single_carrier".to_string());
+    }
+    names
+}
+
+/// 将频段锁定为当前主小区所在频段，减少载波聚合
+///
+/// # Returns
+/// 修改前的频段锁定状态（用于恢复）
+async fn lock_serving_band(conn: &Connection) -> Result<SavedBandLock, String> {
+    let serving = get_serving_cell_info(conn)
+        .await
+        .map_err(|e| format!("Failed to get serving cell: {}", e))?;
+    let tech = serving.tech.as_str();
+    let cmd_config = get_cell_command_config(tech)
+        .ok_or_else(|| format!("Unsupported network type: {}", tech))?;
+
+    let response = send_at_command(conn, cmd_config.primary)
+        .await
+        .map_err(|e| format!("Primary cell AT command failed: {}", e))?;
+    let parsed = parse_at_response_to_2d_vec(&response);
+    // 第一行第一个值为主载波频段（其余为辅载波）
+    let band: u8 = parsed
+        .first()
+        .and_then(|row| row.first())
+        .and_then(|b| b.trim().parse().ok())
+        .filter(|b| *b > 0)
+        .ok_or_else(|| "Serving band not available".to_string())?;
+
+    if tech == "nr" {
+        let current = send_at_command(conn, "AT+SPLBAND=3")
+            .await
+            .map_err(|e| format!("Failed to read NR band lock: {}", e))?;
+        let (fdd_mask, tdd_mask) = parse_splband_nr_response(&current);
+
+        let tdd = bands_to_bitmask(&[band], 41);
+        let fdd = if tdd == 0 { bands_to_bitmask(&[band], 100) } else { 0 };
+        if tdd == 0 && fdd == 0 {
+            return Err(format!("Band n{} cannot be locked", band));
+        }
+        send_at_command(conn, &build_splband_nr_command(fdd, tdd))
+            .await
+            .map_err(|e| format!("Failed to lock NR band: {}", e))?;
+
+        Ok(SavedBandLock {
+            is_nr: true,
+            fdd_mask,
+            tdd_mask,
+            applied_fdd_mask: fdd,
+            applied_tdd_mask: tdd,
+        })
+    } else {
+        let current = send_at_command(conn, "AT+SPLBAND=0")
+            .await
+            .map_err(|e| format!("Failed to read LTE band lock: {}", e))?;
+        let (fdd_mask, tdd_mask) = parse_splband_lte_response(&current);
+
+        let (fdd, tdd) = if band >= 33 {
+            (0, bands_to_bitmask(&[band], 33))
+        } else {
+            (bands_to_bitmask(&[band], 1), 0)
+        };
+        if tdd == 0 && fdd == 0 {
+            return Err(format!("Band B{} cannot be locked", band));
+        }
+        send_at_command(conn, &build_splband_lte_command(fdd, tdd))
+            .await
+            .map_err(|e| format!("Failed to lock LTE band: {}", e))?;
+
+        Ok(SavedBandLock {
+            is_nr: false,
+            fdd_mask,
+            tdd_mask,
+            applied_fdd_mask: fdd,
+            applied_tdd_mask: tdd,
+        })
+    }
+}
+
+/// 恢复强制 LTE 前的射频模式
+///
+/// # Returns
+/// 已恢复返回 true；射频模式已不是 LTE only（被其他操作修改）时不恢复，返回 false
+async fn restore_radio_mode(conn: &Connection, mode: RadioMode) -> Result<bool, String> {
+    let current = get_radio_mode(conn)
+        .await
+        .map_err(|e| format!("Failed to read radio mode: {}", e))?;
+    if RadioMode::from_ofono_value(&current.technology_preference) != Some(RadioMode::LteOnly) {
+        return Ok(false);
+    }
+    set_radio_mode(conn, mode)
+        .await
+        .map(|_| true)
+        .map_err(|e| format!("Failed to restore radio mode: {}", e))
+}
+
+/// 恢复修改前的频段锁定状态
+///
+/// # Returns
+/// 已恢复返回 true；当前锁定与保护动作写入的不一致（被其他操作修改）时不恢复，返回 false
+async fn restore_band_lock(conn: &Connection, saved: SavedBandLock) -> Result<bool, String> {
+    let query = if saved.is_nr { "AT+SPLBAND=3" } else { "AT+SPLBAND=0" };
+    let current = send_at_command(conn, query)
+        .await
+        .map_err(|e| format!("Failed to read band lock: {}", e))?;
+    let current = if saved.is_nr {
+        parse_splband_nr_response(&current)
+    } else {
+        parse_splband_lte_response(&current)
+    };
+    if current != (saved.applied_fdd_mask, saved.applied_tdd_mask) {
+        return Ok(false);
+    }
+
+    let unlocked = saved.fdd_mask == 0 && saved.tdd_mask == 0;
+    let cmd = match (saved.is_nr, unlocked) {
+        (true, true) => "AT+SPLBAND=2,0,0,0,0".to_string(),
+        (true, false) => build_splband_nr_command(saved.fdd_mask, saved.tdd_mask),
+        (false, true) => "AT+SPLBAND=1,0,0,0,0,0".to_string(),
+        (false, false) => build_splband_lte_command(saved.fdd_mask, saved.tdd_mask),
+    };
+    send_at_command(conn, &cmd)
+        .await
+        .map(|_| true)
+        .map_err(|e| format!("Failed to restore band lock: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_level_hysteresis() {
+        let config = ThermalConfig {
+            warning_temp: 75.0,
+            critical_temp: 85.0,
+            recovery_temp: 70.0,
+            ..Default::default()
+        };
+
+        assert_eq!(evaluate_level(ThermalLevel::Normal, 74.9, &config), ThermalLevel::Normal);
+        assert_eq!(evaluate_level(ThermalLevel::Normal, 75.0, &config), ThermalLevel::Warning);
+        assert_eq!(evaluate_level(ThermalLevel::Warning, 86.0, &config), ThermalLevel::Critical);
+        // 过温后降到告警阈值以下但未到恢复阈值，保持过温
+        assert_eq!(evaluate_level(ThermalLevel::Critical, 72.0, &config), ThermalLevel::Critical);
+        assert_eq!(evaluate_level(ThermalLevel::Warning, 72.0, &config), ThermalLevel::Warning);
+        assert_eq!(evaluate_level(ThermalLevel::Critical, 70.0, &config), ThermalLevel::Normal);
+    }
+}
@@ -36,8 +36,12 @@
 
 use std::fs;
 use std::io::{self, Write};
+use std::collections::BTreeSet;
 use std::path::Path;
 use std::process::Command;
+use std::sync::Mutex;
+
+use crate::config::{LanConfig, ShapingConfig};
 
 /// USB 模式配置
 #[derive(Debug, Clone)]
@@ -116,10 +120,6 @@ const SLOG_TRANSPORT_PATH: &str = "/sys/module/slog_bridge/parameters/log_transp
 /// AT 指令设备路径
 const AT_DEVICE_PATH: &str = "/dev/stty_lte30";
 
-/// 默认 USB 网络接口 IP 地址
-const USB_INTERFACE_IP: &str = "192.168.66.1";
-const USB_INTERFACE_MAC: &str = "CC:E8:AC:C0:00:00";
-
 /// 默认 UDC 名称
 const DEFAULT_UDC: &str = "29100000.dwc3";
 
@@ -177,15 +177,44 @@ fn set_log_transport(enable: bool) -> io::Result<()> {
     Ok(())
 }
 
-/// 启用 SFP 硬件转发加速
-fn enable_sfp_acceleration() -> io::Result<()> {
+/// 需要关闭 SFP 硬件转发加速的功能
+///
+/// 加速后的流量绕过 qdisc 和 netfilter，限速、计数和 TTL 改写都会失效
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SfpInhibitor {
+    /// LAN 限速
+    Shaping,
+    /// 按客户端流量统计
+    Accounting,
+    /// TTL / Hop Limit 规范化
+    Ttl,
+}
+
+/// 当前要求关闭 SFP 的功能
+static SFP_INHIBITORS: Mutex<BTreeSet<SfpInhibitor>> = Mutex::new(BTreeSet::new());
+
+/// 登记或撤销关闭 SFP 的要求
+///
+/// 任一功能要求关闭时 SFP 保持关闭，全部撤销后重新开启
+pub fn inhibit_sfp(inhibitor: SfpInhibitor, inhibit: bool) {
+    let mut inhibitors = SFP_INHIBITORS.lock().unwrap_or_else(|e| e.into_inner());
+    if inhibit {
+        inhibitors.insert(inhibitor);
+    } else {
+        inhibitors.remove(&inhibitor);
+    }
+    set_sfp_acceleration(inhibitors.is_empty());
+}
+
+/// 启用或关闭 SFP 硬件转发加速
+fn set_sfp_acceleration(enabled: bool) {
+    let value = if enabled { "1" } else { "0" };
     if Path::new(SFP_ENABLE_PATH).exists() {
-        let _ = write_to_file(SFP_ENABLE_PATH, "1");
+        let _ = write_to_file(SFP_ENABLE_PATH, value);
     }
-    if Path::new(SFP_TETHER_SCHEME_PATH).exists() {
+    if enabled && Path::new(SFP_TETHER_SCHEME_PATH).exists() {
         let _ = write_to_file(SFP_TETHER_SCHEME_PATH, "1");
     }
-    Ok(())
 }
 
 /// 删除 CDC 功能
@@ -380,13 +409,25 @@ fn wait_for_functionfs_mount() -> Result<(), String> {
 /// 7. 创建功能符号链接
 /// 8. 启动 adbd (如果是 multi_functions 模式)
 /// 9. 启用 UDC
-/// 10. 配置 USB 网络接口和 iptables 规则
+/// 10. 按 LAN 配置设置 USB 网络接口
+/// 11. 重新下发 LAN 限速
+///
+/// ## Arguments
+/// * `mode` - USB 模式
+/// * `lan` - LAN 配置（接口地址、MAC、DHCP）
+/// * `wan_interface` - WAN 接口（内置 DHCP 模式下的 MASQUERADE 出接口）
+/// * `shaping` - LAN 限速配置（接口重建后重新下发）
 ///
 /// ## 注意事项
 /// - 热切换会导致 USB 连接短暂断开（约 1-2 秒）
 /// - macOS 可能需要更长时间识别新设备
 /// - 建议使用模式 1 (NCM) 以获得最佳兼容性
-pub fn switch_usb_mode_advanced(mode: u8) -> Result<(), String> {
+pub fn switch_usb_mode_advanced(
+    mode: u8,
+    lan: &LanConfig,
+    wan_interface: &str,
+    shaping: &ShapingConfig,
+) -> Result<(), String> {
     let config = UsbModeConfig::get(mode)
         .ok_or_else(|| format!("Invalid USB mode: {}. Valid modes: 1=NCM, 2=ECM, 3=RNDIS, 4=NCM(no ADB)", mode))?;
     
@@ -501,13 +542,13 @@ pub fn switch_usb_mode_advanced(mode: u8) -> Result<(), String> {
     // 13. 设置 MAC 地址
     let dev_addr_path = format!("{}/dev_addr", function_path);
     if Path::new(&dev_addr_path).exists() {
-        let _ = write_to_file(&dev_addr_path, &USB_INTERFACE_MAC.to_lowercase());
+        let _ = write_to_file(&dev_addr_path, &lan.mac_address.to_lowercase());
     }
     // 设置 host_addr 以保证 RNDIS/NCM 正常枚举
     let host_addr_path = format!("{}/host_addr", function_path);
     if Path::new(&host_addr_path).exists() {
         // 为 host MAC 生成 01 后缀
-        let mut parts: Vec<&str> = USB_INTERFACE_MAC.split(':').collect();
+        let mut parts: Vec<&str> = lan.mac_address.split([':', '-']).collect();
         if let Some(last) = parts.last_mut() {
             *last = "01";
         }
@@ -538,7 +579,10 @@ pub fn switch_usb_mode_advanced(mode: u8) -> Result<(), String> {
     std::thread::sleep(std::time::Duration::from_millis(1000));
     
     // 20. 配置网络接口
-    configure_usb_network()?;
+    configure_usb_network(lan, wan_interface)?;
+    
+    // 21. 重新下发限速（接口重建后 qdisc 丢失），失败记录在限速状态中
+    let _ = crate::shaping::apply_blocking(shaping, &lan.interface);
     
     Ok(())
 }
@@ -630,83 +674,55 @@ fn stop_adbd() -> io::Result<()> {
 /// 此函数实现完整的 USB 网络初始化，参考 /etc/route_test.sh 脚本。
 /// 
 /// ## 初始化流程
-/// 1. 启用 connman gadget tethering
-/// 2. 配置 usb0 接口 IP 和 MAC 地址
+/// 1. 未启用内置 DHCP 时启用 connman gadget tethering
+/// 2. 按 LAN 配置设置接口 IP 和 MAC 地址（见 [`crate::lan::apply_blocking`]）
+///    并添加默认路由
 /// 3. 关闭 sipa_usb0 接口
 /// 4. 启用 SFP 硬件转发加速
-/// 5. 配置 iptables 防火墙规则
-/// 6. 标记配置完成
-fn configure_usb_network() -> Result<(), String> {
+/// 5. 标记配置完成
+fn configure_usb_network(lan: &LanConfig, wan_interface: &str) -> Result<(), String> {
     // 等待接口出现
     std::thread::sleep(std::time::Duration::from_millis(500));
     
-    // 1. 配置 connman gadget tethering
-    // 先关闭再重新启用（避免 "Already enabled" 错误）
-    let _ = Command::new("connmanctl")
-        .args(["tether", "gadget", "off"])
-        .output();
-    
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    
-    let _ = Command::new("connmanctl")
-        .args(["disable", "gadget"])
-        .output();
-    
-    std::thread::sleep(std::time::Duration::from_millis(200));
-    
-    // 重新启用
-    let _ = Command::new("connmanctl")
-        .args(["enable", "gadget"])
-        .output();
-    
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    
-    let _ = Command::new("connmanctl")
-        .args(["tether", "gadget", "on"])
-        .output();
-    
-    std::thread::sleep(std::time::Duration::from_millis(300));
-    
-    // 2. 配置 usb0 接口
-    // 等待接口出现并重试
-    let max_retries = 5;
-    for retry in 0..max_retries {
-        // 检查接口是否存在
-        let check = Command::new("ifconfig")
-            .arg("-a")
+    // 1. 配置 connman gadget tethering（内置 DHCP 服务启用时由 lan 模块关闭）
+    if !lan.dhcp_enabled {
+        // 先关闭再重新启用（避免 "Already enabled" 错误）
+        let _ = Command::new("connmanctl")
+            .args(["tether", "gadget", "off"])
             .output();
         
-        if let Ok(output) = check {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            if output_str.contains("usb0") || output_str.contains(USB_INTERFACE_IP) {
-                break;
-            }
-        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
         
-        if retry < max_retries - 1 {
-            // 尝试添加 IP 地址
-            let _ = Command::new("ifconfig")
-                .args(["usb0", "add", USB_INTERFACE_IP])
-                .output();
-            
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        }
+        let _ = Command::new("connmanctl")
+            .args(["disable", "gadget"])
+            .output();
+        
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        
+        // 重新启用
+        let _ = Command::new("connmanctl")
+            .args(["enable", "gadget"])
+            .output();
+        
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        
+        let _ = Command::new("connmanctl")
+            .args(["tether", "gadget", "on"])
+            .output();
+        
+        std::thread::sleep(std::time::Duration::from_millis(300));
     }
     
-    // 设置 IP 地址和子网掩码
-    let _ = Command::new("ifconfig")
-        .args(["usb0", USB_INTERFACE_IP, "netmask", "255.255.255.0"])
-        .output();
-    
-    // 设置 MAC 地址
-    let _ = Command::new("ifconfig")
-        .args(["usb0", "hw", "ether", USB_INTERFACE_MAC])
-        .output();
-    
-    // 启动接口
-    let _ = Command::new("ip")
-        .args(["link", "set", "dev", "usb0", "up"])
-        .output();
+    // 2. 配置 LAN 接口
+    // 等待接口出现并重试
+    let interface_path = format!("/sys/class/net/{}", lan.interface);
+    for _ in 0..5 {
+        if Path::new(&interface_path).exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+    crate::lan::apply_blocking(lan, wan_interface)?;
     
     // 添加默认路由（用于主机端访问）
     let _ = Command::new("ip")
@@ -718,8 +734,9 @@ fn configure_usb_network() -> Result<(), String> {
         .args(["sipa_usb0", "down"])
         .output();
     
-    // 4. 启用 SFP 硬件转发加速
-    let _ = enable_sfp_acceleration();
+    // 4. 启用 SFP 硬件转发加速（限速、计数或 TTL 规范化生效时保持关闭）
+    let inhibited = !SFP_INHIBITORS.lock().unwrap_or_else(|e| e.into_inner()).is_empty();
+    set_sfp_acceleration(!inhibited);
     
     // 5. 标记配置完成
     let _ = fs::write("/tmp/sipa_usb0_ok", "");
@@ -12,6 +12,7 @@
 //! 
 //! 包含 AT 指令解析、数据处理等工具函数
 
+use crate::arfcn;
 use crate::models::{CellInfo, IpAddress, NetworkInterfaceInfo};
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -56,56 +57,21 @@ pub fn get_cell_command_config(tech: &str) -> Option<CellCommandConfig> {
     cmd_map.get(tech).cloned()
 }
 
-/// 根据 NR ARFCN 推算频段（返回完整格式如 n41）
-/// 参考 3GPP TS 38.104
-fn arfcn_to_nr_band(arfcn: u32) -> String {
-    match arfcn {
-        // N1 (2100 MHz FDD)
-        422000..=434000 => "n1".to_string(),
-        // N3 (1800 MHz FDD)
-        361000..=376000 => "n3".to_string(),
-        // N8 (900 MHz FDD)
-        185000..=192000 => "n8".to_string(),
-        // N28 (700 MHz FDD)
-        151600..=160600 => "n28".to_string(),
-        // N41 (2600 MHz TDD)
-        499200..=537999 => "n41".to_string(),
-        // N77 (3700 MHz TDD) - 与 N78 重叠，优先判断为 N78
-        620000..=680000 => "n78".to_string(),
-        // N79 (4700 MHz TDD)
-        693334..=733333 => "n79".to_string(),
-        _ => "".to_string(),
-    }
-}
-
-/// 根据 LTE EARFCN 推算频段（返回完整格式如 B3）
-/// 参考 3GPP TS 36.101
-fn earfcn_to_lte_band(earfcn: u32) -> String {
-    match earfcn {
-        // B1 (2100 MHz FDD)
-        0..=599 => "B1".to_string(),
-        // B3 (1800 MHz FDD)
-        1200..=1949 => "B3".to_string(),
-        // B5 (850 MHz FDD)
-        2400..=2649 => "B5".to_string(),
-        // B7 (2600 MHz FDD)
-        2750..=3449 => "B7".to_string(),
-        // B8 (900 MHz FDD)
-        3450..=3799 => "B8".to_string(),
-        // B20 (800 MHz FDD)
-        6150..=6449 => "B20".to_string(),
-        // B28 (700 MHz FDD)
-        9210..=9659 => "B28".to_string(),
-        // B38 (2600 MHz TDD)
-        37750..=38249 => "B38".to_string(),
-        // B39 (1900 MHz TDD)
-        38250..=38649 => "B39".to_string(),
-        // B40 (2300 MHz TDD)
-        38650..=39649 => "B40".to_string(),
-        // B41 (2500 MHz TDD)
-        39650..=41589 => "B41".to_string(),
-        _ => "".to_string(),
+/// 根据频点号补充频段和上下行频率
+///
+/// 模组已上报频段时保留原值，并用它区分重叠频段；否则使用换算出的频段
+fn fill_band_and_freq(cell: &mut CellInfo) {
+    let Some(arfcn) = cell.arfcn.split(',').next().and_then(|s| s.trim().parse::<u32>().ok()) else {
+        return;
+    };
+    let Some(info) = arfcn::lookup(&cell.tech, arfcn, arfcn::parse_band_number(&cell.band)) else {
+        return;
+    };
+    if cell.band.is_empty() || cell.band == "0" {
+        cell.band = info.band;
     }
+    cell.dl_freq_mhz = Some(info.dl_freq_mhz);
+    cell.ul_freq_mhz = info.ul_freq_mhz;
 }
 
 /// 将 AT 指令返回的字符串解析为二维数组
@@ -196,12 +162,7 @@ pub fn parse_primary_cell(tech: &str, parsed_data: &[Vec<String>]) -> CellInfo {
             if parsed_data.len() >= 16 {
                 cell_info.tech = tech.to_string();
                 // 给 NR 频段加 n 前缀
-                let raw_band = parsed_data[0].join(",");
-                cell_info.band = if !raw_band.is_empty() && raw_band != "0" {
-                    format!("n{}", raw_band)
-                } else {
-                    raw_band
-                };
+                cell_info.band = arfcn::format_band(tech, &parsed_data[0].join(","));
                 cell_info.arfcn = parsed_data[1].join(",");
                 cell_info.pci = parsed_data[2].first().cloned().unwrap_or_default();
                 // 返回原始值×100，不做除法，让前端处理单位转换
@@ -214,12 +175,7 @@ pub fn parse_primary_cell(tech: &str, parsed_data: &[Vec<String>]) -> CellInfo {
             if parsed_data.len() >= 34 {
                 cell_info.tech = tech.to_string();
                 // 给 LTE 频段加 B 前缀
-                let raw_band = parsed_data[0].join(",");
-                cell_info.band = if !raw_band.is_empty() && raw_band != "0" {
-                    format!("B{}", raw_band)
-                } else {
-                    raw_band
-                };
+                cell_info.band = arfcn::format_band(tech, &parsed_data[0].join(","));
                 cell_info.arfcn = parsed_data[1].join(",");
                 cell_info.pci = parsed_data[2].join(",");
                 // 返回原始值×100，不做除法，让前端处理单位转换
@@ -231,6 +187,7 @@ pub fn parse_primary_cell(tech: &str, parsed_data: &[Vec<String>]) -> CellInfo {
         _ => {}
     }
     
+    fill_band_and_freq(&mut cell_info);
     cell_info
 }
 
@@ -283,17 +240,9 @@ pub fn parse_neighbor_cells(tech: &str, parsed_data: &[Vec<String>]) -> Vec<Cell
                 }
                 
                 // 尝试从数据中获取频段，如果为空或"0"则通过 ARFCN 推算
-                let raw_band = parsed_data[0].get(i).cloned().unwrap_or_default();
-                let band = if raw_band.is_empty() || raw_band == "0" {
-                    // 通过 ARFCN 推算频段
-                    arfcn.parse::<u32>()
-                        .map(arfcn_to_nr_band)
-                        .unwrap_or_default()
-                } else {
-                    raw_band
-                };
+                let band = parsed_data[0].get(i).cloned().unwrap_or_default();
                 
-                let cell = CellInfo {
+                let mut cell = CellInfo {
                     is_serving: false, // 邻区标记
                     tech: tech.to_string(),
                     band,
@@ -303,7 +252,9 @@ pub fn parse_neighbor_cells(tech: &str, parsed_data: &[Vec<String>]) -> Vec<Cell
                     rsrp: parsed_data[3].get(i).cloned().unwrap_or_default(),
                     rsrq: parsed_data[4].get(i).cloned().unwrap_or_default(),
                     sinr: parsed_data[5].get(i).cloned().unwrap_or_default(),
+                    ..Default::default()
                 };
+                fill_band_and_freq(&mut cell);
                 
                 result.push(cell);
             }
@@ -320,17 +271,9 @@ pub fn parse_neighbor_cells(tech: &str, parsed_data: &[Vec<String>]) -> Vec<Cell
                 }
                 
                 // 尝试从数据中获取频段，如果不存在或为"0"则通过 EARFCN 推算
-                let raw_band = if row.len() > 12 { row[12].clone() } else { String::new() };
-                let band = if raw_band.is_empty() || raw_band == "0" {
-                    // 通过 EARFCN 推算频段
-                    row[0].parse::<u32>()
-                        .map(earfcn_to_lte_band)
-                        .unwrap_or_default()
-                } else {
-                    raw_band
-                };
+                let band = if row.len() > 12 { row[12].clone() } else { String::new() };
                 
-                let cell = CellInfo {
+                let mut cell = CellInfo {
                     is_serving: false, // 邻区标记
                     tech: tech.to_string(),
                     band,
@@ -340,7 +283,9 @@ pub fn parse_neighbor_cells(tech: &str, parsed_data: &[Vec<String>]) -> Vec<Cell
                     rsrp: row[2].clone(),
                     rsrq: row[3].clone(),
                     sinr: "-".to_string(),  // LTE邻区不提供SINR
+                    ..Default::default()
                 };
+                fill_band_and_freq(&mut cell);
                 
                 result.push(cell);
             }
@@ -1395,3 +1340,15 @@ pub fn build_splband_nr_command(fdd_mask: u16, tdd_mask: u16) -> String {
     format!("AT+SPLBAND=2,{},0,{},0", fdd_mask, tdd_mask)
 }
 
+/// 密钥掩码前缀
+pub const SECRET_MASK_PREFIX: &str = "****";
+
+/// 掩码密钥（API Key、Token 等）：只保留末 4 位，8 位及以下全部隐藏，未设置时为空
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    match chars.len() {
+        0 => String::new(),
+        1..=8 => SECRET_MASK_PREFIX.to_string(),
+        n => format!("{}{}", SECRET_MASK_PREFIX, chars[n - 4..].iter().collect::<String>()),
+    }
+}
@@ -0,0 +1,517 @@
+/*
+ * @Author: 1orz cloudorzi@gmail.com
+ * @Date: 2025-12-16 14:32:08
+ * @LastEditors: 1orz cloudorzi@gmail.com
+ * @LastEditTime: 2025-12-16 14:32:08
+ * @FilePath: /udx710-backend/backend/src/watchdog.rs
+ * @Description: 
+ * 
+ * Copyright (c) 2025 by 1orz, All Rights Reserved. 
+ */
+//! 数据连接恢复阶梯
+//!
+//! watchdog 每次检查的结果（正常 / 断开 / 无需恢复）交给 [`RecoveryController`]，
+//! 连续失败次数达到某一步的阈值时执行该步动作，按配置依次升级：
+//! 重新激活 context → 飞行模式切换 → 重新注册运营商 → 重启 ofono → 重启系统。
+//!
+//! - 每一步有最多尝试次数，用完后升级到下一步；全部用完后重复最后一个可用步骤
+//! - 每次尝试后等待 backoff_secs，同一步重复尝试时翻倍（不超过 max_backoff_secs）
+//! - 重启系统受 24 小时次数上限约束，执行记录写入数据库，重启后依然有效
+//!
+//! 启用连通性探测时，context 激活但连续多轮探测失败同样视为断开（见 [`crate::probe`]）。
+//!
+//! SIM 卡在位但未注册网络（如不在覆盖范围内）时只执行到重新注册 / 重启 ofono，不重启系统。
+//! 小区优化或安全锁定观察期间锁定会导致短暂掉网，此时暂停恢复阶梯（见 [`RecoveryController::suspend`]）。
+
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::config::{RecoveryAction, RecoveryStep, WatchdogConfig};
+use crate::data_session::{DataSessionTracker, SessionEndReason};
+use crate::db::{Database, WatchdogActionRecord};
+use crate::models::DataContextSnapshot;
+use crate::probe::{ProbeResult, ProbeTracker};
+
+/// 状态接口返回的最近动作条数
+const RECENT_ACTIONS: i64 = 20;
+
+/// 恢复动作记录保留天数
+const ACTION_RETENTION_DAYS: i64 = 30;
+
+/// 最近一次检查后的运行状态
+static STATUS: Mutex<Option<WatchdogStatus>> = Mutex::new(None);
+
+/// 一次检查的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataHealth {
+    /// 数据连接正常
+    Healthy,
+    /// 数据连接断开，需要恢复
+    Down,
+    /// SIM 卡在位但未注册网络，只执行到重新注册 / 重启 ofono
+    Unregistered,
+    /// 无需恢复（飞行模式、无 SIM 卡、未配置 APN 等）
+    Idle,
+}
+
+impl DataHealth {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DataHealth::Healthy => "healthy",
+            DataHealth::Down => "down",
+            DataHealth::Unregistered => "unregistered",
+            DataHealth::Idle => "idle",
+        }
+    }
+
+    /// 该检查结果下允许执行的恢复动作
+    ///
+    /// 未注册网络时激活 context 无效，重启系统也无法改善覆盖
+    fn allows(&self, action: RecoveryAction) -> bool {
+        match self {
+            DataHealth::Unregistered => matches!(
+                action,
+                RecoveryAction::AirplaneToggle | RecoveryAction::Reregister | RecoveryAction::RestartOfono
+            ),
+            _ => true,
+        }
+    }
+}
+
+/// watchdog 状态
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct WatchdogStatus {
+    pub enabled: bool,
+    /// 最近一次检查结果：healthy / down / unregistered / idle / paused
+    pub health: String,
+    /// 最近一次检查的状态描述
+    pub last_status: String,
+    pub last_check: Option<String>,
+    /// 连续失败检查次数
+    pub consecutive_failures: u32,
+    /// 本次断开开始时间
+    pub down_since: Option<String>,
+    /// 本次断开中最近执行的恢复动作
+    pub current_step: Option<String>,
+    /// 该动作已尝试次数
+    pub step_attempts: u32,
+    /// 下一次允许执行恢复动作的时间
+    pub next_attempt_at: Option<String>,
+    /// 最近 24 小时内重启系统次数
+    pub reboots_last_24h: i64,
+    pub max_reboots_per_day: u32,
+    /// 连通性探测连续失败轮数
+    pub probe_failures: u32,
+    pub last_probe_at: Option<String>,
+    /// 最近一轮连通性探测结果
+    pub probe_results: Vec<ProbeResult>,
+    /// 最近的恢复动作（按时间倒序）
+    pub recent_actions: Vec<WatchdogActionRecord>,
+}
+
+/// 恢复阶梯状态机
+#[derive(Debug, Default)]
+pub struct RecoveryLadder {
+    failures: u32,
+    step: usize,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl RecoveryLadder {
+    /// 连接正常或无需恢复，重置阶梯
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// 记录一次失败检查，返回需要执行的步骤（序号和动作）
+    ///
+    /// # Arguments
+    /// * `steps` - 恢复阶梯配置
+    /// * `allowed` - 当前允许执行的动作（重启次数上限、未注册网络时的限制）
+    /// * `max_backoff_secs` - 退避时间上限
+    pub fn record_failure(
+        &mut self,
+        now: Instant,
+        steps: &[RecoveryStep],
+        allowed: impl Fn(RecoveryAction) -> bool,
+        max_backoff_secs: u64,
+    ) -> Option<(usize, RecoveryAction)> {
+        self.failures = self.failures.saturating_add(1);
+        if self.next_attempt.is_some_and(|at| now < at) {
+            return None;
+        }
+
+        let runnable = |i: usize| {
+            let step = &steps[i];
+            step.enabled && step.max_attempts > 0 && allowed(step.action)
+        };
+
+        // 跳过已用完或不可用的步骤
+        let mut index = self.step;
+        let mut attempts = self.attempts;
+        while index < steps.len() && (!runnable(index) || attempts >= steps[index].max_attempts) {
+            index += 1;
+            attempts = 0;
+        }
+        // 全部用完时重复最后一个可用步骤
+        if index >= steps.len() {
+            index = (0..steps.len()).rev().find(|&i| runnable(i))?;
+            attempts = if index == self.step {
+                self.attempts
+            } else {
+                steps[index].max_attempts
+            };
+        }
+
+        let step = &steps[index];
+        if self.failures < step.failure_threshold {
+            return None;
+        }
+
+        let delay = step
+            .backoff_secs
+            .saturating_mul(1u64 << attempts.min(16))
+            .min(max_backoff_secs);
+        self.step = index;
+        self.attempts = attempts + 1;
+        self.next_attempt = Some(now + Duration::from_secs(delay));
+        Some((index, step.action))
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+/// 执行恢复动作
+///
+/// 每个动作都会断开当前数据会话，执行前标记结束原因，避免计为网络侧中断
+async fn execute_action(
+    conn: &Connection,
+    action: RecoveryAction,
+    watchdog_context: Option<&str>,
+    config: &WatchdogConfig,
+    sessions: &DataSessionTracker,
+) -> Result<String, String> {
+    sessions.mark_end_reason(SessionEndReason::WatchdogRestore);
+    match action {
+        RecoveryAction::Reactivate => {
+            // context 仍处于激活状态（连通性探测失败）时先去激活
+            if crate::dbus::get_data_connection_status(conn, watchdog_context).await.unwrap_or(false) {
+                crate::dbus::set_data_connection(conn, watchdog_context, false)
+                    .await
+                    .map_err(|e| format!("Deactivation failed: {}", e))?;
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            crate::dbus::set_data_connection(conn, watchdog_context, true)
+                .await
+                .map(|_| "Context activated".to_string())
+                .map_err(|e| format!("Activation failed: {}", e))
+        }
+        RecoveryAction::AirplaneToggle => {
+            crate::dbus::set_airplane_mode(conn, true)
+                .await
+                .map_err(|e| format!("Failed to go offline: {}", e))?;
+            tokio::time::sleep(Duration::from_secs(config.airplane_toggle_secs)).await;
+            crate::dbus::set_airplane_mode(conn, false)
+                .await
+                .map(|_| format!("Modem toggled offline for {}s", config.airplane_toggle_secs))
+                .map_err(|e| format!("Failed to go online: {}", e))
+        }
+        RecoveryAction::Reregister => {
+            crate::dbus::register_operator_auto(conn)
+                .await
+                .map(|_| "Network re-registration requested".to_string())
+                .map_err(|e| format!("Register failed: {}", e))
+        }
+        RecoveryAction::RestartOfono => {
+            let argv = config.ofono_restart_method.argv();
+            let output = Command::new(argv[0])
+                .args(&argv[1..])
+                .output()
+                .await
+                .map_err(|e| format!("Failed to run {}: {}", argv[0], e))?;
+            if output.status.success() {
+                Ok(format!("Executed: {}", argv.join(" ")))
+            } else {
+                Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+        }
+        RecoveryAction::Reboot => {
+            tokio::spawn(async {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                let _ = Command::new("reboot").output().await;
+            });
+            Ok("System will reboot in 3 seconds".to_string())
+        }
+    }
+}
+
+/// 恢复控制器（由 watchdog 循环持有）
+pub struct RecoveryController {
+    db: Arc<Database>,
+    sessions: Arc<DataSessionTracker>,
+    ladder: RecoveryLadder,
+    probes: ProbeTracker,
+    down_since: Option<String>,
+    current_step: Option<RecoveryAction>,
+}
+
+impl RecoveryController {
+    pub fn new(db: Arc<Database>, sessions: Arc<DataSessionTracker>) -> Self {
+        Self {
+            db,
+            sessions,
+            ladder: RecoveryLadder::default(),
+            probes: ProbeTracker::default(),
+            down_since: None,
+            current_step: None,
+        }
+    }
+
+    /// 最近 24 小时内重启系统次数
+    fn reboots_last_24h(&self) -> i64 {
+        let since = (Utc::now() - ChronoDuration::hours(24)).to_rfc3339();
+        self.db
+            .count_watchdog_actions_since(RecoveryAction::Reboot.as_str(), &since)
+            .unwrap_or(0)
+    }
+
+    /// 暂停恢复阶梯（小区优化、安全锁定观察期间）
+    ///
+    /// 锁定引起的掉网不计入失败次数，恢复后从第一步重新开始
+    pub fn suspend(&mut self, reason: &str, config: &WatchdogConfig) {
+        self.ladder.reset();
+        self.probes.reset();
+        self.down_since = None;
+        self.current_step = None;
+        let snapshot = WatchdogStatus {
+            enabled: config.enabled,
+            health: "paused".to_string(),
+            last_status: reason.to_string(),
+            last_check: Some(Utc::now().to_rfc3339()),
+            reboots_last_24h: self.reboots_last_24h(),
+            max_reboots_per_day: config.max_reboots_per_day,
+            ..Default::default()
+        };
+        if let Ok(mut current) = STATUS.lock() {
+            *current = Some(snapshot);
+        }
+    }
+
+    /// 处理一次检查结果，必要时执行恢复动作
+    ///
+    /// # Arguments
+    /// * `conn` - D-Bus 连接
+    /// * `health` - 检查结果
+    /// * `status` - 检查状态描述
+    /// * `context` - 数据连接上下文快照（连通性探测使用其接口、地址和 DNS）
+    /// * `watchdog_context` - 手动指定的 watchdog context（重新激活时使用）
+    /// * `config` - watchdog 配置
+    ///
+    /// # Returns
+    /// 连通性探测失败时返回探测失败描述
+    pub async fn on_check(
+        &mut self,
+        conn: &Connection,
+        mut health: DataHealth,
+        status: &str,
+        context: Option<&DataContextSnapshot>,
+        watchdog_context: Option<&str>,
+        config: &WatchdogConfig,
+    ) -> Option<String> {
+        let mut probe_failure = None;
+        if health == DataHealth::Healthy && config.enabled && config.probe.enabled {
+            let (interface, source, dns_servers) = context
+                .map(|c| (c.interface.as_str(), c.ipv4_address.as_str(), c.dns_servers.as_slice()))
+                .unwrap_or_default();
+            probe_failure = self.probes.check(&config.probe, interface, source, dns_servers).await;
+            if probe_failure.is_some() {
+                health = DataHealth::Down;
+            }
+        } else {
+            self.probes.reset();
+        }
+        let status = probe_failure.as_deref().unwrap_or(status);
+        
+        let now = Instant::now();
+        let mut reboots = self.reboots_last_24h();
+
+        if matches!(health, DataHealth::Down | DataHealth::Unregistered) && config.enabled {
+            if self.down_since.is_none() {
+                self.down_since = Some(Utc::now().to_rfc3339());
+            }
+            let reboot_allowed = reboots < config.max_reboots_per_day as i64;
+            let allowed = |action| health.allows(action) && (action != RecoveryAction::Reboot || reboot_allowed);
+            if let Some((index, action)) =
+                self.ladder
+                    .record_failure(now, &config.steps, allowed, config.max_backoff_secs)
+            {
+                let failures = self.ladder.failures();
+                info!(step = index, action = action.as_str(), failures, "Watchdog: executing recovery action");
+                self.current_step = Some(action);
+
+                // 重启前先落库，保证次数上限在重启后仍然有效
+                if action == RecoveryAction::Reboot {
+                    reboots += 1;
+                    if let Err(e) = self.db.insert_watchdog_action(action.as_str(), failures, true, status) {
+                        warn!(error = %e, "Watchdog: failed to record recovery action");
+                    }
+                }
+                let result = execute_action(conn, action, watchdog_context, config, &self.sessions).await;
+                match &result {
+                    Ok(detail) => info!(action = action.as_str(), detail = %detail, "Watchdog: recovery action done"),
+                    Err(e) => warn!(action = action.as_str(), error = %e, "Watchdog: recovery action failed"),
+                }
+                if action != RecoveryAction::Reboot {
+                    let (success, detail) = match &result {
+                        Ok(detail) => (true, detail.as_str()),
+                        Err(e) => (false, e.as_str()),
+                    };
+                    if let Err(e) = self.db.insert_watchdog_action(action.as_str(), failures, success, detail) {
+                        warn!(error = %e, "Watchdog: failed to record recovery action");
+                    }
+                }
+                let before = (Utc::now() - ChronoDuration::days(ACTION_RETENTION_DAYS)).to_rfc3339();
+                let _ = self.db.cleanup_watchdog_actions(&before);
+                self.probes.probe_now();
+            }
+        } else {
+            if health == DataHealth::Healthy && self.current_step.is_some() {
+                info!(failures = self.ladder.failures(), "Watchdog: data connection recovered");
+            }
+            self.ladder.reset();
+            self.down_since = None;
+            self.current_step = None;
+        }
+
+        let next_attempt_at = self.ladder.next_attempt.filter(|at| *at > now).and_then(|at| {
+            ChronoDuration::from_std(at - now)
+                .ok()
+                .map(|d| (Utc::now() + d).to_rfc3339())
+        });
+        let snapshot = WatchdogStatus {
+            enabled: config.enabled,
+            health: health.as_str().to_string(),
+            last_status: status.to_string(),
+            last_check: Some(Utc::now().to_rfc3339()),
+            consecutive_failures: self.ladder.failures(),
+            down_since: self.down_since.clone(),
+            current_step: self.current_step.map(|a| a.as_str().to_string()),
+            step_attempts: self.ladder.attempts,
+            next_attempt_at,
+            reboots_last_24h: reboots,
+            max_reboots_per_day: config.max_reboots_per_day,
+            probe_failures: self.probes.consecutive_failures(),
+            last_probe_at: self.probes.last_probe_at(),
+            probe_results: self.probes.results().to_vec(),
+            recent_actions: Vec::new(),
+        };
+        if let Ok(mut current) = STATUS.lock() {
+            *current = Some(snapshot);
+        }
+        probe_failure
+    }
+}
+
+/// 获取 watchdog 状态（附带最近的恢复动作）
+pub fn status(db: &Database, config: &WatchdogConfig) -> WatchdogStatus {
+    let mut status = STATUS
+        .lock()
+        .ok()
+        .and_then(|s| s.clone())
+        .unwrap_or_else(|| WatchdogStatus {
+            health: "unknown".to_string(),
+            ..Default::default()
+        });
+    status.enabled = config.enabled;
+    status.max_reboots_per_day = config.max_reboots_per_day;
+    status.recent_actions = db.get_watchdog_actions(RECENT_ACTIONS).unwrap_or_default();
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(action: RecoveryAction, failure_threshold: u32, max_attempts: u32, backoff_secs: u64) -> RecoveryStep {
+        RecoveryStep {
+            action,
+            enabled: true,
+            failure_threshold,
+            max_attempts,
+            backoff_secs,
+        }
+    }
+
+    #[test]
+    fn test_ladder_escalation() {
+        let steps = vec![
+            step(RecoveryAction::Reactivate, 1, 2, 0),
+            step(RecoveryAction::AirplaneToggle, 4, 1, 0),
+            step(RecoveryAction::Reboot, 5, 1, 0),
+        ];
+        let mut ladder = RecoveryLadder::default();
+        let now = Instant::now();
+        let mut run = |reboot: bool| {
+            ladder
+                .record_failure(now, &steps, |a| reboot || a != RecoveryAction::Reboot, 60)
+                .map(|(_, a)| a)
+        };
+
+        assert_eq!(run(true), Some(RecoveryAction::Reactivate));
+        assert_eq!(run(true), Some(RecoveryAction::Reactivate));
+        // 第一步用完，第二步未到阈值
+        assert_eq!(run(true), None);
+        assert_eq!(run(true), Some(RecoveryAction::AirplaneToggle));
+        // 超过重启上限时跳过重启，重复最后一个可用步骤
+        assert_eq!(run(false), Some(RecoveryAction::AirplaneToggle));
+        assert_eq!(run(true), Some(RecoveryAction::Reboot));
+
+        ladder.reset();
+        assert_eq!(ladder.record_failure(now, &steps, |_| true, 60).map(|(i, _)| i), Some(0));
+    }
+
+    #[test]
+    fn test_ladder_unregistered() {
+        let steps = vec![
+            step(RecoveryAction::Reactivate, 1, 1, 0),
+            step(RecoveryAction::Reregister, 1, 1, 0),
+            step(RecoveryAction::RestartOfono, 1, 1, 0),
+            step(RecoveryAction::Reboot, 1, 1, 0),
+        ];
+        let mut ladder = RecoveryLadder::default();
+        let now = Instant::now();
+        let allowed = |a| DataHealth::Unregistered.allows(a);
+
+        // 未注册网络时跳过重新激活，止步于重启 ofono
+        assert_eq!(ladder.record_failure(now, &steps, allowed, 60).map(|(_, a)| a), Some(RecoveryAction::Reregister));
+        assert_eq!(ladder.record_failure(now, &steps, allowed, 60).map(|(_, a)| a), Some(RecoveryAction::RestartOfono));
+        assert_eq!(ladder.record_failure(now, &steps, allowed, 60).map(|(_, a)| a), Some(RecoveryAction::RestartOfono));
+    }
+
+    #[test]
+    fn test_ladder_backoff() {
+        let steps = vec![step(RecoveryAction::Reactivate, 1, 3, 10)];
+        let mut ladder = RecoveryLadder::default();
+        let start = Instant::now();
+
+        assert!(ladder.record_failure(start, &steps, |_| true, 15).is_some());
+        assert!(ladder.record_failure(start + Duration::from_secs(9), &steps, |_| true, 15).is_none());
+        assert!(ladder.record_failure(start + Duration::from_secs(10), &steps, |_| true, 15).is_some());
+        // 第二次尝试后退避翻倍为 20 秒，受上限限制为 15 秒
+        assert!(ladder.record_failure(start + Duration::from_secs(24), &steps, |_| true, 15).is_none());
+        assert!(ladder.record_failure(start + Duration::from_secs(25), &steps, |_| true, 15).is_some());
+        assert_eq!(ladder.failures(), 5);
+
+        // 所有步骤被禁用时不执行任何动作
+        let disabled = vec![RecoveryStep { enabled: false, ..steps[0].clone() }];
+        assert!(RecoveryLadder::default().record_failure(start, &disabled, |_| true, 15).is_none());
+    }
+}
@@ -70,6 +70,23 @@ impl WebhookSender {
         self.send_webhook_raw(&config, &payload).await
     }
     
+    /// 转发设备事件（过温告警、恢复等）
+    ///
+    /// # Arguments
+    /// * `event` - 事件名称（如 thermal_critical）
+    /// * `message` - 事件详情
+    pub async fn forward_event(&self, event: &str, message: &str) -> Result<(), String> {
+        let config = self.get_config();
+        
+        if !config.enabled || !config.forward_events || config.url.is_empty() {
+            return Ok(());
+        }
+        
+        let payload = render_event_template(&config.event_template, event, message);
+        
+        self.send_webhook_raw(&config, &payload).await
+    }
+    
     /// 发送原始 JSON 字符串的 Webhook 请求
     async fn send_webhook_raw(&self, config: &WebhookConfig, payload: &str) -> Result<(), String> {
         let mut request = self.client.post(&config.url);
@@ -193,6 +210,19 @@ fn render_call_template(template: &str, call: &CallRecord) -> String {
         .replace("{{time}}", &call.start_time)
 }
 
+/// 渲染设备事件模板，替换变量
+/// 支持的变量：{{event}}, {{message}}, {{timestamp}}
+fn render_event_template(template: &str, event: &str, message: &str) -> String {
+    let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    
+    template
+        .replace("{{event}}", event)
+        .replace("{{message}}", &escape_json_string(message))
+        .replace("{{timestamp}}", &timestamp)
+        // 别名支持
+        .replace("{{time}}", &timestamp)
+}
+
 /// 转义 JSON 字符串中的特殊字符
 fn escape_json_string(s: &str) -> String {
     s.replace('\\', "\\\\")
@@ -75,12 +75,44 @@ export interface CellInfo {
   ssb_rsrp?: string | number
   ssb_rsrq?: string | number
   ssb_sinr?: string | number
+  // 由 ARFCN 换算的中心频率（MHz）
+  dl_freq_mhz?: number
+  ul_freq_mhz?: number | null
 }
 
 // 小区列表响应
+// 分量载波（载波聚合 / EN-DC）
+export interface ComponentCarrier {
+  role: 'pcell' | 'scell' | 'pscell'
+  tech: string
+  band: string
+  arfcn: number
+  pci: number
+  dl_freq_mhz?: number
+  rsrp: string // 原始值×100
+  rsrq: string
+  sinr: string
+}
+
+export interface EnDcLink {
+  anchor_band: string
+  anchor_arfcn: number
+  anchor_pci: number
+  nr_band: string
+  nr_arfcn: number
+  nr_pci: number
+}
+
+export interface CarrierAggregation {
+  mode: 'single' | 'lte_ca' | 'nr_ca' | 'endc'
+  carriers: ComponentCarrier[]
+  endc?: EnDcLink
+}
+
 export interface CellsResponse {
   serving_cell: ServingCell
   cells: CellInfo[]
+  carrier_aggregation?: CarrierAggregation
 }
 
 // QoS 信息
@@ -102,6 +134,33 @@ export interface DataConnectionStatus {
   active: boolean
 }
 
+// 数据连接 IP 配置
+export interface DataIpConfig {
+  context_path: string
+  active: boolean
+  apn: string
+  interface: string
+  ipv4: {
+    method: string
+    address: string
+    netmask: string
+    gateway: string
+    dns: string[]
+  }
+  ipv6: {
+    address: string
+    prefix_length: number | null
+    prefix: string           // 如 2409:8a00:1:2::/64
+    gateway: string
+    dns: string[]
+  }
+  has_ipv6: boolean
+  public_ip: string | null
+  public_ip_error: string | null
+  nat_type: 'none' | 'cgnat' | 'private' | 'nat' | 'unknown'
+  behind_cgnat: boolean | null
+}
+
 // 漫游状态响应
 export interface RoamingResponse {
   roaming_allowed: boolean  // 是否允许漫游数据
@@ -564,12 +623,14 @@ export interface ApnContext {
   username: string       // 用户名
   password: string       // 密码
   auth_method: string    // 认证方式: none/pap/chap
-  context_type: string   // 类型: internet/mms/ims
+  context_type: string   // 类型: internet/mms/ims/wap
+  watchdog: boolean      // 是否为 watchdog 保持激活的 context
 }
 
 // APN 列表响应
 export interface ApnListResponse {
   contexts: ApnContext[]
+  watchdog_context: string  // watchdog 保持激活的 context 路径
 }
 
 // 设置 APN 请求